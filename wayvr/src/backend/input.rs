@@ -1,20 +1,22 @@
 use std::f32::consts::PI;
 use std::process::{Child, Command};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use glam::{Affine3A, Vec2, Vec3A, Vec3Swizzles};
 
 use idmap_derive::IntegerId;
 use smallvec::{SmallVec, smallvec};
-use strum::AsRefStr;
+use strum::{AsRefStr, EnumString};
 use wlx_common::common::LeftRight;
+use wlx_common::config::GeneralConfig;
 use wlx_common::windowing::{OverlayWindowState, Positioning};
 
 use crate::backend::task::{InputTask, OverlayTask};
 use crate::overlays::anchor::{ANCHOR_NAME, GRAB_HELP_NAME};
 use crate::overlays::watch::WATCH_NAME;
 use crate::state::{AppSession, AppState};
+use crate::subsystem::heart_rate::command::HrCommand;
 use crate::subsystem::hid::WheelDelta;
 use crate::subsystem::input::KeyboardFocus;
 use crate::windowing::backend::OverlayEventData;
@@ -47,12 +49,42 @@ pub enum TrackedDeviceRole {
     Tracker,
 }
 
+/// Bindable actions that fire once per press, named as in `hr_threshold_action`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum InputAction {
+    ShowHide,
+    ToggleDashboard,
+    HrTogglePolling,
+    HrForcePoll,
+    HrCycleSource,
+}
+
+impl InputAction {
+    const fn state(self, state: &PointerState) -> bool {
+        match self {
+            Self::ShowHide => state.show_hide,
+            Self::ToggleDashboard => state.toggle_dashboard,
+            Self::HrTogglePolling => state.hr_toggle_polling,
+            Self::HrForcePoll => state.hr_force_poll,
+            Self::HrCycleSource => state.hr_cycle_source,
+        }
+    }
+}
+
 pub struct InputState {
     pub hmd: Affine3A,
     pub ipd: f32,
     pub pointers: [Pointer; 2],
     pub devices: Vec<TrackedDevice>,
     processes: Vec<Child>,
+    /// Fired by a heart rate crossing, pressed with the next input update
+    pending_actions: Vec<InputAction>,
+    /// Fired by a heart rate crossing, pressed with this input update
+    fired_actions: Vec<InputAction>,
+    /// Whether the heart rate was last seen above `hr_threshold_bpm`
+    hr_above_threshold: bool,
+    hr_threshold_fired_at: Option<Instant>,
 }
 
 impl InputState {
@@ -63,9 +95,71 @@ impl InputState {
             pointers: [Pointer::new(0), Pointer::new(1)],
             devices: Vec::new(),
             processes: Vec::new(),
+            pending_actions: Vec::new(),
+            fired_actions: Vec::new(),
+            hr_above_threshold: false,
+            hr_threshold_fired_at: None,
         }
     }
 
+    /// Fires `hr_threshold_action` once the heart rate rises above `hr_threshold_bpm`, as if its
+    /// binding was pressed. Staying above doesn't fire it again, and neither does a new crossing
+    /// within `hr_threshold_cooldown_secs`. Returns the threshold if it fired.
+    pub fn heart_rate_tick(&mut self, rate: Option<u32>, config: &GeneralConfig) -> Option<u32> {
+        let (Some(threshold), Some(rate)) = (config.hr_threshold_bpm, rate) else {
+            // keep the state through gaps in the readings, so coming back doesn't count as a crossing
+            return None;
+        };
+
+        let above = rate > threshold;
+        let crossed = above && !self.hr_above_threshold;
+        self.hr_above_threshold = above;
+        if !crossed {
+            return None;
+        }
+
+        let cooldown = Duration::from_secs(config.hr_threshold_cooldown_secs);
+        if self
+            .hr_threshold_fired_at
+            .is_some_and(|fired_at| fired_at.elapsed() < cooldown)
+        {
+            log::debug!("Heart rate crossed {threshold} BPM again, still cooling down.");
+            return None;
+        }
+        self.hr_threshold_fired_at = Some(Instant::now());
+        log::info!("Heart rate crossed {threshold} BPM.");
+
+        if let Some(name) = config.hr_threshold_action.as_deref() {
+            match name.parse() {
+                Ok(action) => self.pending_actions.push(action),
+                Err(_) => log::warn!("Unknown hr_threshold_action '{name}'."),
+            }
+        }
+        Some(threshold)
+    }
+
+    /// Whether `action` was pressed with the last input update, on either hand
+    /// or by a heart rate crossing
+    pub fn action_pressed(&self, action: InputAction) -> bool {
+        self.fired_actions.contains(&action)
+            || self
+                .pointers
+                .iter()
+                .any(|pointer| action.state(&pointer.now) && !action.state(&pointer.before))
+    }
+
+    /// Commands of the heart rate actions that were pressed with the last input update
+    pub fn heart_rate_commands(&self) -> Vec<HrCommand> {
+        [
+            (InputAction::HrTogglePolling, HrCommand::TogglePolling),
+            (InputAction::HrForcePoll, HrCommand::ForcePoll),
+            (InputAction::HrCycleSource, HrCommand::CycleSource),
+        ]
+        .into_iter()
+        .filter_map(|(action, command)| self.action_pressed(action).then_some(command))
+        .collect()
+    }
+
     pub fn handle_task(&mut self, task: InputTask) {
         match task {
             InputTask::Haptics { device, haptics } => {
@@ -78,9 +172,10 @@ impl InputState {
         }
     }
 
-    pub const fn pre_update(&mut self) {
+    pub fn pre_update(&mut self) {
         self.pointers[0].before = self.pointers[0].now;
         self.pointers[1].before = self.pointers[1].now;
+        self.fired_actions = std::mem::take(&mut self.pending_actions);
     }
 
     pub fn post_update(&mut self, session: &AppSession) {
@@ -280,6 +375,9 @@ pub struct PointerState {
     pub click_modifier_right: bool,
     pub click_modifier_middle: bool,
     pub move_mouse: bool,
+    pub hr_toggle_polling: bool,
+    pub hr_force_poll: bool,
+    pub hr_cycle_source: bool,
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -1028,3 +1126,95 @@ fn raycast_cylinder(
 
     Some((t, hit_local.xy()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heart_rate_actions_fire_once_per_press() {
+        let mut input = InputState::new();
+        assert!(input.heart_rate_commands().is_empty());
+
+        input.pre_update();
+        input.pointers[1].now.hr_toggle_polling = true;
+        assert_eq!(input.heart_rate_commands(), [HrCommand::TogglePolling]);
+
+        // held down
+        input.pre_update();
+        assert!(input.heart_rate_commands().is_empty());
+
+        input.pre_update();
+        input.pointers[1].now.hr_toggle_polling = false;
+        assert!(input.heart_rate_commands().is_empty());
+    }
+
+    #[test]
+    fn heart_rate_actions_of_both_hands_are_combined() {
+        let mut input = InputState::new();
+        input.pointers[0].now.hr_force_poll = true;
+        input.pointers[1].now.hr_force_poll = true;
+        input.pointers[1].now.hr_toggle_polling = true;
+
+        assert_eq!(
+            input.heart_rate_commands(),
+            [HrCommand::TogglePolling, HrCommand::ForcePoll]
+        );
+    }
+
+    fn threshold_config(cooldown_secs: u64) -> GeneralConfig {
+        serde_json::from_str(&format!(
+            r#"{{"hr_threshold_bpm": 170, "hr_threshold_action": "hr_force_poll",
+                "hr_threshold_cooldown_secs": {cooldown_secs}}}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn threshold_fires_on_the_rising_edge_only() {
+        let config = threshold_config(0);
+        let mut input = InputState::new();
+
+        let fired: Vec<_> = [150, 175, 180, 172, 165, 172]
+            .into_iter()
+            .map(|rate| input.heart_rate_tick(Some(rate), &config).is_some())
+            .collect();
+        // staying above doesn't fire again, dropping below re-arms it
+        assert_eq!(fired, [false, true, false, false, false, true]);
+    }
+
+    #[test]
+    fn threshold_presses_the_action_with_the_next_update() {
+        let config = threshold_config(0);
+        let mut input = InputState::new();
+        input.heart_rate_tick(Some(175), &config);
+        assert!(input.heart_rate_commands().is_empty());
+
+        input.pre_update();
+        assert_eq!(input.heart_rate_commands(), [HrCommand::ForcePoll]);
+        assert!(!input.action_pressed(InputAction::ShowHide));
+
+        input.pre_update();
+        assert!(input.heart_rate_commands().is_empty());
+    }
+
+    #[test]
+    fn threshold_crossings_within_the_cooldown_are_ignored() {
+        let config = threshold_config(600);
+        let mut input = InputState::new();
+
+        assert_eq!(input.heart_rate_tick(Some(175), &config), Some(170));
+        input.heart_rate_tick(Some(160), &config);
+        assert_eq!(input.heart_rate_tick(Some(175), &config), None);
+    }
+
+    #[test]
+    fn gaps_in_the_readings_are_no_crossing() {
+        let config = threshold_config(0);
+        let mut input = InputState::new();
+
+        assert!(input.heart_rate_tick(Some(175), &config).is_some());
+        assert!(input.heart_rate_tick(None, &config).is_none());
+        assert!(input.heart_rate_tick(Some(176), &config).is_none());
+    }
+}
@@ -35,6 +35,9 @@ const PATH_CLICK_MODIFIER_MIDDLE: &str = "/actions/default/in/ClickModifierMiddl
 const PATH_CLICK_MODIFIER_RIGHT: &str = "/actions/default/in/ClickModifierRight";
 const PATH_CLICK: &str = "/actions/default/in/Click";
 const PATH_GRAB: &str = "/actions/default/in/Grab";
+const PATH_HR_CYCLE_SOURCE: &str = "/actions/default/in/HeartRateCycleSource";
+const PATH_HR_FORCE_POLL: &str = "/actions/default/in/HeartRateForcePoll";
+const PATH_HR_TOGGLE_POLLING: &str = "/actions/default/in/HeartRateTogglePolling";
 const PATH_MOVE_MOUSE: &str = "/actions/default/in/MoveMouse";
 const PATH_SCROLL: &str = "/actions/default/in/Scroll";
 const PATH_SHOW_HIDE: &str = "/actions/default/in/ShowHide";
@@ -58,6 +61,9 @@ pub(super) struct OpenVrInputSource {
     click_modifier_right_hnd: ActionHandle,
     click_modifier_middle_hnd: ActionHandle,
     move_mouse_hnd: ActionHandle,
+    hr_toggle_polling_hnd: ActionHandle,
+    hr_force_poll_hnd: ActionHandle,
+    hr_cycle_source_hnd: ActionHandle,
 }
 
 pub(super) struct OpenVrHandSource {
@@ -83,6 +89,9 @@ impl OpenVrInputSource {
         let click_modifier_right_hnd = input.get_action_handle(PATH_CLICK_MODIFIER_RIGHT)?;
         let click_modifier_middle_hnd = input.get_action_handle(PATH_CLICK_MODIFIER_MIDDLE)?;
         let move_mouse_hnd = input.get_action_handle(PATH_MOVE_MOUSE)?;
+        let hr_toggle_polling_hnd = input.get_action_handle(PATH_HR_TOGGLE_POLLING)?;
+        let hr_force_poll_hnd = input.get_action_handle(PATH_HR_FORCE_POLL)?;
+        let hr_cycle_source_hnd = input.get_action_handle(PATH_HR_CYCLE_SOURCE)?;
 
         let input_hnd: Vec<InputValueHandle> = INPUT_SOURCES
             .iter()
@@ -121,6 +130,9 @@ impl OpenVrInputSource {
             click_modifier_right_hnd,
             click_modifier_middle_hnd,
             move_mouse_hnd,
+            hr_toggle_polling_hnd,
+            hr_force_poll_hnd,
+            hr_cycle_source_hnd,
         })
     }
 
@@ -258,6 +270,21 @@ impl OpenVrInputSource {
                 .map(|x| x.0.bState)
                 .unwrap_or(false);
 
+            app_hand.now.hr_toggle_polling = input
+                .get_digital_action_data(self.hr_toggle_polling_hnd, hand.input_hnd)
+                .map(|x| x.0.bState)
+                .unwrap_or(false);
+
+            app_hand.now.hr_force_poll = input
+                .get_digital_action_data(self.hr_force_poll_hnd, hand.input_hnd)
+                .map(|x| x.0.bState)
+                .unwrap_or(false);
+
+            app_hand.now.hr_cycle_source = input
+                .get_digital_action_data(self.hr_cycle_source_hnd, hand.input_hnd)
+                .map(|x| x.0.bState)
+                .unwrap_or(false);
+
             let scroll = input
                 .get_analog_action_data(self.scroll_hnd, hand.input_hnd)
                 .map(|x| (x.0.x, x.0.y))
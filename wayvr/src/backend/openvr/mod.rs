@@ -18,7 +18,7 @@ use crate::{
     FRAME_COUNTER, RUNNING,
     backend::{
         BackendError, XrBackend,
-        input::interact,
+        input::{InputAction, interact},
         openvr::{
             helpers::adjust_gain,
             input::{OpenVrInputSource, set_action_manifest},
@@ -35,7 +35,7 @@ use crate::{
         watch::{WATCH_NAME, watch_fade},
     },
     state::AppState,
-    subsystem::notifications::NotificationManager,
+    subsystem::{heart_rate::HeartRateSource, notifications::NotificationManager},
     windowing::{
         backend::{RenderResources, RenderTarget, ShouldRender},
         manager::OverlayWindowManager,
@@ -166,6 +166,8 @@ pub fn openvr_run(show_by_default: bool, headless: bool) -> Result<(), BackendEr
             const EV_CHAP_CHANGED: u32 = EVREventType::VREvent_ChaperoneUniverseHasChanged as u32;
             const EV_SCENE_CHANGED: u32 = EVREventType::VREvent_SceneApplicationChanged as u32;
             const EV_IPD_CHANGED: u32 = EVREventType::VREvent_IpdChanged as u32;
+            const EV_STANDBY_ENTER: u32 = EVREventType::VREvent_EnterStandbyMode as u32;
+            const EV_STANDBY_LEAVE: u32 = EVREventType::VREvent_LeaveStandbyMode as u32;
 
             while let Some(event) = system_mgr.poll_next_event() {
                 match event.event_type {
@@ -176,6 +178,10 @@ pub fn openvr_run(show_by_default: bool, headless: bool) -> Result<(), BackendEr
                     EV_DEV_ACTIVATED | EV_DEV_DEACTIVATED | EV_DEV_UPDATED => {
                         next_device_update = Instant::now();
                     }
+                    EV_STANDBY_ENTER | EV_STANDBY_LEAVE => {
+                        app.headset_active = event.event_type == EV_STANDBY_LEAVE;
+                        log::info!("Headset active: {}", app.headset_active);
+                    }
                     EV_SEAT_ZERO | EV_STAND_ZERO | EV_CHAP_CHANGED | EV_SCENE_CHANGED => {
                         playspace.playspace_changed(&mut compositor_mgr, &mut chaperone_mgr);
                     }
@@ -211,6 +217,8 @@ pub fn openvr_run(show_by_default: bool, headless: bool) -> Result<(), BackendEr
         }
 
         app.dbus.tick();
+        app.config_reload_tick();
+        app.heart_rate_tick();
         notifications.submit_pending(&mut app);
 
         app.tasks.retrieve_due(&mut due_tasks);
@@ -244,22 +252,12 @@ pub fn openvr_run(show_by_default: bool, headless: bool) -> Result<(), BackendEr
         input_source.update(universe.clone(), &mut input_mgr, &mut system_mgr, &mut app);
         app.input_state.post_update(&app.session);
 
-        if app
-            .input_state
-            .pointers
-            .iter()
-            .any(|p| p.now.show_hide && !p.before.show_hide)
-        {
+        if app.input_state.action_pressed(InputAction::ShowHide) {
             lines.mark_dirty(); // workaround to prevent lines from not showing
             overlays.show_hide(&mut app);
         }
 
-        if app
-            .input_state
-            .pointers
-            .iter()
-            .any(|p| p.now.toggle_dashboard && !p.before.toggle_dashboard)
-        {
+        if app.input_state.action_pressed(InputAction::ToggleDashboard) {
             app.tasks
                 .enqueue(TaskType::Overlay(OverlayTask::ToggleDashboard));
         }
@@ -339,6 +337,10 @@ pub fn openvr_run(show_by_default: bool, headless: bool) -> Result<(), BackendEr
         // chaperone
     } // main_loop
 
+    app.heart_rate.flush_on_shutdown();
+    if let Some(daily_max) = app.heart_rate_daily_max.as_ref() {
+        daily_max.save();
+    }
     overlays.persist_layout(&mut app);
     if let Err(e) = save_state(&app.session.config) {
         log::error!("Could not save state: {e:?}");
@@ -158,6 +158,9 @@ pub(super) struct OpenXrHandSource {
     modifier_right: CustomClickAction,
     modifier_middle: CustomClickAction,
     move_mouse: CustomClickAction,
+    hr_toggle_polling: CustomClickAction,
+    hr_force_poll: CustomClickAction,
+    hr_cycle_source: CustomClickAction,
     scroll: xr::Action<Vector2f>,
     haptics: xr::Action<xr::Haptic>,
 }
@@ -485,6 +488,21 @@ impl OpenXrPointer {
                 .space_reset
                 .state(pointer.before.space_reset, xr, session)?;
 
+        pointer.now.hr_toggle_polling =
+            self.source
+                .hr_toggle_polling
+                .state(pointer.before.hr_toggle_polling, xr, session)?;
+
+        pointer.now.hr_force_poll =
+            self.source
+                .hr_force_poll
+                .state(pointer.before.hr_force_poll, xr, session)?;
+
+        pointer.now.hr_cycle_source =
+            self.source
+                .hr_cycle_source
+                .state(pointer.before.hr_cycle_source, xr, session)?;
+
         Ok(())
     }
 }
@@ -523,6 +541,9 @@ impl OpenXrHandSource {
             modifier_right: CustomClickAction::new(action_set, "click_modifier_right", side)?,
             modifier_middle: CustomClickAction::new(action_set, "click_modifier_middle", side)?,
             move_mouse: CustomClickAction::new(action_set, "move_mouse", side)?,
+            hr_toggle_polling: CustomClickAction::new(action_set, "hr_toggle_polling", side)?,
+            hr_force_poll: CustomClickAction::new(action_set, "hr_force_poll", side)?,
+            hr_cycle_source: CustomClickAction::new(action_set, "hr_cycle_source", side)?,
             haptics: action_haptics,
         })
     }
@@ -701,6 +722,30 @@ fn suggest_bindings(instance: &xr::Instance, hands: &[&OpenXrHandSource; 3]) {
 
         add_custom!(profile.move_mouse, move_mouse, hands, bindings, instance);
 
+        add_custom!(
+            profile.hr_toggle_polling,
+            hr_toggle_polling,
+            hands,
+            bindings,
+            instance
+        );
+
+        add_custom!(
+            profile.hr_force_poll,
+            hr_force_poll,
+            hands,
+            bindings,
+            instance
+        );
+
+        add_custom!(
+            profile.hr_cycle_source,
+            hr_cycle_source,
+            hands,
+            bindings,
+            instance
+        );
+
         if instance
             .suggest_interaction_profile_bindings(profile_path, &bindings)
             .is_err()
@@ -748,6 +793,9 @@ struct OpenXrActionConfProfile {
     click_modifier_right: Option<OpenXrActionConfAction>,
     click_modifier_middle: Option<OpenXrActionConfAction>,
     move_mouse: Option<OpenXrActionConfAction>,
+    hr_toggle_polling: Option<OpenXrActionConfAction>,
+    hr_force_poll: Option<OpenXrActionConfAction>,
+    hr_cycle_source: Option<OpenXrActionConfAction>,
     scroll: Option<OpenXrActionConfAction>,
     haptic: Option<OpenXrActionConfAction>,
 }
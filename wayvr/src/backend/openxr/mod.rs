@@ -16,7 +16,7 @@ use crate::{
     FRAME_COUNTER, RUNNING,
     backend::{
         BackendError, XrBackend,
-        input::interact,
+        input::{InputAction, interact},
         openxr::{lines::LinePool, overlay::OpenXrOverlayData},
         task::{OverlayTask, TaskType},
     },
@@ -27,7 +27,7 @@ use crate::{
         watch::{WATCH_NAME, watch_fade},
     },
     state::AppState,
-    subsystem::notifications::NotificationManager,
+    subsystem::{heart_rate::HeartRateSource, notifications::NotificationManager},
     windowing::{
         backend::{RenderResources, RenderTarget, ShouldRender},
         manager::OverlayWindowManager,
@@ -183,6 +183,15 @@ pub fn openxr_run(show_by_default: bool, headless: bool) -> Result<(), BackendEr
                     // Session state change is where we can begin and end sessions, as well as
                     // find quit messages!
                     log::info!("entered state {:?}", e.state());
+                    match e.state() {
+                        xr::SessionState::VISIBLE | xr::SessionState::FOCUSED => {
+                            app.headset_active = true;
+                        }
+                        xr::SessionState::IDLE | xr::SessionState::SYNCHRONIZED => {
+                            app.headset_active = false;
+                        }
+                        _ => {}
+                    }
                     match e.state() {
                         xr::SessionState::READY => {
                             xr_state.session.begin(VIEW_TYPE)?;
@@ -277,21 +286,11 @@ pub fn openxr_run(show_by_default: bool, headless: bool) -> Result<(), BackendEr
             blocker.update(&mut app);
         }
 
-        if app
-            .input_state
-            .pointers
-            .iter()
-            .any(|p| p.now.show_hide && !p.before.show_hide)
-        {
+        if app.input_state.action_pressed(InputAction::ShowHide) {
             overlays.show_hide(&mut app);
         }
 
-        if app
-            .input_state
-            .pointers
-            .iter()
-            .any(|p| p.now.toggle_dashboard && !p.before.toggle_dashboard)
-        {
+        if app.input_state.action_pressed(InputAction::ToggleDashboard) {
             app.tasks
                 .enqueue(TaskType::Overlay(OverlayTask::ToggleDashboard));
         }
@@ -468,6 +467,8 @@ pub fn openxr_run(show_by_default: bool, headless: bool) -> Result<(), BackendEr
         // End layer submit
 
         app.dbus.tick();
+        app.config_reload_tick();
+        app.heart_rate_tick();
         notifications.submit_pending(&mut app);
 
         app.tasks.retrieve_due(&mut due_tasks);
@@ -500,6 +501,10 @@ pub fn openxr_run(show_by_default: bool, headless: bool) -> Result<(), BackendEr
         watch.config.active_state.as_mut().unwrap().transform = watch_transform;
     } // main_loop
 
+    app.heart_rate.flush_on_shutdown();
+    if let Some(daily_max) = app.heart_rate_daily_max.as_ref() {
+        daily_max.save();
+    }
     overlays.persist_layout(&mut app);
     if let Err(e) = save_state(&app.session.config) {
         log::error!("Could not save state: {e:?}");
@@ -1,7 +1,11 @@
 use config::{Config, File};
 use log::error;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
 use wayvr_ipc::packet_client::WvrProcessLaunchParams;
 use wlx_common::{
     astr_containers::AStrMap,
@@ -51,6 +55,20 @@ pub fn load_config_with_conf_d<ConfigData>(
     root_config_filename: &str,
     ctype: config_io::ConfigRoot,
 ) -> ConfigData
+where
+    ConfigData: for<'de> Deserialize<'de>,
+{
+    match try_load_config_with_conf_d(root_config_filename, ctype) {
+        Ok(config) => config,
+        Err(e) => panic!("{e}"),
+    }
+}
+
+/// Same as `load_config_with_conf_d`, but returns the error instead of panicking
+pub fn try_load_config_with_conf_d<ConfigData>(
+    root_config_filename: &str,
+    ctype: config_io::ConfigRoot,
+) -> anyhow::Result<ConfigData>
 where
     ConfigData: for<'de> Deserialize<'de>,
 {
@@ -87,14 +105,10 @@ where
 
     match settings_builder.build() {
         Ok(settings) => match settings.try_deserialize::<ConfigData>() {
-            Ok(config) => config,
-            Err(e) => {
-                panic!("Failed to deserialize settings: {e}");
-            }
+            Ok(config) => Ok(config),
+            Err(e) => Err(anyhow::anyhow!("Failed to deserialize settings: {e}")),
         },
-        Err(e) => {
-            panic!("Failed to build settings: {e}");
-        }
+        Err(e) => Err(anyhow::anyhow!("Failed to build settings: {e}")),
     }
 }
 
@@ -102,6 +116,81 @@ pub fn load_general_config() -> GeneralConfig {
     load_config_with_conf_d::<GeneralConfig>("config.yaml", config_io::ConfigRoot::Generic)
 }
 
+pub fn try_load_general_config() -> anyhow::Result<GeneralConfig> {
+    try_load_config_with_conf_d::<GeneralConfig>("config.yaml", config_io::ConfigRoot::Generic)
+}
+
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Notices edits of config.yaml and the files in conf.d by their modification times.
+/// Files written by wayvr itself (`zz-saved-*`) are left out, they are in use already.
+pub struct ConfigWatcher {
+    next_check_at: Instant,
+    modified: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl Default for ConfigWatcher {
+    fn default() -> Self {
+        Self {
+            next_check_at: Instant::now() + CONFIG_CHECK_INTERVAL,
+            modified: watched_config_files(),
+        }
+    }
+}
+
+impl ConfigWatcher {
+    /// Whether a file was changed, added or removed since the last call
+    pub fn changed(&mut self) -> bool {
+        if Instant::now() < self.next_check_at {
+            return false;
+        }
+        self.next_check_at = Instant::now() + CONFIG_CHECK_INTERVAL;
+
+        let modified = watched_config_files();
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+/// Which of `keys` differ between the two configs, by their names in the config file
+pub fn changed_keys<'a>(
+    old: &GeneralConfig,
+    new: &GeneralConfig,
+    keys: &[&'a str],
+) -> Vec<&'a str> {
+    let (Ok(old), Ok(new)) = (serde_json::to_value(old), serde_json::to_value(new)) else {
+        return Vec::new();
+    };
+    keys.iter()
+        .copied()
+        .filter(|key| old.get(key) != new.get(key))
+        .collect()
+}
+
+fn watched_config_files() -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut paths = vec![config_io::get_config_root().join("config.yaml")];
+    if let Ok(entries) = std::fs::read_dir(config_io::ConfigRoot::Generic.get_conf_d_path()) {
+        paths.extend(
+            entries
+                .filter_map(Result::ok)
+                .filter(|entry| !entry.file_name().to_string_lossy().starts_with("zz-saved-"))
+                .map(|entry| entry.path()),
+        );
+    }
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let modified = path.metadata().and_then(|meta| meta.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
+
 #[derive(Serialize)]
 pub struct AutoSettings {
     pub ui_animation_speed: f32,
@@ -229,3 +318,116 @@ pub fn save_state(config: &GeneralConfig) -> anyhow::Result<()> {
     log::info!("State was saved successfully.");
     Ok(())
 }
+
+// Fitbit tokens that are obtained via login or rotated during refresh
+
+#[derive(Serialize)]
+pub struct AutoFitbitTokens<'a> {
+    pub fitbit_access_token: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fitbit_refresh_token: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+pub struct AutoFitbitProfileTokens<'a> {
+    pub fitbit_profiles: HashMap<&'a str, AutoFitbitProfileToken<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct AutoFitbitProfileToken<'a> {
+    pub access_token: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<&'a str>,
+}
+
+/// Every profile gets its own file, so saving one doesn't drop the tokens of the others
+fn get_fitbit_tokens_path(profile: Option<&str>) -> PathBuf {
+    let file_name = profile.map_or_else(
+        || "zz-saved-fitbit.json5".to_string(),
+        |profile| {
+            let profile: String = profile
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            format!("zz-saved-fitbit-{profile}.json5")
+        },
+    );
+
+    config_io::ConfigRoot::Generic
+        .get_conf_d_path()
+        .join(file_name)
+}
+
+/// `profile` is the selected `fitbit_profile`, None for the top-level credentials
+pub fn save_fitbit_tokens(
+    profile: Option<&str>,
+    access_token: &str,
+    refresh_token: Option<&str>,
+) -> anyhow::Result<()> {
+    let json = match profile {
+        Some(profile) => serde_json::to_string_pretty(&AutoFitbitProfileTokens {
+            fitbit_profiles: HashMap::from([(
+                profile,
+                AutoFitbitProfileToken {
+                    access_token,
+                    refresh_token,
+                },
+            )]),
+        }),
+        None => serde_json::to_string_pretty(&AutoFitbitTokens {
+            fitbit_access_token: access_token,
+            fitbit_refresh_token: refresh_token,
+        }),
+    }
+    .unwrap(); // want panic
+    std::fs::write(get_fitbit_tokens_path(profile), json)?;
+
+    log::info!("Fitbit tokens were saved successfully.");
+    Ok(())
+}
+
+// Garmin tokens that are rotated during refresh
+
+#[derive(Serialize)]
+pub struct AutoGarminTokens<'a> {
+    pub garmin_access_token: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub garmin_refresh_token: Option<&'a str>,
+}
+
+fn get_garmin_tokens_path() -> PathBuf {
+    config_io::ConfigRoot::Generic
+        .get_conf_d_path()
+        .join("zz-saved-garmin.json5")
+}
+
+pub fn save_garmin_tokens(access_token: &str, refresh_token: Option<&str>) -> anyhow::Result<()> {
+    let conf = AutoGarminTokens {
+        garmin_access_token: access_token,
+        garmin_refresh_token: refresh_token,
+    };
+
+    let json = serde_json::to_string_pretty(&conf).unwrap(); // want panic
+    std::fs::write(get_garmin_tokens_path(), json)?;
+
+    log::info!("Garmin tokens were saved successfully.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_keys_lists_the_differing_ones() {
+        let old: GeneralConfig = serde_json::from_str("{}").unwrap();
+        let new: GeneralConfig =
+            serde_json::from_str(r#"{"hr_source": "ble", "hr_bias": {"ble": -3}}"#).unwrap();
+
+        assert_eq!(
+            changed_keys(&old, &new, &["hr_source", "hr_fifo_path", "hr_bias"]),
+            ["hr_source", "hr_bias"]
+        );
+        assert!(changed_keys(&new, &new, &["hr_source", "hr_bias"]).is_empty());
+    }
+}
@@ -33,7 +33,10 @@ use crate::{
     gui::panel::{log_cmd_invalid_arg, log_cmd_missing_arg},
     overlays::{custom::create_custom, toast::Toast, wayvr::WvrCommand},
     state::AppState,
-    subsystem::hid::VirtualKey,
+    subsystem::{
+        heart_rate::{HeartRateSource, command::HrCommand},
+        hid::VirtualKey,
+    },
     windowing::{OverlaySelector, backend::OverlayEventData, window::OverlayCategory},
 };
 
@@ -583,6 +586,44 @@ pub(super) fn setup_custom_button<S: 'static>(
                 );
                 Ok(EventResult::Consumed)
             }),
+            "::HeartRatePollingToggle" => Box::new(move |_common, data, app, _| {
+                if !test_button(data) || !test_duration(&button, app) {
+                    return Ok(EventResult::Pass);
+                }
+
+                app.apply_heart_rate_command(HrCommand::TogglePolling);
+                Ok(EventResult::Consumed)
+            }),
+            "::HeartRateForcePoll" => Box::new(move |_common, data, app, _| {
+                if !test_button(data) || !test_duration(&button, app) {
+                    return Ok(EventResult::Pass);
+                }
+
+                app.apply_heart_rate_command(HrCommand::ForcePoll);
+                Ok(EventResult::Consumed)
+            }),
+            "::HeartRateCycleSource" => Box::new(move |_common, data, app, _| {
+                if !test_button(data) || !test_duration(&button, app) {
+                    return Ok(EventResult::Pass);
+                }
+
+                app.apply_heart_rate_command(HrCommand::CycleSource);
+                Ok(EventResult::Consumed)
+            }),
+            "::HeartRateCalibrateResting" => Box::new(move |_common, data, app, _| {
+                if !test_button(data) || !test_duration(&button, app) {
+                    return Ok(EventResult::Pass);
+                }
+
+                if app.heart_rate.is_calibrating_resting() {
+                    log::debug!("Resting heart rate calibration already running.");
+                } else {
+                    let secs = app.session.config.hr_resting_calibration_secs;
+                    app.heart_rate
+                        .start_resting_calibration(Duration::from_secs(secs));
+                }
+                Ok(EventResult::Consumed)
+            }),
             "::Shutdown" => Box::new(move |_common, data, app, _| {
                 if !test_button(data) || !test_duration(&button, app) {
                     return Ok(EventResult::Pass);
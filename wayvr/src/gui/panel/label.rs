@@ -11,10 +11,12 @@ use wgui::{
     parser::{CustomAttribsInfoOwned, ParserState, parse_color_hex},
     widget::{EventResult, label::WidgetLabel},
 };
+use wlx_common::config::HrFormat;
 
 use crate::{
     gui::panel::{log_invalid_attrib, log_missing_attrib},
     state::AppState,
+    subsystem::heart_rate::{HeartRateSource, Trend},
 };
 
 #[allow(clippy::too_many_lines)]
@@ -154,10 +156,48 @@ pub(super) fn setup_custom_label<S: 'static>(
             ipd_on_tick(common, data, app);
             Ok(EventResult::Pass)
         }),
-        "fitbit_hr" => Box::new(|common, data, app, _| {
-            fitbit_on_tick(common, data, app);
-            Ok(EventResult::Pass)
-        }),
+        "heart_rate" | "fitbit_hr" => {
+            let paused: Rc<str> = attribs.get_value("_paused").unwrap_or("paused").into();
+            let format = attribs.get_value("_format").and_then(|format| {
+                format
+                    .parse::<HrFormat>()
+                    .inspect_err(|_| {
+                        let msg = format!("expected bare, padded or suffix, found \"{format}\"");
+                        log_invalid_attrib(parser_state, TAG, "_format", &msg);
+                    })
+                    .ok()
+            });
+
+            Box::new(move |common, data, app, _| {
+                heart_rate_on_tick(&paused, format, common, data, app);
+                Ok(EventResult::Pass)
+            })
+        }
+        "hr_trend" => {
+            let arrows: [Rc<str>; 3] = [
+                attribs.get_value("_rising").unwrap_or("↑").into(),
+                attribs.get_value("_falling").unwrap_or("↓").into(),
+                attribs.get_value("_steady").unwrap_or("→").into(),
+            ];
+
+            Box::new(move |common, data, app, _| {
+                hr_trend_on_tick(&arrows, common, data, app);
+                Ok(EventResult::Pass)
+            })
+        }
+        "hr_stats" => {
+            let stat = attribs.get_value("_stat").unwrap_or("avg");
+            if !matches!(stat, "min" | "max" | "avg" | "count") {
+                log_invalid_attrib(parser_state, TAG, "_stat", stat);
+                return;
+            }
+            let stat: Rc<str> = stat.into();
+
+            Box::new(move |common, data, app, _| {
+                hr_stats_on_tick(&stat, common, data, app);
+                Ok(EventResult::Pass)
+            })
+        }
         unk => {
             log_invalid_attrib(parser_state, TAG, "_source", unk);
             return;
@@ -267,17 +307,82 @@ fn ipd_on_tick(
     label.set_text(common, Translation::from_raw_text(&text));
 }
 
-fn fitbit_on_tick(
+fn heart_rate_on_tick(
+    paused: &str,
+    format: Option<HrFormat>,
     common: &mut event::CallbackDataCommon,
     data: &mut event::CallbackData,
     app: &AppState,
 ) {
-    let mut fitbit_state = app.fitbit_state.borrow_mut();
-    fitbit_state.update(&app.session.config, app.watch_visible);
+    let label = data.obj.get_as_mut::<WidgetLabel>().unwrap();
+    let text = if app.heart_rate.polling_enabled() {
+        app.heart_rate
+            .formatted(format.unwrap_or(app.session.config.hr_format))
+    } else {
+        paused.to_string()
+    };
+    label.set_text(common, Translation::from_raw_text(&text));
+}
+
+fn hr_trend_on_tick(
+    arrows: &[Rc<str>; 3],
+    common: &mut event::CallbackDataCommon,
+    data: &mut event::CallbackData,
+    app: &AppState,
+) {
+    let text = if app.heart_rate.smoothed_rate().is_none() {
+        ""
+    } else {
+        match app.heart_rate.trend() {
+            Trend::Rising => &*arrows[0],
+            Trend::Falling => &*arrows[1],
+            Trend::Steady => &*arrows[2],
+        }
+    };
+
+    let label = data.obj.get_as_mut::<WidgetLabel>().unwrap();
+    label.set_text(common, Translation::from_raw_text(text));
+}
+
+fn hr_stats_on_tick(
+    stat: &str,
+    common: &mut event::CallbackDataCommon,
+    data: &mut event::CallbackData,
+    app: &AppState,
+) {
+    let stats = &app.heart_rate_stats;
+    let rate = match stat {
+        "min" => stats.min().map(|rate| rate as f32),
+        "max" => stats.max().map(|rate| rate as f32),
+        "avg" => stats.avg(),
+        "calories" => {
+            let text = stats.calories_session().map_or_else(
+                || "--".to_string(),
+                |calories| format!("{calories:.0} kcal"),
+            );
+            let label = data.obj.get_as_mut::<WidgetLabel>().unwrap();
+            label.set_text(common, Translation::from_raw_text(&text));
+            return;
+        }
+        "coherence" => {
+            let text = stats
+                .coherence()
+                .map_or_else(|| "--".to_string(), |coherence| format!("{coherence:.2}"));
+            let label = data.obj.get_as_mut::<WidgetLabel>().unwrap();
+            label.set_text(common, Translation::from_raw_text(&text));
+            return;
+        }
+        _ => {
+            let text = stats.count().to_string();
+            let label = data.obj.get_as_mut::<WidgetLabel>().unwrap();
+            label.set_text(common, Translation::from_raw_text(&text));
+            return;
+        }
+    };
 
     let label = data.obj.get_as_mut::<WidgetLabel>().unwrap();
-    let text = match fitbit_state.last_rate() {
-        Some(rate) => format!("{rate} bpm"),
+    let text = match rate {
+        Some(rate) => format!("{rate:.0} bpm"),
         None => "--".to_string(),
     };
     label.set_text(common, Translation::from_raw_text(&text));
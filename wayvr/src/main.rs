@@ -86,6 +86,18 @@ struct Args {
     /// Path to write logs to
     #[arg(short, long, value_name = "FILE_PATH")]
     log_to: Option<String>,
+
+    /// Log in to Fitbit via the browser, save the obtained tokens and exit
+    #[arg(long)]
+    fitbit_login: bool,
+
+    /// Poll Fitbit once, print whether the credentials work and exit
+    #[arg(long)]
+    check_fitbit: bool,
+
+    /// Print the heart rate status of the running instance and exit. Needs hr_dbus.
+    #[arg(long)]
+    status: bool,
 }
 
 #[allow(clippy::unnecessary_wraps)]
@@ -96,6 +108,33 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Args::default()
     };
 
+    // asks the running instance, so it must not be replaced
+    if args.status {
+        if !crate::subsystem::dbus::print_health() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // one-shot commands that must not replace a running instance
+    if args.fitbit_login {
+        wlx_common::config_io::ConfigRoot::Generic.ensure_dir();
+        let config = crate::config::load_general_config();
+        if let Err(e) = crate::subsystem::fitbit::authorize(&config) {
+            eprintln!("Fitbit login failed: {e:?}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if args.check_fitbit {
+        let config = crate::config::load_general_config();
+        if !crate::subsystem::fitbit::check(&config) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     if !args.multi && !ensure_single_instance(args.replace) {
         println!("Looks like WayVR is already running.");
         println!("Use --replace and I will terminate it for you.");
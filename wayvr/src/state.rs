@@ -1,8 +1,11 @@
 use glam::Affine3A;
 use idmap::IdMap;
 use smallvec::{SmallVec, smallvec};
-use std::cell::RefCell;
-use std::sync::Arc;
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use wgui::log::LogErr;
 use wgui::{
     drawing, font_config::WguiFontConfig, gfx::WGfx, globals::WguiGlobals, parser::parse_color_hex,
@@ -20,15 +23,67 @@ use crate::backend::wayvr::WvrServerState;
 #[cfg(feature = "osc")]
 use crate::subsystem::osc::OscSender;
 
+#[cfg(feature = "discord")]
+use crate::subsystem::discord::DiscordPresence;
+#[cfg(feature = "hr-http")]
+use crate::subsystem::hr_http::HrHttpServer;
+#[cfg(feature = "hr-ws")]
+use crate::subsystem::hr_ws::HrWsServer;
+#[cfg(feature = "prometheus")]
+use crate::subsystem::metrics::MetricsServer;
+#[cfg(feature = "midi")]
+use crate::subsystem::midi::MidiSender;
+#[cfg(feature = "pulsoid")]
+use crate::subsystem::pulsoid::PulsoidServer;
+
 use crate::{
     backend::{XrBackend, input::InputState, task::TaskContainer},
-    config::load_general_config,
+    config::{ConfigWatcher, changed_keys, load_general_config, try_load_general_config},
     graphics::WGfxExtras,
     gui,
     ipc::{event_queue::SyncEventQueue, ipc_server, signal::WayVRSignal},
-    subsystem::{dbus::DbusConnector, fitbit::FitbitState, input::HidWrapper},
+    subsystem::{
+        dbus::{DbusConnector, HeartRateService, SessionRequest},
+        heart_rate::{
+            self, HeartRateSource, HrStatus, IdleReason,
+            broadcast::ReadingBroadcast,
+            command::{CommandQueue, HrCommand},
+            control::ControlSocket,
+            daily::DailyMax,
+            health::Health,
+            history::ReadingHistory,
+            milestone::{Milestone, MilestoneTracker},
+            notify::{HrAlert, HrNotification, HrNotifier, SignalLostWatch},
+            recorder::HeartRateRecorder,
+            smoothing::SmoothedHeartRate,
+            stats::HeartRateStats,
+            status_file::StatusFile,
+        },
+        input::HidWrapper,
+    },
 };
 
+/// Config keys of the outputs and services set up at startup, a reload doesn't apply them.
+/// Changes to `heart_rate::SOURCE_CONFIG_KEYS` rebuild the source instead.
+const RESTART_CONFIG_KEYS: &[&str] = &[
+    "hr_daily_max",
+    "hr_daily_timezone",
+    "pulsoid_port",
+    "midi_port",
+    "discord_client_id",
+    "hr_http_port",
+    "hr_http_bind",
+    "hr_http_allow_origin",
+    "hr_ws_port",
+    "hr_ws_bind",
+    "hr_metrics_bind",
+    "hr_dbus",
+    "hr_control_socket",
+    "hr_log_path",
+    "hr_status_file_path",
+    "config_watch",
+];
+
 pub struct AppState {
     pub session: AppSession,
     pub tasks: TaskContainer,
@@ -47,7 +102,31 @@ pub struct AppState {
     pub anchor: Affine3A,
     pub anchor_grabbed: bool,
     pub watch_visible: bool,
-    pub fitbit_state: RefCell<FitbitState>,
+    /// False while the VR runtime reports the headset in standby
+    pub headset_active: bool,
+    pub heart_rate: SmoothedHeartRate,
+    pub heart_rate_stats: HeartRateStats,
+    /// With `hr_daily_max`
+    pub heart_rate_daily_max: Option<DailyMax>,
+    pub heart_rate_history: ReadingHistory,
+    /// Accepted readings for outputs on their own threads
+    pub heart_rate_readings: ReadingBroadcast,
+    pub heart_rate_service: Option<HeartRateService>,
+    pub heart_rate_recorder: Option<HeartRateRecorder>,
+    pub heart_rate_status_file: Option<StatusFile>,
+    pub heart_rate_control: Option<ControlSocket>,
+    /// Commands of the D-Bus service and the control socket
+    pub heart_rate_commands: CommandQueue,
+    pub heart_rate_notifier: HrNotifier,
+    heart_rate_battery_warned: bool,
+    heart_rate_auth_warned: bool,
+    heart_rate_scope_warned: bool,
+    heart_rate_disagree_warned: bool,
+    heart_rate_signal: SignalLostWatch,
+    /// Rate and staleness as last logged with `hr_log_readings`
+    heart_rate_logged: Option<(u32, bool)>,
+    heart_rate_milestones: MilestoneTracker,
+    config_watcher: Option<ConfigWatcher>,
 
     pub wgui_globals: WguiGlobals,
 
@@ -63,6 +142,24 @@ pub struct AppState {
     #[cfg(feature = "osc")]
     pub osc_sender: Option<OscSender>,
 
+    #[cfg(feature = "midi")]
+    pub midi_sender: Option<MidiSender>,
+
+    #[cfg(feature = "discord")]
+    pub discord_presence: Option<DiscordPresence>,
+
+    #[cfg(feature = "pulsoid")]
+    pub pulsoid_server: Option<PulsoidServer>,
+
+    #[cfg(feature = "hr-http")]
+    pub hr_http_server: Option<HrHttpServer>,
+
+    #[cfg(feature = "hr-ws")]
+    pub hr_ws_server: Option<HrWsServer>,
+
+    #[cfg(feature = "prometheus")]
+    pub metrics_server: Option<MetricsServer>,
+
     pub wvr_server: Option<WvrServerState>,
 
     #[cfg(feature = "openxr")]
@@ -89,7 +186,70 @@ impl AppState {
         let mut hid_provider = HidWrapper::new();
 
         #[cfg(feature = "osc")]
-        let osc_sender = crate::subsystem::osc::OscSender::new(session.config.osc_out_port).ok();
+        let osc_sender = crate::subsystem::osc::OscSender::new(
+            session.config.osc_out_port,
+            &session.config.osc_out_endpoints,
+        )
+        .ok();
+
+        #[cfg(feature = "midi")]
+        let midi_sender = session
+            .config
+            .midi_port
+            .as_deref()
+            .filter(|port| !port.trim().is_empty())
+            .and_then(|port| {
+                MidiSender::new(port.trim())
+                    .map_err(|e| log::warn!("Will not send heart rate over MIDI: {e:?}"))
+                    .ok()
+            });
+
+        #[cfg(feature = "discord")]
+        let discord_presence = session
+            .config
+            .discord_client_id
+            .as_deref()
+            .filter(|client_id| !client_id.trim().is_empty())
+            .map(|client_id| DiscordPresence::new(client_id.trim().to_string()));
+
+        let heart_rate_readings = ReadingBroadcast::default();
+
+        #[cfg(feature = "pulsoid")]
+        let pulsoid_server = session.config.pulsoid_port.and_then(|port| {
+            PulsoidServer::new(port, heart_rate_readings.subscribe())
+                .map_err(|e| log::warn!("Will not serve heart rate over websocket: {e:?}"))
+                .ok()
+        });
+
+        #[cfg(feature = "hr-http")]
+        let hr_http_server = session.config.hr_http_port.and_then(|port| {
+            HrHttpServer::new(
+                &session.config.hr_http_bind,
+                port,
+                session.config.hr_http_allow_origin.clone(),
+            )
+            .map_err(|e| log::warn!("Will not serve heart rate over HTTP: {e:?}"))
+            .ok()
+        });
+
+        #[cfg(feature = "hr-ws")]
+        let hr_ws_server = session.config.hr_ws_port.and_then(|port| {
+            HrWsServer::new(&session.config.hr_ws_bind, port)
+                .map_err(|e| log::warn!("Will not stream heart rate over websocket: {e:?}"))
+                .ok()
+        });
+
+        #[cfg(feature = "prometheus")]
+        let metrics_server = session
+            .config
+            .hr_metrics_bind
+            .as_deref()
+            .filter(|bind| !bind.trim().is_empty())
+            .and_then(|bind| {
+                MetricsServer::new(bind.trim())
+                    .map_err(|e| log::warn!("Will not serve heart rate metrics: {e:?}"))
+                    .ok()
+            });
 
         let wgui_shared = WSharedContext::new(gfx.clone())?;
         let theme = session.config.theme_path.clone();
@@ -141,6 +301,56 @@ impl AppState {
         let mut desktop_finder = DesktopFinder::new();
         desktop_finder.refresh();
 
+        let heart_rate = SmoothedHeartRate::new(heart_rate::from_config(&session.config));
+        let heart_rate_commands = CommandQueue::default();
+
+        let heart_rate_service = session
+            .config
+            .hr_dbus
+            .then(|| {
+                let alive_interval = session.config.hr_dbus_alive_interval_secs;
+                HeartRateService::new(
+                    (alive_interval > 0).then(|| Duration::from_secs(alive_interval)),
+                    heart_rate_commands.sender(),
+                )
+                .map_err(|e| log::warn!("Will not publish heart rate on D-Bus: {e:?}"))
+                .ok()
+            })
+            .flatten();
+
+        let heart_rate_control = session
+            .config
+            .hr_control_socket
+            .as_deref()
+            .filter(|path| !path.trim().is_empty())
+            .and_then(|path| {
+                ControlSocket::new(PathBuf::from(path.trim()), heart_rate_commands.sender())
+                    .map_err(|e| log::warn!("Will not open the heart rate control socket: {e:?}"))
+                    .ok()
+            });
+
+        let heart_rate_recorder = session
+            .config
+            .hr_log_path
+            .as_deref()
+            .filter(|path| !path.trim().is_empty())
+            .map(|path| {
+                HeartRateRecorder::new(
+                    PathBuf::from(path.trim()),
+                    session
+                        .config
+                        .hr_log_max_size_mb
+                        .saturating_mul(1024 * 1024),
+                )
+            });
+
+        let heart_rate_status_file = session
+            .config
+            .hr_status_file_path
+            .as_deref()
+            .filter(|path| !path.trim().is_empty())
+            .map(|path| StatusFile::new(PathBuf::from(path.trim())));
+
         Ok(Self {
             session,
             tasks,
@@ -155,7 +365,29 @@ impl AppState {
             anchor: Affine3A::IDENTITY,
             anchor_grabbed: false,
             watch_visible: false,
-            fitbit_state: RefCell::new(FitbitState::default()),
+            headset_active: true,
+            heart_rate,
+            heart_rate_stats: HeartRateStats::default(),
+            heart_rate_daily_max: session
+                .config
+                .hr_daily_max
+                .then(|| DailyMax::new(&session.config)),
+            heart_rate_history: ReadingHistory::default(),
+            heart_rate_readings,
+            heart_rate_service,
+            heart_rate_recorder,
+            heart_rate_status_file,
+            heart_rate_control,
+            heart_rate_commands,
+            heart_rate_notifier: HrNotifier::default(),
+            heart_rate_battery_warned: false,
+            heart_rate_auth_warned: false,
+            heart_rate_scope_warned: false,
+            heart_rate_disagree_warned: false,
+            heart_rate_signal: SignalLostWatch::default(),
+            heart_rate_logged: None,
+            heart_rate_milestones: MilestoneTracker::default(),
+            config_watcher: session.config.config_watch.then(ConfigWatcher::default),
             wgui_globals: WguiGlobals::new(
                 assets,
                 defaults,
@@ -171,6 +403,22 @@ impl AppState {
             #[cfg(feature = "osc")]
             osc_sender,
 
+            #[cfg(feature = "midi")]
+            midi_sender,
+            #[cfg(feature = "discord")]
+            discord_presence,
+            #[cfg(feature = "pulsoid")]
+            pulsoid_server,
+
+            #[cfg(feature = "hr-http")]
+            hr_http_server,
+
+            #[cfg(feature = "hr-ws")]
+            hr_ws_server,
+
+            #[cfg(feature = "prometheus")]
+            metrics_server,
+
             wvr_server,
 
             #[cfg(feature = "openxr")]
@@ -178,6 +426,477 @@ impl AppState {
         })
     }
 
+    /// Swaps in the config once its files change. The heart rate source keeps running, so the
+    /// current reading and the stats carry over, and settings read on every tick apply right
+    /// away. Fitbit credentials are picked up on the next poll.
+    pub fn config_reload_tick(&mut self) {
+        if !self
+            .config_watcher
+            .as_mut()
+            .is_some_and(ConfigWatcher::changed)
+        {
+            return;
+        }
+
+        let config = match try_load_general_config() {
+            Ok(config) => config,
+            Err(e) => {
+                log::warn!("Could not reload the config, keeping the last one: {e}");
+                return;
+            }
+        };
+        let old = std::mem::replace(&mut self.session.config, config);
+        let new = &self.session.config;
+        log::info!("Config reloaded.");
+
+        #[cfg(feature = "osc")]
+        if old.osc_out_port != new.osc_out_port || old.osc_out_endpoints != new.osc_out_endpoints {
+            self.osc_sender =
+                crate::subsystem::osc::OscSender::new(new.osc_out_port, &new.osc_out_endpoints)
+                    .inspect_err(|e| log::warn!("Could not set up OSC again: {e:?}"))
+                    .ok();
+        }
+
+        let source_changes = changed_keys(&old, new, heart_rate::SOURCE_CONFIG_KEYS);
+        if !source_changes.is_empty() {
+            log::info!(
+                "Setting up the heart rate source again for {}.",
+                source_changes.join(", ")
+            );
+            self.heart_rate.rebuild_source(new);
+        }
+
+        let restart_needed = changed_keys(&old, new, RESTART_CONFIG_KEYS);
+        if !restart_needed.is_empty() {
+            log::warn!(
+                "Changes to {} only apply after a restart.",
+                restart_needed.join(", ")
+            );
+        }
+    }
+
+    /// Commands of the D-Bus service, the control socket, input bindings and panel buttons,
+    /// so all of them behave the same
+    pub fn apply_heart_rate_command(&mut self, command: HrCommand) {
+        match command {
+            HrCommand::ForcePoll if self.heart_rate.poll_in_flight() => {
+                log::debug!("Heart rate poll already in flight.");
+            }
+            HrCommand::ForcePoll => self.heart_rate.force_poll(),
+            HrCommand::Pause | HrCommand::Resume | HrCommand::TogglePolling => {
+                let enabled = match command {
+                    HrCommand::TogglePolling => !self.heart_rate.polling_enabled(),
+                    _ => command == HrCommand::Resume,
+                };
+                log::info!(
+                    "Heart rate polling {}.",
+                    if enabled { "resumed" } else { "paused" }
+                );
+                self.heart_rate.set_polling_enabled(enabled);
+            }
+            HrCommand::ResetStats => {
+                log::info!("Resetting heart rate stats.");
+                self.heart_rate_stats.reset();
+            }
+            HrCommand::CycleSource => self.heart_rate.cycle_source(),
+            HrCommand::ClearSourceOverride => self.heart_rate.clear_source_override(),
+        }
+    }
+
+    /// The source is only updated here, outputs get read-only access to it or subscribe to
+    /// `heart_rate_readings`. So enabling more of them never causes more polling.
+    pub fn heart_rate_tick(&mut self) {
+        // polls need both the watch in view and the headset in use
+        let headset_idle = self.session.config.hr_pause_while_headset_idle && !self.headset_active;
+        self.heart_rate.set_headset_idle(headset_idle);
+        self.heart_rate
+            .update(&self.session.config, self.watch_visible && !headset_idle);
+        self.heart_rate_readings.tick(&self.heart_rate);
+
+        let backfill = self.heart_rate.take_backfill();
+        if !backfill.is_empty() {
+            self.heart_rate_history
+                .backfill(&backfill, &self.session.config);
+            self.heart_rate_stats.backfill(&backfill);
+            if let Some(daily_max) = self.heart_rate_daily_max.as_mut() {
+                daily_max.backfill(&backfill);
+            }
+        }
+
+        self.heart_rate_stats
+            .observe(&self.heart_rate, &self.session.config);
+        if let Some(daily_max) = self.heart_rate_daily_max.as_mut() {
+            daily_max.observe(&self.heart_rate);
+        }
+        let milestones = self.heart_rate_milestones.update(
+            self.heart_rate.smoothed_rate(),
+            self.heart_rate.zone(),
+            self.heart_rate.resting(),
+            self.heart_rate_stats.max(),
+            &self.session.config,
+        );
+        for milestone in milestones {
+            self.heart_rate_milestone(milestone);
+        }
+        self.heart_rate_history
+            .observe(&self.heart_rate, &self.session.config);
+
+        if self.session.config.hr_log_readings {
+            self.heart_rate_log_tick();
+        }
+
+        for command in self.heart_rate_commands.take_commands() {
+            self.apply_heart_rate_command(command);
+        }
+        for command in self.input_state.heart_rate_commands() {
+            self.apply_heart_rate_command(command);
+        }
+        self.heart_rate_commands.tick(&self.heart_rate);
+
+        if self
+            .heart_rate_service
+            .as_ref()
+            .is_some_and(HeartRateService::take_test_notification)
+        {
+            match self.session.config.hr_threshold_bpm {
+                Some(threshold) => {
+                    log::info!("Sending a test notification for {threshold} BPM.");
+                    self.heart_rate_threshold_alert(threshold, true);
+                }
+                None => {
+                    log::warn!("hr_threshold_bpm is not set, there is no notification to test.")
+                }
+            }
+        }
+
+        if let Some(service) = self.heart_rate_service.as_mut() {
+            match service.take_session_request() {
+                Some(SessionRequest::Start) => {
+                    log::info!("Starting a heart rate session.");
+                    self.heart_rate_stats.start_session();
+                }
+                Some(SessionRequest::Stop) => {
+                    if self.heart_rate_stats.stop_session() {
+                        log::info!("Heart rate session ended.");
+                        service.send_session_ended(&self.heart_rate_stats);
+                        heart_rate::export::export_session(
+                            &self.session.config,
+                            &self.heart_rate_stats,
+                        );
+                        heart_rate::summary::notify_session_ended(
+                            &mut self.heart_rate_notifier,
+                            &self.session.config,
+                            &self.heart_rate_stats,
+                            &self.heart_rate,
+                        );
+                    }
+                }
+                None => {}
+            }
+            service.tick(
+                &self.heart_rate,
+                &self.heart_rate_stats,
+                self.heart_rate_daily_max.as_ref().and_then(DailyMax::max),
+                &self.heart_rate_history,
+                &self.session.config,
+            );
+        }
+
+        if let Some(control) = self.heart_rate_control.as_mut() {
+            control.tick(&self.heart_rate);
+        }
+
+        if let Some(recorder) = self.heart_rate_recorder.as_mut() {
+            recorder.tick(&self.heart_rate);
+        }
+
+        if let Some(status_file) = self.heart_rate_status_file.as_mut() {
+            status_file.tick(&self.heart_rate, &self.session.config);
+        }
+
+        if let Some(threshold) = self
+            .input_state
+            .heart_rate_tick(self.heart_rate.smoothed_rate(), &self.session.config)
+        {
+            self.heart_rate_threshold_alert(threshold, false);
+        }
+
+        let threshold = self.session.config.hr_low_battery_percent;
+        match self.heart_rate.battery_percent() {
+            Some(percent) if threshold > 0 && percent <= threshold => {
+                if !self.heart_rate_battery_warned {
+                    self.heart_rate_battery_warned = true;
+                    log::warn!("Heart rate strap battery is at {percent}%.");
+                    self.heart_rate_alert(
+                        HrAlert::BatteryLow,
+                        "TOAST.HR_BATTERY_LOW",
+                        "TOAST.HR_BATTERY_CHARGE",
+                    );
+                }
+            }
+            // warn again once the strap was charged or swapped
+            _ => self.heart_rate_battery_warned = false,
+        }
+
+        if self.heart_rate.auth_revoked() && !self.heart_rate_auth_warned {
+            self.heart_rate_auth_warned = true;
+            self.heart_rate_alert(
+                HrAlert::AuthRevoked,
+                "TOAST.HR_AUTH_REVOKED",
+                "TOAST.HR_LOGIN_AGAIN",
+            );
+        }
+
+        if !self.heart_rate.scope_missing() {
+            // warn again if new credentials lack the scope too
+            self.heart_rate_scope_warned = false;
+        } else if !self.heart_rate_scope_warned {
+            self.heart_rate_scope_warned = true;
+            self.heart_rate_alert(
+                HrAlert::ScopeMissing,
+                "TOAST.HR_SCOPE_MISSING",
+                "TOAST.HR_AUTHORIZE_HEARTRATE",
+            );
+        }
+
+        if !self.heart_rate.sources_disagree() {
+            // warn again once they agreed in between
+            self.heart_rate_disagree_warned = false;
+        } else if !self.heart_rate_disagree_warned {
+            self.heart_rate_disagree_warned = true;
+            if self.session.config.hr_composite_disagree_notify {
+                self.heart_rate_alert(
+                    HrAlert::SourcesDisagree,
+                    "TOAST.HR_SOURCES_DISAGREE",
+                    "TOAST.HR_CHECK_STRAP_FIT",
+                );
+            }
+        }
+
+        self.heart_rate_lost_tick();
+
+        if self.heart_rate.take_calibrated_resting().is_some() {
+            self.heart_rate_alert(
+                HrAlert::RestingCalibrated,
+                "TOAST.HR_RESTING_CALIBRATED",
+                "TOAST.HR_RESTING_SEE_LOG",
+            );
+        }
+
+        #[cfg(feature = "hr-http")]
+        if let Some(server) = self.hr_http_server.as_mut() {
+            server.tick(&self.heart_rate, &self.heart_rate_history);
+        }
+
+        #[cfg(feature = "hr-ws")]
+        if let Some(server) = self.hr_ws_server.as_mut() {
+            server.tick(&self.heart_rate);
+        }
+
+        #[cfg(feature = "prometheus")]
+        if let Some(server) = self.metrics_server.as_mut() {
+            server.tick(&self.heart_rate);
+        }
+
+        #[cfg(feature = "osc")]
+        if let Some(sender) = self.osc_sender.as_mut() {
+            let _ = sender
+                .send_heart_rate(&self.heart_rate, &self.session.config)
+                .inspect_err(|e| log::debug!("Could not send heart rate over OSC: {e:?}"));
+            let _ = sender
+                .send_heart_rate_percent(&self.heart_rate, &self.session.config)
+                .inspect_err(|e| log::debug!("Could not send heart rate over OSC: {e:?}"));
+            let _ = sender
+                .send_zone_bools(&self.heart_rate, &self.session.config)
+                .inspect_err(|e| log::debug!("Could not send heart rate zone over OSC: {e:?}"));
+            if self.session.config.osc_haptic_enabled {
+                let _ = sender
+                    .send_haptic_intensity(&self.heart_rate, &self.session.config)
+                    .inspect_err(|e| {
+                        log::debug!("Could not send haptic intensity over OSC: {e:?}")
+                    });
+            }
+            if self.session.config.osc_heartbeat_enabled {
+                let _ = sender
+                    .send_heartbeat(&self.heart_rate, &self.session.config.osc_heartbeat_address)
+                    .inspect_err(|e| log::debug!("Could not send heartbeat over OSC: {e:?}"));
+            }
+        }
+
+        #[cfg(feature = "midi")]
+        if let Some(sender) = self.midi_sender.as_mut() {
+            let _ = sender
+                .send_heart_rate(&self.heart_rate, &self.session.config)
+                .inspect_err(|e| log::debug!("Could not send heart rate over MIDI: {e:?}"));
+        }
+
+        #[cfg(feature = "discord")]
+        if let Some(presence) = self.discord_presence.as_mut() {
+            presence.tick(&self.heart_rate, &self.session.config);
+        }
+
+        self.heart_rate_health_tick();
+    }
+
+    /// Shows an alert about the heart rate source through `hr_notify_backend`, like all other
+    /// heart rate notifications. The texts are the translations of the keys.
+    fn heart_rate_alert(&mut self, alert: HrAlert, summary_key: &str, body_key: &str) {
+        let (summary, body) = {
+            let mut i18n = self.wgui_globals.i18n();
+            (
+                i18n.translate(summary_key).to_string(),
+                i18n.translate(body_key).to_string(),
+            )
+        };
+        self.heart_rate_notifier.send(
+            alert,
+            HrNotification {
+                summary,
+                body,
+                bpm: self.heart_rate.smoothed_rate(),
+                zone: self.heart_rate.zone(),
+                test: false,
+            },
+            &self.session.config,
+        );
+    }
+
+    /// Notifies about the heart rate crossing `hr_threshold_bpm`. A `test` one goes the same way,
+    /// through `hr_notify_backend` and the cooldown, marked as a test.
+    fn heart_rate_threshold_alert(&mut self, threshold: u32, test: bool) {
+        self.heart_rate_notifier.send(
+            HrAlert::Threshold,
+            HrNotification::threshold(
+                threshold,
+                self.heart_rate.smoothed_rate(),
+                self.heart_rate.zone(),
+                test,
+            ),
+            &self.session.config,
+        );
+    }
+
+    /// Logs `milestone`, emits it on D-Bus and shows it with `hr_milestone_notify`
+    fn heart_rate_milestone(&mut self, milestone: Milestone) {
+        let bpm = match milestone {
+            Milestone::CooldownComplete => self.heart_rate.smoothed_rate(),
+            Milestone::NewPeak => self.heart_rate_stats.max(),
+        };
+        let body = match milestone {
+            Milestone::CooldownComplete => format!(
+                "Back to {} BPM, near your resting heart rate.",
+                bpm.unwrap_or_default()
+            ),
+            Milestone::NewPeak => {
+                format!("New peak of the session: {} BPM.", bpm.unwrap_or_default())
+            }
+        };
+        log::info!("Heart rate milestone: {body}");
+
+        if let Some(service) = self.heart_rate_service.as_ref() {
+            service.send_milestone(milestone, bpm);
+        }
+        if self.session.config.hr_milestone_notify {
+            self.heart_rate_notifier.send(
+                HrAlert::Milestone(milestone),
+                HrNotification {
+                    summary: match milestone {
+                        Milestone::CooldownComplete => "Cooldown complete".to_string(),
+                        Milestone::NewPeak => "New heart rate peak".to_string(),
+                    },
+                    body,
+                    bpm,
+                    zone: self.heart_rate.zone(),
+                    test: false,
+                },
+                &self.session.config,
+            );
+        }
+    }
+
+    /// Gathers what `GetHealth` answers with, after all outputs had their turn
+    fn heart_rate_health_tick(&mut self) {
+        let Some(service) = self.heart_rate_service.as_ref() else {
+            return;
+        };
+
+        let mut health = Health::new(&self.heart_rate);
+        health.today_max = self.heart_rate_daily_max.as_ref().and_then(DailyMax::max);
+        health.output("dbus", service.last_sent_at());
+        #[cfg(feature = "osc")]
+        if let Some(sender) = self.osc_sender.as_ref() {
+            health.output("osc", sender.last_sent_heart_rate_at());
+        }
+        if let Some(status_file) = self.heart_rate_status_file.as_ref() {
+            health.output("status_file", status_file.written_at());
+        }
+        if let Some(recorder) = self.heart_rate_recorder.as_ref() {
+            health.output("log", recorder.last_row_at());
+        }
+
+        if let Some(service) = self.heart_rate_service.as_mut() {
+            service.set_health(health);
+        }
+    }
+
+    /// The rate only changes with a fresh reading, while the staleness changes on its own
+    fn heart_rate_log_tick(&mut self) {
+        let Some(rate) = self.heart_rate.last_rate() else {
+            self.heart_rate_logged = None;
+            return;
+        };
+
+        let stale = self.heart_rate.is_stale();
+        let unchanged = self
+            .heart_rate_logged
+            .is_some_and(|(logged_rate, logged_stale)| {
+                logged_stale == stale && (logged_rate == rate || !self.heart_rate.is_fresh())
+            });
+        if unchanged {
+            return;
+        }
+
+        self.heart_rate_logged = Some((rate, stale));
+        let name = self.heart_rate.name();
+        if stale {
+            log::info!("HR {rate} bpm ({name}, stale)");
+        } else {
+            log::info!("HR {rate} bpm ({name})");
+        }
+    }
+
+    /// Warns once the heart rate was gone for `hr_signal_lost_minutes` after having been live,
+    /// e.g. because the strap slipped off mid-workout. A hidden watch or paused polling doesn't
+    /// count as a loss.
+    fn heart_rate_lost_tick(&mut self) {
+        let lost_after =
+            Duration::from_secs(u64::from(self.session.config.hr_signal_lost_minutes) * 60);
+        if lost_after.is_zero() {
+            return;
+        }
+
+        if matches!(
+            self.heart_rate.idle_reason(),
+            Some(IdleReason::WatchHidden | IdleReason::HeadsetIdle | IdleReason::Paused)
+        ) {
+            self.heart_rate_signal.forget();
+            return;
+        }
+
+        let live = matches!(self.heart_rate.status(), HrStatus::Live(_));
+        if self
+            .heart_rate_signal
+            .tick(live, lost_after, Instant::now())
+        {
+            log::warn!("No heart rate for {} minutes.", lost_after.as_secs() / 60);
+            self.heart_rate_alert(
+                HrAlert::SignalLost,
+                "TOAST.HR_SIGNAL_LOST",
+                "TOAST.HR_CHECK_STRAP",
+            );
+        }
+    }
+
     #[cfg(feature = "openxr")]
     pub fn monado_init(&mut self) {
         log::debug!("Connecting to Monado IPC");
@@ -223,3 +942,17 @@ pub struct ScreenMeta {
     pub name: Arc<str>,
     pub native_handle: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restart_config_keys_are_config_keys() {
+        let config =
+            serde_json::to_value(serde_json::from_str::<GeneralConfig>("{}").unwrap()).unwrap();
+        for key in RESTART_CONFIG_KEYS {
+            assert!(config.get(key).is_some(), "{key} is not in the config");
+        }
+    }
+}
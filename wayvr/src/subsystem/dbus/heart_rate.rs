@@ -0,0 +1,543 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use dbus::{
+    Message,
+    arg::{self, RefArg},
+    blocking::Connection,
+    channel::{MatchingReceiver, Sender},
+    message::MatchRule,
+};
+use serde::Serialize;
+
+use wlx_common::config::GeneralConfig;
+
+use crate::subsystem::heart_rate::{
+    HeartRateSource,
+    change::ChangeThreshold,
+    command::{CommandReply, CommandSender, HrCommand},
+    health::Health,
+    history::ReadingHistory,
+    milestone::Milestone,
+    stats::HeartRateStats,
+    zone,
+};
+
+const BUS_NAME: &str = "io.github.wayvr.HeartRate";
+const OBJECT_PATH: &str = "/io/github/wayvr/HeartRate";
+const INTERFACE: &str = "io.github.wayvr.HeartRate";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+/// How long `--status` waits for the running instance to answer
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Heart rate as consumed by bridges, e.g. into MQTT
+#[derive(Serialize, Clone, PartialEq)]
+struct HeartRatePayload {
+    bpm: Option<u32>,
+    /// The reading before `hr_bias` or `fitbit_interpolation`, only while it differs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw_bpm: Option<u32>,
+    /// Unix time of the last successful reading
+    ts: Option<u64>,
+    source: &'static str,
+    /// Set while `CycleSource` pinned `source` instead of it being picked by priority
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pinned_source: Option<&'static str>,
+    /// The strap the readings come from, for sources that can use several
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+    stale: bool,
+    /// initializing, live, stale or disconnected
+    status: &'static str,
+    /// watch_hidden, headset_idle, paused or disconnected while the source isn't polling
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+    zone: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quota_remaining: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    battery_percent: Option<u8>,
+}
+
+#[derive(Serialize, Clone, PartialEq)]
+struct HeartRateStatsPayload {
+    min: Option<u32>,
+    max: Option<u32>,
+    avg: Option<f32>,
+    count: u64,
+    /// With `hr_daily_max`, kept across sessions until midnight
+    #[serde(skip_serializing_if = "Option::is_none")]
+    today_max: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    calories: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    coherence: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    segments: Vec<SegmentPayload>,
+}
+
+#[derive(Serialize, Clone, PartialEq)]
+struct SegmentPayload {
+    /// Unix time of the first reading of the segment
+    start: u64,
+    above: bool,
+    min: u32,
+    max: u32,
+    avg: f32,
+}
+
+#[derive(Serialize)]
+struct MilestonePayload {
+    /// cooldown_complete or new_peak
+    event: &'static str,
+    bpm: Option<u32>,
+}
+
+/// Summary of the current or last session, as `SessionEnded` payload and properties
+#[derive(Serialize, Clone, Default)]
+struct SessionSummary {
+    min: Option<u32>,
+    max: Option<u32>,
+    avg: Option<f32>,
+    duration_secs: u64,
+}
+
+impl SessionSummary {
+    fn new(stats: &HeartRateStats) -> Self {
+        Self {
+            min: stats.min(),
+            max: stats.max(),
+            avg: stats.avg(),
+            duration_secs: stats.session_duration().unwrap_or_default().as_secs(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Properties {
+    session: SessionSummary,
+    /// Color of the current zone as #rrggbb, empty without a reading
+    zone_color: String,
+}
+
+impl Properties {
+    const NAMES: [&str; 5] = [
+        "SessionMin",
+        "SessionMax",
+        "SessionAvg",
+        "SessionDuration",
+        "ZoneColor",
+    ];
+
+    /// Properties can't be missing, so there is no reading yet while they are 0
+    fn property(&self, name: &str) -> Option<arg::Variant<Box<dyn RefArg>>> {
+        let session = &self.session;
+        let value: Box<dyn RefArg> = match name {
+            "SessionMin" => Box::new(session.min.unwrap_or_default()),
+            "SessionMax" => Box::new(session.max.unwrap_or_default()),
+            "SessionAvg" => Box::new(f64::from(session.avg.unwrap_or_default())),
+            "SessionDuration" => Box::new(session.duration_secs),
+            "ZoneColor" => Box::new(self.zone_color.clone()),
+            _ => return None,
+        };
+        Some(arg::Variant(value))
+    }
+
+    fn properties(&self) -> arg::PropMap {
+        Self::NAMES
+            .into_iter()
+            .filter_map(|name| Some((name.to_string(), self.property(name)?)))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum SessionRequest {
+    Start,
+    Stop,
+}
+
+/// Publishes the current heart rate on the session bus.
+///
+/// `GetHeartRate` returns the current reading as JSON,
+/// `HeartRateChanged` is emitted with the same payload whenever it changes.
+/// `GetHeartRateStats` returns the session summary, `ResetStats` starts a new session.
+/// `GetHeartRateHistory` returns the recent readings as a JSON array, oldest first.
+/// `ForcePoll` polls right away and returns false if a request is already in flight.
+/// `PausePolling` and `ResumePolling` stop and restart polling, like the control socket.
+/// `StartSession` resets the stats and `StopSession` freezes them, which emits `SessionEnded`
+/// with the summary as JSON. The `SessionMin`, `SessionMax`, `SessionAvg` and `SessionDuration`
+/// properties show the summary of the current or last session, in BPM and seconds.
+/// `ZoneColor` is the `hr_zone_colors` entry of the current zone, for overlays tinting by it.
+/// `CycleSource` pins the next source of `hr_source: composite`, `ClearSourceOverride` goes
+/// back to picking it by priority.
+/// `TestNotification` fires the `hr_threshold_bpm` notification as a test, cooldown included.
+/// `GetHealth` returns the state of the source and the outputs as JSON, see `Health`.
+/// `Alive` is emitted with the current unix time every `alive_interval`, independent of changes.
+pub struct HeartRateService {
+    connection: Connection,
+    payload: Arc<Mutex<String>>,
+    stats_payload: Arc<Mutex<String>>,
+    history_payload: Arc<Mutex<String>>,
+    health_payload: Arc<Mutex<String>>,
+    test_notification_requested: Arc<AtomicBool>,
+    /// The last of `StartSession` and `StopSession` since the last check
+    session_request: Arc<Mutex<Option<SessionRequest>>>,
+    properties: Arc<Mutex<Properties>>,
+    last_payload: Option<HeartRatePayload>,
+    bpm_threshold: ChangeThreshold,
+    last_stats_payload: Option<HeartRateStatsPayload>,
+    history_generation: Option<u64>,
+    last_health: Option<Health>,
+    /// When `HeartRateChanged` was last emitted
+    last_sent_at: Option<SystemTime>,
+    alive_interval: Option<Duration>,
+    next_alive_at: Instant,
+}
+
+impl HeartRateService {
+    /// `ResetStats`, `ForcePoll`, `PausePolling`, `ResumePolling`, `CycleSource` and
+    /// `ClearSourceOverride` are sent to `commands`
+    pub fn new(alive_interval: Option<Duration>, commands: CommandSender) -> anyhow::Result<Self> {
+        let connection = Connection::new_session()?;
+        connection.request_name(BUS_NAME, false, true, false)?;
+
+        let payload = Arc::new(Mutex::new(String::from("{}")));
+        let stats_payload = Arc::new(Mutex::new(String::from("{}")));
+        let history_payload = Arc::new(Mutex::new(String::from("[]")));
+        let health_payload = Arc::new(Mutex::new(String::from("{}")));
+        let test_notification_requested = Arc::new(AtomicBool::new(false));
+        let session_request = Arc::new(Mutex::new(None));
+        let properties = Arc::new(Mutex::new(Properties::default()));
+
+        let reply_payload = payload.clone();
+        let reply_stats_payload = stats_payload.clone();
+        let reply_history_payload = history_payload.clone();
+        let reply_health_payload = health_payload.clone();
+        let reply_test_notification_requested = test_notification_requested.clone();
+        let reply_session_request = session_request.clone();
+        let reply_properties = properties.clone();
+        let _ = connection.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |message: Message, connection: &Connection| {
+                if message.path().as_deref() != Some(OBJECT_PATH) {
+                    return true;
+                }
+
+                let reply = match (message.interface().as_deref(), message.member().as_deref()) {
+                    (Some(INTERFACE), Some("GetHeartRate")) => {
+                        let json = reply_payload.lock().unwrap().clone();
+                        message.method_return().append1(json)
+                    }
+                    (Some(INTERFACE), Some("GetHeartRateStats")) => {
+                        let json = reply_stats_payload.lock().unwrap().clone();
+                        message.method_return().append1(json)
+                    }
+                    (Some(INTERFACE), Some("GetHeartRateHistory")) => {
+                        let json = reply_history_payload.lock().unwrap().clone();
+                        message.method_return().append1(json)
+                    }
+                    (Some(INTERFACE), Some("GetHealth")) => {
+                        let json = reply_health_payload.lock().unwrap().clone();
+                        message.method_return().append1(json)
+                    }
+                    (Some(INTERFACE), Some("ResetStats")) => {
+                        commands.send(HrCommand::ResetStats);
+                        message.method_return()
+                    }
+                    (Some(INTERFACE), Some("ForcePoll")) => {
+                        let accepted =
+                            commands.send(HrCommand::ForcePoll) == CommandReply::Accepted;
+                        message.method_return().append1(accepted)
+                    }
+                    (Some(INTERFACE), Some(member @ ("PausePolling" | "ResumePolling"))) => {
+                        commands.send(if member == "PausePolling" {
+                            HrCommand::Pause
+                        } else {
+                            HrCommand::Resume
+                        });
+                        message.method_return()
+                    }
+                    (Some(INTERFACE), Some("TestNotification")) => {
+                        reply_test_notification_requested.store(true, Ordering::Relaxed);
+                        message.method_return()
+                    }
+                    (Some(INTERFACE), Some(member @ ("StartSession" | "StopSession"))) => {
+                        *reply_session_request.lock().unwrap() =
+                            Some(if member == "StartSession" {
+                                SessionRequest::Start
+                            } else {
+                                SessionRequest::Stop
+                            });
+                        message.method_return()
+                    }
+                    (Some(INTERFACE), Some(member @ ("CycleSource" | "ClearSourceOverride"))) => {
+                        commands.send(if member == "CycleSource" {
+                            HrCommand::CycleSource
+                        } else {
+                            HrCommand::ClearSourceOverride
+                        });
+                        message.method_return()
+                    }
+                    (Some(PROPERTIES_INTERFACE), Some("Get")) => {
+                        let property = match message.read2::<&str, &str>() {
+                            Ok((INTERFACE, name)) => {
+                                reply_properties.lock().unwrap().property(name)
+                            }
+                            _ => None,
+                        };
+                        match property {
+                            Some(value) => message.method_return().append1(value),
+                            None => message.error(
+                                &"org.freedesktop.DBus.Error.UnknownProperty".into(),
+                                c"Unknown property",
+                            ),
+                        }
+                    }
+                    (Some(PROPERTIES_INTERFACE), Some("GetAll")) => {
+                        let properties = match message.read1::<&str>() {
+                            Ok(INTERFACE) => reply_properties.lock().unwrap().properties(),
+                            _ => arg::PropMap::new(),
+                        };
+                        message.method_return().append1(properties)
+                    }
+                    _ => message.error(
+                        &"org.freedesktop.DBus.Error.UnknownMethod".into(),
+                        c"Unknown method",
+                    ),
+                };
+                let _ = connection.send(reply);
+                true
+            }),
+        );
+
+        log::info!("Publishing heart rate on D-Bus as {BUS_NAME}");
+        Ok(Self {
+            connection,
+            payload,
+            stats_payload,
+            history_payload,
+            health_payload,
+            test_notification_requested,
+            session_request,
+            properties,
+            last_payload: None,
+            bpm_threshold: ChangeThreshold::default(),
+            last_stats_payload: None,
+            history_generation: None,
+            last_health: None,
+            last_sent_at: None,
+            alive_interval,
+            next_alive_at: Instant::now(),
+        })
+    }
+
+    /// Whether `TestNotification` was called since the last check
+    pub fn take_test_notification(&self) -> bool {
+        self.test_notification_requested
+            .swap(false, Ordering::Relaxed)
+    }
+
+    /// Whether `StartSession` or `StopSession` was called since the last check, the later one
+    pub fn take_session_request(&self) -> Option<SessionRequest> {
+        self.session_request.lock().unwrap().take()
+    }
+
+    /// Emits `Milestone` with {"event", "bpm"}
+    pub fn send_milestone(&self, milestone: Milestone, bpm: Option<u32>) {
+        let payload = MilestonePayload {
+            event: milestone.into(),
+            bpm,
+        };
+        match serde_json::to_string(&payload) {
+            Ok(json) => {
+                if let Ok(signal) = Message::new_signal(OBJECT_PATH, INTERFACE, "Milestone") {
+                    let _ = self.connection.send(signal.append1(json));
+                }
+            }
+            Err(e) => log::error!("Could not serialize heart rate milestone: {e:?}"),
+        }
+    }
+
+    /// Emits `SessionEnded`, once `stats` were frozen by `StopSession`
+    pub fn send_session_ended(&self, stats: &HeartRateStats) {
+        let summary = SessionSummary::new(stats);
+        match serde_json::to_string(&summary) {
+            Ok(json) => {
+                if let Ok(signal) = Message::new_signal(OBJECT_PATH, INTERFACE, "SessionEnded") {
+                    let _ = self.connection.send(signal.append1(json));
+                }
+            }
+            Err(e) => log::error!("Could not serialize heart rate session: {e:?}"),
+        }
+        self.properties.lock().unwrap().session = summary;
+    }
+
+    /// `HeartRateChanged` is only emitted for BPM changes of at least `hr_change_threshold`
+    pub fn tick(
+        &mut self,
+        source: &dyn HeartRateSource,
+        stats: &HeartRateStats,
+        today_max: Option<u32>,
+        history: &ReadingHistory,
+        config: &GeneralConfig,
+    ) {
+        let payload = HeartRatePayload {
+            bpm: self.bpm_threshold.apply(source.last_rate(), config),
+            raw_bpm: source
+                .raw_rate()
+                .filter(|raw| Some(*raw) != source.last_rate()),
+            ts: source
+                .last_reading_time()
+                .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|since_epoch| since_epoch.as_secs()),
+            source: source.name(),
+            pinned_source: source.source_override(),
+            device: source.device().map(str::to_string),
+            stale: source.is_stale(),
+            status: source.status().into(),
+            reason: source.idle_reason().map(Into::into),
+            zone: source.zone().map(Into::into),
+            quota_remaining: source.quota_remaining(),
+            battery_percent: source.battery_percent(),
+        };
+
+        if self.last_payload.as_ref() != Some(&payload) {
+            match serde_json::to_string(&payload) {
+                Ok(json) => {
+                    if let Ok(signal) =
+                        Message::new_signal(OBJECT_PATH, INTERFACE, "HeartRateChanged")
+                    {
+                        let _ = self.connection.send(signal.append1(json.clone()));
+                        self.last_sent_at = Some(SystemTime::now());
+                    }
+                    *self.payload.lock().unwrap() = json;
+                }
+                Err(e) => log::error!("Could not serialize heart rate: {e:?}"),
+            }
+            self.last_payload = Some(payload);
+        }
+
+        let stats_payload = HeartRateStatsPayload {
+            min: stats.min(),
+            max: stats.max(),
+            avg: stats.avg(),
+            count: stats.count(),
+            today_max,
+            calories: stats.calories_session(),
+            coherence: stats.coherence(),
+            segments: stats
+                .segments()
+                .iter()
+                .map(|segment| SegmentPayload {
+                    start: segment
+                        .started_at
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs(),
+                    above: segment.above,
+                    min: segment.min,
+                    max: segment.max,
+                    avg: segment.avg(),
+                })
+                .collect(),
+        };
+
+        if self.last_stats_payload.as_ref() != Some(&stats_payload) {
+            match serde_json::to_string(&stats_payload) {
+                Ok(json) => *self.stats_payload.lock().unwrap() = json,
+                Err(e) => log::error!("Could not serialize heart rate stats: {e:?}"),
+            }
+            self.last_stats_payload = Some(stats_payload);
+        }
+
+        if self.history_generation != Some(history.generation()) {
+            self.history_generation = Some(history.generation());
+            match serde_json::to_string(&history.entries().collect::<Vec<_>>()) {
+                Ok(json) => *self.history_payload.lock().unwrap() = json,
+                Err(e) => log::error!("Could not serialize heart rate history: {e:?}"),
+            }
+        }
+
+        {
+            let mut properties = self.properties.lock().unwrap();
+            properties.session = SessionSummary::new(stats);
+            // the same zone as in the payload, so it follows the hysteresis too
+            properties.zone_color = source
+                .zone()
+                .map(|zone| zone::color(zone, config).to_string())
+                .unwrap_or_default();
+        }
+
+        self.send_alive();
+
+        let _ = self.connection.process(Duration::ZERO);
+    }
+
+    pub const fn last_sent_at(&self) -> Option<SystemTime> {
+        self.last_sent_at
+    }
+
+    /// Answers the following `GetHealth` calls with `health`
+    pub fn set_health(&mut self, health: Health) {
+        if self.last_health.as_ref() == Some(&health) {
+            return;
+        }
+        match serde_json::to_string(&health) {
+            Ok(json) => *self.health_payload.lock().unwrap() = json,
+            Err(e) => log::error!("Could not serialize heart rate health: {e:?}"),
+        }
+        self.last_health = Some(health);
+    }
+
+    fn send_alive(&mut self) {
+        let Some(interval) = self.alive_interval else {
+            return;
+        };
+
+        let now = Instant::now();
+        if now < self.next_alive_at {
+            return;
+        }
+        self.next_alive_at = now + interval;
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs())
+            .unwrap_or_default();
+        if let Ok(signal) = Message::new_signal(OBJECT_PATH, INTERFACE, "Alive") {
+            let _ = self.connection.send(signal.append1(timestamp));
+        }
+    }
+}
+
+/// Asks the running instance for `GetHealth` and prints it, for `--status`.
+/// Returns whether it answered.
+pub fn print_health() -> bool {
+    let health = Connection::new_session()
+        .and_then(|connection| {
+            let (json,): (String,) = connection
+                .with_proxy(BUS_NAME, OBJECT_PATH, STATUS_TIMEOUT)
+                .method_call(INTERFACE, "GetHealth", ())?;
+            Ok(json)
+        })
+        .map_err(anyhow::Error::from)
+        .and_then(|json| Ok(serde_json::from_str::<Health>(&json)?));
+
+    match health {
+        Ok(health) => {
+            print!("{health}");
+            true
+        }
+        Err(e) => {
+            println!("Could not get the heart rate status from WayVR: {e}");
+            println!("Make sure WayVR is running with hr_dbus enabled.");
+            false
+        }
+    }
+}
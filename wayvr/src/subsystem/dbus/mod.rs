@@ -14,8 +14,11 @@ use crate::subsystem::dbus::{
 };
 
 mod fcitx5;
+mod heart_rate;
 mod notifications;
 
+pub use heart_rate::{HeartRateService, SessionRequest, print_health};
+
 pub type DbusReceiveCallback = Box<dyn FnMut(Message, &Connection) -> bool + Send>;
 pub type DbusMatchCallback = Box<dyn FnMut((), &Connection, &Message) -> bool + Send>;
 
@@ -0,0 +1,330 @@
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, bail};
+use serde_json::{Value, json};
+use wlx_common::config::GeneralConfig;
+
+use crate::subsystem::heart_rate::{HeartRateSource, change::ChangeThreshold, zone::HrZone};
+
+/// Discord accepts 5 activity updates per 20s, later ones are held back until this passed
+const MIN_UPDATE_INTERVAL: Duration = Duration::from_secs(4);
+/// How often to look for Discord again while it isn't running
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+const IO_TIMEOUT: Duration = Duration::from_secs(2);
+
+const OP_HANDSHAKE: u32 = 0;
+const OP_FRAME: u32 = 1;
+
+/// What the worker is told to show, None clears the presence
+type Activity = Option<(u32, Option<HrZone>)>;
+
+/// The `SET_ACTIVITY` command for `activity`, e.g. "72 BPM" with "Cardio zone" below it
+pub fn presence_payload(activity: Activity, pid: u32, nonce: u64) -> Value {
+    let activity = activity.map(|(bpm, zone)| {
+        let mut activity = json!({ "details": format!("♥ {bpm} BPM") });
+        if let Some(zone) = zone {
+            let name: &str = zone.into();
+            let mut name = name.replace('_', " ");
+            name[..1].make_ascii_uppercase();
+            activity["state"] = json!(format!("{name} zone"));
+        }
+        activity
+    });
+    json!({
+        "cmd": "SET_ACTIVITY",
+        "args": { "pid": pid, "activity": activity },
+        "nonce": nonce.to_string(),
+    })
+}
+
+/// Shows the heart rate in the Discord Rich Presence of the user, through the IPC socket of
+/// the local Discord client. The rate only changes once it moved by `hr_change_threshold`.
+/// While Discord isn't running nothing happens, it's looked for again every
+/// `RECONNECT_INTERVAL`. The presence is cleared once this is dropped.
+pub struct DiscordPresence {
+    sender: Option<Sender<Activity>>,
+    worker: Option<JoinHandle<()>>,
+    threshold: ChangeThreshold,
+    last_sent: Option<Activity>,
+}
+
+impl DiscordPresence {
+    /// `client_id` is the one of a Discord application, whose name shows as the activity
+    pub fn new(client_id: String) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let worker = std::thread::spawn(move || run_worker(&client_id, &receiver));
+        Self {
+            sender: Some(sender),
+            worker: Some(worker),
+            threshold: ChangeThreshold::default(),
+            last_sent: None,
+        }
+    }
+
+    pub fn tick(&mut self, source: &dyn HeartRateSource, config: &GeneralConfig) {
+        let activity = self
+            .threshold
+            .apply(source.smoothed_rate(), config)
+            .map(|bpm| (bpm, source.zone()));
+        if self.last_sent == Some(activity) {
+            return;
+        }
+        self.last_sent = Some(activity);
+        if let Some(sender) = self.sender.as_ref() {
+            let _ = sender.send(activity);
+        }
+    }
+}
+
+impl Drop for DiscordPresence {
+    fn drop(&mut self) {
+        // the worker clears the presence once the channel is closed
+        self.sender = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Looks for Discord no more than once every `RECONNECT_INTERVAL`
+struct Reconnect {
+    next_at: Instant,
+}
+
+impl Reconnect {
+    const fn new(now: Instant) -> Self {
+        Self { next_at: now }
+    }
+
+    /// Whether to try connecting now, which also schedules the attempt after it
+    fn attempt(&mut self, now: Instant) -> bool {
+        if now < self.next_at {
+            return false;
+        }
+        self.defer(now);
+        true
+    }
+
+    /// After losing the connection, so a Discord that's shutting down isn't hammered
+    fn defer(&mut self, now: Instant) {
+        self.next_at = now + RECONNECT_INTERVAL;
+    }
+}
+
+fn run_worker(client_id: &str, activities: &Receiver<Activity>) {
+    let mut connection: Option<DiscordIpc> = None;
+    let mut reconnect = Reconnect::new(Instant::now());
+    let mut sent_at: Option<Instant> = None;
+    // the latest activity that didn't go out yet
+    let mut pending: Option<Activity> = None;
+
+    loop {
+        match activities.recv_timeout(Duration::from_millis(500)) {
+            Ok(activity) => pending = Some(activity),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        let Some(activity) = pending else {
+            continue;
+        };
+        if sent_at.is_some_and(|at| at.elapsed() < MIN_UPDATE_INTERVAL) {
+            continue;
+        }
+        if connection.is_none() && reconnect.attempt(Instant::now()) {
+            connection = DiscordIpc::connect(client_id, &socket_paths())
+                .inspect_err(|e| log::debug!("Discord presence: {e:?}"))
+                .ok();
+        }
+        let Some(ipc) = connection.as_mut() else {
+            continue;
+        };
+
+        match ipc.set_activity(activity) {
+            Ok(()) => {
+                pending = None;
+                sent_at = Some(Instant::now());
+            }
+            Err(e) => {
+                log::debug!("Discord presence: {e:?}");
+                connection = None;
+                reconnect.defer(Instant::now());
+            }
+        }
+    }
+
+    if let Some(mut ipc) = connection {
+        let _ = ipc.set_activity(None);
+    }
+}
+
+/// Where the Discord client listens, including the Flatpak and Snap ones
+fn socket_paths() -> Vec<PathBuf> {
+    let dirs: Vec<PathBuf> = ["XDG_RUNTIME_DIR", "TMPDIR"]
+        .into_iter()
+        .filter_map(|var| std::env::var_os(var).map(PathBuf::from))
+        .chain([PathBuf::from("/tmp")])
+        .collect();
+
+    let mut paths = Vec::new();
+    for dir in &dirs {
+        for subdir in ["", "app/com.discordapp.Discord", "snap.discord"] {
+            for i in 0..10 {
+                paths.push(dir.join(subdir).join(format!("discord-ipc-{i}")));
+            }
+        }
+    }
+    paths
+}
+
+struct DiscordIpc {
+    stream: UnixStream,
+    nonce: u64,
+}
+
+impl DiscordIpc {
+    /// Through the first of `paths` that Discord listens on
+    fn connect(client_id: &str, paths: &[PathBuf]) -> anyhow::Result<Self> {
+        let stream = paths
+            .iter()
+            .find_map(|path| UnixStream::connect(path).ok())
+            .context("Discord is not running")?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        let mut ipc = Self { stream, nonce: 0 };
+        ipc.write_frame(OP_HANDSHAKE, &json!({ "v": 1, "client_id": client_id }))?;
+        let (op, ready) = ipc.read_frame()?;
+        if op != OP_FRAME || ready["evt"] != "READY" {
+            bail!("Discord refused the connection: {ready}");
+        }
+        log::info!("Showing heart rate in the Discord presence.");
+        Ok(ipc)
+    }
+
+    fn set_activity(&mut self, activity: Activity) -> anyhow::Result<()> {
+        self.nonce += 1;
+        let payload = presence_payload(activity, std::process::id(), self.nonce);
+        self.write_frame(OP_FRAME, &payload)?;
+        let (_, response) = self.read_frame()?;
+        if response["evt"] == "ERROR" {
+            bail!("Discord rejected the presence: {}", response["data"]);
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, op: u32, payload: &Value) -> anyhow::Result<()> {
+        let body = serde_json::to_vec(payload)?;
+        let mut frame = Vec::with_capacity(8 + body.len());
+        frame.extend_from_slice(&op.to_le_bytes());
+        frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&body);
+        self.stream.write_all(&frame)?;
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> anyhow::Result<(u32, Value)> {
+        let mut header = [0; 8];
+        self.stream.read_exact(&mut header)?;
+        let op = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let mut body = vec![0; len as usize];
+        self.stream.read_exact(&mut body)?;
+        Ok((op, serde_json::from_slice(&body)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    #[test]
+    fn payload_shows_the_rate_and_zone() {
+        let payload = presence_payload(Some((142, Some(HrZone::FatBurn))), 1234, 7);
+        assert_eq!(
+            payload,
+            json!({
+                "cmd": "SET_ACTIVITY",
+                "args": {
+                    "pid": 1234,
+                    "activity": { "details": "♥ 142 BPM", "state": "Fat burn zone" }
+                },
+                "nonce": "7",
+            })
+        );
+
+        let payload = presence_payload(Some((64, None)), 1234, 8);
+        assert_eq!(
+            payload["args"]["activity"],
+            json!({ "details": "♥ 64 BPM" })
+        );
+    }
+
+    #[test]
+    fn no_activity_clears_the_presence() {
+        let payload = presence_payload(None, 1234, 9);
+        assert_eq!(payload["args"]["activity"], Value::Null);
+    }
+
+    #[test]
+    fn absent_discord_is_looked_for_again_after_the_interval() {
+        let start = Instant::now();
+        let mut reconnect = Reconnect::new(start);
+
+        assert!(reconnect.attempt(start));
+        assert!(!reconnect.attempt(start + Duration::from_secs(10)));
+        assert!(!reconnect.attempt(start + RECONNECT_INTERVAL - Duration::from_millis(1)));
+        assert!(reconnect.attempt(start + RECONNECT_INTERVAL));
+
+        // a lost connection waits a whole interval as well
+        let lost_at = start + Duration::from_secs(45);
+        reconnect.defer(lost_at);
+        assert!(!reconnect.attempt(lost_at + Duration::from_secs(29)));
+        assert!(reconnect.attempt(lost_at + RECONNECT_INTERVAL));
+    }
+
+    #[test]
+    fn connects_once_discord_is_there() {
+        let dir = std::env::temp_dir().join(format!("wayvr-discord-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("discord-ipc-0");
+        let _ = std::fs::remove_file(&path);
+        let paths = [path.clone()];
+
+        let error = DiscordIpc::connect("client", &paths).err().unwrap();
+        assert_eq!(error.to_string(), "Discord is not running");
+
+        // answers the handshake and one activity, and hands back what it got
+        let listener = UnixListener::bind(&path).unwrap();
+        let discord = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut discord = DiscordIpc { stream, nonce: 0 };
+            let (_, handshake) = discord.read_frame().unwrap();
+            discord
+                .write_frame(OP_FRAME, &json!({ "evt": "READY" }))
+                .unwrap();
+            let (_, activity) = discord.read_frame().unwrap();
+            discord
+                .write_frame(OP_FRAME, &json!({ "evt": null }))
+                .unwrap();
+            (handshake, activity)
+        });
+
+        let mut ipc = DiscordIpc::connect("client", &paths).unwrap();
+        ipc.set_activity(Some((72, None))).unwrap();
+        let (handshake, activity) = discord.join().unwrap();
+        assert_eq!(handshake, json!({ "v": 1, "client_id": "client" }));
+        assert_eq!(activity["args"]["activity"]["details"], "♥ 72 BPM");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    process::Command,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, bail};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use wlx_common::config::GeneralConfig;
+
+use super::{
+    Credentials, FitbitTokenResponse, HttpOptions, HttpResponse, TOKEN_PATH, TokenUpdate,
+    curl_with_status,
+};
+
+const AUTHORIZE_URL: &str = "https://www.fitbit.com/oauth2/authorize";
+const SCOPES: &str = "heartrate profile";
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Runs the OAuth2 Authorization Code flow with PKCE against Fitbit and saves
+/// the resulting tokens, so they don't have to be copied into the config by hand.
+pub fn authorize(config: &GeneralConfig) -> anyhow::Result<()> {
+    let Credentials {
+        profile,
+        client_id,
+        client_secret,
+        ..
+    } = Credentials::from_config(config);
+    let client_id = client_id.context("fitbit_client_id must be set in order to log in")?;
+    if let Some(name) = profile.as_deref() {
+        println!("Logging in with the Fitbit profile {name}.");
+    }
+
+    let port = config.fitbit_oauth_port;
+    let redirect_uri = format!("http://localhost:{port}/callback");
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .with_context(|| format!("Could not listen for the Fitbit redirect on port {port}"))?;
+    listener.set_nonblocking(true)?;
+
+    let verifier = code_verifier();
+    let state = uuid::Uuid::new_v4().simple().to_string();
+    let url = format!(
+        "{AUTHORIZE_URL}?response_type=code&client_id={}&scope={}&code_challenge={}&code_challenge_method=S256&state={state}&redirect_uri={}",
+        url_encode(&client_id),
+        url_encode(SCOPES),
+        code_challenge(&verifier),
+        url_encode(&redirect_uri),
+    );
+
+    println!("Open the following URL in your browser to log in to Fitbit:");
+    println!("{url}");
+    let _ = Command::new("xdg-open").arg(&url).spawn();
+
+    let code = wait_for_code(&listener, &state, Instant::now() + LOGIN_TIMEOUT)?;
+    let update = exchange_code(
+        &HttpOptions::from_config(config),
+        &client_id,
+        client_secret.as_deref(),
+        &code,
+        &verifier,
+        &redirect_uri,
+    )?;
+
+    crate::config::save_fitbit_tokens(
+        profile.as_deref(),
+        &update.access_token,
+        update.refresh_token.as_deref(),
+    )?;
+    println!("Fitbit login successful.");
+    Ok(())
+}
+
+fn wait_for_code(listener: &TcpListener, state: &str, deadline: Instant) -> anyhow::Result<String> {
+    loop {
+        let mut stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    bail!("Timed out waiting for the Fitbit login to complete");
+                }
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let Some(target) = read_request_target(&mut stream) else {
+            continue;
+        };
+
+        let Some(query) = target.strip_prefix("/callback?") else {
+            respond(&mut stream, "404 Not Found", "Not found.");
+            continue;
+        };
+
+        let mut code = None;
+        let mut error = None;
+        let mut returned_state = None;
+        for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            match key {
+                "code" => code = Some(url_decode(value)),
+                "error" => error = Some(url_decode(value)),
+                "state" => returned_state = Some(url_decode(value)),
+                _ => {}
+            }
+        }
+
+        if let Some(error) = error {
+            respond(
+                &mut stream,
+                "400 Bad Request",
+                "Fitbit login was not completed. You may close this window.",
+            );
+            bail!("Fitbit authorization failed: {error}");
+        }
+
+        if returned_state.as_deref() != Some(state) {
+            respond(&mut stream, "400 Bad Request", "Invalid login state.");
+            continue;
+        }
+
+        if let Some(code) = code {
+            respond(
+                &mut stream,
+                "200 OK",
+                "Fitbit login successful. You may close this window.",
+            );
+            return Ok(code);
+        }
+
+        respond(
+            &mut stream,
+            "400 Bad Request",
+            "Missing authorization code.",
+        );
+    }
+}
+
+fn read_request_target(stream: &mut TcpStream) -> Option<String> {
+    stream.set_nonblocking(false).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+
+    let mut buf = [0u8; 4096];
+    let len = stream.read(&mut buf).ok()?;
+    let request = std::str::from_utf8(&buf[..len]).ok()?;
+
+    // e.g. GET /callback?code=...&state=... HTTP/1.1
+    let mut parts = request.lines().next()?.split_whitespace();
+    if parts.next()? != "GET" {
+        return None;
+    }
+    parts.next().map(str::to_string)
+}
+
+fn respond(stream: &mut TcpStream, status: &str, message: &str) {
+    let body = format!("<!DOCTYPE html><html><body><p>{message}</p></body></html>");
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let _ = stream.write_all(response.as_bytes());
+}
+
+fn exchange_code(
+    http: &HttpOptions,
+    client_id: &str,
+    client_secret: Option<&str>,
+    code: &str,
+    verifier: &str,
+    redirect_uri: &str,
+) -> anyhow::Result<TokenUpdate> {
+    let form = format!(
+        "client_id={}&grant_type=authorization_code&code={}&code_verifier={verifier}&redirect_uri={}",
+        url_encode(client_id),
+        url_encode(code),
+        url_encode(redirect_uri),
+    );
+
+    let mut args: Vec<String> = vec![];
+    // server-type applications have to authenticate, personal/client ones don't
+    if let Some(client_secret) = client_secret {
+        args.extend(["--user".into(), format!("{client_id}:{client_secret}")]);
+    }
+    args.extend([
+        "--request".into(),
+        "POST".into(),
+        "--header".into(),
+        "Content-Type: application/x-www-form-urlencoded".into(),
+        http.api_url(TOKEN_PATH),
+    ]);
+
+    let HttpResponse { status, body, .. } = curl_with_status(http, args, Some(form.as_str()))?;
+    if status >= 400 {
+        bail!("Fitbit token exchange failed ({status})");
+    }
+
+    let response: FitbitTokenResponse = serde_json::from_slice(&body)?;
+    Ok(TokenUpdate {
+        access_token: response.access_token,
+        expires_in: Duration::from_secs(response.expires_in),
+        refresh_token: response.refresh_token,
+    })
+}
+
+fn code_verifier() -> String {
+    // 96 characters from the unreserved set, RFC 7636 allows 43 to 128
+    (0..3)
+        .map(|_| uuid::Uuid::new_v4().simple().to_string())
+        .collect()
+}
+
+fn code_challenge(verifier: &str) -> String {
+    let digest = Sha256::digest(verifier.as_bytes());
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(digest)
+}
+
+fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char);
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,132 @@
+use std::time::{Duration, Instant};
+
+use wlx_common::config::GeneralConfig;
+
+use super::{
+    Credentials, FetchRequest, FetchResponse, FetchResult, FitbitError, HttpOptions,
+    fetch_latest_rate,
+};
+
+/// Retries of transient errors stop once this has passed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Polls Fitbit once with the configured credentials and prints what went wrong, since a broken
+/// setup otherwise only shows as a heart rate that never updates. Returns whether a poll worked.
+pub fn check(config: &GeneralConfig) -> bool {
+    let credentials = Credentials::from_config(config);
+    if let Some(name) = credentials.profile.as_deref() {
+        println!("Using the Fitbit profile {name}.");
+    }
+
+    if credentials.access_token.is_none() && credentials.refresh_token.is_none() {
+        println!("No Fitbit access or refresh token is set. Log in with --fitbit-login first.");
+        return false;
+    }
+
+    let refresh_token = credentials.refresh_token;
+    let request = FetchRequest {
+        http: HttpOptions::from_config(config),
+        user_id: credentials.user_id.unwrap_or_else(|| "-".to_string()),
+        profile: None,
+        profile_due: true,
+        config_access_token: credentials.access_token,
+        cached_access_token: None,
+        cached_expiry: None,
+        refresh_token: refresh_token.clone(),
+        client_id: credentials.client_id,
+        client_secret: credentials.client_secret,
+        backfill: None,
+        retry_deadline: Instant::now() + CHECK_TIMEOUT,
+    };
+
+    let FetchResponse {
+        result,
+        rate_limit,
+        profile,
+    } = fetch_latest_rate(request);
+
+    match profile {
+        Some(Ok(profile)) => println!(
+            "Logged in as {} ({}).",
+            profile.display_name.as_deref().unwrap_or("unknown"),
+            profile.timezone
+        ),
+        Some(Err(e)) => println!("Could not look up the profile, using local time: {e}"),
+        None => {}
+    }
+
+    let (token, ok) = match result {
+        FetchResult::Ok {
+            rate,
+            measured_at,
+            token,
+            ..
+        } => {
+            if token.is_some() {
+                println!("The access token was expired and has been refreshed.");
+            }
+            match (rate, measured_at) {
+                (Some(rate), Some(measured_at)) => {
+                    println!(
+                        "Fitbit works. The latest heart rate is {rate} BPM, from {measured_at}."
+                    );
+                }
+                (Some(rate), None) => {
+                    println!("Fitbit works. The latest heart rate is {rate} BPM.");
+                }
+                (None, _) => println!(
+                    "Fitbit works, but there is no heart rate for the requested time yet. Sync the watch and try again."
+                ),
+            }
+            (token, true)
+        }
+        FetchResult::Err { error, token } => {
+            match error {
+                FitbitError::RateLimited { retry_after } => match (retry_after, rate_limit) {
+                    (Some(retry_after), _) => println!(
+                        "Fitbit's rate limit is used up, retry in {}s.",
+                        retry_after.as_secs()
+                    ),
+                    (None, Some(rate_limit)) => println!(
+                        "Fitbit's rate limit is used up, it resets in {}s.",
+                        rate_limit.reset_secs
+                    ),
+                    (None, None) => println!("Fitbit's rate limit is used up."),
+                },
+                FitbitError::Http { status: 401, .. } => println!(
+                    "Fitbit rejected the access token: {error}. Log in again with --fitbit-login."
+                ),
+                _ => println!("Fitbit poll failed: {error}"),
+            }
+            (token, false)
+        }
+        FetchResult::ScopeMissing { token } => {
+            println!(
+                "Fitbit denied access to the heart rate, the login lacks the heartrate scope. \
+                 Log in again with --fitbit-login and allow heart rate access."
+            );
+            (token, false)
+        }
+        FetchResult::AuthRevoked => {
+            println!(
+                "The Fitbit refresh token was revoked or has expired. Log in again with --fitbit-login."
+            );
+            (None, false)
+        }
+    };
+
+    // the old refresh token stops working once it was used, so the new one has to be kept
+    if let Some(token) = token {
+        let refresh_token = token.refresh_token.or(refresh_token);
+        if let Err(e) = crate::config::save_fitbit_tokens(
+            credentials.profile.as_deref(),
+            &token.access_token,
+            refresh_token.as_deref(),
+        ) {
+            println!("Could not save the refreshed Fitbit tokens: {e:?}");
+            return false;
+        }
+    }
+
+    ok
+}
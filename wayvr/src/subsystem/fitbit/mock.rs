@@ -0,0 +1,99 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use super::{FetchRequest, FetchResponse, FetchResult, FitbitError, TokenUpdate};
+
+enum Step {
+    Rate(u32),
+    Empty,
+    RateLimited,
+    Unauthorized,
+    Refreshed(u32),
+    /// Always the same dataset entry, as while the watch doesn't sync
+    Frozen(u32),
+}
+
+// a 429 exercises the backoff, a 401 followed by a token update exercises the refresh path,
+// the frozen entries never advance, which counts as stale after `fitbit_sync_stall_secs`
+const SCRIPT: [Step; 11] = [
+    Step::Rate(72),
+    Step::Rate(75),
+    Step::Empty,
+    Step::RateLimited,
+    Step::Rate(81),
+    Step::Unauthorized,
+    Step::Refreshed(78),
+    Step::Rate(74),
+    Step::Frozen(70),
+    Step::Frozen(70),
+    Step::Frozen(70),
+];
+
+static NEXT_STEP: AtomicUsize = AtomicUsize::new(0);
+
+pub(super) fn fetch_scripted(request: FetchRequest) -> FetchResponse {
+    let index = NEXT_STEP.fetch_add(1, Ordering::Relaxed) % SCRIPT.len();
+    log::debug!("Fitbit mock step {index} for user {}", request.user_id);
+
+    FetchResponse {
+        result: scripted_result(index),
+        rate_limit: None,
+        profile: None,
+    }
+}
+
+fn scripted_result(index: usize) -> FetchResult {
+    match SCRIPT[index] {
+        Step::Rate(rate) => FetchResult::Ok {
+            rate: Some(rate),
+            measured_at: None,
+            backfill: Vec::new(),
+            resting: None,
+            token: None,
+        },
+        Step::Empty => FetchResult::Ok {
+            rate: None,
+            measured_at: None,
+            backfill: Vec::new(),
+            resting: None,
+            token: None,
+        },
+        Step::RateLimited => FetchResult::Err {
+            error: FitbitError::RateLimited { retry_after: None },
+            token: None,
+        },
+        Step::Unauthorized => FetchResult::Err {
+            error: FitbitError::Http {
+                status: 401,
+                message: "Unauthorized",
+            },
+            token: None,
+        },
+        Step::Refreshed(rate) => FetchResult::Ok {
+            rate: Some(rate),
+            measured_at: None,
+            backfill: Vec::new(),
+            resting: None,
+            token: Some(TokenUpdate {
+                access_token: format!("mock-access-{index}"),
+                expires_in: Duration::from_secs(60),
+                refresh_token: Some(format!("mock-refresh-{index}")),
+            }),
+        },
+        Step::Frozen(rate) => FetchResult::Ok {
+            rate: Some(rate),
+            measured_at: frozen_time(),
+            backfill: Vec::new(),
+            resting: None,
+            token: None,
+        },
+    }
+}
+
+fn frozen_time() -> Option<NaiveDateTime> {
+    NaiveDate::from_ymd_opt(2024, 1, 1)?.and_hms_opt(12, 0, 0)
+}
@@ -0,0 +1,3173 @@
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::ops::RangeInclusive;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Days, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer, Serialize, de::DeserializeOwned};
+use thiserror::Error;
+use wlx_common::config::GeneralConfig;
+
+use crate::subsystem::{
+    heart_rate::{HeartRateSource, HrStatus, IdleReason, PollHealth, SourceProfile},
+    http::{self, CurlOptions, HttpResponse},
+};
+
+mod auth;
+mod check;
+mod mock;
+mod poll_thread;
+
+pub use auth::authorize;
+pub use check::check;
+
+const FITBIT_API_BASE_URL: &str = "https://api.fitbit.com";
+const TOKEN_PATH: &str = "/oauth2/token";
+
+/// Starts at `FITBIT_MIN_REQUEST_INTERVAL`, a faster step would never be taken
+const FITBIT_POLL_INTERVALS: [Duration; 3] = [
+    Duration::from_secs(3),
+    Duration::from_secs(10),
+    Duration::from_secs(30),
+];
+
+/// Applies regardless of the poll ramp, which restarts whenever the watch is shown
+const FITBIT_MIN_REQUEST_INTERVAL: Duration = Duration::from_secs(3);
+const FITBIT_BUDGET_WINDOW: Duration = Duration::from_secs(3600);
+/// Stop polling until the quota resets once fewer requests than this are left
+const FITBIT_QUOTA_LOW_THRESHOLD: u32 = 10;
+const FITBIT_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// Wait after a 429 that didn't say how long
+const FITBIT_RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(60);
+/// Wait after an HTML page came back instead of JSON, Fitbit is usually down for maintenance then
+const FITBIT_OUTAGE_BACKOFF: Duration = Duration::from_secs(300);
+/// A `Retry-After` beyond the hourly quota window is taken as bogus
+const FITBIT_MAX_RETRY_AFTER: Duration = Duration::from_secs(3600);
+/// How long shutdown waits for a poll in flight, which may carry a rotated refresh token
+const FITBIT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+/// Detail levels of the intraday heart rate API. `1sec` needs access to it being granted.
+const FITBIT_DETAIL_LEVELS: [&str; 4] = ["1sec", "1min", "5min", "15min"];
+const FITBIT_DEFAULT_DETAIL_LEVEL: &str = "1min";
+/// Version segment of the heart rate endpoint, the profile is only served by version 1
+const FITBIT_DEFAULT_API_VERSION: &str = "1";
+/// The profile rarely changes, so it's only looked up again after this
+const FITBIT_PROFILE_MAX_AGE: Duration = Duration::from_secs(86400);
+const FITBIT_PROFILE_RETRY_DELAY: Duration = Duration::from_secs(600);
+/// Consecutive failed polls after which the connection counts as lost
+const FITBIT_DISCONNECTED_AFTER_FAILURES: u32 = 3;
+/// Wall-clock and monotonic time drifting apart by this much between two updates
+/// means the system was suspended or its clock was changed
+const FITBIT_CLOCK_JUMP: Duration = Duration::from_secs(60);
+/// How often the config is looked at for new credentials, see `start_warmup_on_new_credentials`
+const FITBIT_CREDENTIALS_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct FitbitState {
+    last_rate: Option<u32>,
+    last_read_at: Option<SystemTime>,
+    /// Dataset time of `last_rate`, the same entry is returned until Fitbit has a new one
+    last_measured_at: Option<NaiveDateTime>,
+    /// Whether the next poll still has to fetch `fitbit_backfill_minutes`
+    backfill_due: bool,
+    /// Fetched by the first poll, until `take_backfill`
+    backfill: Vec<(SystemTime, u32)>,
+    fresh: bool,
+    /// `last_rate` is stale once no new reading arrived for this long
+    stale_after: Duration,
+    /// Zero doesn't check for it, see `sync_stalled`
+    sync_stall_after: Duration,
+    /// When `last_measured_at` last advanced
+    measured_advanced_at: Option<Instant>,
+    /// Whether polls succeeded for `fitbit_sync_stall_secs` without `last_measured_at`
+    /// advancing, until it does
+    sync_stalled: bool,
+    consecutive_failures: u32,
+    next_poll_at: Instant,
+    next_interval_index: usize,
+    /// Added to `next_poll_at` whenever the ramp restarts, see `set_poll_phase`
+    poll_phase: Duration,
+    last_watch_visible: bool,
+    /// Debounced `watch_visible`
+    watch_visible: bool,
+    /// When `watch_visible` started to differ from the debounced state
+    visibility_changed_at: Option<Instant>,
+    polling_enabled: bool,
+    /// Both clocks at the last `update`, see `check_clock_jump`
+    last_update_at: Option<(Instant, SystemTime)>,
+    /// Whether a poll was made yet, see `fitbit_poll_on_startup`
+    polled: bool,
+    pending: Option<Receiver<FetchResponse>>,
+    /// Start times of the polls within the last `FITBIT_BUDGET_WINDOW`
+    request_times: VecDeque<Instant>,
+    access_token: Option<String>,
+    access_token_expires_at: Option<Instant>,
+    /// The same as wall-clock time, which keeps running during a suspend
+    access_token_expires_at_wall: Option<SystemTime>,
+    refresh_token: Option<String>,
+    /// Whether the last successful poll had to refresh the access token first
+    last_poll_refreshed: bool,
+    /// Token refreshes since startup. Many of them hint at a short expiry or a skewed clock.
+    token_refreshes: u32,
+    profile: Option<Profile>,
+    profile_due_at: Instant,
+    /// Of the day, as the heart rate responses report it
+    resting_rate: Option<u32>,
+    rate_limit_remaining: Option<u32>,
+    fetch: FetchFn,
+    /// Calls `fetch` right in `update` and handles its response there, without a thread
+    poll_inline: bool,
+    /// Started with the first poll
+    poll_thread: Option<poll_thread::PollThread>,
+    persist_tokens: bool,
+    /// The refresh token was revoked, only logging in again helps
+    auth_revoked: bool,
+    /// Revoked refresh tokens and 403s since `first_terminal_error_at`, see
+    /// `tolerate_terminal_error`
+    terminal_errors: u32,
+    first_terminal_error_at: Option<Instant>,
+    /// `fitbit_auth_error_tolerance` and `fitbit_auth_error_window_secs`
+    terminal_error_tolerance: u32,
+    terminal_error_window: Duration,
+    /// Access and refresh token from the config that Fitbit answered with a 403,
+    /// no more polls are made until either of them changes
+    scope_rejected: Option<(Option<String>, Option<String>)>,
+    /// Access and refresh token from the config at the last poll
+    requested_with: (Option<String>, Option<String>),
+    /// `fitbit_profile` the tokens belong to, None for the top-level credentials
+    profile_name: Option<String>,
+    /// Access and refresh token from the config when they were last checked for a change,
+    /// None before the first check
+    warmup_credentials: Option<(Option<String>, Option<String>)>,
+    credentials_checked_at: Option<Instant>,
+    /// Quick polls left after new credentials showed up
+    warmup_polls_left: u32,
+    /// Whether the warmup is still waiting for its first successful poll
+    warmup_unconfirmed: bool,
+}
+
+/// Performs a single poll on the worker thread.
+type FetchFn = fn(FetchRequest) -> FetchResponse;
+
+/// A panic, e.g. over an unexpected response, is turned into a failed poll, so it goes through
+/// the usual backoff instead of a new poll being made right away
+fn fetch_catching_panics(fetch: FetchFn, request: FetchRequest) -> FetchResponse {
+    std::panic::catch_unwind(AssertUnwindSafe(|| fetch(request))).unwrap_or_else(|panic| {
+        let reason = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        FetchResponse {
+            result: FetchResult::Err {
+                error: FitbitError::Parse(format!("Fitbit poll panicked: {reason}")),
+                token: None,
+            },
+            rate_limit: None,
+            profile: None,
+        }
+    })
+}
+
+impl Default for FitbitState {
+    fn default() -> Self {
+        Self {
+            last_rate: None,
+            last_read_at: None,
+            last_measured_at: None,
+            backfill_due: true,
+            backfill: Vec::new(),
+            fresh: false,
+            stale_after: Duration::MAX,
+            sync_stall_after: Duration::ZERO,
+            measured_advanced_at: None,
+            sync_stalled: false,
+            consecutive_failures: 0,
+            next_poll_at: Instant::now(),
+            next_interval_index: 0,
+            poll_phase: Duration::ZERO,
+            last_watch_visible: false,
+            watch_visible: false,
+            visibility_changed_at: None,
+            polling_enabled: true,
+            last_update_at: None,
+            polled: false,
+            pending: None,
+            request_times: VecDeque::new(),
+            access_token: None,
+            access_token_expires_at: None,
+            access_token_expires_at_wall: None,
+            refresh_token: None,
+            last_poll_refreshed: false,
+            token_refreshes: 0,
+            profile: None,
+            profile_due_at: Instant::now(),
+            resting_rate: None,
+            rate_limit_remaining: None,
+            fetch: fetch_latest_rate,
+            poll_inline: false,
+            poll_thread: None,
+            persist_tokens: true,
+            auth_revoked: false,
+            terminal_errors: 0,
+            first_terminal_error_at: None,
+            terminal_error_tolerance: 1,
+            terminal_error_window: Duration::ZERO,
+            scope_rejected: None,
+            requested_with: (None, None),
+            profile_name: None,
+            warmup_credentials: None,
+            credentials_checked_at: None,
+            warmup_polls_left: 0,
+            warmup_unconfirmed: false,
+        }
+    }
+}
+
+// intraday data only contains BPM values, so there is no HRV to report
+impl HeartRateSource for FitbitState {
+    fn name(&self) -> &'static str {
+        "fitbit"
+    }
+
+    fn update(&mut self, config: &GeneralConfig, watch_visible: bool) {
+        self.fresh = false;
+        self.stale_after = Duration::from_secs(config.fitbit_stale_after_secs);
+        self.sync_stall_after = Duration::from_secs(config.fitbit_sync_stall_secs);
+        self.terminal_error_tolerance = config.fitbit_auth_error_tolerance;
+        self.terminal_error_window = Duration::from_secs(config.fitbit_auth_error_window_secs);
+        self.check_clock_jump();
+        if let Some(receiver) = self.pending.as_ref() {
+            match receiver.try_recv() {
+                Ok(response) => {
+                    self.pending = None;
+                    self.handle_response(response);
+                }
+                Err(TryRecvError::Disconnected) => {
+                    log::warn!("Fitbit poll ended without a response.");
+                    self.pending = None;
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+
+        if self.auth_revoked {
+            return;
+        }
+
+        let debounce = Duration::from_millis(config.fitbit_visibility_debounce_ms);
+        let watch_visible = self.debounce_visibility(watch_visible, debounce);
+
+        // a single poll while the watch is still hidden, so it has a value once it's shown.
+        // the ramp isn't started by it, that still happens once the watch is shown
+        let startup_poll = config.fitbit_poll_on_startup && !self.polled;
+
+        // treated like a hidden watch, so the ramp restarts once polling is resumed
+        if !self.polling_enabled || (!watch_visible && !startup_poll) {
+            self.last_watch_visible = false;
+            return;
+        }
+
+        if watch_visible && !self.last_watch_visible {
+            self.next_poll_at = Instant::now() + self.poll_phase;
+            self.next_interval_index = 0;
+            self.last_watch_visible = true;
+        }
+
+        let now = Instant::now();
+        if self
+            .credentials_checked_at
+            .is_none_or(|at| now.duration_since(at) >= FITBIT_CREDENTIALS_CHECK_INTERVAL)
+        {
+            self.credentials_checked_at = Some(now);
+            self.start_warmup_on_new_credentials(config, now);
+        }
+
+        if now < self.next_poll_at || self.pending.is_some() {
+            return;
+        }
+
+        let Credentials {
+            profile,
+            access_token: config_access_token,
+            refresh_token: config_refresh_token,
+            user_id,
+            client_id,
+            client_secret,
+        } = Credentials::from_config(config);
+
+        if profile != self.profile_name {
+            // the cached tokens belong to the other profile
+            match profile.as_deref() {
+                Some(name) => log::info!("Using the Fitbit profile {name}."),
+                None => log::info!("Using the top-level Fitbit credentials."),
+            }
+            self.access_token = None;
+            self.access_token_expires_at = None;
+            self.access_token_expires_at_wall = None;
+            self.refresh_token = None;
+            self.scope_rejected = None;
+            self.profile_name.clone_from(&profile);
+        }
+
+        if let Some(rejected) = self.scope_rejected.take() {
+            if rejected == (config_access_token.clone(), config_refresh_token.clone()) {
+                self.scope_rejected = Some(rejected);
+                self.next_poll_at = now + *FITBIT_POLL_INTERVALS.last().unwrap();
+                return;
+            }
+
+            // the rotated tokens belong to the login that lacked the scope
+            log::info!("Fitbit credentials changed, polling again.");
+            self.access_token = None;
+            self.access_token_expires_at = None;
+            self.access_token_expires_at_wall = None;
+            self.refresh_token = None;
+        }
+
+        if self.access_token.is_none() {
+            self.access_token.clone_from(&config_access_token);
+        }
+
+        let user_id = user_id.unwrap_or_else(|| "-".to_string());
+
+        // a rotated refresh token invalidates the one from the config
+        let refresh_token = self
+            .refresh_token
+            .clone()
+            .or_else(|| config_refresh_token.clone());
+
+        while self
+            .request_times
+            .front()
+            .is_some_and(|time| now.duration_since(*time) >= FITBIT_BUDGET_WINDOW)
+        {
+            self.request_times.pop_front();
+        }
+
+        let budget = config.fitbit_hourly_request_budget as usize;
+        let budget_exhausted = self.request_times.len() >= budget;
+        let min_interval = if budget_exhausted {
+            *FITBIT_POLL_INTERVALS.last().unwrap()
+        } else {
+            FITBIT_MIN_REQUEST_INTERVAL
+        };
+
+        if self
+            .request_times
+            .back()
+            .is_some_and(|time| now.duration_since(*time) < min_interval)
+        {
+            return;
+        }
+
+        if budget_exhausted {
+            log::debug!("Fitbit hourly request budget of {budget} used up, polling slowly.");
+        }
+
+        self.polled = true;
+        self.request_times.push_back(now);
+        while self.request_times.len() > budget.max(1) {
+            self.request_times.pop_front();
+        }
+
+        let access_token = self.access_token.clone();
+        let token_expiry = self.access_token_expires_at;
+        log::debug!("Fitbit poll attempt.");
+
+        // the ramp starts over once the warmup is done
+        let interval = if self.warmup_polls_left > 0 {
+            self.warmup_polls_left -= 1;
+            Duration::from_secs(config.fitbit_warmup_interval_secs).max(FITBIT_MIN_REQUEST_INTERVAL)
+        } else {
+            let interval = FITBIT_POLL_INTERVALS
+                .get(self.next_interval_index)
+                .copied()
+                .unwrap_or_else(|| *FITBIT_POLL_INTERVALS.last().unwrap());
+            self.next_interval_index =
+                (self.next_interval_index + 1).min(FITBIT_POLL_INTERVALS.len() - 1);
+            interval
+        };
+        self.next_poll_at = now + interval;
+
+        self.requested_with = (config_access_token.clone(), config_refresh_token);
+        // the history is what the backfill is for
+        let backfill =
+            (self.backfill_due && config.fitbit_backfill_minutes > 0 && config.hr_history_len > 0)
+                .then(|| Duration::from_secs(u64::from(config.fitbit_backfill_minutes) * 60));
+        let request = FetchRequest {
+            http: HttpOptions::from_config(config),
+            user_id,
+            profile: self.profile.clone(),
+            profile_due: now >= self.profile_due_at,
+            config_access_token,
+            cached_access_token: access_token,
+            cached_expiry: token_expiry,
+            refresh_token,
+            client_id,
+            client_secret,
+            backfill,
+            retry_deadline: now + interval,
+        };
+
+        if self.poll_inline {
+            let response = fetch_catching_panics(self.fetch, request);
+            self.handle_response(response);
+            return;
+        }
+
+        let receiver = self
+            .poll_thread
+            .get_or_insert_with(poll_thread::PollThread::new)
+            .spawn_fetch(self.fetch, request);
+
+        self.pending = Some(receiver);
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.last_rate
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        self.last_read_at
+    }
+
+    /// The dataset time is taken in the timezone of the profile, or local time without it
+    fn last_reading_time(&self) -> Option<SystemTime> {
+        self.last_measured_at
+            .and_then(|measured_at| self.dataset_time(measured_at))
+            .or(self.last_read_at)
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.fresh
+    }
+
+    /// The latest dataset entry may itself be minutes old, so its time counts if known.
+    /// It also counts while the watch isn't syncing, see `check_sync_stall`.
+    fn is_stale(&self) -> bool {
+        self.sync_stalled
+            || self
+                .last_reading_time()
+                .is_none_or(|read_at| read_at.elapsed().unwrap_or_default() >= self.stale_after)
+    }
+
+    fn status(&self) -> HrStatus {
+        if self.auth_revoked
+            || self.scope_rejected.is_some()
+            || self.consecutive_failures >= FITBIT_DISCONNECTED_AFTER_FAILURES
+        {
+            return HrStatus::Disconnected;
+        }
+
+        match self.last_rate {
+            Some(rate) if self.is_stale() => HrStatus::Stale(rate),
+            Some(rate) => HrStatus::Live(rate),
+            None => HrStatus::Initializing,
+        }
+    }
+
+    fn idle_reason(&self) -> Option<IdleReason> {
+        if self.status() == HrStatus::Disconnected {
+            Some(IdleReason::Disconnected)
+        } else if !self.polling_enabled {
+            Some(IdleReason::Paused)
+        } else if !self.last_watch_visible {
+            Some(IdleReason::WatchHidden)
+        } else if self.sync_stalled {
+            Some(IdleReason::NotSyncing)
+        } else {
+            None
+        }
+    }
+
+    fn quota_remaining(&self) -> Option<u32> {
+        self.rate_limit_remaining
+    }
+
+    fn set_polling_enabled(&mut self, enabled: bool) {
+        self.polling_enabled = enabled;
+    }
+
+    fn polling_enabled(&self) -> bool {
+        self.polling_enabled
+    }
+
+    fn set_poll_phase(&mut self, phase: Duration) {
+        self.poll_phase = phase;
+    }
+
+    fn force_poll(&mut self) {
+        if self.pending.is_some() {
+            return;
+        }
+        log::debug!("Fitbit poll forced.");
+        self.next_poll_at = Instant::now();
+        self.next_interval_index = 0;
+    }
+
+    fn poll_in_flight(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    fn take_backfill(&mut self) -> Vec<(SystemTime, u32)> {
+        std::mem::take(&mut self.backfill)
+    }
+
+    fn poll_health(&self) -> PollHealth {
+        PollHealth {
+            failures: self.consecutive_failures,
+            reconnects: 0,
+            next_poll_at: Some(PollHealth::wall_time(self.next_poll_at)),
+            token_expires_at: self.access_token_expires_at_wall,
+        }
+    }
+
+    fn source_profile(&self) -> SourceProfile {
+        SourceProfile {
+            resting: self.resting_rate,
+            age: self.profile.as_ref().and_then(|profile| profile.age),
+        }
+    }
+
+    fn auth_revoked(&self) -> bool {
+        self.auth_revoked
+    }
+
+    fn scope_missing(&self) -> bool {
+        self.scope_rejected.is_some()
+    }
+
+    fn flush_on_shutdown(&mut self) {
+        FitbitState::flush_on_shutdown(self);
+    }
+}
+
+/// A Fitbit setup that can't work, found before the first poll
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("neither an access token nor a refresh token is set, log in with --fitbit-login")]
+    MissingToken,
+    #[error("a refresh token is set, but fitbit_client_id is missing to renew it with")]
+    MissingClientId,
+    #[error("fitbit_detail_level '{0}' is not one of {levels}", levels = FITBIT_DETAIL_LEVELS.join(", "))]
+    InvalidDetailLevel(String),
+    #[error("fitbit_api_base_url '{0}' is not an http(s) URL")]
+    InvalidApiBaseUrl(String),
+    #[error("fitbit_api_version '{0}' is not a version like 1 or 1.2")]
+    InvalidApiVersion(String),
+    #[error("fitbit_extra_headers entry '{0}' is not of the form `Name: value`")]
+    InvalidExtraHeader(String),
+}
+
+impl FitbitState {
+    /// Checks that the configured credentials fit together and starts out with them,
+    /// so a broken setup is reported at startup instead of as failing polls.
+    pub fn from_config(config: &GeneralConfig) -> Result<Self, ConfigError> {
+        let Credentials {
+            profile,
+            access_token,
+            refresh_token,
+            client_id,
+            ..
+        } = Credentials::from_config(config);
+
+        if access_token.is_none() && refresh_token.is_none() {
+            return Err(ConfigError::MissingToken);
+        }
+
+        if refresh_token.is_some() {
+            if client_id.is_none() {
+                return Err(ConfigError::MissingClientId);
+            }
+            if access_token.is_none() {
+                log::info!(
+                    "No Fitbit access token is set, the first poll gets one with the refresh token."
+                );
+            }
+        }
+
+        let level = config.fitbit_detail_level.trim();
+        if !FITBIT_DETAIL_LEVELS.contains(&level) {
+            return Err(ConfigError::InvalidDetailLevel(level.to_string()));
+        }
+
+        if let Some(url) = config
+            .fitbit_api_base_url
+            .as_deref()
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            && !(url.starts_with("http://") || url.starts_with("https://"))
+        {
+            return Err(ConfigError::InvalidApiBaseUrl(url.to_string()));
+        }
+
+        let version = config.fitbit_api_version.trim();
+        if !is_api_version(version) {
+            return Err(ConfigError::InvalidApiVersion(version.to_string()));
+        }
+
+        if let Some(header) = config
+            .fitbit_extra_headers
+            .iter()
+            .find(|header| !header.contains(':'))
+        {
+            return Err(ConfigError::InvalidExtraHeader(header.clone()));
+        }
+
+        Ok(Self {
+            access_token,
+            refresh_token,
+            profile_name: profile,
+            ..Default::default()
+        })
+    }
+
+    /// Replays a scripted sequence of responses instead of talking to Fitbit.
+    /// Tokens handed out by the script are never saved.
+    pub fn mock() -> Self {
+        Self::scripted(mock::fetch_scripted)
+    }
+
+    /// Gets its responses from `fetch` instead of Fitbit, right within `update`. Every call that
+    /// polls handles the response before it returns, so the ramp, backoff and token handling can
+    /// be stepped through deterministically, without a network or a thread.
+    fn scripted(fetch: FetchFn) -> Self {
+        Self {
+            fetch,
+            poll_inline: true,
+            persist_tokens: false,
+            ..Default::default()
+        }
+    }
+
+    /// The refresh token is single-use, so losing a rotated one means logging in again.
+    pub fn flush_on_shutdown(&mut self) {
+        let Some(receiver) = self.pending.take() else {
+            return;
+        };
+
+        match receiver.recv_timeout(FITBIT_SHUTDOWN_TIMEOUT) {
+            Ok(response) => self.handle_response(response),
+            Err(RecvTimeoutError::Timeout) => {
+                log::warn!(
+                    "Fitbit poll did not finish before shutdown, tokens it rotates are lost."
+                );
+            }
+            Err(RecvTimeoutError::Disconnected) => {}
+        }
+    }
+
+    /// A dataset time as wall-clock time, see `last_reading_time`
+    fn dataset_time(&self, measured_at: NaiveDateTime) -> Option<SystemTime> {
+        let measured_at = self.profile.as_ref().map_or_else(
+            || {
+                Local
+                    .from_local_datetime(&measured_at)
+                    .earliest()
+                    .map(|time| time.with_timezone(&Utc))
+            },
+            |profile| {
+                profile
+                    .timezone
+                    .from_local_datetime(&measured_at)
+                    .earliest()
+                    .map(|time| time.with_timezone(&Utc))
+            },
+        )?;
+        Some(measured_at.into())
+    }
+
+    /// The visibility only changes once it was stable for `debounce`, a watch that flickers
+    /// while turning the head would restart the poll ramp over and over otherwise.
+    fn debounce_visibility(&mut self, watch_visible: bool, debounce: Duration) -> bool {
+        if watch_visible == self.watch_visible {
+            self.visibility_changed_at = None;
+            return self.watch_visible;
+        }
+
+        let now = Instant::now();
+        let changed_at = *self.visibility_changed_at.get_or_insert(now);
+        if now.duration_since(changed_at) >= debounce {
+            self.watch_visible = watch_visible;
+            self.visibility_changed_at = None;
+        }
+        self.watch_visible
+    }
+
+    fn handle_response(&mut self, response: FetchResponse) {
+        let FetchResponse {
+            result,
+            rate_limit,
+            profile,
+        } = response;
+        if let Some(rate_limit) = rate_limit {
+            self.apply_rate_limit(rate_limit);
+        }
+        if !matches!(result, FetchResult::Ok { .. }) {
+            // the usual backoff takes over instead of more quick polls
+            self.warmup_polls_left = 0;
+            self.warmup_unconfirmed = false;
+        }
+        match result {
+            FetchResult::Ok {
+                rate,
+                measured_at,
+                backfill,
+                resting,
+                token,
+            } => {
+                if std::mem::replace(&mut self.backfill_due, false) && !backfill.is_empty() {
+                    // the latest entry comes in as the reading itself
+                    self.backfill = backfill
+                        .into_iter()
+                        .filter(|(at, _)| measured_at.is_some_and(|measured_at| *at < measured_at))
+                        .filter_map(|(at, rate)| Some((self.dataset_time(at)?, rate)))
+                        .collect();
+                    log::info!(
+                        "Backfilled {} Fitbit heart rate readings.",
+                        self.backfill.len()
+                    );
+                }
+                if std::mem::take(&mut self.warmup_unconfirmed) {
+                    log::info!("The new Fitbit credentials work.");
+                }
+                // an empty dataset means there's no new data yet, keep the last reading
+                let advanced = measured_at.is_none() || measured_at != self.last_measured_at;
+                if rate.is_some() && advanced {
+                    self.last_rate = rate;
+                    self.last_read_at = Some(SystemTime::now());
+                    self.last_measured_at = measured_at;
+                    self.measured_advanced_at = Some(Instant::now());
+                    self.fresh = true;
+                }
+                self.check_sync_stall(rate.is_some() && advanced);
+                if resting.is_some() && resting != self.resting_rate {
+                    log::debug!(
+                        "Fitbit resting heart rate of the day: {} BPM",
+                        resting.unwrap_or_default()
+                    );
+                    self.resting_rate = resting;
+                }
+                self.last_poll_refreshed = token.is_some();
+                if let Some(token) = token {
+                    self.apply_token_update(token);
+                }
+                self.consecutive_failures = 0;
+                self.terminal_errors = 0;
+                self.first_terminal_error_at = None;
+                #[cfg(feature = "prometheus")]
+                crate::subsystem::metrics::record_poll_success(self.last_poll_refreshed);
+                if self.last_poll_refreshed {
+                    log::debug!(
+                        "Fitbit poll success after refreshing the token, {} refreshes since startup.",
+                        self.token_refreshes
+                    );
+                } else {
+                    log::debug!("Fitbit poll success.");
+                }
+            }
+            FetchResult::Err { error, token } => {
+                self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+                #[cfg(feature = "prometheus")]
+                crate::subsystem::metrics::record_poll_error(error.status());
+                // a refresh may have succeeded before the data request failed
+                if let Some(token) = token {
+                    self.apply_token_update(token);
+                }
+                match error {
+                    FitbitError::RateLimited { retry_after } => {
+                        let backoff = retry_after.unwrap_or(FITBIT_RATE_LIMIT_BACKOFF);
+                        log::warn!(
+                            "Fitbit poll rate limited (429). Backing off for {}s.",
+                            backoff.as_secs()
+                        );
+                        self.next_poll_at = Instant::now() + backoff;
+                        self.next_interval_index = FITBIT_POLL_INTERVALS.len() - 1;
+                    }
+                    FitbitError::NotJson { .. } => {
+                        log::warn!(
+                            "Fitbit poll failed: {error}. Backing off for {}s.",
+                            FITBIT_OUTAGE_BACKOFF.as_secs()
+                        );
+                        self.next_poll_at = Instant::now() + FITBIT_OUTAGE_BACKOFF;
+                        self.next_interval_index = FITBIT_POLL_INTERVALS.len() - 1;
+                    }
+                    FitbitError::Transport(_)
+                    | FitbitError::Http { .. }
+                    | FitbitError::Parse(_)
+                    | FitbitError::MissingCredential(_)
+                    | FitbitError::AuthRevoked => log::warn!("Fitbit poll failed: {error}"),
+                }
+            }
+            FetchResult::AuthRevoked => {
+                // the token endpoint answers invalid_grant with a 400
+                #[cfg(feature = "prometheus")]
+                crate::subsystem::metrics::record_poll_error(400);
+                if self.tolerate_terminal_error() {
+                    log::warn!("Fitbit rejected the refresh token, trying again.");
+                } else {
+                    log::error!(
+                        "Fitbit revoked the refresh token, run `wayvr --fitbit-login` to log in again."
+                    );
+                    self.auth_revoked = true;
+                }
+            }
+            FetchResult::ScopeMissing { token } => {
+                #[cfg(feature = "prometheus")]
+                crate::subsystem::metrics::record_poll_error(403);
+                if let Some(token) = token {
+                    self.apply_token_update(token);
+                }
+                if self.tolerate_terminal_error() {
+                    log::warn!("Fitbit denied access to the heart rate (403), trying again.");
+                } else {
+                    log::error!(
+                        "Fitbit denied access to the heart rate (403), the login lacks the heartrate scope. \
+                         Run `wayvr --fitbit-login` and allow heart rate access."
+                    );
+                    self.scope_rejected = Some(std::mem::take(&mut self.requested_with));
+                }
+            }
+        }
+
+        // applied after a token update, which may belong to a different account
+        match profile {
+            Some(Ok(profile)) => {
+                log::debug!(
+                    "Fitbit profile of {}: timezone {}, locale {}, age {}",
+                    profile.display_name.as_deref().unwrap_or("unknown user"),
+                    profile.timezone,
+                    profile.locale.as_deref().unwrap_or("unknown"),
+                    profile
+                        .age
+                        .map_or_else(|| "unknown".to_string(), |age| age.to_string())
+                );
+                self.profile = Some(profile);
+                self.profile_due_at = Instant::now() + FITBIT_PROFILE_MAX_AGE;
+            }
+            Some(Err(message)) => {
+                log::warn!("Could not look up the Fitbit profile, using local time: {message}");
+                self.profile_due_at = Instant::now() + FITBIT_PROFILE_RETRY_DELAY;
+            }
+            None => {}
+        }
+    }
+
+    /// Starts a burst of `fitbit_warmup_polls` quick polls once the credentials in the config
+    /// change, so a new login is confirmed right away. The credentials at the first check and
+    /// tokens rotated by this instance don't count, and showing the watch doesn't either.
+    fn start_warmup_on_new_credentials(&mut self, config: &GeneralConfig, now: Instant) {
+        let Credentials {
+            access_token,
+            refresh_token,
+            ..
+        } = Credentials::from_config(config);
+        let credentials = (access_token, refresh_token);
+        if self.warmup_credentials.as_ref() == Some(&credentials) {
+            return;
+        }
+
+        let first_check = self.warmup_credentials.replace(credentials).is_none();
+        let (access_token, refresh_token) = self.warmup_credentials.as_ref().unwrap();
+        let rotated = refresh_token.is_some() && *refresh_token == self.refresh_token;
+        if first_check
+            || rotated
+            || (access_token.is_none() && refresh_token.is_none())
+            || config.fitbit_warmup_polls == 0
+        {
+            return;
+        }
+
+        log::info!(
+            "New Fitbit credentials, polling {} times every {}s to check them.",
+            config.fitbit_warmup_polls,
+            config.fitbit_warmup_interval_secs
+        );
+        self.warmup_polls_left = config.fitbit_warmup_polls;
+        self.warmup_unconfirmed = true;
+        self.next_poll_at = now;
+        self.next_interval_index = 0;
+    }
+
+    /// The monotonic clock stops during a suspend, so `next_poll_at` and the ramp would carry on
+    /// as if no time passed. After a suspend or a clock change the ramp starts over with a fresh
+    /// poll, and the token counts as expired if wall-clock time says so, the refresh
+    /// response then brings the actual expiry.
+    fn check_clock_jump(&mut self) {
+        let now = (Instant::now(), SystemTime::now());
+        let Some((last_instant, last_wall)) = self.last_update_at.replace(now) else {
+            return;
+        };
+
+        let monotonic = now.0.duration_since(last_instant);
+        let drift = match now.1.duration_since(last_wall) {
+            Ok(wall) => wall.abs_diff(monotonic),
+            // the clock was set back
+            Err(e) => monotonic + e.duration(),
+        };
+        // long gaps alone are normal, e.g. while `CachedHeartRate` holds back updates
+        if drift <= FITBIT_CLOCK_JUMP {
+            return;
+        }
+
+        log::info!("System was suspended or its clock changed, polling Fitbit again.");
+        self.next_poll_at = now.0 + self.poll_phase;
+        self.next_interval_index = 0;
+        if self
+            .access_token_expires_at_wall
+            .is_some_and(|expires_at| now.1 >= expires_at)
+        {
+            self.access_token_expires_at = Some(now.0);
+        }
+    }
+
+    /// Called on every successful poll. Fitbit keeps returning the last entry while the watch
+    /// doesn't sync, which would look like a live reading, so the reading counts as stale once
+    /// that went on for `sync_stall_after`.
+    fn check_sync_stall(&mut self, advanced: bool) {
+        let stalled = !advanced
+            && !self.sync_stall_after.is_zero()
+            && self
+                .measured_advanced_at
+                .is_some_and(|at| at.elapsed() >= self.sync_stall_after);
+        if stalled && !self.sync_stalled {
+            log::warn!(
+                "Fitbit has no newer heart rate for {}s although polls succeed, is the watch syncing?",
+                self.sync_stall_after.as_secs()
+            );
+        } else if !stalled && self.sync_stalled {
+            log::info!("Fitbit is syncing new heart rate readings again.");
+        }
+        self.sync_stalled = stalled;
+    }
+
+    /// Counts a revoked refresh token or 403, and whether it is treated like any other failed
+    /// poll, since Fitbit occasionally answers with those on server trouble. Polling only stops
+    /// once `terminal_error_tolerance` of them came within `terminal_error_window`.
+    fn tolerate_terminal_error(&mut self) -> bool {
+        let now = Instant::now();
+        if self
+            .first_terminal_error_at
+            .is_none_or(|at| now.duration_since(at) > self.terminal_error_window)
+        {
+            self.terminal_errors = 0;
+            self.first_terminal_error_at = Some(now);
+        }
+        self.terminal_errors += 1;
+
+        if self.terminal_errors >= self.terminal_error_tolerance {
+            // new credentials get the same tolerance again
+            self.terminal_errors = 0;
+            self.first_terminal_error_at = None;
+            return false;
+        }
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        true
+    }
+
+    fn apply_rate_limit(&mut self, rate_limit: RateLimit) {
+        log::debug!(
+            "Fitbit quota: {} requests remaining, resets in {}s",
+            rate_limit.remaining,
+            rate_limit.reset_secs
+        );
+        self.rate_limit_remaining = Some(rate_limit.remaining);
+
+        // don't wait for a 429 and its penalty
+        if rate_limit.remaining < FITBIT_QUOTA_LOW_THRESHOLD {
+            log::warn!(
+                "Fitbit quota almost used up, pausing polls for {}s.",
+                rate_limit.reset_secs
+            );
+            let reset_at = Instant::now() + Duration::from_secs(rate_limit.reset_secs);
+            self.next_poll_at = self.next_poll_at.max(reset_at);
+            self.next_interval_index = FITBIT_POLL_INTERVALS.len() - 1;
+        }
+    }
+
+    fn apply_token_update(&mut self, update: TokenUpdate) {
+        self.token_refreshes = self.token_refreshes.saturating_add(1);
+        #[cfg(feature = "prometheus")]
+        crate::subsystem::metrics::record_token_refresh();
+
+        // the cached profile stays in use until the new one arrives
+        self.profile_due_at = Instant::now();
+        self.access_token = Some(update.access_token);
+        self.access_token_expires_at = Some(Instant::now() + update.expires_in);
+        self.access_token_expires_at_wall = Some(SystemTime::now() + update.expires_in);
+        if let Some(refresh_token) = update.refresh_token {
+            self.refresh_token = Some(refresh_token);
+        }
+
+        if !self.persist_tokens {
+            return;
+        }
+
+        if let Some(access_token) = self.access_token.as_deref() {
+            let _ = crate::config::save_fitbit_tokens(
+                self.profile_name.as_deref(),
+                access_token,
+                self.refresh_token.as_deref(),
+            )
+            .inspect_err(|e| log::error!("Could not save Fitbit tokens: {e:?}"));
+        }
+    }
+}
+
+/// Drives the handling of poll results directly, without a network or a thread
+#[cfg(test)]
+impl FitbitState {
+    /// Handles `result` as if a poll just returned it
+    fn inject_result(&mut self, result: FetchResult) {
+        self.handle_response(FetchResponse {
+            result,
+            rate_limit: None,
+            profile: None,
+        });
+    }
+
+    const fn next_poll_at(&self) -> Instant {
+        self.next_poll_at
+    }
+
+    const fn next_interval_index(&self) -> usize {
+        self.next_interval_index
+    }
+}
+
+/// Credentials of the selected `fitbit_profile`, or the top-level ones.
+/// Every request resolves them through here, so they always come from the same place.
+struct Credentials {
+    /// None for the top-level credentials
+    profile: Option<String>,
+    access_token: Option<String>,
+    refresh_token: Option<String>,
+    user_id: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+impl Credentials {
+    fn from_config(config: &GeneralConfig) -> Self {
+        static REPORTED: Once = Once::new();
+
+        if let Some(name) = config
+            .fitbit_profile
+            .as_deref()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let non_empty = |value: Option<&str>| {
+                value
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                    .map(str::to_string)
+            };
+
+            match config.fitbit_profiles.get(name) {
+                Some(profile) if non_empty(profile.client_id.as_deref()).is_some() => {
+                    return Self {
+                        profile: Some(name.to_string()),
+                        access_token: non_empty(profile.access_token.as_deref()),
+                        refresh_token: non_empty(profile.refresh_token.as_deref()),
+                        user_id: non_empty(profile.user_id.as_deref()),
+                        client_id: non_empty(profile.client_id.as_deref()),
+                        client_secret: non_empty(profile.client_secret.as_deref()),
+                    };
+                }
+                Some(_) => REPORTED.call_once(|| {
+                    log::warn!(
+                        "Fitbit profile '{name}' needs a client_id. Using the top-level credentials."
+                    );
+                }),
+                None => REPORTED.call_once(|| {
+                    log::warn!(
+                        "Fitbit profile '{name}' is not in fitbit_profiles. Using the top-level credentials."
+                    );
+                }),
+            }
+        }
+
+        Self {
+            profile: None,
+            access_token: credential(
+                "access token",
+                "WAYVR_FITBIT_ACCESS_TOKEN",
+                config.fitbit_access_token.as_deref(),
+            ),
+            refresh_token: credential(
+                "refresh token",
+                "WAYVR_FITBIT_REFRESH_TOKEN",
+                config.fitbit_refresh_token.as_deref(),
+            ),
+            user_id: credential(
+                "user ID",
+                "WAYVR_FITBIT_USER_ID",
+                config.fitbit_user_id.as_deref(),
+            ),
+            client_id: credential(
+                "client ID",
+                "WAYVR_FITBIT_CLIENT_ID",
+                config.fitbit_client_id.as_deref(),
+            ),
+            client_secret: credential(
+                "client secret",
+                "WAYVR_FITBIT_CLIENT_SECRET",
+                config.fitbit_client_secret.as_deref(),
+            ),
+        }
+    }
+}
+
+/// Environment variables take precedence over the config, to keep secrets out of dotfiles.
+fn credential(name: &str, env_var: &str, config_value: Option<&str>) -> Option<String> {
+    if let Some(value) = std::env::var(env_var)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+    {
+        log::debug!("Fitbit {name} taken from {env_var}");
+        return Some(value);
+    }
+
+    let value = config_value
+        .filter(|value| !value.trim().is_empty())
+        .map(|value| value.to_string());
+    if value.is_some() {
+        log::debug!("Fitbit {name} taken from the config");
+    }
+    value
+}
+
+struct FetchResponse {
+    result: FetchResult,
+    /// Quota as reported by the last request that included it
+    rate_limit: Option<RateLimit>,
+    /// Set if the profile was due to be looked up
+    profile: Option<Result<Profile, String>>,
+}
+
+/// Parts of the user's profile, cached for a day
+#[derive(Clone)]
+struct Profile {
+    timezone: Tz,
+    locale: Option<String>,
+    display_name: Option<String>,
+    age: Option<u32>,
+}
+
+#[derive(Clone, Copy)]
+struct RateLimit {
+    remaining: u32,
+    reset_secs: u64,
+}
+
+enum FetchResult {
+    Ok {
+        rate: Option<u32>,
+        /// Dataset time of `rate`, in the timezone of the user's profile
+        measured_at: Option<NaiveDateTime>,
+        /// Dataset entries of the requested backfill, oldest first
+        backfill: Vec<(NaiveDateTime, u32)>,
+        /// Resting rate of the day, once Fitbit worked it out
+        resting: Option<u32>,
+        token: Option<TokenUpdate>,
+    },
+    Err {
+        error: FitbitError,
+        /// Set if the refresh token was rotated before the error happened
+        token: Option<TokenUpdate>,
+    },
+    /// The token endpoint answered with `invalid_grant`, polling stops for good
+    AuthRevoked,
+    /// The heart rate request was answered with a 403, retrying won't help
+    /// until the user authorizes the `heartrate` scope
+    ScopeMissing {
+        /// Set if the refresh token was rotated before the error happened
+        token: Option<TokenUpdate>,
+    },
+}
+
+impl FetchResult {
+    fn from_sample(sample: Option<Sample>, token: Option<TokenUpdate>) -> Self {
+        match sample {
+            Some(sample) => Self::Ok {
+                rate: Some(sample.rate),
+                measured_at: Some(sample.measured_at),
+                backfill: sample.backfill,
+                resting: sample.resting,
+                token,
+            },
+            None => Self::Ok {
+                rate: None,
+                measured_at: None,
+                backfill: Vec::new(),
+                resting: None,
+                token,
+            },
+        }
+    }
+}
+
+struct TokenUpdate {
+    access_token: String,
+    expires_in: Duration,
+    refresh_token: Option<String>,
+}
+
+/// Settings shared by all requests to Fitbit
+#[derive(Clone)]
+struct HttpOptions {
+    curl: CurlOptions,
+    retries: u32,
+    base_url: String,
+    /// Latest quota seen by requests made with these options
+    last_rate_limit: Cell<Option<RateLimit>>,
+    last_retry_after: Cell<Option<Duration>>,
+    dump_path: Option<PathBuf>,
+    /// Dataset entries outside of this are sync placeholders, e.g. a trailing 0
+    plausible_bpm: RangeInclusive<u32>,
+    detail_level: &'static str,
+    /// e.g. 1 or 1.2, used for the heart rate endpoint
+    api_version: String,
+    /// Only request the data of this recent window instead of the whole day
+    lookback: Option<Duration>,
+    /// Number of the latest plausible dataset entries the reported rate is averaged over
+    average_entries: usize,
+    /// Dataset entries younger than this may still change, see `select_entries`
+    settle: Option<chrono::Duration>,
+    /// Which of the dataset entries with the same time is kept, see `dedup_dataset`
+    duplicate_times: DuplicateTimes,
+    /// How long after midnight yesterday's dataset is looked at while today's is still empty
+    midnight_grace: chrono::Duration,
+    /// The access token is refreshed once it expires within this
+    refresh_margin: Duration,
+}
+
+impl HttpOptions {
+    fn from_config(config: &GeneralConfig) -> Self {
+        let non_empty = |value: Option<&str>| {
+            value
+                .filter(|value| !value.trim().is_empty())
+                .map(|value| value.trim().to_string())
+        };
+
+        Self {
+            curl: CurlOptions {
+                insecure: insecure_tls(config.fitbit_allow_insecure_tls),
+                headers: config
+                    .fitbit_extra_headers
+                    .iter()
+                    .filter(|header| header.contains(':'))
+                    .map(|header| header.trim().to_string())
+                    .collect(),
+                ..CurlOptions::from_config(
+                    config,
+                    Duration::from_secs(config.fitbit_request_timeout_secs.max(1)),
+                )
+            },
+            retries: config.fitbit_request_retries,
+            base_url: non_empty(config.fitbit_api_base_url.as_deref()).map_or_else(
+                || FITBIT_API_BASE_URL.to_string(),
+                |url| url.trim_end_matches('/').to_string(),
+            ),
+            last_rate_limit: Cell::new(None),
+            last_retry_after: Cell::new(None),
+            dump_path: non_empty(config.fitbit_debug_dump_path.as_deref()).map(PathBuf::from),
+            plausible_bpm: config.hr_min_bpm.max(1)..=config.hr_max_bpm,
+            detail_level: detail_level(&config.fitbit_detail_level),
+            api_version: api_version(&config.fitbit_api_version),
+            lookback: (config.fitbit_lookback_minutes > 0)
+                .then(|| Duration::from_secs(u64::from(config.fitbit_lookback_minutes) * 60)),
+            average_entries: (config.fitbit_average_entries as usize).max(1),
+            settle: (config.fitbit_settle_secs > 0)
+                .then(|| chrono::Duration::seconds(i64::from(config.fitbit_settle_secs))),
+            duplicate_times: duplicate_times(&config.fitbit_duplicate_times),
+            midnight_grace: chrono::Duration::minutes(i64::from(
+                config.fitbit_midnight_grace_minutes,
+            )),
+            refresh_margin: Duration::from_secs(config.fitbit_token_refresh_margin_secs),
+        }
+    }
+
+    /// `path` is expected to start with a slash
+    fn api_url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+}
+
+/// Options are read for every poll, so the warning is only logged once
+fn insecure_tls(allowed: bool) -> bool {
+    static REPORTED: Once = Once::new();
+
+    if allowed {
+        REPORTED.call_once(|| {
+            log::warn!(
+                "fitbit_allow_insecure_tls is enabled: Fitbit certificates are NOT verified. \
+                 Only use this for testing against a mirror, never with real credentials over the internet."
+            );
+        });
+    }
+    allowed
+}
+
+/// Options are read for every poll, so an invalid level is only reported once
+fn api_version(version: &str) -> String {
+    static REPORTED: Once = Once::new();
+
+    let version = version.trim();
+    if is_api_version(version) {
+        return version.to_string();
+    }
+
+    REPORTED.call_once(|| {
+        log::warn!(
+            "Unsupported Fitbit API version '{version}'. Using {FITBIT_DEFAULT_API_VERSION}."
+        );
+    });
+    FITBIT_DEFAULT_API_VERSION.to_string()
+}
+
+/// Dot separated numbers, a path segment can't be smuggled in
+fn is_api_version(version: &str) -> bool {
+    !version.is_empty()
+        && version
+            .split('.')
+            .all(|part| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit()))
+}
+
+fn detail_level(level: &str) -> &'static str {
+    static REPORTED: Once = Once::new();
+
+    let level = level.trim();
+    FITBIT_DETAIL_LEVELS
+        .into_iter()
+        .find(|supported| *supported == level)
+        .unwrap_or_else(|| {
+            REPORTED.call_once(|| {
+                log::warn!(
+                    "Unsupported Fitbit detail level '{level}', use one of {}. Using {FITBIT_DEFAULT_DETAIL_LEVEL}.",
+                    FITBIT_DETAIL_LEVELS.join(", ")
+                );
+            });
+            FITBIT_DEFAULT_DETAIL_LEVEL
+        })
+}
+
+/// Which entry wins when a dataset has several for the same time, as happens around a sync
+#[derive(Clone, Copy)]
+enum DuplicateTimes {
+    /// The one written last, which is the one the sync settled on
+    Last,
+    First,
+    Max,
+}
+
+fn duplicate_times(value: &str) -> DuplicateTimes {
+    static REPORTED: Once = Once::new();
+
+    match value.trim() {
+        "last" => DuplicateTimes::Last,
+        "first" => DuplicateTimes::First,
+        "max" => DuplicateTimes::Max,
+        other => {
+            REPORTED.call_once(|| {
+                log::warn!(
+                    "Unsupported fitbit_duplicate_times '{other}', use last, first or max. Using last."
+                );
+            });
+            DuplicateTimes::Last
+        }
+    }
+}
+
+struct FetchRequest {
+    http: HttpOptions,
+    user_id: String,
+    profile: Option<Profile>,
+    profile_due: bool,
+    config_access_token: Option<String>,
+    cached_access_token: Option<String>,
+    cached_expiry: Option<Instant>,
+    refresh_token: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    /// How far back to return the dataset entries besides the latest rate
+    backfill: Option<Duration>,
+    /// Retries must not delay the result past the next poll
+    retry_deadline: Instant,
+}
+
+fn fetch_latest_rate(request: FetchRequest) -> FetchResponse {
+    let http = request.http.clone();
+    let mut profile = None;
+    let result = fetch_rate(&http, request, &mut profile);
+    FetchResponse {
+        result,
+        rate_limit: http.last_rate_limit.get(),
+        profile,
+    }
+}
+
+/// `profile_lookup` is set if the profile was due and the lookup was attempted
+fn fetch_rate(
+    http: &HttpOptions,
+    request: FetchRequest,
+    profile_lookup: &mut Option<Result<Profile, String>>,
+) -> FetchResult {
+    let FetchRequest {
+        http: _,
+        user_id,
+        profile,
+        profile_due,
+        config_access_token,
+        cached_access_token,
+        cached_expiry,
+        refresh_token,
+        client_id,
+        client_secret,
+        backfill,
+        retry_deadline,
+    } = request;
+
+    let mut token = cached_access_token.or(config_access_token);
+    let expired = refresh_due(cached_expiry, http.refresh_margin, Instant::now());
+    let can_refresh = refresh_token.is_some() && client_id.is_some();
+    let mut token_update = None;
+
+    // without an access token yet, e.g. with only a refresh token configured, it's refreshed
+    // right away the same as an expired one
+    if (expired || token.is_none()) && can_refresh {
+        match refresh_access_token(
+            http,
+            refresh_token.clone(),
+            client_id.clone(),
+            client_secret.clone(),
+        ) {
+            Ok(update) => {
+                token = Some(update.access_token.clone());
+                token_update = Some(update);
+            }
+            Err(error) => return refresh_failed(error, None),
+        }
+    }
+
+    let Some(token) = token else {
+        return FetchResult::Err {
+            error: FitbitError::MissingCredential("access token"),
+            token: None,
+        };
+    };
+
+    // a failed lookup must not hold up the heart rate, local time is usually right anyway
+    let mut timezone = profile.map(|profile| profile.timezone);
+    if profile_due {
+        let lookup = with_retries(http, retry_deadline, || {
+            request_profile(http, &user_id, &token)
+        });
+        if let Ok(profile) = lookup.as_ref() {
+            timezone = Some(profile.timezone);
+        }
+        *profile_lookup = Some(lookup.map_err(|err| err.to_string()));
+    }
+
+    let result = with_retries(http, retry_deadline, || {
+        request_latest_rate(http, &user_id, &token, timezone, backfill)
+    });
+    match result {
+        Ok(sample) => FetchResult::from_sample(sample, token_update),
+        Err(FitbitError::Http { status: 401, .. }) => {
+            // the original refresh token is invalid once it was used
+            let refresh_token = token_update
+                .as_ref()
+                .and_then(|update| update.refresh_token.clone())
+                .or(refresh_token);
+            match refresh_access_token(http, refresh_token, client_id, client_secret) {
+                Ok(update) => {
+                    let token = update.access_token.clone();
+                    let result = with_retries(http, retry_deadline, || {
+                        request_latest_rate(http, &user_id, &token, timezone, backfill)
+                    });
+                    match result {
+                        Ok(sample) => FetchResult::from_sample(sample, Some(update)),
+                        Err(FitbitError::Http { status: 403, .. }) => FetchResult::ScopeMissing {
+                            token: Some(update),
+                        },
+                        Err(error) => {
+                            log::debug!("Fitbit poll failed after refresh: {error}");
+                            FetchResult::Err {
+                                error,
+                                token: Some(update),
+                            }
+                        }
+                    }
+                }
+                Err(error) => refresh_failed(error, token_update),
+            }
+        }
+        Err(FitbitError::Http { status: 403, .. }) => FetchResult::ScopeMissing {
+            token: token_update,
+        },
+        Err(error) => {
+            log::debug!("Fitbit poll failed: {error}");
+            FetchResult::Err {
+                error,
+                token: token_update,
+            }
+        }
+    }
+}
+
+/// Whether the access token expired or does so within `margin`. Refreshing a little early saves
+/// the 401 of a request that runs into the expiry.
+fn refresh_due(expiry: Option<Instant>, margin: Duration, now: Instant) -> bool {
+    expiry.is_some_and(|expiry| {
+        let expires_soon = now + margin >= expiry;
+        if expires_soon && now < expiry {
+            log::debug!("Fitbit access token expires soon, refreshing it ahead of time.");
+        }
+        expires_soon
+    })
+}
+
+fn refresh_failed(error: FitbitError, token: Option<TokenUpdate>) -> FetchResult {
+    match error {
+        FitbitError::AuthRevoked => FetchResult::AuthRevoked,
+        error => FetchResult::Err { error, token },
+    }
+}
+
+/// Repeats `request` on network and server errors, while another attempt fits before `deadline`.
+/// 429, 401 and 403 are handled by the caller, other client errors won't go away by retrying.
+fn with_retries<T>(
+    http: &HttpOptions,
+    deadline: Instant,
+    mut request: impl FnMut() -> Result<T, FitbitError>,
+) -> Result<T, FitbitError> {
+    let mut attempt = 0;
+    loop {
+        match request() {
+            Err(err)
+                if err.is_transient()
+                    && attempt < http.retries
+                    && Instant::now() + FITBIT_RETRY_DELAY < deadline =>
+            {
+                attempt += 1;
+                log::debug!(
+                    "Fitbit request failed, retrying ({attempt}/{}): {err}",
+                    http.retries
+                );
+                std::thread::sleep(FITBIT_RETRY_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Fitbit evaluates dates in the timezone of the user's profile, not the local one.
+/// Without the profile timezone, local time is used.
+fn request_latest_rate(
+    http: &HttpOptions,
+    user_id: &str,
+    token: &str,
+    timezone: Option<Tz>,
+    backfill: Option<Duration>,
+) -> Result<Option<Sample>, FitbitError> {
+    let now = timezone.map_or_else(
+        || Local::now().naive_local(),
+        |tz| Utc::now().with_timezone(&tz).naive_local(),
+    );
+    let today = now.date();
+    let since = |window: Duration| {
+        chrono::Duration::from_std(window)
+            .ok()
+            .map(|window| now - window)
+    };
+    let backfill_since = backfill.and_then(since);
+    // the backfill comes with the same request, a whole day covers it already
+    let window_start = match (http.lookback.and_then(since), backfill_since) {
+        (Some(lookback_start), Some(backfill_start)) => Some(lookback_start.min(backfill_start)),
+        (lookback_start, _) => lookback_start,
+    };
+
+    let range =
+        window_start.map(|start| (start.max(today.and_time(NaiveTime::MIN)).time(), now.time()));
+    if let Some(sample) =
+        request_heart_rate(http, user_id, today, range, now, backfill_since, token)?
+    {
+        return Ok(Some(sample));
+    }
+
+    let Some((yesterday, range)) = yesterday_fallback(http, now, window_start) else {
+        return Ok(None);
+    };
+    log::debug!("Fitbit dataset for {today} is empty, trying {yesterday}.");
+    request_heart_rate(http, user_id, yesterday, range, now, backfill_since, token)
+}
+
+/// The day and range to look at while today's dataset is empty. Shortly after midnight, the
+/// latest reading is still in yesterday's dataset. Later on there's just no new data yet, which
+/// isn't worth a second request.
+fn yesterday_fallback(
+    http: &HttpOptions,
+    now: NaiveDateTime,
+    window_start: Option<NaiveDateTime>,
+) -> Option<(NaiveDate, Option<(NaiveTime, NaiveTime)>)> {
+    let today = now.date();
+    if now - today.and_time(NaiveTime::MIN) >= http.midnight_grace {
+        return None;
+    }
+    let yesterday = today.checked_sub_days(Days::new(1))?;
+    let range = match window_start {
+        // the window doesn't reach back into yesterday
+        Some(start) if start.date() == today => return None,
+        Some(start) => Some((start.time(), NaiveTime::from_hms_opt(23, 59, 59).unwrap())),
+        None => None,
+    };
+    Some((yesterday, range))
+}
+
+/// `range` limits the dataset to part of the day, from and to a time of day
+fn heart_rate_url(
+    http: &HttpOptions,
+    user_id: &str,
+    date: NaiveDate,
+    range: Option<(NaiveTime, NaiveTime)>,
+) -> String {
+    let mut path = format!(
+        "/{}/user/{user_id}/activities/heart/date/{}/1d/{}",
+        http.api_version,
+        date.format("%Y-%m-%d"),
+        http.detail_level
+    );
+    if let Some((start, end)) = range {
+        path.push_str(&format!(
+            "/time/{}/{}",
+            start.format("%H:%M"),
+            end.format("%H:%M")
+        ));
+    }
+    path.push_str(".json");
+    http.api_url(&path)
+}
+
+fn request_profile(http: &HttpOptions, user_id: &str, token: &str) -> Result<Profile, FitbitError> {
+    let HttpResponse { status, body, .. } = curl_with_status(
+        http,
+        vec![
+            "--header".into(),
+            format!("Authorization: Bearer {token}"),
+            "--header".into(),
+            "Accept: application/json".into(),
+            http.api_url(&format!("/1/user/{user_id}/profile.json")),
+        ],
+        None,
+    )
+    .map_err(FitbitError::transport)?;
+
+    if status >= 400 {
+        return Err(FitbitError::from_status(
+            status,
+            &body,
+            "Fitbit profile request failed",
+            http,
+        ));
+    }
+
+    let response: FitbitProfileResponse = parse_json(status, &body)?;
+    let FitbitProfile {
+        timezone,
+        locale,
+        display_name,
+        age,
+    } = response.user;
+    let timezone = timezone
+        .parse::<Tz>()
+        .map_err(|_| FitbitError::Parse(format!("Unknown Fitbit profile timezone: {timezone}")))?;
+    Ok(Profile {
+        timezone,
+        locale,
+        display_name,
+        age: age.filter(|age| *age > 0),
+    })
+}
+
+/// The latest entry of a day's dataset
+struct Sample {
+    rate: u32,
+    measured_at: NaiveDateTime,
+    /// The plausible entries since `backfill_since`, oldest first
+    backfill: Vec<(NaiveDateTime, u32)>,
+    /// From the day summary next to the dataset
+    resting: Option<u32>,
+}
+
+fn request_heart_rate(
+    http: &HttpOptions,
+    user_id: &str,
+    date: NaiveDate,
+    range: Option<(NaiveTime, NaiveTime)>,
+    now: NaiveDateTime,
+    backfill_since: Option<NaiveDateTime>,
+    token: &str,
+) -> Result<Option<Sample>, FitbitError> {
+    let args = vec![
+        "--header".into(),
+        format!("Authorization: Bearer {token}"),
+        "--header".into(),
+        "Accept: application/json".into(),
+        heart_rate_url(http, user_id, date, range),
+    ];
+    let HttpResponse { status, body, .. } =
+        curl_with_status(http, args.clone(), None).map_err(FitbitError::transport)?;
+
+    let result = if status >= 400 {
+        Err(FitbitError::from_status(
+            status,
+            &body,
+            "Fitbit heart rate request failed",
+            http,
+        ))
+    } else {
+        parse_json::<FitbitHeartResponse>(status, &body).and_then(|response| {
+            let resting = response.resting_rate();
+            let mut intraday = response.into_intraday()?;
+            dedup_dataset(&mut intraday.dataset, http.duplicate_times);
+            let entries = select_entries(http, &intraday.dataset, date, now)?;
+            let Some(latest) = entries.first() else {
+                return Ok(None);
+            };
+            let sum: u32 = entries.iter().map(|entry| entry.value).sum();
+            let mut backfill = Vec::new();
+            if let Some(since) = backfill_since {
+                for entry in intraday
+                    .dataset
+                    .iter()
+                    .filter(|entry| http.plausible_bpm.contains(&entry.value))
+                {
+                    let at = entry_time(date, entry)?;
+                    if at >= since {
+                        backfill.push((at, entry.value));
+                    }
+                }
+            }
+            Ok(Some(Sample {
+                rate: (sum as f32 / entries.len() as f32).round() as u32,
+                measured_at: entry_time(date, latest)?,
+                backfill,
+                resting,
+            }))
+        })
+    };
+
+    if let Some(path) = http.dump_path.as_ref() {
+        dump_response(path, &args, status, &body, &result);
+    }
+
+    result
+}
+
+/// The entries the rate is averaged over, latest first.
+/// These are the latest plausible entries, not placeholders appended during a sync. With
+/// `settle`, the last entry is skipped while it is younger than that, since the minute it
+/// covers may still be filling up and its value change on the next poll.
+fn select_entries<'a>(
+    http: &HttpOptions,
+    dataset: &'a [FitbitDatasetEntry],
+    date: NaiveDate,
+    now: NaiveDateTime,
+) -> Result<Vec<&'a FitbitDatasetEntry>, FitbitError> {
+    let plausible = || {
+        dataset
+            .iter()
+            .rev()
+            .filter(|entry| http.plausible_bpm.contains(&entry.value))
+    };
+
+    let mut skip = 0;
+    if let Some(settle) = http.settle {
+        let mut latest = plausible();
+        // the only entry is better than none
+        if let (Some(last), Some(_)) = (latest.next(), latest.next())
+            && now - entry_time(date, last)? < settle
+        {
+            skip = 1;
+        }
+    }
+
+    Ok(plausible().skip(skip).take(http.average_entries).collect())
+}
+
+/// Leaves one entry per time in place of the first one, with the value picked by `keep`, so
+/// the latest reading doesn't flip between duplicates from one poll to the next
+fn dedup_dataset(dataset: &mut Vec<FitbitDatasetEntry>, keep: DuplicateTimes) {
+    let total = dataset.len();
+    let mut index: HashMap<String, usize> = HashMap::with_capacity(total);
+    let mut kept: Vec<FitbitDatasetEntry> = Vec::with_capacity(total);
+    for entry in dataset.drain(..) {
+        match index.get(&entry.time) {
+            Some(&i) => {
+                let first = &mut kept[i];
+                first.value = match keep {
+                    DuplicateTimes::Last => entry.value,
+                    DuplicateTimes::First => first.value,
+                    DuplicateTimes::Max => first.value.max(entry.value),
+                };
+            }
+            None => {
+                index.insert(entry.time.clone(), kept.len());
+                kept.push(entry);
+            }
+        }
+    }
+    if kept.len() < total {
+        log::debug!(
+            "Merged {} Fitbit dataset entries with a duplicate time.",
+            total - kept.len()
+        );
+    }
+    *dataset = kept;
+}
+
+fn entry_time(date: NaiveDate, entry: &FitbitDatasetEntry) -> Result<NaiveDateTime, FitbitError> {
+    NaiveTime::parse_from_str(&entry.time, "%H:%M:%S")
+        .map(|time| date.and_time(time))
+        .map_err(|err| FitbitError::Parse(format!("Invalid dataset time: {err}")))
+}
+
+#[derive(Serialize)]
+struct ResponseDump<'a> {
+    request: Vec<&'a str>,
+    status: u16,
+    rate: Option<u32>,
+    error: Option<String>,
+    body: String,
+}
+
+/// Overwrites `path` with the given response, for bug reports.
+fn dump_response(
+    path: &Path,
+    args: &[String],
+    status: u16,
+    body: &[u8],
+    result: &Result<Option<Sample>, FitbitError>,
+) {
+    let dump = ResponseDump {
+        request: args
+            .iter()
+            .map(|arg| {
+                if arg.starts_with("Authorization:") {
+                    "Authorization: <redacted>"
+                } else {
+                    arg.as_str()
+                }
+            })
+            .collect(),
+        status,
+        rate: result
+            .as_ref()
+            .ok()
+            .and_then(Option::as_ref)
+            .map(|sample| sample.rate),
+        error: result.as_ref().err().map(ToString::to_string),
+        body: String::from_utf8_lossy(body).into_owned(),
+    };
+
+    let written = serde_json::to_string_pretty(&dump)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| std::fs::write(path, json).map_err(anyhow::Error::from));
+    if let Err(e) = written {
+        log::warn!(
+            "Could not write Fitbit response to {}: {e:?}",
+            path.display()
+        );
+    }
+}
+
+fn refresh_access_token(
+    http: &HttpOptions,
+    refresh_token: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+) -> Result<TokenUpdate, FitbitError> {
+    let refresh_token = refresh_token.ok_or(FitbitError::MissingCredential("refresh token"))?;
+    let client_id = client_id.ok_or(FitbitError::MissingCredential("client ID"))?;
+
+    let mut form = format!("grant_type=refresh_token&refresh_token={refresh_token}");
+    let mut args: Vec<String> = vec![];
+    // server-type applications authenticate, personal/client ones only name themselves
+    match client_secret {
+        Some(client_secret) => {
+            args.extend(["--user".into(), format!("{client_id}:{client_secret}")]);
+        }
+        None => form.push_str(&format!("&client_id={client_id}")),
+    }
+    args.extend([
+        "--request".into(),
+        "POST".into(),
+        "--header".into(),
+        "Content-Type: application/x-www-form-urlencoded".into(),
+        http.api_url(TOKEN_PATH),
+    ]);
+    let HttpResponse { status, body, .. } =
+        curl_with_status(http, args, Some(form.as_str())).map_err(FitbitError::transport)?;
+
+    if status >= 400 {
+        return Err(refresh_error(status, &body, http));
+    }
+
+    let response: FitbitTokenResponse = parse_json(status, &body)?;
+    Ok(TokenUpdate {
+        access_token: response.access_token,
+        expires_in: Duration::from_secs(response.expires_in),
+        refresh_token: response.refresh_token,
+    })
+}
+
+/// An error answer of the token endpoint, `invalid_grant` means the refresh token is dead
+fn refresh_error(status: u16, body: &[u8], http: &HttpOptions) -> FitbitError {
+    let errors = serde_json::from_slice::<FitbitErrorResponse>(body)
+        .map(|response| response.errors)
+        .unwrap_or_default();
+    if errors
+        .iter()
+        .any(|error| error.error_type == "invalid_grant")
+    {
+        return FitbitError::AuthRevoked;
+    }
+    if errors
+        .iter()
+        .any(|error| error.error_type == "invalid_client")
+    {
+        return FitbitError::Http {
+            status,
+            message: "Fitbit rejected the client ID or secret",
+        };
+    }
+    FitbitError::from_status(status, body, "Fitbit refresh failed", http)
+}
+
+const RATE_LIMIT_HEADERS: [&str; 3] = [
+    "fitbit-rate-limit-remaining",
+    "fitbit-rate-limit-reset",
+    "retry-after",
+];
+
+/// Also keeps track of the quota reported by Fitbit. `form` is POSTed as the body, see
+/// `http::curl_with_form`.
+fn curl_with_status(
+    http: &HttpOptions,
+    args: Vec<String>,
+    form: Option<&str>,
+) -> anyhow::Result<HttpResponse> {
+    let response = match form {
+        Some(form) => http::curl_with_form(&http.curl, &RATE_LIMIT_HEADERS, args, form)?,
+        None => http::curl_with_status(&http.curl, &RATE_LIMIT_HEADERS, args)?,
+    };
+
+    if let [remaining, reset, retry_after] = response.headers.as_slice() {
+        if let (Ok(remaining), Ok(reset_secs)) = (remaining.parse::<u32>(), reset.parse::<u64>()) {
+            http.last_rate_limit.set(Some(RateLimit {
+                remaining,
+                reset_secs,
+            }));
+        }
+        http.last_retry_after
+            .set(parse_retry_after(retry_after, Utc::now()));
+    }
+
+    Ok(response)
+}
+
+/// Fitbit serves an HTML page instead of JSON during outages, even with a 200
+fn looks_like_json(body: &[u8]) -> bool {
+    body.iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .is_some_and(|byte| matches!(byte, b'{' | b'['))
+}
+
+fn parse_json<T: DeserializeOwned>(status: u16, body: &[u8]) -> Result<T, FitbitError> {
+    if !looks_like_json(body) {
+        return Err(FitbitError::NotJson { status });
+    }
+    serde_json::from_slice(body).map_err(FitbitError::parse)
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date, e.g.
+/// `Wed, 21 Oct 2015 07:28:00 GMT`. A date in the past means right away.
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+
+    let delay = match value.parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => {
+            let until = DateTime::parse_from_rfc2822(value)
+                .inspect_err(|e| log::debug!("Ignoring Fitbit Retry-After {value:?}: {e}"))
+                .ok()?;
+            (until.with_timezone(&Utc) - now)
+                .to_std()
+                .unwrap_or_default()
+        }
+    };
+    Some(delay.min(FITBIT_MAX_RETRY_AFTER))
+}
+
+#[derive(Deserialize)]
+struct FitbitHeartResponse {
+    #[serde(rename = "activities-heart-intraday")]
+    intraday: Option<FitbitIntraday>,
+    /// Searched for the dataset if the key above is missing, e.g. with a mirror
+    #[serde(flatten)]
+    other: serde_json::Map<String, serde_json::Value>,
+}
+
+impl FitbitHeartResponse {
+    /// `restingHeartRate` of the day summary under `activities-heart`. It's missing until
+    /// Fitbit has enough data of the day.
+    fn resting_rate(&self) -> Option<u32> {
+        self.other
+            .get("activities-heart")?
+            .as_array()?
+            .first()?
+            .get("value")?
+            .get("restingHeartRate")?
+            .as_u64()
+            .and_then(|rate| u32::try_from(rate).ok())
+            .filter(|rate| *rate > 0)
+    }
+
+    /// The intraday data under its documented key, or else under the first key that mentions
+    /// "intraday" and holds a dataset
+    fn into_intraday(self) -> Result<FitbitIntraday, FitbitError> {
+        if let Some(intraday) = self.intraday {
+            return Ok(intraday);
+        }
+
+        self.other
+            .into_iter()
+            .filter(|(key, value)| key.contains("intraday") && value.get("dataset").is_some())
+            .find_map(|(key, value)| {
+                let intraday = serde_json::from_value(value).ok()?;
+                intraday_key_found(&key);
+                Some(intraday)
+            })
+            .ok_or_else(|| {
+                FitbitError::Parse("no activities-heart-intraday in the response".to_string())
+            })
+    }
+}
+
+/// Responses are parsed for every poll, so the key is only reported once
+fn intraday_key_found(key: &str) {
+    static REPORTED: Once = Once::new();
+
+    REPORTED.call_once(|| {
+        log::warn!(
+            "Fitbit response has no activities-heart-intraday, reading the dataset from '{key}' instead."
+        );
+    });
+}
+
+#[derive(Deserialize)]
+struct FitbitIntraday {
+    #[serde(default, deserialize_with = "lenient_dataset")]
+    dataset: Vec<FitbitDatasetEntry>,
+}
+
+/// Skips the entries that don't parse, e.g. without a `value`, so one of them doesn't cost the
+/// reading of a response full of good ones
+fn lenient_dataset<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<FitbitDatasetEntry>, D::Error> {
+    let raw = Vec::<serde_json::Value>::deserialize(deserializer)?;
+    let total = raw.len();
+    let entries: Vec<FitbitDatasetEntry> = raw
+        .into_iter()
+        .filter_map(|entry| serde_json::from_value(entry).ok())
+        .collect();
+    if entries.len() < total {
+        log::debug!(
+            "Skipped {} malformed Fitbit dataset entries.",
+            total - entries.len()
+        );
+    }
+    Ok(entries)
+}
+
+#[derive(Deserialize)]
+struct FitbitDatasetEntry {
+    /// e.g. 13:45:00
+    time: String,
+    value: u32,
+}
+
+#[derive(Deserialize)]
+struct FitbitProfileResponse {
+    user: FitbitProfile,
+}
+
+#[derive(Deserialize)]
+struct FitbitProfile {
+    timezone: String,
+    #[serde(default)]
+    locale: Option<String>,
+    #[serde(rename = "displayName", default)]
+    display_name: Option<String>,
+    /// Only with the `profile` scope's access to the birth date
+    #[serde(default)]
+    age: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct FitbitTokenResponse {
+    access_token: String,
+    expires_in: u64,
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FitbitErrorResponse {
+    #[serde(default)]
+    errors: Vec<FitbitErrorEntry>,
+}
+
+#[derive(Deserialize)]
+struct FitbitErrorEntry {
+    #[serde(rename = "errorType")]
+    error_type: String,
+}
+
+/// Why a request to Fitbit failed
+#[derive(Debug)]
+enum FitbitError {
+    /// curl failed or timed out, no response came back
+    Transport(String),
+    /// An error status not covered by the other variants, e.g. 401, 403 or 5xx
+    Http { status: u16, message: &'static str },
+    /// The response, or a value in it, couldn't be understood
+    Parse(String),
+    /// A credential needed for the request isn't set up
+    MissingCredential(&'static str),
+    /// The token endpoint answered with `invalid_grant`, the refresh token was revoked or expired
+    AuthRevoked,
+    /// 429, with its `Retry-After` if there was one
+    RateLimited { retry_after: Option<Duration> },
+    /// The body wasn't JSON, usually a maintenance page during an outage
+    NotJson { status: u16 },
+}
+
+impl FitbitError {
+    fn transport(err: anyhow::Error) -> Self {
+        Self::Transport(err.to_string())
+    }
+
+    fn parse(err: serde_json::Error) -> Self {
+        Self::Parse(err.to_string())
+    }
+
+    /// `message` describes the request, `http` has the `Retry-After` of a 429
+    fn from_status(status: u16, body: &[u8], message: &'static str, http: &HttpOptions) -> Self {
+        if status == 429 {
+            Self::RateLimited {
+                retry_after: http.last_retry_after.get(),
+            }
+        } else if status >= 500 && !body.is_empty() && !looks_like_json(body) {
+            Self::NotJson { status }
+        } else {
+            Self::Http { status, message }
+        }
+    }
+
+    /// The HTTP status the error came with, 0 if there was none
+    const fn status(&self) -> u16 {
+        match self {
+            Self::Http { status, .. } => *status,
+            Self::RateLimited { .. } => 429,
+            Self::NotJson { status } => *status,
+            // the token endpoint answers invalid_grant with a 400
+            Self::AuthRevoked => 400,
+            Self::Transport(_) | Self::Parse(_) | Self::MissingCredential(_) => 0,
+        }
+    }
+
+    const fn is_transient(&self) -> bool {
+        match self {
+            Self::Transport(_) => true,
+            Self::Http { status, .. } => *status >= 500,
+            // retrying right away gets the same page, the poll backs off instead
+            Self::Parse(_)
+            | Self::MissingCredential(_)
+            | Self::AuthRevoked
+            | Self::RateLimited { .. }
+            | Self::NotJson { .. } => false,
+        }
+    }
+}
+
+impl std::fmt::Display for FitbitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Transport(message) => write!(f, "{message}"),
+            Self::Http { status, message } => write!(f, "{message} (status {status})"),
+            Self::Parse(message) => write!(f, "Unexpected Fitbit response: {message}"),
+            Self::MissingCredential(what) => write!(f, "Fitbit {what} is missing"),
+            Self::AuthRevoked => write!(f, "Fitbit refresh token was revoked (invalid_grant)"),
+            Self::RateLimited { .. } => write!(f, "Fitbit rate limit reached (status 429)"),
+            Self::NotJson { status } => write!(
+                f,
+                "Fitbit returned non-JSON (status {status}), likely maintenance"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FitbitError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystem::heart_rate::history::ReadingHistory;
+
+    #[test]
+    fn force_poll_clears_the_scheduled_wait() {
+        let mut state = FitbitState {
+            next_poll_at: Instant::now() + Duration::from_secs(300),
+            next_interval_index: FITBIT_POLL_INTERVALS.len() - 1,
+            ..Default::default()
+        };
+
+        state.force_poll();
+        assert!(state.next_poll_at <= Instant::now());
+        assert_eq!(state.next_interval_index, 0);
+    }
+
+    #[test]
+    fn force_poll_does_nothing_while_a_poll_is_in_flight() {
+        let (_reply, receiver) = std::sync::mpsc::channel();
+        let scheduled = Instant::now() + Duration::from_secs(300);
+        let mut state = FitbitState {
+            next_poll_at: scheduled,
+            pending: Some(receiver),
+            ..Default::default()
+        };
+
+        assert!(state.poll_in_flight());
+        state.force_poll();
+        assert_eq!(state.next_poll_at, scheduled);
+    }
+
+    #[test]
+    fn rate_limit_jumps_to_the_slowest_interval() {
+        let mut state = FitbitState {
+            last_rate: Some(88),
+            ..Default::default()
+        };
+        assert_eq!(state.next_interval_index(), 0);
+
+        let before = Instant::now();
+        state.inject_result(FetchResult::Err {
+            error: FitbitError::RateLimited {
+                retry_after: Some(Duration::from_secs(120)),
+            },
+            token: None,
+        });
+
+        assert!(state.next_poll_at() >= before + Duration::from_secs(120));
+        assert_eq!(state.next_interval_index(), FITBIT_POLL_INTERVALS.len() - 1);
+        assert_eq!(state.last_rate(), Some(88));
+    }
+
+    #[test]
+    fn empty_dataset_keeps_the_last_reading() {
+        let mut state = FitbitState::default();
+        let measured_at = NaiveDate::from_ymd_opt(2024, 5, 1)
+            .and_then(|date| date.and_hms_opt(12, 30, 0))
+            .unwrap();
+
+        state.inject_result(FetchResult::Ok {
+            rate: Some(72),
+            measured_at: Some(measured_at),
+            backfill: Vec::new(),
+            resting: None,
+            token: None,
+        });
+        assert_eq!(state.last_rate(), Some(72));
+
+        // what an empty dataset turns into
+        state.inject_result(FetchResult::from_sample(None, None));
+        assert_eq!(state.last_rate(), Some(72));
+        assert_eq!(state.last_measured_at, Some(measured_at));
+    }
+
+    #[test]
+    fn dataset_time_that_stops_advancing_turns_stale() {
+        let mut state = FitbitState {
+            sync_stall_after: Duration::from_secs(600),
+            last_watch_visible: true,
+            ..Default::default()
+        };
+        let synced_at = |minute| {
+            NaiveDate::from_ymd_opt(2024, 5, 1).and_then(|date| date.and_hms_opt(12, minute, 0))
+        };
+        let frozen = |minute| FetchResult::Ok {
+            rate: Some(70),
+            measured_at: synced_at(minute),
+            backfill: Vec::new(),
+            resting: None,
+            token: None,
+        };
+
+        state.inject_result(frozen(30));
+        state.inject_result(frozen(30));
+        assert_eq!(state.status(), HrStatus::Live(70));
+        assert_eq!(state.idle_reason(), None);
+
+        // polls kept returning the same entry for longer than the threshold
+        state.measured_advanced_at = Some(Instant::now() - Duration::from_secs(601));
+        state.inject_result(frozen(30));
+        assert!(state.is_stale());
+        assert_eq!(state.status(), HrStatus::Stale(70));
+        assert_eq!(state.idle_reason(), Some(IdleReason::NotSyncing));
+        state.inject_result(frozen(30));
+        assert!(state.is_stale());
+
+        // the watch synced again
+        state.inject_result(frozen(31));
+        assert!(!state.is_stale());
+        assert_eq!(state.idle_reason(), None);
+    }
+
+    #[test]
+    fn backfill_fills_the_history_before_the_first_reading() {
+        let config: GeneralConfig = serde_json::from_str(r#"{"hr_history_len": 10}"#).unwrap();
+        let mut state = FitbitState::default();
+        let minute = |minute| {
+            NaiveDate::from_ymd_opt(2024, 5, 1)
+                .and_then(|date| date.and_hms_opt(12, minute, 0))
+                .unwrap()
+        };
+
+        state.inject_result(FetchResult::Ok {
+            rate: Some(80),
+            measured_at: Some(minute(30)),
+            backfill: vec![
+                (minute(26), 70),
+                (minute(27), 72),
+                (minute(28), 75),
+                (minute(29), 78),
+                (minute(30), 80),
+            ],
+            resting: None,
+            token: None,
+        });
+
+        let mut history = ReadingHistory::default();
+        history.observe(&state, &config);
+        history.backfill(&state.take_backfill(), &config);
+        let bpms: Vec<Option<u32>> = history.entries().map(|entry| entry.bpm).collect();
+        // the latest entry is only there as the reading itself
+        assert_eq!(bpms, [Some(70), Some(72), Some(75), Some(78), Some(80)]);
+        let ts: Vec<u64> = history.entries().map(|entry| entry.ts).collect();
+        assert!(ts.is_sorted());
+
+        // only the first poll backfills
+        assert!(state.take_backfill().is_empty());
+        state.inject_result(FetchResult::Ok {
+            rate: Some(81),
+            measured_at: Some(minute(31)),
+            backfill: vec![(minute(30), 80), (minute(31), 81)],
+            resting: None,
+            token: None,
+        });
+        assert!(state.take_backfill().is_empty());
+    }
+
+    fn rate_limited(_request: FetchRequest) -> FetchResponse {
+        FetchResponse {
+            result: FetchResult::Err {
+                error: FitbitError::RateLimited { retry_after: None },
+                token: None,
+            },
+            rate_limit: None,
+            profile: None,
+        }
+    }
+
+    /// Covers the tokio loop when built with `fitbit-async`
+    #[test]
+    fn poll_thread_backs_off_from_a_429_like_an_inline_poll() {
+        let config: GeneralConfig = serde_json::from_str("{}").unwrap();
+        let request = || FetchRequest {
+            http: HttpOptions::from_config(&config),
+            user_id: "-".to_string(),
+            profile: None,
+            profile_due: false,
+            config_access_token: None,
+            cached_access_token: None,
+            cached_expiry: None,
+            refresh_token: None,
+            client_id: None,
+            client_secret: None,
+            backfill: None,
+            retry_deadline: Instant::now(),
+        };
+        let before = Instant::now();
+
+        let mut inline = FitbitState::default();
+        inline.handle_response(fetch_catching_panics(rate_limited, request()));
+
+        let mut threaded = FitbitState::default();
+        let response = poll_thread::PollThread::new()
+            .spawn_fetch(rate_limited, request())
+            .recv()
+            .unwrap();
+        threaded.handle_response(response);
+
+        for state in [&inline, &threaded] {
+            assert!(state.next_poll_at() >= before + FITBIT_RATE_LIMIT_BACKOFF);
+            assert_eq!(state.next_interval_index(), FITBIT_POLL_INTERVALS.len() - 1);
+        }
+    }
+
+    /// Refresh tokens of the polls of `refresh_then_fail`
+    static REFRESH_TOKENS_SENT: std::sync::Mutex<Vec<Option<String>>> =
+        std::sync::Mutex::new(Vec::new());
+
+    /// The first poll refreshes the token and then fails to get the heart rate
+    fn refresh_then_fail(request: FetchRequest) -> FetchResponse {
+        let mut sent = REFRESH_TOKENS_SENT.lock().unwrap();
+        sent.push(request.refresh_token);
+        let result = if sent.len() == 1 {
+            FetchResult::Err {
+                error: FitbitError::Transport("connection reset".to_string()),
+                token: Some(TokenUpdate {
+                    access_token: "rotated-access".to_string(),
+                    expires_in: Duration::from_secs(28_800),
+                    refresh_token: Some("rotated-refresh".to_string()),
+                }),
+            }
+        } else {
+            FetchResult::from_sample(None, None)
+        };
+        FetchResponse {
+            result,
+            rate_limit: None,
+            profile: None,
+        }
+    }
+
+    #[test]
+    fn next_poll_uses_the_token_rotated_before_a_failure() {
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"fitbit_access_token": "access", "fitbit_refresh_token": "refresh",
+                "fitbit_client_id": "client", "fitbit_visibility_debounce_ms": 0}"#,
+        )
+        .unwrap();
+        let mut state = FitbitState::scripted(refresh_then_fail);
+
+        state.update(&config, true);
+        state.next_poll_at = Instant::now();
+        state.request_times.clear();
+        state.update(&config, true);
+
+        assert_eq!(
+            *REFRESH_TOKENS_SENT.lock().unwrap(),
+            [
+                Some("refresh".to_string()),
+                Some("rotated-refresh".to_string())
+            ]
+        );
+        assert_eq!(state.access_token.as_deref(), Some("rotated-access"));
+    }
+
+    #[test]
+    fn visibility_flicker_is_debounced() {
+        let debounce = Duration::from_millis(500);
+        let mut state = FitbitState {
+            watch_visible: true,
+            ..Default::default()
+        };
+
+        for _ in 0..10 {
+            assert!(state.debounce_visibility(false, debounce));
+            assert!(state.debounce_visibility(true, debounce));
+        }
+
+        // hidden for longer than the debounce
+        assert!(state.debounce_visibility(false, debounce));
+        state.visibility_changed_at = Some(Instant::now() - Duration::from_millis(600));
+        assert!(!state.debounce_visibility(false, debounce));
+    }
+
+    /// The value of the entry `select_entries` puts first, as of 13:45:30, after merging the
+    /// duplicates like a response does
+    fn selected(config: &str, dataset: &[(&str, u32)]) -> Option<u32> {
+        let config: GeneralConfig = serde_json::from_str(config).unwrap();
+        let http = HttpOptions::from_config(&config);
+        let mut dataset: Vec<FitbitDatasetEntry> = dataset
+            .iter()
+            .map(|&(time, value)| FitbitDatasetEntry {
+                time: time.to_string(),
+                value,
+            })
+            .collect();
+        let date = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        let now = date.and_hms_opt(13, 45, 30).unwrap();
+
+        dedup_dataset(&mut dataset, http.duplicate_times);
+        let entries = select_entries(&http, &dataset, date, now).unwrap();
+        entries.first().map(|entry| entry.value)
+    }
+
+    #[test]
+    fn duplicate_times_are_merged_before_selecting() {
+        let dataset = [
+            ("13:44:00", 70),
+            ("13:45:00", 72),
+            ("13:45:00", 80),
+            // rewritten during a sync, after the later minute
+            ("13:44:00", 90),
+        ];
+        assert_eq!(selected("{}", &dataset), Some(80));
+        assert_eq!(
+            selected(r#"{"fitbit_duplicate_times": "first"}"#, &dataset),
+            Some(72)
+        );
+        assert_eq!(
+            selected(
+                r#"{"fitbit_duplicate_times": "max"}"#,
+                &[("13:45:00", 80), ("13:45:00", 75)]
+            ),
+            Some(80)
+        );
+    }
+
+    #[test]
+    fn trailing_placeholder_zero_is_skipped() {
+        assert_eq!(
+            selected("{}", &[("13:44:00", 72), ("13:45:00", 0)]),
+            Some(72)
+        );
+    }
+
+    #[test]
+    fn unsettled_entry_is_skipped_with_settle() {
+        let dataset = [("13:43:00", 70), ("13:44:00", 72), ("13:45:00", 90)];
+        assert_eq!(selected("{}", &dataset), Some(90));
+        assert_eq!(
+            selected(r#"{"fitbit_settle_secs": 60}"#, &dataset),
+            Some(72)
+        );
+        // settled by now
+        assert_eq!(
+            selected(r#"{"fitbit_settle_secs": 20}"#, &dataset),
+            Some(90)
+        );
+        // the only entry is kept
+        assert_eq!(
+            selected(r#"{"fitbit_settle_secs": 60}"#, &[("13:45:00", 90)]),
+            Some(90)
+        );
+    }
+
+    static PROFILE_DUE: std::sync::Mutex<Vec<bool>> = std::sync::Mutex::new(vec![]);
+
+    fn with_profile(request: FetchRequest) -> FetchResponse {
+        PROFILE_DUE.lock().unwrap().push(request.profile_due);
+        FetchResponse {
+            result: FetchResult::Ok {
+                rate: Some(70),
+                measured_at: None,
+                backfill: vec![],
+                resting: None,
+                token: None,
+            },
+            rate_limit: None,
+            profile: request.profile_due.then(|| {
+                Ok(Profile {
+                    timezone: Tz::UTC,
+                    locale: None,
+                    display_name: None,
+                    age: None,
+                })
+            }),
+        }
+    }
+
+    #[test]
+    fn profile_is_looked_up_once_a_day() {
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"fitbit_access_token": "access", "fitbit_visibility_debounce_ms": 0}"#,
+        )
+        .unwrap();
+        let mut state = FitbitState::scripted(with_profile);
+
+        for _ in 0..3 {
+            state.next_poll_at = Instant::now();
+            state.request_times.clear();
+            state.update(&config, true);
+        }
+        assert_eq!(*PROFILE_DUE.lock().unwrap(), [true, false, false]);
+        assert!(
+            state.profile_due_at
+                >= Instant::now() + FITBIT_PROFILE_MAX_AGE - Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn v1_2_response_parses() {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"fitbit_api_version": "1.2"}"#).unwrap();
+        let http = HttpOptions::from_config(&config);
+        let date = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        assert!(heart_rate_url(&http, "-", date, None).contains("/1.2/user/-/activities/heart/"));
+
+        let body = br#"{
+            "activities-heart": [{
+                "dateTime": "2024-05-01",
+                "value": {
+                    "customHeartRateZones": [],
+                    "heartRateZones": [{"name": "Out of Range", "min": 30, "max": 91, "minutes": 564}],
+                    "restingHeartRate": 58
+                }
+            }],
+            "activities-heart-intraday": {
+                "dataset": [
+                    {"time": "13:44:00", "value": 71},
+                    {"time": "13:45:00", "value": 73, "confidence": 2}
+                ],
+                "datasetInterval": 1,
+                "datasetType": "minute"
+            }
+        }"#;
+
+        let response: FitbitHeartResponse = parse_json(200, body).unwrap();
+        assert_eq!(response.resting_rate(), Some(58));
+        let intraday = response.into_intraday().unwrap();
+        let values: Vec<u32> = intraday.dataset.iter().map(|entry| entry.value).collect();
+        assert_eq!(values, [71, 73]);
+    }
+
+    #[test]
+    fn dataset_under_a_renamed_key_is_still_read() {
+        let body = br#"{
+            "activities-heart": [{"dateTime": "2024-05-01", "value": {"restingHeartRate": 58}}],
+            "activities-heart-intraday-v2": {
+                "dataset": [{"time": "13:44:00", "value": 71}, {"time": "13:45:00", "value": 73}],
+                "datasetInterval": 1
+            }
+        }"#;
+        let response: FitbitHeartResponse = parse_json(200, body).unwrap();
+        let intraday = response.into_intraday().unwrap();
+        let values: Vec<u32> = intraday.dataset.iter().map(|entry| entry.value).collect();
+        assert_eq!(values, [71, 73]);
+
+        // a key that only mentions intraday isn't taken for the dataset
+        let body = br#"{"activities-heart-intraday-note": "moved", "summary": {"dataset": []}}"#;
+        let response: FitbitHeartResponse = parse_json(200, body).unwrap();
+        assert!(matches!(
+            response.into_intraday(),
+            Err(FitbitError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_dataset_entries_are_skipped() {
+        let body = br#"{
+            "activities-heart-intraday": {
+                "dataset": [
+                    {"time": "13:41:00", "value": 68},
+                    {"time": "13:42:00"},
+                    {"time": "13:43:00", "value": "high"},
+                    {"value": 70},
+                    {"time": "13:44:00", "value": 71},
+                    null
+                ]
+            }
+        }"#;
+        let response: FitbitHeartResponse = parse_json(200, body).unwrap();
+        let intraday = response.into_intraday().unwrap();
+        let entries: Vec<(&str, u32)> = intraday
+            .dataset
+            .iter()
+            .map(|entry| (entry.time.as_str(), entry.value))
+            .collect();
+        assert_eq!(entries, [("13:41:00", 68), ("13:44:00", 71)]);
+    }
+
+    fn panicking(_request: FetchRequest) -> FetchResponse {
+        panic!("unexpected response shape");
+    }
+
+    #[test]
+    fn panicking_fetch_counts_as_a_failed_poll() {
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"fitbit_access_token": "access", "fitbit_visibility_debounce_ms": 0}"#,
+        )
+        .unwrap();
+        let mut state = FitbitState::scripted(panicking);
+
+        state.update(&config, true);
+        assert_eq!(state.consecutive_failures, 1);
+        assert!(state.pending.is_none());
+        assert!(state.next_poll_at > Instant::now());
+    }
+
+    #[test]
+    fn retry_after_in_seconds_and_as_a_date() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            parse_retry_after(" 120 ", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:29:30 GMT", now),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("", now), None);
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn rate_limit_waits_as_long_as_retry_after_says() {
+        let mut state = FitbitState::default();
+        let before = Instant::now();
+        state.inject_result(FetchResult::Err {
+            error: FitbitError::RateLimited {
+                retry_after: Some(Duration::from_secs(15)),
+            },
+            token: None,
+        });
+
+        assert!(state.next_poll_at() >= before + Duration::from_secs(15));
+        assert!(state.next_poll_at() < before + FITBIT_RATE_LIMIT_BACKOFF);
+    }
+
+    /// What a poll that ran into a 401, refreshed and then succeeded returns, for an expired
+    /// access token, and a plain success with the new one
+    fn refreshed_on_401(request: FetchRequest) -> FetchResponse {
+        let token =
+            (request.cached_access_token.as_deref() == Some("expired")).then(|| TokenUpdate {
+                access_token: "fresh".to_string(),
+                expires_in: Duration::from_secs(28800),
+                refresh_token: Some("rotated".to_string()),
+            });
+        FetchResponse {
+            result: FetchResult::Ok {
+                rate: Some(70),
+                measured_at: None,
+                backfill: vec![],
+                resting: None,
+                token,
+            },
+            rate_limit: None,
+            profile: None,
+        }
+    }
+
+    #[test]
+    fn refresh_after_a_401_is_counted_once() {
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"fitbit_access_token": "expired", "fitbit_refresh_token": "refresh",
+                "fitbit_client_id": "client", "fitbit_visibility_debounce_ms": 0}"#,
+        )
+        .unwrap();
+        let mut state = FitbitState::scripted(refreshed_on_401);
+
+        state.update(&config, true);
+        assert_eq!(state.token_refreshes, 1);
+        assert!(state.last_poll_refreshed);
+
+        state.next_poll_at = Instant::now();
+        state.request_times.clear();
+        state.update(&config, true);
+        assert_eq!(state.token_refreshes, 1);
+        assert!(!state.last_poll_refreshed);
+        assert_eq!(state.access_token.as_deref(), Some("fresh"));
+    }
+
+    /// Refreshes when the poll has no access token to send, as with only a refresh token
+    fn refreshed_without_access_token(request: FetchRequest) -> FetchResponse {
+        let token = request.cached_access_token.is_none().then(|| {
+            assert_eq!(request.config_access_token, None);
+            assert_eq!(request.refresh_token.as_deref(), Some("refresh"));
+            TokenUpdate {
+                access_token: "fresh".to_string(),
+                expires_in: Duration::from_secs(28800),
+                refresh_token: Some("rotated".to_string()),
+            }
+        });
+        FetchResponse {
+            result: FetchResult::Ok {
+                rate: Some(64),
+                measured_at: None,
+                backfill: vec![],
+                resting: None,
+                token,
+            },
+            rate_limit: None,
+            profile: None,
+        }
+    }
+
+    #[test]
+    fn refresh_token_alone_is_enough_to_start() {
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"fitbit_refresh_token": "refresh", "fitbit_client_id": "client",
+                "fitbit_client_secret": "secret", "fitbit_visibility_debounce_ms": 0}"#,
+        )
+        .unwrap();
+        let mut state = FitbitState {
+            fetch: refreshed_without_access_token,
+            poll_inline: true,
+            persist_tokens: false,
+            ..FitbitState::from_config(&config).unwrap()
+        };
+        assert_eq!(state.access_token, None);
+
+        state.update(&config, true);
+        assert_eq!(state.last_rate(), Some(64));
+        assert_eq!(state.token_refreshes, 1);
+        assert_eq!(state.access_token.as_deref(), Some("fresh"));
+        assert_eq!(state.refresh_token.as_deref(), Some("rotated"));
+        // the ramp goes on as after any first poll
+        assert!(state.next_poll_at() > Instant::now());
+        assert_eq!(state.next_interval_index(), 1);
+
+        // the next poll uses the new access token
+        state.next_poll_at = Instant::now();
+        state.request_times.clear();
+        state.update(&config, true);
+        assert_eq!(state.token_refreshes, 1);
+        assert_eq!(state.access_token.as_deref(), Some("fresh"));
+    }
+
+    #[test]
+    fn no_step_of_the_ramp_is_below_the_minimum_interval() {
+        assert!(
+            FITBIT_POLL_INTERVALS
+                .iter()
+                .all(|interval| *interval >= FITBIT_MIN_REQUEST_INTERVAL)
+        );
+    }
+
+    #[test]
+    fn suspend_restarts_the_ramp() {
+        let now = Instant::now();
+        let wall = SystemTime::now();
+        let mut state = FitbitState {
+            next_poll_at: now + Duration::from_secs(300),
+            next_interval_index: FITBIT_POLL_INTERVALS.len() - 1,
+            access_token_expires_at: Some(now + Duration::from_secs(3600)),
+            access_token_expires_at_wall: Some(wall - Duration::from_secs(60)),
+            // two hours passed on the wall clock, none on the monotonic one
+            last_update_at: Some((now, wall - Duration::from_secs(7200))),
+            ..Default::default()
+        };
+
+        state.check_clock_jump();
+        assert!(state.next_poll_at() <= Instant::now());
+        assert_eq!(state.next_interval_index(), 0);
+        assert!(
+            state
+                .access_token_expires_at
+                .is_some_and(|at| at <= Instant::now())
+        );
+    }
+
+    #[test]
+    fn steady_clock_keeps_the_ramp() {
+        let scheduled = Instant::now() + Duration::from_secs(300);
+        let mut state = FitbitState {
+            next_poll_at: scheduled,
+            next_interval_index: FITBIT_POLL_INTERVALS.len() - 1,
+            last_update_at: Some((Instant::now(), SystemTime::now())),
+            ..Default::default()
+        };
+
+        state.check_clock_jump();
+        assert_eq!(state.next_poll_at(), scheduled);
+        assert_eq!(state.next_interval_index(), FITBIT_POLL_INTERVALS.len() - 1);
+    }
+
+    fn warmup_config(access_token: &str) -> GeneralConfig {
+        serde_json::from_value(serde_json::json!({
+            "fitbit_access_token": access_token,
+            "fitbit_visibility_debounce_ms": 0,
+            "fitbit_warmup_polls": 3,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn warmup_starts_once_per_credential_change() {
+        let (first, second) = (warmup_config("first"), warmup_config("second"));
+        let now = Instant::now();
+        let mut state = FitbitState::default();
+
+        state.start_warmup_on_new_credentials(&first, now);
+        assert_eq!(state.warmup_polls_left, 0);
+
+        state.start_warmup_on_new_credentials(&second, now);
+        assert_eq!(state.warmup_polls_left, 3);
+        state.warmup_polls_left = 0;
+        state.start_warmup_on_new_credentials(&second, now);
+        assert_eq!(state.warmup_polls_left, 0);
+
+        state.start_warmup_on_new_credentials(&first, now);
+        assert_eq!(state.warmup_polls_left, 3);
+    }
+
+    fn steady(_request: FetchRequest) -> FetchResponse {
+        FetchResponse {
+            result: FetchResult::Ok {
+                rate: Some(70),
+                measured_at: None,
+                backfill: vec![],
+                resting: None,
+                token: None,
+            },
+            rate_limit: None,
+            profile: None,
+        }
+    }
+
+    #[test]
+    fn showing_the_watch_doesnt_start_a_warmup() {
+        let config = warmup_config("first");
+        let mut state = FitbitState::scripted(steady);
+
+        for watch_visible in [true, false, true, false, true] {
+            state.credentials_checked_at = None;
+            state.next_poll_at = Instant::now();
+            state.request_times.clear();
+            state.update(&config, watch_visible);
+            assert_eq!(state.warmup_polls_left, 0);
+        }
+    }
+
+    #[test]
+    fn empty_today_falls_back_to_yesterday_after_midnight() {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"fitbit_midnight_grace_minutes": 15}"#).unwrap();
+        let http = HttpOptions::from_config(&config);
+        let today = NaiveDate::from_ymd_opt(2024, 5, 2).unwrap();
+        let yesterday = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        let at = |date: NaiveDate, h, m| date.and_hms_opt(h, m, 0).unwrap();
+
+        assert_eq!(
+            yesterday_fallback(&http, at(today, 0, 5), None),
+            Some((yesterday, None))
+        );
+        assert_eq!(
+            yesterday_fallback(&http, at(today, 0, 5), Some(at(yesterday, 23, 50))),
+            Some((
+                yesterday,
+                Some((
+                    NaiveTime::from_hms_opt(23, 50, 0).unwrap(),
+                    NaiveTime::from_hms_opt(23, 59, 59).unwrap()
+                ))
+            ))
+        );
+        // the lookback window stays within today
+        assert_eq!(
+            yesterday_fallback(&http, at(today, 0, 5), Some(at(today, 0, 1))),
+            None
+        );
+        assert_eq!(yesterday_fallback(&http, at(today, 0, 20), None), None);
+    }
+
+    #[test]
+    fn token_expiring_within_the_margin_is_refreshed_first() {
+        let now = Instant::now();
+        let margin = Duration::from_secs(60);
+
+        assert!(refresh_due(
+            Some(now + Duration::from_secs(30)),
+            margin,
+            now
+        ));
+        assert!(refresh_due(Some(now - Duration::from_secs(1)), margin, now));
+        assert!(!refresh_due(
+            Some(now + Duration::from_secs(120)),
+            margin,
+            now
+        ));
+        // a token from the config has no known expiry, a 401 tells
+        assert!(!refresh_due(None, margin, now));
+    }
+
+    #[test]
+    fn maintenance_page_backs_off() {
+        let config: GeneralConfig = serde_json::from_str("{}").unwrap();
+        let http = HttpOptions::from_config(&config);
+        let page =
+            b"\n<!DOCTYPE html><html><body><h1>Fitbit is down for maintenance</h1></body></html>";
+
+        let error = parse_json::<FitbitHeartResponse>(200, page).err().unwrap();
+        assert!(matches!(error, FitbitError::NotJson { status: 200 }));
+        assert!(matches!(
+            FitbitError::from_status(503, page, "Fitbit heart rate request failed", &http),
+            FitbitError::NotJson { status: 503 }
+        ));
+
+        let mut state = FitbitState::default();
+        let before = Instant::now();
+        state.inject_result(FetchResult::Err { error, token: None });
+        assert!(state.next_poll_at() >= before + FITBIT_OUTAGE_BACKOFF);
+    }
+
+    #[test]
+    fn invalid_grant_revokes_the_login() {
+        let config: GeneralConfig = serde_json::from_str("{}").unwrap();
+        let body = br#"{"errors": [{"errorType": "invalid_grant",
+            "message": "Refresh token invalid: abc"}], "success": false}"#;
+
+        let error = refresh_error(400, body, &HttpOptions::from_config(&config));
+        assert!(matches!(error, FitbitError::AuthRevoked));
+        assert!(matches!(
+            refresh_failed(error, None),
+            FetchResult::AuthRevoked
+        ));
+    }
+
+    static REVOKED_POLLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    fn revoked(_request: FetchRequest) -> FetchResponse {
+        REVOKED_POLLS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        FetchResponse {
+            result: FetchResult::AuthRevoked,
+            rate_limit: None,
+            profile: None,
+        }
+    }
+
+    #[test]
+    fn revoked_login_stops_polling() {
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"fitbit_refresh_token": "refresh", "fitbit_client_id": "client",
+                "fitbit_visibility_debounce_ms": 0, "fitbit_auth_error_tolerance": 1}"#,
+        )
+        .unwrap();
+        let mut state = FitbitState::scripted(revoked);
+
+        state.update(&config, true);
+        assert!(state.auth_revoked);
+        for _ in 0..3 {
+            state.next_poll_at = Instant::now();
+            state.request_times.clear();
+            state.update(&config, true);
+        }
+        assert_eq!(REVOKED_POLLS.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn one_spurious_terminal_error_is_tolerated() {
+        let mut state = FitbitState {
+            terminal_error_tolerance: 2,
+            terminal_error_window: Duration::from_secs(600),
+            ..Default::default()
+        };
+        let success = || FetchResult::Ok {
+            rate: Some(70),
+            measured_at: None,
+            backfill: vec![],
+            resting: None,
+            token: None,
+        };
+
+        state.inject_result(FetchResult::AuthRevoked);
+        assert!(!state.auth_revoked);
+        // a success in between starts the count over
+        state.inject_result(success());
+        state.inject_result(FetchResult::AuthRevoked);
+        assert!(!state.auth_revoked);
+        state.inject_result(FetchResult::AuthRevoked);
+        assert!(state.auth_revoked);
+    }
+
+    static FORBIDDEN_POLLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    fn forbidden(_request: FetchRequest) -> FetchResponse {
+        FORBIDDEN_POLLS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        FetchResponse {
+            result: FetchResult::ScopeMissing { token: None },
+            rate_limit: None,
+            profile: None,
+        }
+    }
+
+    #[test]
+    fn missing_scope_stops_polling_until_the_credentials_change() {
+        let config_with = |access_token: &str| -> GeneralConfig {
+            serde_json::from_value(serde_json::json!({
+                "fitbit_access_token": access_token,
+                "fitbit_visibility_debounce_ms": 0,
+                "fitbit_auth_error_tolerance": 1,
+            }))
+            .unwrap()
+        };
+        let polls = || FORBIDDEN_POLLS.load(std::sync::atomic::Ordering::Relaxed);
+        let mut state = FitbitState::scripted(forbidden);
+        let mut poll = |config: &GeneralConfig| {
+            state.next_poll_at = Instant::now();
+            state.request_times.clear();
+            state.update(config, true);
+        };
+
+        let config = config_with("no-heartrate-scope");
+        poll(&config);
+        assert_eq!(polls(), 1);
+        poll(&config);
+        poll(&config);
+        assert_eq!(polls(), 1);
+
+        poll(&config_with("reauthorized"));
+        assert_eq!(polls(), 2);
+    }
+
+    #[test]
+    fn token_rotated_before_a_failure_is_kept() {
+        let mut state = FitbitState {
+            access_token: Some("old-access".to_string()),
+            refresh_token: Some("old-refresh".to_string()),
+            persist_tokens: false,
+            ..Default::default()
+        };
+
+        state.inject_result(FetchResult::Err {
+            error: FitbitError::Transport("timed out".to_string()),
+            token: Some(TokenUpdate {
+                access_token: "new-access".to_string(),
+                expires_in: Duration::from_secs(28_800),
+                refresh_token: Some("new-refresh".to_string()),
+            }),
+        });
+        state.inject_result(FetchResult::Err {
+            error: FitbitError::Transport("timed out".to_string()),
+            token: None,
+        });
+
+        assert_eq!(state.access_token.as_deref(), Some("new-access"));
+        assert_eq!(state.refresh_token.as_deref(), Some("new-refresh"));
+        assert!(
+            state
+                .access_token_expires_at
+                .is_some_and(|expires_at| expires_at > Instant::now())
+        );
+    }
+}
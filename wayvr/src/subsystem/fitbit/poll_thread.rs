@@ -0,0 +1,115 @@
+use std::sync::mpsc;
+
+use super::{FetchFn, FetchRequest, FetchResponse, fetch_catching_panics};
+
+struct Job {
+    fetch: FetchFn,
+    request: FetchRequest,
+    reply: mpsc::Sender<FetchResponse>,
+}
+
+#[cfg(not(feature = "fitbit-async"))]
+type JobSender = mpsc::Sender<Job>;
+#[cfg(feature = "fitbit-async")]
+type JobSender = tokio::sync::mpsc::UnboundedSender<Job>;
+
+/// A single thread that makes the polls of one `FitbitState` one after another,
+/// instead of a new thread for every poll. With `fitbit-async` it runs a tokio loop
+/// that also gives up on a stuck poll, so it can't hold up the ones behind it.
+/// Dropping it ends the thread once the poll in flight is done. It isn't joined, that would
+/// hold up the shutdown until curl gives up, `flush_on_shutdown` already waited long enough.
+pub(super) struct PollThread {
+    jobs: Option<JobSender>,
+}
+
+impl PollThread {
+    pub(super) fn new() -> Self {
+        #[cfg(not(feature = "fitbit-async"))]
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        #[cfg(feature = "fitbit-async")]
+        let (jobs, receiver) = tokio::sync::mpsc::unbounded_channel::<Job>();
+
+        let spawned = std::thread::Builder::new()
+            .name("fitbit-poll".into())
+            .spawn(move || {
+                // ends once the sender is dropped along with the `PollThread`
+                run(receiver);
+                log::debug!("Fitbit poll thread stopped.");
+            })
+            .inspect_err(|e| log::error!("Could not start the Fitbit poll thread: {e:?}"));
+
+        Self {
+            jobs: spawned.ok().map(|_| jobs),
+        }
+    }
+
+    /// Queues a poll, the receiver gets its response once the thread is done with it
+    pub(super) fn spawn_fetch(
+        &self,
+        fetch: FetchFn,
+        request: FetchRequest,
+    ) -> mpsc::Receiver<FetchResponse> {
+        let (reply, receiver) = mpsc::channel();
+        let job = Job {
+            fetch,
+            request,
+            reply,
+        };
+
+        // a dropped job disconnects the receiver, so the poll counts as done
+        if let Some(jobs) = self.jobs.as_ref()
+            && jobs.send(job).is_err()
+        {
+            log::error!("Fitbit poll thread stopped, no more polls are made.");
+        }
+        receiver
+    }
+}
+
+#[cfg(not(feature = "fitbit-async"))]
+fn run(jobs: mpsc::Receiver<Job>) {
+    for Job {
+        fetch,
+        request,
+        reply,
+    } in jobs
+    {
+        let _ = reply.send(fetch_catching_panics(fetch, request));
+    }
+}
+
+#[cfg(feature = "fitbit-async")]
+fn run(mut jobs: tokio::sync::mpsc::UnboundedReceiver<Job>) {
+    // dropping the receiver makes further polls fail, which `spawn_fetch` logs
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            log::error!("Could not start the Fitbit poll runtime: {e:?}");
+            return;
+        }
+    };
+
+    runtime.block_on(async move {
+        while let Some(Job {
+            fetch,
+            request,
+            reply,
+        }) = jobs.recv().await
+        {
+            // curl gives up by itself, this only keeps a stuck request from blocking
+            // the polls behind it
+            let timeout = request.http.curl.timeout * (request.http.retries + 2);
+            let poll = tokio::task::spawn_blocking(move || fetch_catching_panics(fetch, request));
+            match tokio::time::timeout(timeout, poll).await {
+                Ok(Ok(response)) => {
+                    let _ = reply.send(response);
+                }
+                Ok(Err(e)) => log::error!("Fitbit poll failed: {e}"),
+                Err(_) => log::warn!("Fitbit poll did not finish in {timeout:?}."),
+            }
+        }
+    });
+}
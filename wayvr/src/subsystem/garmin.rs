@@ -0,0 +1,586 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError, channel};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Deserialize;
+use wlx_common::config::GeneralConfig;
+
+use crate::subsystem::{
+    heart_rate::{HeartRateSource, HrStatus, IdleReason, PollHealth},
+    http::{CurlOptions, HttpResponse, curl_with_form, curl_with_status},
+};
+
+const GARMIN_API_BASE_URL: &str = "https://apis.garmin.com/wellness-api/rest";
+const GARMIN_TOKEN_URL: &str = "https://diauth.garmin.com/di-oauth2-service/oauth/token";
+const GARMIN_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Garmin only has new samples after the watch synced, so there's no point in polling as often as Fitbit
+const GARMIN_POLL_INTERVALS: [Duration; 3] = [
+    Duration::from_secs(10),
+    Duration::from_secs(30),
+    Duration::from_secs(60),
+];
+
+/// The wellness API doesn't allow querying more than a day at once
+const GARMIN_LOOKBACK: Duration = Duration::from_secs(86400);
+
+/// Watches only upload every now and then, so a sample is trusted for a while longer than one of Fitbit
+const GARMIN_STALE_AFTER: Duration = Duration::from_secs(30 * 60);
+
+/// Shown as disconnected once this many polls in a row failed
+const GARMIN_DISCONNECTED_AFTER_FAILURES: u32 = 3;
+
+/// A refresh followed by the heart rate request, each may take the full timeout
+const GARMIN_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2 * GARMIN_REQUEST_TIMEOUT.as_secs());
+
+pub struct GarminState {
+    last_rate: Option<u32>,
+    last_read_at: Option<SystemTime>,
+    next_poll_at: Instant,
+    next_interval_index: usize,
+    /// Added to `next_poll_at` whenever the ramp restarts, see `set_poll_phase`
+    poll_phase: Duration,
+    last_watch_visible: bool,
+    polling_enabled: bool,
+    pending: Option<Receiver<FetchResult>>,
+    access_token: Option<String>,
+    access_token_expires_at: Option<Instant>,
+    refresh_token: Option<String>,
+    consecutive_failures: u32,
+    persist_tokens: bool,
+}
+
+impl Default for GarminState {
+    fn default() -> Self {
+        Self {
+            last_rate: None,
+            last_read_at: None,
+            next_poll_at: Instant::now(),
+            next_interval_index: 0,
+            poll_phase: Duration::ZERO,
+            last_watch_visible: false,
+            polling_enabled: true,
+            pending: None,
+            access_token: None,
+            access_token_expires_at: None,
+            refresh_token: None,
+            consecutive_failures: 0,
+            persist_tokens: true,
+        }
+    }
+}
+
+impl HeartRateSource for GarminState {
+    fn name(&self) -> &'static str {
+        "garmin"
+    }
+
+    fn update(&mut self, config: &GeneralConfig, watch_visible: bool) {
+        if let Some(receiver) = self.pending.as_ref() {
+            match receiver.try_recv() {
+                Ok(result) => {
+                    self.pending = None;
+                    self.handle_result(result);
+                }
+                Err(TryRecvError::Disconnected) => {
+                    self.pending = None;
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+
+        if !watch_visible || !self.polling_enabled {
+            self.last_watch_visible = false;
+            return;
+        }
+
+        if !self.last_watch_visible {
+            self.next_poll_at = Instant::now() + self.poll_phase;
+            self.next_interval_index = 0;
+            self.last_watch_visible = true;
+        }
+
+        let now = Instant::now();
+        if now < self.next_poll_at || self.pending.is_some() {
+            return;
+        }
+
+        let non_empty = |value: Option<&str>| {
+            value
+                .filter(|value| !value.trim().is_empty())
+                .map(|value| value.trim().to_string())
+        };
+
+        let request = FetchRequest {
+            access_token: self
+                .access_token
+                .clone()
+                .or_else(|| non_empty(config.garmin_access_token.as_deref())),
+            access_token_expires_at: self.access_token_expires_at,
+            // a rotated refresh token invalidates the one from the config
+            refresh_token: self
+                .refresh_token
+                .clone()
+                .or_else(|| non_empty(config.garmin_refresh_token.as_deref())),
+            client_id: non_empty(config.garmin_client_id.as_deref()),
+            client_secret: non_empty(config.garmin_client_secret.as_deref()),
+            curl: CurlOptions::from_config(config, GARMIN_REQUEST_TIMEOUT),
+        };
+
+        log::debug!("Garmin poll attempt.");
+        let interval = GARMIN_POLL_INTERVALS
+            .get(self.next_interval_index)
+            .copied()
+            .unwrap_or_else(|| *GARMIN_POLL_INTERVALS.last().unwrap());
+        self.next_poll_at = now + interval;
+        self.next_interval_index =
+            (self.next_interval_index + 1).min(GARMIN_POLL_INTERVALS.len() - 1);
+
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(fetch_latest_rate(request));
+        });
+        self.pending = Some(receiver);
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.last_rate
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        self.last_read_at
+    }
+
+    fn is_stale(&self) -> bool {
+        self.last_read_at
+            .is_none_or(|read_at| read_at.elapsed().unwrap_or_default() >= GARMIN_STALE_AFTER)
+    }
+
+    fn status(&self) -> HrStatus {
+        if self.consecutive_failures >= GARMIN_DISCONNECTED_AFTER_FAILURES {
+            return HrStatus::Disconnected;
+        }
+
+        match self.last_rate {
+            Some(rate) if self.is_stale() => HrStatus::Stale(rate),
+            Some(rate) => HrStatus::Live(rate),
+            None => HrStatus::Initializing,
+        }
+    }
+
+    fn set_polling_enabled(&mut self, enabled: bool) {
+        self.polling_enabled = enabled;
+    }
+
+    fn polling_enabled(&self) -> bool {
+        self.polling_enabled
+    }
+
+    fn idle_reason(&self) -> Option<IdleReason> {
+        if self.status() == HrStatus::Disconnected {
+            Some(IdleReason::Disconnected)
+        } else if !self.polling_enabled {
+            Some(IdleReason::Paused)
+        } else if !self.last_watch_visible {
+            Some(IdleReason::WatchHidden)
+        } else {
+            None
+        }
+    }
+
+    fn set_poll_phase(&mut self, phase: Duration) {
+        self.poll_phase = phase;
+    }
+
+    fn force_poll(&mut self) {
+        if self.pending.is_some() {
+            return;
+        }
+        self.next_poll_at = Instant::now();
+        self.next_interval_index = 0;
+    }
+
+    fn poll_in_flight(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    fn poll_health(&self) -> PollHealth {
+        PollHealth {
+            failures: self.consecutive_failures,
+            reconnects: 0,
+            next_poll_at: Some(PollHealth::wall_time(self.next_poll_at)),
+            token_expires_at: self.access_token_expires_at.map(PollHealth::wall_time),
+        }
+    }
+
+    /// Refresh tokens are rotated, so a refresh that is still running has to be saved.
+    fn flush_on_shutdown(&mut self) {
+        let Some(receiver) = self.pending.take() else {
+            return;
+        };
+
+        match receiver.recv_timeout(GARMIN_SHUTDOWN_TIMEOUT) {
+            Ok(result) => self.handle_result(result),
+            Err(RecvTimeoutError::Timeout) => {
+                log::warn!(
+                    "Garmin poll did not finish before shutdown, tokens it rotates are lost."
+                );
+            }
+            Err(RecvTimeoutError::Disconnected) => {}
+        }
+    }
+}
+
+impl GarminState {
+    fn handle_result(&mut self, result: FetchResult) {
+        match result {
+            FetchResult::Ok { sample, token } => {
+                // nothing was synced within the lookback, keep the last reading
+                if let Some((rate, read_at)) = sample {
+                    self.last_rate = Some(rate);
+                    self.last_read_at = Some(read_at);
+                }
+                if let Some(token) = token {
+                    self.apply_token_update(token);
+                }
+                self.consecutive_failures = 0;
+                log::debug!("Garmin poll success.");
+            }
+            FetchResult::Err { message, token } => {
+                if let Some(token) = token {
+                    self.apply_token_update(token);
+                }
+                self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+                log::warn!("Garmin poll failed: {message}");
+            }
+        }
+    }
+
+    fn apply_token_update(&mut self, update: TokenUpdate) {
+        self.access_token = Some(update.access_token);
+        self.access_token_expires_at = Some(Instant::now() + update.expires_in);
+        if let Some(refresh_token) = update.refresh_token {
+            self.refresh_token = Some(refresh_token);
+        }
+
+        if !self.persist_tokens {
+            return;
+        }
+        if let Some(access_token) = self.access_token.as_deref() {
+            let _ = crate::config::save_garmin_tokens(access_token, self.refresh_token.as_deref())
+                .inspect_err(|e| log::error!("Could not save Garmin tokens: {e:?}"));
+        }
+    }
+}
+
+enum FetchResult {
+    Ok {
+        /// The latest sample and when it was measured
+        sample: Option<(u32, SystemTime)>,
+        token: Option<TokenUpdate>,
+    },
+    Err {
+        message: String,
+        /// Set if the token was refreshed before the error happened
+        token: Option<TokenUpdate>,
+    },
+}
+
+struct TokenUpdate {
+    access_token: String,
+    expires_in: Duration,
+    refresh_token: Option<String>,
+}
+
+struct FetchRequest {
+    access_token: Option<String>,
+    access_token_expires_at: Option<Instant>,
+    refresh_token: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    curl: CurlOptions,
+}
+
+fn fetch_latest_rate(request: FetchRequest) -> FetchResult {
+    let curl = &request.curl;
+
+    let expired = request
+        .access_token_expires_at
+        .is_some_and(|expiry| Instant::now() >= expiry);
+    let mut token = request.access_token.clone();
+    let mut token_update = None;
+
+    if expired || token.is_none() {
+        match refresh_access_token(curl, &request) {
+            Ok(update) => {
+                token = Some(update.access_token.clone());
+                token_update = Some(update);
+            }
+            // an expired token is still worth a try, refreshing may not be set up
+            Err(err) if token.is_some() => log::debug!("Garmin refresh failed: {err}"),
+            Err(err) => {
+                return FetchResult::Err {
+                    message: err.to_string(),
+                    token: None,
+                };
+            }
+        }
+    }
+
+    let Some(token) = token else {
+        return FetchResult::Err {
+            message: "Garmin access token is missing".to_string(),
+            token: None,
+        };
+    };
+
+    match request_heart_rate(curl, &token) {
+        Ok(sample) => FetchResult::Ok {
+            sample,
+            token: token_update,
+        },
+        Err(err) => FetchResult::Err {
+            message: err.to_string(),
+            token: token_update,
+        },
+    }
+}
+
+fn request_heart_rate(
+    curl: &CurlOptions,
+    token: &str,
+) -> anyhow::Result<Option<(u32, SystemTime)>> {
+    let end = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+    let start = end.saturating_sub(GARMIN_LOOKBACK);
+    let url = format!(
+        "{GARMIN_API_BASE_URL}/dailies?uploadStartTimeInSeconds={}&uploadEndTimeInSeconds={}",
+        start.as_secs(),
+        end.as_secs()
+    );
+
+    let HttpResponse { status, body, .. } = curl_with_status(
+        curl,
+        &[],
+        vec![
+            "--header".into(),
+            format!("Authorization: Bearer {token}"),
+            "--header".into(),
+            "Accept: application/json".into(),
+            url,
+        ],
+    )?;
+
+    if status >= 400 {
+        return Err(anyhow::anyhow!(
+            "Garmin heart rate request failed ({status})"
+        ));
+    }
+
+    latest_sample(&body)
+}
+
+/// The newest heart rate sample of all dailies in `body`
+fn latest_sample(body: &[u8]) -> anyhow::Result<Option<(u32, SystemTime)>> {
+    let dailies: Vec<GarminDaily> = serde_json::from_slice(body)?;
+    Ok(dailies
+        .iter()
+        .flat_map(|daily| {
+            daily
+                .heart_rate_samples
+                .iter()
+                .filter_map(|(offset, rate)| {
+                    let offset = offset.parse::<u64>().ok()?;
+                    Some((daily.start_time + offset, *rate))
+                })
+        })
+        .max_by_key(|(time, _)| *time)
+        .map(|(time, rate)| (rate, SystemTime::UNIX_EPOCH + Duration::from_secs(time))))
+}
+
+fn refresh_access_token(curl: &CurlOptions, request: &FetchRequest) -> anyhow::Result<TokenUpdate> {
+    let form = refresh_form(request)?;
+    let HttpResponse { status, body, .. } = curl_with_form(
+        curl,
+        &[],
+        vec![
+            "--request".into(),
+            "POST".into(),
+            "--header".into(),
+            "Content-Type: application/x-www-form-urlencoded".into(),
+            GARMIN_TOKEN_URL.into(),
+        ],
+        &form,
+    )?;
+
+    if status >= 400 {
+        return Err(anyhow::anyhow!("Garmin refresh failed ({status})"));
+    }
+
+    let response: GarminTokenResponse = serde_json::from_slice(&body)?;
+    Ok(TokenUpdate {
+        access_token: response.access_token,
+        expires_in: Duration::from_secs(response.expires_in),
+        refresh_token: response.refresh_token,
+    })
+}
+
+/// The body of the refresh request, it holds the client secret and is sent on stdin
+fn refresh_form(request: &FetchRequest) -> anyhow::Result<String> {
+    let refresh_token = request
+        .refresh_token
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("Garmin refresh token is missing"))?;
+    let client_id = request
+        .client_id
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("Garmin client ID is missing"))?;
+    let client_secret = request
+        .client_secret
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("Garmin client secret is missing"))?;
+
+    Ok(format!(
+        "grant_type=refresh_token&client_id={client_id}&client_secret={client_secret}&refresh_token={refresh_token}"
+    ))
+}
+
+#[derive(Deserialize)]
+struct GarminDaily {
+    #[serde(rename = "startTimeInSeconds")]
+    start_time: u64,
+    /// Seconds since `start_time` mapped to the heart rate at that time
+    #[serde(rename = "timeOffsetHeartRateSamples", default)]
+    heart_rate_samples: HashMap<String, u32>,
+}
+
+#[derive(Deserialize)]
+struct GarminTokenResponse {
+    access_token: String,
+    expires_in: u64,
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystem::http::DEFAULT_CURL;
+
+    fn offline_state() -> GarminState {
+        GarminState {
+            persist_tokens: false,
+            ..Default::default()
+        }
+    }
+
+    fn request(client_secret: Option<&str>) -> FetchRequest {
+        FetchRequest {
+            access_token: None,
+            access_token_expires_at: None,
+            refresh_token: Some("refresh".into()),
+            client_id: Some("client".into()),
+            client_secret: client_secret.map(Into::into),
+            curl: CurlOptions {
+                binary: DEFAULT_CURL.into(),
+                timeout: GARMIN_REQUEST_TIMEOUT,
+                proxy: None,
+                ca_cert: None,
+                insecure: false,
+                headers: vec![],
+            },
+        }
+    }
+
+    #[test]
+    fn latest_sample_is_the_newest_of_all_dailies() {
+        let body = br#"[
+            {"startTimeInSeconds": 1000, "timeOffsetHeartRateSamples": {"0": 60, "30": 62}},
+            {"startTimeInSeconds": 2000, "timeOffsetHeartRateSamples": {"15": 75, "x": 99}},
+            {"startTimeInSeconds": 3000}
+        ]"#;
+
+        let (rate, read_at) = latest_sample(body).unwrap().unwrap();
+        assert_eq!(rate, 75);
+        assert_eq!(read_at, SystemTime::UNIX_EPOCH + Duration::from_secs(2015));
+    }
+
+    #[test]
+    fn latest_sample_is_none_without_samples() {
+        assert!(latest_sample(b"[]").unwrap().is_none());
+    }
+
+    #[test]
+    fn refresh_form_carries_the_secret() {
+        let form = refresh_form(&request(Some("secret"))).unwrap();
+        assert_eq!(
+            form,
+            "grant_type=refresh_token&client_id=client&client_secret=secret&refresh_token=refresh"
+        );
+        assert!(refresh_form(&request(None)).is_err());
+    }
+
+    #[test]
+    fn status_follows_readings_and_failures() {
+        let mut state = offline_state();
+        assert_eq!(state.status(), HrStatus::Initializing);
+
+        state.handle_result(FetchResult::Ok {
+            sample: Some((70, SystemTime::now())),
+            token: None,
+        });
+        assert_eq!(state.status(), HrStatus::Live(70));
+        assert!(!state.is_stale());
+
+        state.last_read_at = Some(SystemTime::now() - GARMIN_STALE_AFTER);
+        assert_eq!(state.status(), HrStatus::Stale(70));
+
+        for _ in 0..GARMIN_DISCONNECTED_AFTER_FAILURES {
+            state.handle_result(FetchResult::Err {
+                message: "offline".into(),
+                token: None,
+            });
+        }
+        assert_eq!(state.status(), HrStatus::Disconnected);
+        assert_eq!(state.idle_reason(), Some(IdleReason::Disconnected));
+        assert_eq!(
+            state.poll_health().failures,
+            GARMIN_DISCONNECTED_AFTER_FAILURES
+        );
+    }
+
+    #[test]
+    fn empty_poll_keeps_the_last_reading() {
+        let mut state = offline_state();
+        state.handle_result(FetchResult::Ok {
+            sample: Some((64, SystemTime::now())),
+            token: None,
+        });
+        state.handle_result(FetchResult::Ok {
+            sample: None,
+            token: None,
+        });
+        assert_eq!(state.last_rate(), Some(64));
+    }
+
+    #[test]
+    fn flush_on_shutdown_keeps_rotated_tokens() {
+        let mut state = offline_state();
+        let (sender, receiver) = channel();
+        sender
+            .send(FetchResult::Err {
+                message: "heart rate request failed".into(),
+                token: Some(TokenUpdate {
+                    access_token: "access".into(),
+                    expires_in: Duration::from_secs(3600),
+                    refresh_token: Some("rotated".into()),
+                }),
+            })
+            .unwrap();
+        state.pending = Some(receiver);
+
+        state.flush_on_shutdown();
+        assert!(!state.poll_in_flight());
+        assert_eq!(state.access_token.as_deref(), Some("access"));
+        assert_eq!(state.refresh_token.as_deref(), Some("rotated"));
+    }
+}
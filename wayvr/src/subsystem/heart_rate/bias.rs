@@ -0,0 +1,47 @@
+use std::ops::RangeInclusive;
+
+use wlx_common::config::GeneralConfig;
+
+use super::HeartRateSource;
+
+/// Adds `hr_bias` of the source to its readings, for sensors that read consistently high or low
+/// compared to others. The result stays within `hr_min_bpm..=hr_max_bpm`.
+pub struct BiasedHeartRate {
+    inner: Box<dyn HeartRateSource>,
+    bias: i32,
+    range: RangeInclusive<u32>,
+}
+
+impl BiasedHeartRate {
+    pub fn new(inner: Box<dyn HeartRateSource>, bias: i32) -> Self {
+        log::info!(
+            "Heart rate of {} is corrected by {bias:+} BPM, see hr_bias.",
+            inner.name()
+        );
+        Self {
+            inner,
+            bias,
+            range: 0..=u32::MAX,
+        }
+    }
+}
+
+impl HeartRateSource for BiasedHeartRate {
+    fn inner(&self) -> Option<&dyn HeartRateSource> {
+        Some(self.inner.as_ref())
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn HeartRateSource> {
+        Some(self.inner.as_mut())
+    }
+
+    fn update(&mut self, config: &GeneralConfig, watch_visible: bool) {
+        self.inner.update(config, watch_visible);
+        self.range = config.hr_min_bpm..=config.hr_max_bpm.max(config.hr_min_bpm);
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        let rate = self.inner.last_rate()?.saturating_add_signed(self.bias);
+        Some(rate.clamp(*self.range.start(), *self.range.end()))
+    }
+}
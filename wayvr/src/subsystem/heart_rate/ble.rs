@@ -0,0 +1,684 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, bail};
+use dbus::{
+    Message,
+    arg::PropMap,
+    blocking::{
+        Connection,
+        stdintf::org_freedesktop_dbus::{ObjectManager, Properties, PropertiesPropertiesChanged},
+    },
+};
+use wlx_common::config::GeneralConfig;
+
+use super::{HeartRateSource, PollHealth, log_packet, rmssd, rr_bpm};
+
+const BLUEZ: &str = "org.bluez";
+const DEVICE_INTERFACE: &str = "org.bluez.Device1";
+const CHARACTERISTIC_INTERFACE: &str = "org.bluez.GattCharacteristic1";
+const HEART_RATE_SERVICE_UUID: &str = "0000180d-0000-1000-8000-00805f9b34fb";
+const HEART_RATE_MEASUREMENT_UUID: &str = "00002a37-0000-1000-8000-00805f9b34fb";
+/// Battery Level (0x2A19) of the Battery Service (0x180F)
+const BATTERY_LEVEL_UUID: &str = "00002a19-0000-1000-8000-00805f9b34fb";
+
+const DBUS_TIMEOUT: Duration = Duration::from_secs(10);
+const SERVICE_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(15);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// The delay doubles with every attempt in a row that didn't get a measurement, up to this
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// The battery drains slowly, reading it rarely keeps the link free for measurements
+const BATTERY_POLL_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Number of RR intervals to compute HRV over
+const RR_WINDOW: usize = 30;
+
+/// Heart rate from a Bluetooth LE strap exposing the standard Heart Rate Service, via BlueZ.
+pub struct BleHeartRate {
+    receiver: mpsc::Receiver<BleEvent>,
+    running: Arc<AtomicBool>,
+    last_rate: Option<u32>,
+    last_read_at: Option<SystemTime>,
+    last_hrv: Option<f32>,
+    rr_intervals: VecDeque<f32>,
+    battery_percent: Option<u8>,
+    /// Address of the strap the readings come from
+    device: Option<String>,
+    /// Subscriptions that were lost or restarted by the watchdog
+    reconnects: u32,
+    /// Attempts in a row that didn't get a measurement
+    failures: u32,
+}
+
+impl BleHeartRate {
+    /// Connects to the first reachable device of `addresses`, in order of priority,
+    /// or to the first known device that advertises the Heart Rate Service if there are none.
+    /// Once the device is lost for `switch_after`, the others get a chance again.
+    /// `log_packets` logs every measurement as received, before it is decoded.
+    /// With `watchdog`, a subscription that got no notification for that long is restarted.
+    pub fn new(
+        addresses: Vec<String>,
+        switch_after: Duration,
+        watchdog: Option<Duration>,
+        log_packets: bool,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let worker_running = running.clone();
+        std::thread::spawn(move || {
+            run_worker(
+                &addresses,
+                switch_after,
+                watchdog,
+                log_packets,
+                &sender,
+                &worker_running,
+            );
+            log::info!("BLE heart rate worker stopped.");
+        });
+
+        Self {
+            receiver,
+            running,
+            last_rate: None,
+            last_read_at: None,
+            last_hrv: None,
+            rr_intervals: VecDeque::with_capacity(RR_WINDOW),
+            battery_percent: None,
+            device: None,
+            reconnects: 0,
+            failures: 0,
+        }
+    }
+}
+
+impl HeartRateSource for BleHeartRate {
+    fn name(&self) -> &'static str {
+        "ble"
+    }
+
+    fn update(&mut self, config: &GeneralConfig, _watch_visible: bool) {
+        for event in self.receiver.try_iter() {
+            match event {
+                BleEvent::Measurement(measurement) => {
+                    let has_rr = !measurement.rr_intervals.is_empty();
+                    for rr in measurement.rr_intervals {
+                        if self.rr_intervals.len() == RR_WINDOW {
+                            self.rr_intervals.pop_front();
+                        }
+                        self.rr_intervals.push_back(rr);
+                    }
+                    let rr_intervals = self.rr_intervals.make_contiguous();
+                    if let Some(hrv) = rmssd(rr_intervals) {
+                        self.last_hrv = Some(hrv);
+                    }
+
+                    // the reported rate where the strap sent no RR intervals this time
+                    let average = config.hr_ble_rr_average.min(rr_intervals.len());
+                    let rr_rate = if has_rr && average > 0 {
+                        rr_bpm(&rr_intervals[rr_intervals.len() - average..])
+                    } else {
+                        None
+                    };
+                    self.last_rate = Some(rr_rate.unwrap_or(measurement.rate));
+                    self.last_read_at = Some(SystemTime::now());
+                    self.failures = 0;
+                }
+                BleEvent::Battery(percent) => self.battery_percent = Some(percent),
+                BleEvent::Connected(address) => self.device = Some(address),
+                BleEvent::Disconnected => {
+                    if self.device.is_some() {
+                        self.reconnects += 1;
+                    }
+                    if self.last_rate.is_none() {
+                        self.failures += 1;
+                    }
+                    self.device = None;
+                    self.last_rate = None;
+                    self.last_read_at = None;
+                    self.last_hrv = None;
+                    self.rr_intervals.clear();
+                    self.battery_percent = None;
+                }
+            }
+        }
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.last_rate
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        self.last_read_at
+    }
+
+    fn last_hrv(&self) -> Option<f32> {
+        self.last_hrv
+    }
+
+    fn battery_percent(&self) -> Option<u8> {
+        self.battery_percent
+    }
+
+    fn device(&self) -> Option<&str> {
+        self.device.as_deref()
+    }
+
+    fn poll_health(&self) -> PollHealth {
+        PollHealth {
+            failures: self.failures,
+            reconnects: self.reconnects,
+            ..PollHealth::default()
+        }
+    }
+}
+
+impl Drop for BleHeartRate {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+enum BleEvent {
+    /// Subscribed to the device with this address
+    Connected(String),
+    Measurement(HeartRateMeasurement),
+    Battery(u8),
+    Disconnected,
+}
+
+/// A decoded Heart Rate Measurement characteristic (0x2A37)
+pub struct HeartRateMeasurement {
+    pub rate: u32,
+    /// RR intervals in milliseconds
+    pub rr_intervals: Vec<f32>,
+}
+
+impl HeartRateMeasurement {
+    const FLAG_RATE_U16: u8 = 0x01;
+    const FLAG_ENERGY_EXPENDED: u8 = 0x08;
+    const FLAG_RR_INTERVALS: u8 = 0x10;
+
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let flags = *data.first()?;
+        let mut offset = 1;
+
+        let rate = if flags & Self::FLAG_RATE_U16 == 0 {
+            offset += 1;
+            u32::from(*data.get(1)?)
+        } else {
+            offset += 2;
+            u32::from(u16::from_le_bytes([*data.get(1)?, *data.get(2)?]))
+        };
+
+        if flags & Self::FLAG_ENERGY_EXPENDED != 0 {
+            offset += 2;
+        }
+
+        let mut rr_intervals = vec![];
+        if flags & Self::FLAG_RR_INTERVALS != 0 {
+            // RR intervals are transmitted in units of 1/1024 seconds
+            while let Some(bytes) = data.get(offset..offset + 2) {
+                let rr = u16::from_le_bytes([bytes[0], bytes[1]]);
+                rr_intervals.push(f32::from(rr) * 1000. / 1024.);
+                offset += 2;
+            }
+        }
+
+        Some(Self { rate, rr_intervals })
+    }
+}
+
+fn run_worker(
+    addresses: &[String],
+    switch_after: Duration,
+    watchdog: Option<Duration>,
+    log_packets: bool,
+    sender: &mpsc::Sender<BleEvent>,
+    running: &AtomicBool,
+) {
+    // the device that was lost last, and when. It's the only one tried until `switch_after`
+    // runs out, so a strap that briefly drops out doesn't lose to one of lower priority.
+    let mut lost: Option<(String, Instant)> = None;
+    let mut failures = 0;
+
+    while running.load(Ordering::Relaxed) {
+        let candidates = match lost.as_ref() {
+            Some((address, lost_at)) if lost_at.elapsed() < switch_after => {
+                std::slice::from_ref(address)
+            }
+            _ => addresses,
+        };
+
+        let mut subscription = Subscription::default();
+        if let Err(e) = subscribe(
+            candidates,
+            watchdog,
+            log_packets,
+            sender,
+            running,
+            &mut subscription,
+        ) {
+            log::warn!("BLE heart rate: {e:?}");
+        }
+        if let Some(address) = subscription.address {
+            lost = Some((address, Instant::now()));
+        }
+        // a wedged stack may take a few attempts to come back, don't hammer it meanwhile
+        if subscription.measured {
+            failures = 0;
+        } else {
+            failures += 1;
+        }
+
+        if sender.send(BleEvent::Disconnected).is_err() {
+            return;
+        }
+
+        let retry_at = Instant::now() + reconnect_delay(failures);
+        while running.load(Ordering::Relaxed) && Instant::now() < retry_at {
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
+
+/// `RECONNECT_DELAY`, doubled for every attempt in a row after the first that didn't get a
+/// measurement
+fn reconnect_delay(failures: u32) -> Duration {
+    RECONNECT_DELAY
+        .saturating_mul(1 << failures.saturating_sub(1).min(4))
+        .min(MAX_RECONNECT_DELAY)
+}
+
+/// Tells when a subscription went without notifications for longer than `timeout`
+struct NotificationWatchdog {
+    timeout: Option<Duration>,
+    notified_at: Instant,
+}
+
+impl NotificationWatchdog {
+    const fn new(timeout: Option<Duration>, now: Instant) -> Self {
+        Self {
+            timeout,
+            notified_at: now,
+        }
+    }
+
+    /// Whether the subscription is considered stuck, `notified` if one arrived since the last check
+    fn expired(&mut self, notified: bool, now: Instant) -> bool {
+        if notified {
+            self.notified_at = now;
+        }
+        self.timeout
+            .is_some_and(|timeout| now.duration_since(self.notified_at) >= timeout)
+    }
+}
+
+/// How far `subscribe` got
+#[derive(Default)]
+struct Subscription {
+    /// Of the device, once notifications are on
+    address: Option<String>,
+    /// Whether a measurement arrived
+    measured: bool,
+}
+
+/// Fills in `subscription` as it goes.
+/// Returns without an error if none of the candidates is around, that's just disconnected.
+/// Notifications can stop without a disconnect when the Bluetooth stack wedges, so with
+/// `watchdog` it gives up once none arrived for that long, for the worker to start over.
+fn subscribe(
+    candidates: &[String],
+    watchdog: Option<Duration>,
+    log_packets: bool,
+    sender: &mpsc::Sender<BleEvent>,
+    running: &AtomicBool,
+    subscription: &mut Subscription,
+) -> anyhow::Result<()> {
+    let connection = Connection::new_system().context("Could not connect to system bus")?;
+
+    let Some((device_path, address)) = connect_first(&connection, candidates)? else {
+        log::debug!("BLE heart rate: none of {candidates:?} is reachable");
+        return Ok(());
+    };
+    let device = connection.with_proxy(BLUEZ, device_path.clone(), DBUS_TIMEOUT);
+
+    let device_connected = Arc::new(AtomicBool::new(true));
+    let flag = device_connected.clone();
+    device.match_signal(
+        move |changed: PropertiesPropertiesChanged, _: &Connection, _: &Message| {
+            if changed.interface_name == DEVICE_INTERFACE
+                && let Some(value) = changed.changed_properties.get("Connected")
+                && dbus::arg::cast::<bool>(&*value.0) == Some(&false)
+            {
+                flag.store(false, Ordering::Relaxed);
+            }
+            true
+        },
+    )?;
+
+    let characteristic_path = find_characteristic(&connection, &device_path)?;
+    // services are resolved by now, so the battery level is either there or not provided at all
+    let battery_path = lookup_characteristic(&connection, &device_path, BATTERY_LEVEL_UUID)?;
+    if battery_path.is_none() {
+        log::debug!("BLE heart rate: {device_path} does not report its battery level");
+    }
+    let characteristic = connection.with_proxy(BLUEZ, characteristic_path, DBUS_TIMEOUT);
+
+    let measurement_sender = sender.clone();
+    let notified = Arc::new(AtomicBool::new(false));
+    let notified_flag = notified.clone();
+    characteristic.match_signal(
+        move |changed: PropertiesPropertiesChanged, _: &Connection, _: &Message| {
+            if changed.interface_name == CHARACTERISTIC_INTERFACE
+                && let Some(value) = changed.changed_properties.get("Value")
+                && let Some(bytes) = value.0.as_iter().and_then(|iter| {
+                    iter.map(|byte| byte.as_u64().map(|byte| byte as u8))
+                        .collect::<Option<Vec<u8>>>()
+                })
+            {
+                if log_packets {
+                    log_packet("BLE heart rate", &bytes);
+                }
+                notified_flag.store(true, Ordering::Relaxed);
+                if let Some(measurement) = HeartRateMeasurement::parse(&bytes) {
+                    let _ = measurement_sender.send(BleEvent::Measurement(measurement));
+                }
+            }
+            true
+        },
+    )?;
+
+    let result: Result<(), dbus::Error> =
+        characteristic.method_call(CHARACTERISTIC_INTERFACE, "StartNotify", ());
+    result.context("Could not subscribe to heart rate notifications")?;
+    log::info!("BLE heart rate: subscribed to {address} at {device_path}");
+    let _ = sender.send(BleEvent::Connected(address.clone()));
+    subscription.address = Some(address);
+
+    let mut next_battery_read = Instant::now();
+    let mut notification_watchdog = NotificationWatchdog::new(watchdog, Instant::now());
+    while running.load(Ordering::Relaxed) && device_connected.load(Ordering::Relaxed) {
+        let was_notified = notified.swap(false, Ordering::Relaxed);
+        subscription.measured |= was_notified;
+        if notification_watchdog.expired(was_notified, Instant::now()) {
+            let _: Result<(), dbus::Error> =
+                characteristic.method_call(CHARACTERISTIC_INTERFACE, "StopNotify", ());
+            bail!(
+                "No notification from {device_path} for {}s, subscribing again",
+                watchdog.unwrap_or_default().as_secs()
+            );
+        }
+
+        if let Some(battery_path) = battery_path.as_ref()
+            && Instant::now() >= next_battery_read
+        {
+            next_battery_read = Instant::now() + BATTERY_POLL_INTERVAL;
+            let battery = connection.with_proxy(BLUEZ, battery_path, DBUS_TIMEOUT);
+            let result: Result<(Vec<u8>,), dbus::Error> =
+                battery.method_call(CHARACTERISTIC_INTERFACE, "ReadValue", (PropMap::new(),));
+            match result {
+                Ok((value,)) => {
+                    if let Some(percent) = value.first() {
+                        let _ = sender.send(BleEvent::Battery((*percent).min(100)));
+                    }
+                }
+                Err(e) => log::debug!("BLE heart rate: could not read battery level: {e}"),
+            }
+        }
+
+        connection.process(Duration::from_millis(500))?;
+    }
+
+    let _: Result<(), dbus::Error> =
+        characteristic.method_call(CHARACTERISTIC_INTERFACE, "StopNotify", ());
+
+    if !device_connected.load(Ordering::Relaxed) {
+        bail!("Device {device_path} disconnected");
+    }
+    Ok(())
+}
+
+/// Connects to the first of `candidates` that BlueZ knows and that accepts the connection.
+/// Without candidates, falls back to the first paired heart rate device.
+fn connect_first(
+    connection: &Connection,
+    candidates: &[String],
+) -> anyhow::Result<Option<(dbus::Path<'static>, String)>> {
+    if candidates.is_empty() {
+        let (path, address) = find_device(connection, None)?;
+        connect(connection, &path)?;
+        return Ok(Some((path, address)));
+    }
+
+    for candidate in candidates {
+        let result = find_device(connection, Some(candidate))
+            .and_then(|(path, address)| connect(connection, &path).map(|()| (path, address)));
+        match result {
+            Ok(device) => return Ok(Some(device)),
+            Err(e) => log::debug!("BLE heart rate: skipping {candidate}: {e:?}"),
+        }
+    }
+    Ok(None)
+}
+
+fn connect(connection: &Connection, device_path: &dbus::Path<'static>) -> anyhow::Result<()> {
+    let device = connection.with_proxy(BLUEZ, device_path, DBUS_TIMEOUT);
+
+    let connected: bool = device.get(DEVICE_INTERFACE, "Connected")?;
+    if !connected {
+        log::info!("BLE heart rate: connecting to {device_path}");
+        let result: Result<(), dbus::Error> = device.method_call(DEVICE_INTERFACE, "Connect", ());
+        result.context("Could not connect to device")?;
+    }
+    Ok(())
+}
+
+/// The path and address of the device
+fn find_device(
+    connection: &Connection,
+    address: Option<&str>,
+) -> anyhow::Result<(dbus::Path<'static>, String)> {
+    let root = connection.with_proxy(BLUEZ, "/", DBUS_TIMEOUT);
+
+    for (path, interfaces) in root.get_managed_objects()? {
+        let Some(device) = interfaces.get(DEVICE_INTERFACE) else {
+            continue;
+        };
+
+        let Some(device_address) = dbus::arg::prop_cast::<String>(device, "Address") else {
+            continue;
+        };
+
+        let matches = match address {
+            Some(address) => device_address.eq_ignore_ascii_case(address),
+            None => prop_strings(device, "UUIDs")
+                .iter()
+                .any(|uuid| uuid.eq_ignore_ascii_case(HEART_RATE_SERVICE_UUID)),
+        };
+
+        if matches {
+            return Ok((path, device_address.clone()));
+        }
+    }
+
+    match address {
+        Some(address) => bail!("Device {address} is not known to BlueZ. Pair it first."),
+        None => bail!("No paired device with a heart rate service found."),
+    }
+}
+
+fn find_characteristic(
+    connection: &Connection,
+    device_path: &dbus::Path<'static>,
+) -> anyhow::Result<dbus::Path<'static>> {
+    let deadline = Instant::now() + SERVICE_DISCOVERY_TIMEOUT;
+
+    // services are resolved asynchronously after connecting
+    loop {
+        if let Some(path) =
+            lookup_characteristic(connection, device_path, HEART_RATE_MEASUREMENT_UUID)?
+        {
+            return Ok(path);
+        }
+
+        if Instant::now() >= deadline {
+            bail!("Device {device_path} does not provide heart rate measurements");
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+}
+
+fn lookup_characteristic(
+    connection: &Connection,
+    device_path: &dbus::Path<'static>,
+    uuid: &str,
+) -> anyhow::Result<Option<dbus::Path<'static>>> {
+    let root = connection.with_proxy(BLUEZ, "/", DBUS_TIMEOUT);
+    let device_prefix: &str = device_path;
+
+    for (path, interfaces) in root.get_managed_objects()? {
+        if !path.starts_with(device_prefix) {
+            continue;
+        }
+
+        if let Some(characteristic) = interfaces.get(CHARACTERISTIC_INTERFACE)
+            && dbus::arg::prop_cast::<String>(characteristic, "UUID")
+                .is_some_and(|characteristic_uuid| characteristic_uuid.eq_ignore_ascii_case(uuid))
+        {
+            return Ok(Some(path));
+        }
+    }
+
+    Ok(None)
+}
+
+fn prop_strings(props: &PropMap, key: &str) -> Vec<String> {
+    props
+        .get(key)
+        .and_then(|value| value.0.as_iter())
+        .map(|iter| {
+            iter.filter_map(|item| item.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rr_intervals_follow_the_flags() {
+        // 1024 units of 1/1024s each, after a u8 rate
+        let measurement = HeartRateMeasurement::parse(&[0x10, 72, 0x00, 0x04, 0x00, 0x04]).unwrap();
+        assert_eq!(measurement.rate, 72);
+        assert_eq!(measurement.rr_intervals, [1000., 1000.]);
+        assert_eq!(rr_bpm(&measurement.rr_intervals), Some(60));
+
+        // u16 rate and energy expended before the interval
+        let measurement =
+            HeartRateMeasurement::parse(&[0x19, 0x2c, 0x01, 0xff, 0x00, 0x00, 0x02]).unwrap();
+        assert_eq!(measurement.rate, 300);
+        assert_eq!(measurement.rr_intervals, [500.]);
+
+        let measurement = HeartRateMeasurement::parse(&[0x00, 72, 0x00, 0x04]).unwrap();
+        assert!(measurement.rr_intervals.is_empty());
+    }
+
+    #[test]
+    fn captured_polar_h10_payload() {
+        // flags: sensor contact detected, RR intervals present
+        let measurement =
+            HeartRateMeasurement::parse(&[0x16, 0x4f, 0x4d, 0x03, 0x58, 0x03]).unwrap();
+        assert_eq!(measurement.rate, 79);
+        assert_eq!(measurement.rr_intervals.len(), 2);
+        assert!((measurement.rr_intervals[0] - 825.195).abs() < 0.01);
+        assert!((measurement.rr_intervals[1] - 835.938).abs() < 0.01);
+        assert_eq!(rr_bpm(&measurement.rr_intervals), Some(72));
+    }
+
+    #[test]
+    fn watchdog_fires_once_notifications_stop() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut watchdog = NotificationWatchdog::new(Some(Duration::from_secs(3)), start);
+
+        // a strap notifying every second, then freezing after 10s
+        for secs in 1..=10 {
+            assert!(!watchdog.expired(true, at(secs)));
+        }
+        assert!(!watchdog.expired(false, at(11)));
+        assert!(!watchdog.expired(false, at(12)));
+        assert!(watchdog.expired(false, at(13)));
+
+        let mut disabled = NotificationWatchdog::new(None, start);
+        assert!(!disabled.expired(false, at(3600)));
+    }
+
+    #[test]
+    fn reconnects_back_off_while_they_fail() {
+        let delays: Vec<u64> = (0..=7)
+            .map(|failures| reconnect_delay(failures).as_secs())
+            .collect();
+        assert_eq!(delays, [5, 5, 10, 20, 40, 60, 60, 60]);
+    }
+
+    #[test]
+    fn restarted_subscriptions_are_counted() {
+        let (sender, receiver) = mpsc::channel();
+        let mut ble = BleHeartRate {
+            receiver,
+            running: Arc::new(AtomicBool::new(true)),
+            last_rate: None,
+            last_read_at: None,
+            last_hrv: None,
+            rr_intervals: VecDeque::new(),
+            battery_percent: None,
+            device: None,
+            reconnects: 0,
+            failures: 0,
+        };
+        let config: GeneralConfig = serde_json::from_str("{}").unwrap();
+        let measurement = || {
+            BleEvent::Measurement(HeartRateMeasurement {
+                rate: 72,
+                rr_intervals: vec![],
+            })
+        };
+
+        // subscribed and measuring until the watchdog restarts it
+        for event in [
+            BleEvent::Connected("strap".into()),
+            measurement(),
+            BleEvent::Disconnected,
+        ] {
+            sender.send(event).unwrap();
+        }
+        ble.update(&config, true);
+        assert_eq!(ble.last_rate(), None);
+        assert_eq!(ble.poll_health().reconnects, 1);
+        assert_eq!(ble.poll_health().failures, 0);
+
+        // the new subscription stays silent as well
+        sender.send(BleEvent::Connected("strap".into())).unwrap();
+        sender.send(BleEvent::Disconnected).unwrap();
+        ble.update(&config, true);
+        assert_eq!(ble.poll_health().reconnects, 2);
+        assert_eq!(ble.poll_health().failures, 1);
+
+        sender.send(BleEvent::Connected("strap".into())).unwrap();
+        sender.send(measurement()).unwrap();
+        ble.update(&config, true);
+        assert_eq!(ble.last_rate(), Some(72));
+        assert_eq!(ble.poll_health().failures, 0);
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use super::HeartRateSource;
+
+#[derive(Default)]
+struct Shared {
+    /// Counts the published changes, so subscribers can tell which ones they saw
+    version: u64,
+    /// The displayed rate of the latest reading, after smoothing
+    rate: Option<u32>,
+    /// Sources that can't tell are fresh on every update, so readings are told apart by this
+    read_at: Option<SystemTime>,
+}
+
+/// Watch style channel from the source loop to output threads, so no output depends on polling
+/// of its own. The source loop publishes once per accepted reading and once the source lost its
+/// reading. Subscribers only see the latest one, a lagging subscriber skips those in between.
+#[derive(Default)]
+pub struct ReadingBroadcast {
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+}
+
+impl ReadingBroadcast {
+    /// Publishes the new reading of `source` if it got one, after `update`
+    pub fn tick(&self, source: &dyn HeartRateSource) {
+        let (shared, changed) = &*self.shared;
+        let mut shared = shared.lock().unwrap();
+
+        let rate = source.smoothed_rate();
+        let read_at = source.last_read_at();
+        let accepted = source.is_fresh() && read_at != shared.read_at;
+        let lost = rate.is_none() && shared.rate.is_some();
+        if !accepted && !lost {
+            return;
+        }
+
+        shared.version += 1;
+        shared.rate = rate;
+        shared.read_at = read_at;
+        changed.notify_all();
+    }
+
+    pub fn subscribe(&self) -> ReadingSubscriber {
+        ReadingSubscriber {
+            shared: self.shared.clone(),
+            seen: 0,
+        }
+    }
+}
+
+pub struct ReadingSubscriber {
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+    seen: u64,
+}
+
+impl ReadingSubscriber {
+    /// Waits up to `timeout` for a change since the last call. The inner option is None once the
+    /// source lost its reading.
+    pub fn wait_changed(&mut self, timeout: Duration) -> Option<Option<u32>> {
+        let (shared, changed) = &*self.shared;
+        let shared = shared.lock().unwrap();
+        let (shared, _) = changed
+            .wait_timeout_while(shared, timeout, |shared| shared.version == self.seen)
+            .unwrap();
+
+        if shared.version == self.seen {
+            return None;
+        }
+        self.seen = shared.version;
+        Some(shared.rate)
+    }
+
+    /// Whether the `ReadingBroadcast` is gone, so there won't be any more readings
+    pub fn is_closed(&self) -> bool {
+        Arc::strong_count(&self.shared) == 1
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::{Duration, Instant};
+
+use wlx_common::config::GeneralConfig;
+
+use super::{HeartRateSource, HrStatus, IdleReason};
+
+/// Treats a reading as valid for `window` and holds back the polls of `inner` until it runs out,
+/// for cloud APIs with tight quotas that only have new data every few minutes anyway.
+/// `inner` keeps being updated, so a response in flight or a rotated token is still taken in.
+pub struct CachedHeartRate {
+    inner: Box<dyn HeartRateSource>,
+    window: Duration,
+    valid_until: Option<Instant>,
+    /// Whether the last `update` got a new reading
+    fresh: bool,
+    /// As set with `set_polling_enabled`, `inner` only polls while this is on and the window
+    /// ran out
+    polling_enabled: bool,
+}
+
+impl CachedHeartRate {
+    pub fn new(inner: Box<dyn HeartRateSource>, window: Duration) -> Self {
+        Self {
+            inner,
+            window,
+            valid_until: None,
+            fresh: false,
+            polling_enabled: true,
+        }
+    }
+
+    fn is_cached(&self) -> bool {
+        self.valid_until
+            .is_some_and(|valid_until| Instant::now() < valid_until)
+    }
+}
+
+impl HeartRateSource for CachedHeartRate {
+    fn inner(&self) -> Option<&dyn HeartRateSource> {
+        Some(self.inner.as_ref())
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn HeartRateSource> {
+        Some(self.inner.as_mut())
+    }
+
+    fn update(&mut self, config: &GeneralConfig, watch_visible: bool) {
+        self.inner
+            .set_polling_enabled(self.polling_enabled && !self.is_cached());
+        self.inner.update(config, watch_visible);
+
+        self.fresh = self.inner.is_fresh();
+        if self.fresh {
+            self.valid_until = Some(Instant::now() + self.window);
+        }
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.fresh
+    }
+
+    fn is_stale(&self) -> bool {
+        if self.is_cached() {
+            return self.inner.last_rate().is_none();
+        }
+        self.inner.is_stale()
+    }
+
+    fn status(&self) -> HrStatus {
+        match self.inner.status() {
+            HrStatus::Stale(rate) if self.is_cached() => HrStatus::Live(rate),
+            status => status,
+        }
+    }
+
+    /// Polls held back for the cache don't count as paused
+    fn idle_reason(&self) -> Option<IdleReason> {
+        match self.inner.idle_reason() {
+            Some(IdleReason::Paused) if self.polling_enabled => None,
+            reason => reason,
+        }
+    }
+
+    fn set_polling_enabled(&mut self, enabled: bool) {
+        self.polling_enabled = enabled;
+        self.inner.set_polling_enabled(enabled && !self.is_cached());
+    }
+
+    fn polling_enabled(&self) -> bool {
+        self.polling_enabled
+    }
+
+    fn force_poll(&mut self) {
+        // asking for a fresh reading overrides the cache
+        self.valid_until = None;
+        self.inner.set_polling_enabled(self.polling_enabled);
+        self.inner.force_poll();
+    }
+}
@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+use wlx_common::config::GeneralConfig;
+
+/// Holds back changes smaller than `hr_change_threshold` from an output, so twitchy sources
+/// don't flood it. The current rate still goes out every `hr_change_force_secs`.
+#[derive(Default)]
+pub struct ChangeThreshold {
+    emitted: Option<u32>,
+    emitted_at: Option<Instant>,
+}
+
+impl ChangeThreshold {
+    /// The rate to emit, which stays the last emitted one while `rate` is within the threshold
+    pub fn apply(&mut self, rate: Option<u32>, config: &GeneralConfig) -> Option<u32> {
+        let force_after = Duration::from_secs(config.hr_change_force_secs);
+        let hold = match (rate, self.emitted) {
+            (Some(rate), Some(emitted)) => {
+                rate.abs_diff(emitted) < config.hr_change_threshold
+                    && self
+                        .emitted_at
+                        .is_some_and(|emitted_at| emitted_at.elapsed() < force_after)
+            }
+            _ => false,
+        };
+
+        if !hold {
+            self.emitted = rate;
+            self.emitted_at = Some(Instant::now());
+        }
+        self.emitted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> GeneralConfig {
+        serde_json::from_str(r#"{"hr_change_threshold": 3, "hr_change_force_secs": 60}"#).unwrap()
+    }
+
+    #[test]
+    fn small_changes_are_held_back() {
+        let config = config();
+        let mut change = ChangeThreshold::default();
+
+        assert_eq!(change.apply(Some(100), &config), Some(100));
+        assert_eq!(change.apply(Some(101), &config), Some(100));
+        assert_eq!(change.apply(Some(98), &config), Some(100));
+        assert_eq!(change.apply(Some(103), &config), Some(103));
+        assert_eq!(change.apply(None, &config), None);
+        assert_eq!(change.apply(Some(104), &config), Some(104));
+    }
+
+    #[test]
+    fn held_rate_is_forced_out_eventually() {
+        let config = config();
+        let mut change = ChangeThreshold::default();
+
+        change.apply(Some(100), &config);
+        assert_eq!(change.apply(Some(101), &config), Some(100));
+        change.emitted_at = Some(Instant::now() - Duration::from_secs(61));
+        assert_eq!(change.apply(Some(101), &config), Some(101));
+        assert_eq!(change.apply(Some(102), &config), Some(101));
+    }
+}
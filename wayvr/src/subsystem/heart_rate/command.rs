@@ -0,0 +1,120 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+    mpsc,
+};
+
+use super::HeartRateSource;
+
+/// Requests from other threads, e.g. D-Bus calls or the control socket, and from input bindings.
+/// The main loop applies all of them the same way, see `AppState::apply_heart_rate_command`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HrCommand {
+    ForcePoll,
+    Pause,
+    Resume,
+    TogglePolling,
+    ResetStats,
+    /// Pins the next source of `hr_source: composite`, see `HeartRateSource::cycle_source`
+    CycleSource,
+    ClearSourceOverride,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CommandReply {
+    Accepted,
+    /// `ForcePoll` while a request is already in flight
+    Busy,
+    ShuttingDown,
+}
+
+/// Handed to every thread that takes requests
+#[derive(Clone)]
+pub struct CommandSender {
+    sender: mpsc::Sender<HrCommand>,
+    /// Whether the source has a request in flight, as of the last `CommandQueue::tick`
+    poll_in_flight: Arc<AtomicBool>,
+}
+
+impl CommandSender {
+    pub fn send(&self, command: HrCommand) -> CommandReply {
+        if command == HrCommand::ForcePoll && self.poll_in_flight.load(Ordering::Relaxed) {
+            return CommandReply::Busy;
+        }
+        match self.sender.send(command) {
+            Ok(()) => CommandReply::Accepted,
+            Err(_) => CommandReply::ShuttingDown,
+        }
+    }
+}
+
+/// Collects the commands of all interfaces for the main loop
+pub struct CommandQueue {
+    sender: CommandSender,
+    commands: mpsc::Receiver<HrCommand>,
+}
+
+impl Default for CommandQueue {
+    fn default() -> Self {
+        let (sender, commands) = mpsc::channel();
+        Self {
+            sender: CommandSender {
+                sender,
+                poll_in_flight: Arc::new(AtomicBool::new(false)),
+            },
+            commands,
+        }
+    }
+}
+
+impl CommandQueue {
+    pub fn sender(&self) -> CommandSender {
+        self.sender.clone()
+    }
+
+    /// Commands received since the last call
+    pub fn take_commands(&self) -> Vec<HrCommand> {
+        self.commands.try_iter().collect()
+    }
+
+    pub fn tick(&self, source: &dyn HeartRateSource) {
+        self.sender
+            .poll_in_flight
+            .store(source.poll_in_flight(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_arrive_in_order() {
+        let queue = CommandQueue::default();
+        let sender = queue.sender();
+        assert_eq!(sender.send(HrCommand::Pause), CommandReply::Accepted);
+        assert_eq!(sender.send(HrCommand::ResetStats), CommandReply::Accepted);
+        assert_eq!(
+            queue.take_commands(),
+            vec![HrCommand::Pause, HrCommand::ResetStats]
+        );
+        assert!(queue.take_commands().is_empty());
+    }
+
+    #[test]
+    fn force_poll_is_refused_while_a_poll_is_in_flight() {
+        let queue = CommandQueue::default();
+        let sender = queue.sender();
+        queue.sender.poll_in_flight.store(true, Ordering::Relaxed);
+
+        assert_eq!(sender.send(HrCommand::ForcePoll), CommandReply::Busy);
+        assert_eq!(sender.send(HrCommand::Resume), CommandReply::Accepted);
+        assert_eq!(queue.take_commands(), vec![HrCommand::Resume]);
+    }
+
+    #[test]
+    fn commands_fail_once_the_queue_is_gone() {
+        let sender = CommandQueue::default().sender();
+        assert_eq!(sender.send(HrCommand::Pause), CommandReply::ShuttingDown);
+    }
+}
@@ -0,0 +1,469 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use strum::EnumString;
+use wlx_common::config::GeneralConfig;
+
+use super::{HeartRateSource, HrStatus, IdleReason, PollHealth, SourceProfile};
+
+/// Offset between the poll schedules of consecutive sources
+const POLL_STAGGER: Duration = Duration::from_millis(1500);
+
+/// See `hr_composite_mode`
+#[derive(Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum CompositeMode {
+    /// The reading of the first source that has one
+    Priority,
+    /// Like priority, with the last source correcting the drift of the active one
+    Blend,
+}
+
+/// Takes the reading of the first source that currently has one, in order of priority,
+/// unless one was pinned with `cycle_source`.
+/// All sources keep being updated so that a fallback is ready the moment it's needed.
+pub struct CompositeHeartRate {
+    sources: Vec<Box<dyn HeartRateSource>>,
+    active: Option<usize>,
+    /// Used whether it has a reading or not, until cleared
+    pinned: Option<usize>,
+    /// Whether the live sources currently read further apart than `hr_composite_disagree_bpm`
+    disagree: bool,
+    mode: CompositeMode,
+    /// Added to the active source in blend mode, moves towards `correction_target`
+    correction: f32,
+    /// How far the reference read from the active source, as of its last reading
+    correction_target: Option<f32>,
+    /// Of the last reading of the reference that was taken into account
+    reference_read_at: Option<SystemTime>,
+    corrected_at: Option<Instant>,
+}
+
+impl CompositeHeartRate {
+    pub fn new(mut sources: Vec<Box<dyn HeartRateSource>>, mode: CompositeMode) -> Self {
+        // cloud sources would otherwise poll in lockstep, and hit their rate limits together
+        for (index, source) in sources.iter_mut().enumerate() {
+            source.set_poll_phase(POLL_STAGGER * index as u32);
+        }
+
+        Self {
+            sources,
+            active: None,
+            pinned: None,
+            disagree: false,
+            mode,
+            correction: 0.0,
+            correction_target: None,
+            reference_read_at: None,
+            corrected_at: None,
+        }
+    }
+
+    /// In blend mode the last source is taken as the accurate one. Each new reading of it
+    /// sets how far off the active source is, and the correction follows that by at most
+    /// `hr_composite_blend_bpm_per_min`, so the shown rate stays as quick as the active
+    /// source while a lasting offset is evened out over time.
+    fn blend(&mut self, bpm_per_min: f32, now: Instant) {
+        // nothing to correct while the reference is the one shown
+        let blending = self.mode == CompositeMode::Blend && self.pinned.is_none();
+        let Some(active) = self
+            .active
+            .filter(|active| blending && active + 1 < self.sources.len())
+        else {
+            self.reset_correction();
+            return;
+        };
+
+        let reference = &self.sources[self.sources.len() - 1];
+        if !reference.is_stale()
+            && let (Some(rate), Some(read_at)) = (reference.last_rate(), reference.last_read_at())
+            && self.reference_read_at != Some(read_at)
+            && let Some(active_rate) = self.sources[active].last_rate()
+        {
+            self.reference_read_at = Some(read_at);
+            self.correction_target = Some(rate as f32 - active_rate as f32);
+        }
+
+        let elapsed = self
+            .corrected_at
+            .map_or(Duration::ZERO, |at| now.duration_since(at));
+        self.corrected_at = Some(now);
+        if let Some(target) = self.correction_target {
+            let step = bpm_per_min.max(0.0) * elapsed.as_secs_f32() / 60.0;
+            self.correction += (target - self.correction).clamp(-step, step);
+        }
+    }
+
+    fn reset_correction(&mut self) {
+        self.correction = 0.0;
+        self.correction_target = None;
+        self.reference_read_at = None;
+        self.corrected_at = None;
+    }
+
+    /// Warns once the live sources read further apart than `hr_composite_disagree_bpm`, and
+    /// again after they agreed in between. The active source is used either way.
+    fn check_agreement(&mut self, margin: u32) {
+        let live = self
+            .sources
+            .iter()
+            .filter(|source| !source.is_stale())
+            .filter_map(|source| Some((source.name(), source.last_rate()?)));
+        let lowest = live.clone().min_by_key(|(_, rate)| *rate);
+        let highest = live.max_by_key(|(_, rate)| *rate);
+
+        let disagree = match (lowest, highest) {
+            (Some((low_name, low)), Some((high_name, high)))
+                if margin > 0 && high - low > margin =>
+            {
+                if !self.disagree {
+                    log::warn!(
+                        "Heart rate sources disagree: {high_name} reads {high} bpm, {low_name} reads {low} bpm."
+                    );
+                }
+                true
+            }
+            _ => false,
+        };
+        self.disagree = disagree;
+    }
+
+    fn active_source(&self) -> Option<&dyn HeartRateSource> {
+        self.active.map(|index| self.sources[index].as_ref())
+    }
+}
+
+impl HeartRateSource for CompositeHeartRate {
+    fn name(&self) -> &'static str {
+        self.active_source()
+            .map_or("composite", HeartRateSource::name)
+    }
+
+    fn update(&mut self, config: &GeneralConfig, watch_visible: bool) {
+        for source in &mut self.sources {
+            source.update(config, watch_visible);
+        }
+        self.check_agreement(config.hr_composite_disagree_bpm);
+
+        // only switch once every source is up to date, so there's no gap in between
+        let active = self.pinned.or_else(|| {
+            self.sources
+                .iter()
+                .position(|source| !source.is_stale() && source.last_rate().is_some())
+        });
+
+        if active != self.active {
+            let name =
+                |index: Option<usize>| index.map_or("none", |index| self.sources[index].name());
+            log::info!(
+                "Heart rate source switched from {} to {}",
+                name(self.active),
+                name(active)
+            );
+            self.active = active;
+            // the offset was the one of the previous source
+            self.reset_correction();
+        }
+        self.blend(config.hr_composite_blend_bpm_per_min, Instant::now());
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        let rate = self.active_source()?.last_rate()?;
+        Some((rate as f32 + self.correction).round().max(0.0) as u32)
+    }
+
+    fn raw_rate(&self) -> Option<u32> {
+        self.active_source()?.raw_rate()
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        self.active_source()?.last_read_at()
+    }
+
+    fn last_reading_time(&self) -> Option<SystemTime> {
+        self.active_source()?.last_reading_time()
+    }
+
+    fn last_hrv(&self) -> Option<f32> {
+        self.active_source()?.last_hrv()
+    }
+
+    fn idle_reason(&self) -> Option<IdleReason> {
+        match self.active_source() {
+            Some(source) => source.idle_reason(),
+            // explains why none of them has a current reading
+            None => self.sources.iter().find_map(|source| source.idle_reason()),
+        }
+    }
+
+    fn quota_remaining(&self) -> Option<u32> {
+        self.active_source()?.quota_remaining()
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.active_source().is_some_and(HeartRateSource::is_fresh)
+    }
+
+    fn is_stale(&self) -> bool {
+        self.active_source().is_none_or(HeartRateSource::is_stale)
+    }
+
+    fn status(&self) -> HrStatus {
+        if let Some(source) = self.active_source() {
+            return source.status();
+        }
+
+        let initializing = self
+            .sources
+            .iter()
+            .any(|source| source.status() == HrStatus::Initializing);
+        if initializing {
+            HrStatus::Initializing
+        } else {
+            HrStatus::Disconnected
+        }
+    }
+
+    fn battery_percent(&self) -> Option<u8> {
+        self.active_source()?.battery_percent()
+    }
+
+    fn device(&self) -> Option<&str> {
+        self.active_source()?.device()
+    }
+
+    fn set_polling_enabled(&mut self, enabled: bool) {
+        for source in &mut self.sources {
+            source.set_polling_enabled(enabled);
+        }
+    }
+
+    fn polling_enabled(&self) -> bool {
+        self.sources.iter().all(|source| source.polling_enabled())
+    }
+
+    fn force_poll(&mut self) {
+        for source in &mut self.sources {
+            source.force_poll();
+        }
+    }
+
+    fn cycle_source(&mut self) {
+        self.pinned = match self.pinned {
+            None if !self.sources.is_empty() => Some(0),
+            Some(index) if index + 1 < self.sources.len() => Some(index + 1),
+            _ => None,
+        };
+        match self.pinned {
+            Some(index) => log::info!("Heart rate source pinned to {}", self.sources[index].name()),
+            None => log::info!("Heart rate source is picked by priority again"),
+        }
+    }
+
+    fn clear_source_override(&mut self) {
+        if self.pinned.take().is_some() {
+            log::info!("Heart rate source is picked by priority again");
+        }
+    }
+
+    fn source_override(&self) -> Option<&'static str> {
+        self.pinned.map(|index| self.sources[index].name())
+    }
+
+    fn poll_in_flight(&self) -> bool {
+        self.sources.iter().any(|source| source.poll_in_flight())
+    }
+
+    fn take_backfill(&mut self) -> Vec<(SystemTime, u32)> {
+        // the first by priority, the others are taken too so they don't come in later
+        let mut backfill = Vec::new();
+        for source in &mut self.sources {
+            let taken = source.take_backfill();
+            if backfill.is_empty() {
+                backfill = taken;
+            }
+        }
+        backfill
+    }
+
+    fn poll_health(&self) -> PollHealth {
+        self.active_source()
+            .map(HeartRateSource::poll_health)
+            .unwrap_or_default()
+    }
+
+    /// Of the first source that has one, it's about the user rather than the device
+    fn source_profile(&self) -> SourceProfile {
+        self.sources
+            .iter()
+            .map(|source| source.source_profile())
+            .find(|profile| *profile != SourceProfile::default())
+            .unwrap_or_default()
+    }
+
+    fn auth_revoked(&self) -> bool {
+        self.sources.iter().any(|source| source.auth_revoked())
+    }
+
+    fn scope_missing(&self) -> bool {
+        self.sources.iter().any(|source| source.scope_missing())
+    }
+
+    fn sources_disagree(&self) -> bool {
+        self.disagree
+    }
+
+    fn flush_on_shutdown(&mut self) {
+        for source in &mut self.sources {
+            source.flush_on_shutdown();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    /// Reads `rate` from the first `update` on
+    struct Steady {
+        name: &'static str,
+        rate: u32,
+        read_at: Option<SystemTime>,
+    }
+
+    impl HeartRateSource for Steady {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {
+            self.read_at = Some(SystemTime::now());
+        }
+
+        fn last_rate(&self) -> Option<u32> {
+            self.read_at.map(|_| self.rate)
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            self.read_at
+        }
+    }
+
+    fn steady(name: &'static str, rate: u32) -> Box<dyn HeartRateSource> {
+        Box::new(Steady {
+            name,
+            rate,
+            read_at: None,
+        })
+    }
+
+    #[test]
+    fn cycling_changes_the_exposed_source() {
+        let config: GeneralConfig = serde_json::from_str("{}").unwrap();
+        let mut composite = CompositeHeartRate::new(
+            vec![steady("strap", 90), steady("fitbit", 70)],
+            CompositeMode::Priority,
+        );
+
+        composite.update(&config, true);
+        assert_eq!(composite.last_rate(), Some(90));
+        assert_eq!(composite.source_override(), None);
+
+        composite.cycle_source();
+        composite.cycle_source();
+        composite.update(&config, true);
+        assert_eq!(composite.name(), "fitbit");
+        assert_eq!(composite.last_rate(), Some(70));
+        assert_eq!(composite.source_override(), Some("fitbit"));
+
+        // past the last source it's picked by priority again
+        composite.cycle_source();
+        composite.update(&config, true);
+        assert_eq!(composite.last_rate(), Some(90));
+        assert_eq!(composite.source_override(), None);
+    }
+
+    #[test]
+    fn clearing_the_override_goes_back_to_priority() {
+        let config: GeneralConfig = serde_json::from_str("{}").unwrap();
+        let mut composite = CompositeHeartRate::new(
+            vec![steady("strap", 90), steady("fitbit", 70)],
+            CompositeMode::Priority,
+        );
+
+        composite.cycle_source();
+        composite.cycle_source();
+        composite.update(&config, true);
+        assert_eq!(composite.last_rate(), Some(70));
+
+        composite.clear_source_override();
+        composite.update(&config, true);
+        assert_eq!(composite.name(), "strap");
+        assert_eq!(composite.last_rate(), Some(90));
+    }
+
+    /// A strap whose rate the test sets, read all the time
+    struct Fast(Rc<Cell<u32>>);
+
+    impl HeartRateSource for Fast {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            Some(self.0.get())
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            Some(SystemTime::now())
+        }
+    }
+
+    #[test]
+    fn blend_shows_the_fast_source_while_correcting_its_drift() {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"hr_composite_blend_bpm_per_min": 6}"#).unwrap();
+        let strap = Rc::new(Cell::new(90));
+        let fast: Box<dyn HeartRateSource> = Box::new(Fast(strap.clone()));
+        let mut composite =
+            CompositeHeartRate::new(vec![fast, steady("fitbit", 80)], CompositeMode::Blend);
+        composite.update(&config, true);
+        assert_eq!(composite.last_rate(), Some(90));
+
+        // fitbit reads 10 lower, which is corrected by 6 BPM a minute
+        let start = Instant::now();
+        composite.corrected_at = Some(start);
+        composite.blend(6.0, start + Duration::from_secs(30));
+        assert_eq!(composite.last_rate(), Some(87));
+        composite.blend(6.0, start + Duration::from_secs(60));
+        assert_eq!(composite.last_rate(), Some(84));
+
+        // changes of the strap show right away, with the correction so far
+        strap.set(120);
+        assert_eq!(composite.last_rate(), Some(114));
+
+        // the correction stops at the offset fitbit last read
+        composite.blend(6.0, start + Duration::from_secs(600));
+        assert_eq!(composite.last_rate(), Some(110));
+    }
+
+    #[test]
+    fn only_a_disagreement_beyond_the_margin_is_reported() {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"hr_composite_disagree_bpm": 20}"#).unwrap();
+        let disagree = |strap, fitbit| {
+            let mut composite = CompositeHeartRate::new(
+                vec![steady("strap", strap), steady("fitbit", fitbit)],
+                CompositeMode::Priority,
+            );
+            composite.update(&config, true);
+            // the priority source is shown either way
+            assert_eq!(composite.last_rate(), Some(strap));
+            composite.sources_disagree()
+        };
+
+        assert!(disagree(150, 80));
+        assert!(disagree(80, 150));
+        assert!(!disagree(92, 80));
+        assert!(!disagree(100, 80));
+    }
+}
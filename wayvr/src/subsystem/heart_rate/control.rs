@@ -0,0 +1,247 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use anyhow::{Context, bail};
+use serde::Serialize;
+
+use super::{
+    HeartRateSource,
+    command::{CommandReply, CommandSender, HrCommand},
+};
+
+/// Longer lines are answered with an error instead of being buffered
+const MAX_LINE_LENGTH: usize = 256;
+
+/// What `get` returns
+#[derive(Default, Serialize)]
+struct Snapshot {
+    bpm: Option<u32>,
+    /// Unix time of the last successful reading
+    ts: Option<u64>,
+    source: &'static str,
+    stale: bool,
+    status: &'static str,
+    reason: Option<&'static str>,
+    zone: Option<&'static str>,
+    polling: bool,
+}
+
+/// Line based commands on a unix socket, for scripts on setups without D-Bus.
+/// Every connection gets its own thread and may send any number of commands:
+///
+/// - `get` answers with the current heart rate as a line of JSON
+/// - `force-poll` answers `ok`, or `busy` while a request is already in flight
+/// - `pause`, `resume` and `reset-stats` answer `ok`
+///
+/// Anything else is answered with a line starting with `error:`.
+/// Commands go through the same `CommandQueue` as the D-Bus methods.
+pub struct ControlSocket {
+    path: PathBuf,
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+impl ControlSocket {
+    pub fn new(path: PathBuf, commands: CommandSender) -> anyhow::Result<Self> {
+        // left behind if the last session didn't shut down cleanly
+        if path
+            .symlink_metadata()
+            .is_ok_and(|meta| meta.file_type().is_socket())
+        {
+            if UnixStream::connect(&path).is_ok() {
+                bail!("{} is in use by another instance", path.display());
+            }
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Could not remove the stale {}", path.display()))?;
+        }
+
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("Could not listen on {}", path.display()))?;
+
+        let snapshot = Arc::new(Mutex::new(Snapshot::default()));
+
+        let server_snapshot = snapshot.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::debug!("Heart rate control socket accept failed: {e}");
+                        continue;
+                    }
+                };
+
+                let commands = commands.clone();
+                let snapshot = server_snapshot.clone();
+                std::thread::spawn(move || {
+                    let _ = handle_client(stream, &commands, &snapshot)
+                        .inspect_err(|e| log::debug!("Heart rate control client: {e:?}"));
+                });
+            }
+        });
+
+        log::info!("Heart rate control socket listening on {}", path.display());
+        Ok(Self { path, snapshot })
+    }
+
+    pub fn tick(&mut self, source: &dyn HeartRateSource) {
+        let snapshot = Snapshot {
+            bpm: source.smoothed_rate(),
+            ts: source
+                .last_reading_time()
+                .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|since_epoch| since_epoch.as_secs()),
+            source: source.name(),
+            stale: source.is_stale(),
+            status: source.status().into(),
+            reason: source.idle_reason().map(Into::into),
+            zone: source.zone().map(Into::into),
+            polling: source.polling_enabled(),
+        };
+
+        if let Ok(mut current) = self.snapshot.lock() {
+            *current = snapshot;
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn handle_client(
+    stream: UnixStream,
+    commands: &CommandSender,
+    snapshot: &Mutex<Snapshot>,
+) -> anyhow::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let length = (&mut reader)
+            .take(MAX_LINE_LENGTH as u64 + 1)
+            .read_until(b'\n', &mut line)?;
+        if length == 0 {
+            return Ok(());
+        }
+
+        let reply = if line.last() != Some(&b'\n') && length > MAX_LINE_LENGTH {
+            // skip the rest of the line, it can't be a command
+            while line.last() != Some(&b'\n') {
+                line.clear();
+                let length = (&mut reader)
+                    .take(MAX_LINE_LENGTH as u64)
+                    .read_until(b'\n', &mut line)?;
+                if length == 0 {
+                    return Ok(());
+                }
+            }
+            "error: line too long".to_string()
+        } else {
+            let command = String::from_utf8_lossy(&line);
+            match command.trim() {
+                "get" => snapshot
+                    .lock()
+                    .map(|snapshot| serde_json::to_string(&*snapshot))
+                    .unwrap_or_else(|_| Ok("error: state unavailable".to_string()))?,
+                "force-poll" => send(commands, HrCommand::ForcePoll),
+                "pause" => send(commands, HrCommand::Pause),
+                "resume" => send(commands, HrCommand::Resume),
+                "reset-stats" => send(commands, HrCommand::ResetStats),
+                "" => continue,
+                other => format!("error: unknown command {other:?}"),
+            }
+        };
+
+        writeln!(writer, "{reply}")?;
+    }
+}
+
+fn send(commands: &CommandSender, command: HrCommand) -> String {
+    match commands.send(command) {
+        CommandReply::Accepted => "ok",
+        CommandReply::Busy => "busy",
+        CommandReply::ShuttingDown => "error: shutting down",
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystem::heart_rate::command::CommandQueue;
+
+    /// Sends `input` to `handle_client` and returns its replies
+    fn converse(queue: &CommandQueue, snapshot: Snapshot, input: &[u8]) -> Vec<String> {
+        let (client, server) = UnixStream::pair().unwrap();
+        let commands = queue.sender();
+        let server = std::thread::spawn(move || {
+            handle_client(server, &commands, &Mutex::new(snapshot)).unwrap();
+        });
+
+        (&client).write_all(input).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        server.join().unwrap();
+        BufReader::new(client).lines().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn commands_are_queued_and_acknowledged() {
+        let queue = CommandQueue::default();
+        let replies = converse(
+            &queue,
+            Snapshot::default(),
+            b"pause\n\nresume\nreset-stats\nforce-poll\n",
+        );
+
+        assert_eq!(replies, ["ok", "ok", "ok", "ok"]);
+        assert_eq!(
+            queue.take_commands(),
+            [
+                HrCommand::Pause,
+                HrCommand::Resume,
+                HrCommand::ResetStats,
+                HrCommand::ForcePoll
+            ]
+        );
+    }
+
+    #[test]
+    fn get_answers_with_the_snapshot() {
+        let snapshot = Snapshot {
+            bpm: Some(72),
+            source: "fitbit",
+            status: "live",
+            ..Default::default()
+        };
+        let replies = converse(&CommandQueue::default(), snapshot, b"get\n");
+
+        let json: serde_json::Value = serde_json::from_str(&replies[0]).unwrap();
+        assert_eq!(json["bpm"], 72);
+        assert_eq!(json["source"], "fitbit");
+    }
+
+    #[test]
+    fn malformed_lines_are_answered_with_errors() {
+        let queue = CommandQueue::default();
+        let mut input = vec![b'x'; MAX_LINE_LENGTH * 2];
+        input.extend_from_slice(b"\nfly\npause\n");
+        let replies = converse(&queue, Snapshot::default(), &input);
+
+        assert_eq!(replies[0], "error: line too long");
+        assert_eq!(replies[1], "error: unknown command \"fly\"");
+        assert_eq!(replies[2], "ok");
+        assert_eq!(queue.take_commands(), [HrCommand::Pause]);
+    }
+}
@@ -0,0 +1,197 @@
+use std::{path::PathBuf, time::SystemTime};
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use wlx_common::{cache_dir, config::GeneralConfig};
+
+use super::HeartRateSource;
+
+const DAILY_MAX_FILE: &str = "daily_max_heart_rate.json";
+
+#[derive(Serialize, Deserialize)]
+struct SavedMax {
+    /// As YYYY-MM-DD
+    day: String,
+    bpm: u32,
+}
+
+/// The calendar day `time` falls on in `timezone`, or in local time without it
+pub fn day_of(time: SystemTime, timezone: Option<Tz>) -> NaiveDate {
+    let time = DateTime::<Utc>::from(time);
+    timezone.map_or_else(
+        || time.with_timezone(&Local).date_naive(),
+        |tz| time.with_timezone(&tz).date_naive(),
+    )
+}
+
+/// The highest heart rate of the day, with `hr_daily_max`. Unlike the stats it's kept across
+/// sessions and restarts, and only starts over at midnight in `hr_daily_timezone`. The day is
+/// taken from the wall clock time of each reading, so it also starts over after running or
+/// sleeping through midnight.
+pub struct DailyMax {
+    path: PathBuf,
+    timezone: Option<Tz>,
+    /// Day of `max`
+    day: Option<NaiveDate>,
+    max: Option<u32>,
+    last_read_at: Option<SystemTime>,
+}
+
+impl DailyMax {
+    /// Picks up the max saved by the last run, if it's from today
+    pub fn new(config: &GeneralConfig) -> Self {
+        let timezone = config.hr_daily_timezone.as_deref().and_then(|name| {
+            name.trim()
+                .parse::<Tz>()
+                .inspect_err(|_| {
+                    log::warn!("Unknown hr_daily_timezone '{name}', using local time.");
+                })
+                .ok()
+        });
+
+        let path = cache_dir::get_path(DAILY_MAX_FILE);
+        let today = day_of(SystemTime::now(), timezone);
+        let saved = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<SavedMax>(&json).ok())
+            .filter(|saved| saved.day == today.to_string());
+
+        Self {
+            path,
+            timezone,
+            day: saved.is_some().then_some(today),
+            max: saved.map(|saved| saved.bpm),
+            last_read_at: None,
+        }
+    }
+
+    /// Records the latest reading of `source`, if it is a new one.
+    pub fn observe(&mut self, source: &dyn HeartRateSource) {
+        if source.is_stale() {
+            return;
+        }
+        let (Some(rate), Some(read_at)) = (source.last_rate(), source.last_read_at()) else {
+            return;
+        };
+        if self.last_read_at == Some(read_at) {
+            return;
+        }
+        self.last_read_at = Some(read_at);
+        self.record(rate, read_at);
+    }
+
+    /// Counts readings from before startup, see `take_backfill`
+    pub fn backfill(&mut self, readings: &[(SystemTime, u32)]) {
+        for (read_at, rate) in readings {
+            self.record(*rate, *read_at);
+        }
+    }
+
+    /// Readings from a day before the current one are dropped, a later day starts over
+    pub fn record(&mut self, rate: u32, read_at: SystemTime) {
+        let day = day_of(read_at, self.timezone);
+        if self.day.is_some_and(|current| day < current) {
+            return;
+        }
+        if self.day != Some(day) {
+            self.day = Some(day);
+            self.max = None;
+        }
+        self.max = Some(self.max.map_or(rate, |max| max.max(rate)));
+    }
+
+    /// None once the day of the max is over, until the first reading of the new day
+    pub fn max(&self) -> Option<u32> {
+        let today = day_of(SystemTime::now(), self.timezone);
+        self.max.filter(|_| self.day == Some(today))
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = self.write() {
+            log::warn!("Could not save today's max heart rate: {e:?}");
+        }
+    }
+
+    fn write(&self) -> anyhow::Result<()> {
+        let (Some(day), Some(bpm)) = (self.day, self.max) else {
+            return Ok(());
+        };
+        let json = serde_json::to_string(&SavedMax {
+            day: day.to_string(),
+            bpm,
+        })?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, json)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    const BERLIN: Tz = chrono_tz::Europe::Berlin;
+
+    fn berlin(day: u32, hour: u32, minute: u32) -> SystemTime {
+        BERLIN
+            .with_ymd_and_hms(2024, 5, day, hour, minute, 0)
+            .unwrap()
+            .into()
+    }
+
+    fn daily_max() -> DailyMax {
+        DailyMax {
+            path: PathBuf::new(),
+            timezone: Some(BERLIN),
+            day: None,
+            max: None,
+            last_read_at: None,
+        }
+    }
+
+    #[test]
+    fn higher_readings_raise_the_max() {
+        let mut daily = daily_max();
+        daily.record(120, berlin(1, 9, 0));
+        daily.record(150, berlin(1, 12, 0));
+        daily.record(130, berlin(1, 18, 0));
+        assert_eq!(daily.max, Some(150));
+    }
+
+    #[test]
+    fn midnight_in_the_timezone_starts_over() {
+        let mut daily = daily_max();
+        daily.record(160, berlin(1, 23, 30));
+        // 22:10 UTC, still the day before there
+        assert_eq!(
+            day_of(berlin(2, 0, 10), Some(chrono_tz::UTC)),
+            NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()
+        );
+
+        daily.record(90, berlin(2, 0, 10));
+        assert_eq!(daily.day, NaiveDate::from_ymd_opt(2024, 5, 2));
+        assert_eq!(daily.max, Some(90));
+
+        // a late reading of the day before doesn't count
+        daily.record(170, berlin(1, 23, 59));
+        assert_eq!(daily.max, Some(90));
+        daily.record(110, berlin(2, 0, 20));
+        assert_eq!(daily.max, Some(110));
+    }
+
+    #[test]
+    fn days_skipped_while_suspended_start_over_too() {
+        let mut daily = daily_max();
+        daily.record(150, berlin(1, 22, 0));
+        daily.record(80, berlin(4, 7, 0));
+        assert_eq!(daily.day, NaiveDate::from_ymd_opt(2024, 5, 4));
+        assert_eq!(daily.max, Some(80));
+        // the max of a day that is over isn't shown
+        assert_eq!(daily.max(), None);
+    }
+}
@@ -0,0 +1,351 @@
+use std::{
+    fmt::Write as _,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use strum::{AsRefStr, EnumString};
+use wlx_common::config::GeneralConfig;
+
+use super::stats::HeartRateStats;
+
+/// Seconds from the unix epoch to the FIT epoch, 1989-12-31 00:00:00 UTC
+const FIT_EPOCH_OFFSET: u64 = 631_065_600;
+const FIT_PROFILE_VERSION: u16 = 2093;
+
+const FIT_ENUM: u8 = 0x00;
+const FIT_UINT8: u8 = 0x02;
+const FIT_UINT16: u8 = 0x84;
+const FIT_UINT32: u8 = 0x86;
+
+#[derive(Clone, Copy, PartialEq, Eq, AsRefStr, EnumString)]
+#[strum(serialize_all = "lowercase")]
+enum ExportFormat {
+    Tcx,
+    Fit,
+}
+
+/// The readings of a finished session
+struct SessionExport {
+    started_at: SystemTime,
+    duration: Duration,
+    samples: Vec<(SystemTime, u32)>,
+    calories: Option<f32>,
+}
+
+impl SessionExport {
+    fn avg(&self) -> u32 {
+        let sum: u64 = self.samples.iter().map(|(_, bpm)| u64::from(*bpm)).sum();
+        (sum / self.samples.len().max(1) as u64) as u32
+    }
+
+    fn max(&self) -> u32 {
+        self.samples.iter().map(|(_, bpm)| *bpm).max().unwrap_or(0)
+    }
+}
+
+/// Writes the session that just ended to `hr_session_export_dir` as a TCX or FIT file, for
+/// importing the workout into e.g. Strava or Garmin Connect. Nothing is written without a reading.
+pub fn export_session(config: &GeneralConfig, stats: &HeartRateStats) {
+    let Some(dir) = config
+        .hr_session_export_dir
+        .as_deref()
+        .map(str::trim)
+        .filter(|dir| !dir.is_empty())
+    else {
+        return;
+    };
+    if stats.session_samples().is_empty() {
+        log::info!("The heart rate session had no readings, not exporting it.");
+        return;
+    }
+
+    let format = config
+        .hr_session_export_format
+        .trim()
+        .to_lowercase()
+        .parse()
+        .unwrap_or_else(|_| {
+            log::warn!(
+                "Unknown hr_session_export_format '{}', using tcx.",
+                config.hr_session_export_format
+            );
+            ExportFormat::Tcx
+        });
+    let duration = stats.session_duration().unwrap_or_default();
+    let session = SessionExport {
+        started_at: SystemTime::now()
+            .checked_sub(duration)
+            .unwrap_or_else(SystemTime::now),
+        duration,
+        samples: stats.session_samples().to_vec(),
+        calories: stats.calories_session(),
+    };
+
+    let path = Path::new(dir).join(format!(
+        "wayvr-hr-{}.{}",
+        DateTime::<Local>::from(session.started_at).format("%Y%m%d-%H%M%S"),
+        format.as_ref()
+    ));
+    std::thread::spawn(move || {
+        let contents = match format {
+            ExportFormat::Tcx => tcx(&session).into_bytes(),
+            ExportFormat::Fit => fit(&session),
+        };
+        let written = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::write(&path, contents));
+        match written {
+            Ok(()) => log::info!("Heart rate session exported to {}.", path.display()),
+            Err(e) => log::warn!(
+                "Could not export the heart rate session to {}: {e:?}",
+                path.display()
+            ),
+        }
+    });
+}
+
+fn tcx_time(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// An activity with a single lap, the heart rate as its only data
+fn tcx(session: &SessionExport) -> String {
+    let start = tcx_time(session.started_at);
+    let mut tcx = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Activities>
+    <Activity Sport="Other">
+      <Id>{start}</Id>
+      <Lap StartTime="{start}">
+        <TotalTimeSeconds>{}</TotalTimeSeconds>
+        <DistanceMeters>0</DistanceMeters>
+        <Calories>{}</Calories>
+        <AverageHeartRateBpm><Value>{}</Value></AverageHeartRateBpm>
+        <MaximumHeartRateBpm><Value>{}</Value></MaximumHeartRateBpm>
+        <Intensity>Active</Intensity>
+        <TriggerMethod>Manual</TriggerMethod>
+        <Track>
+"#,
+        session.duration.as_secs(),
+        session.calories.unwrap_or(0.).round() as u16,
+        session.avg(),
+        session.max(),
+    );
+    for (time, bpm) in &session.samples {
+        let _ = writeln!(
+            tcx,
+            "          <Trackpoint><Time>{}</Time><HeartRateBpm><Value>{bpm}</Value></HeartRateBpm></Trackpoint>",
+            tcx_time(*time)
+        );
+    }
+    tcx.push_str(
+        r"        </Track>
+      </Lap>
+    </Activity>
+  </Activities>
+</TrainingCenterDatabase>
+",
+    );
+    tcx
+}
+
+fn fit_time(time: SystemTime) -> u32 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        .saturating_sub(FIT_EPOCH_OFFSET) as u32
+}
+
+/// Definition message for `local` as `global`, with (field number, size, base type) fields
+fn fit_define(data: &mut Vec<u8>, local: u8, global: u16, fields: &[(u8, u8, u8)]) {
+    data.extend([0x40 | local, 0, 0]);
+    data.extend(global.to_le_bytes());
+    data.push(fields.len() as u8);
+    for (number, size, base_type) in fields {
+        data.extend([*number, *size, *base_type]);
+    }
+}
+
+/// The messages importers expect of an activity: file id, one record per reading, and a lap,
+/// session and activity summing it up
+fn fit(session: &SessionExport) -> Vec<u8> {
+    let start = fit_time(session.started_at);
+    let end = start.saturating_add(session.duration.as_secs() as u32);
+    // the elapsed and timer times are in ms
+    let elapsed = (session.duration.as_millis() as u32).to_le_bytes();
+    let (avg, max) = (session.avg().min(254) as u8, session.max().min(254) as u8);
+
+    let mut data = vec![];
+    fit_define(
+        &mut data,
+        0,
+        0,
+        &[(0, 1, FIT_ENUM), (1, 2, FIT_UINT16), (4, 4, FIT_UINT32)],
+    );
+    // an activity, by the development manufacturer
+    data.extend([0, 4]);
+    data.extend(255u16.to_le_bytes());
+    data.extend(start.to_le_bytes());
+
+    fit_define(&mut data, 1, 20, &[(253, 4, FIT_UINT32), (3, 1, FIT_UINT8)]);
+    for (time, bpm) in &session.samples {
+        data.push(1);
+        data.extend(fit_time(*time).to_le_bytes());
+        data.push((*bpm).min(254) as u8);
+    }
+
+    fit_define(
+        &mut data,
+        2,
+        19,
+        &[
+            (253, 4, FIT_UINT32),
+            (2, 4, FIT_UINT32),
+            (7, 4, FIT_UINT32),
+            (8, 4, FIT_UINT32),
+            (15, 1, FIT_UINT8),
+            (16, 1, FIT_UINT8),
+        ],
+    );
+    data.push(2);
+    for value in [end, start] {
+        data.extend(value.to_le_bytes());
+    }
+    data.extend(elapsed);
+    data.extend(elapsed);
+    data.extend([avg, max]);
+
+    fit_define(
+        &mut data,
+        3,
+        18,
+        &[
+            (253, 4, FIT_UINT32),
+            (2, 4, FIT_UINT32),
+            (7, 4, FIT_UINT32),
+            (8, 4, FIT_UINT32),
+            (5, 1, FIT_ENUM),
+            (16, 1, FIT_UINT8),
+            (17, 1, FIT_UINT8),
+            (26, 2, FIT_UINT16),
+        ],
+    );
+    data.push(3);
+    for value in [end, start] {
+        data.extend(value.to_le_bytes());
+    }
+    data.extend(elapsed);
+    data.extend(elapsed);
+    // generic sport, a single lap
+    data.extend([0, avg, max]);
+    data.extend(1u16.to_le_bytes());
+
+    fit_define(
+        &mut data,
+        4,
+        34,
+        &[
+            (253, 4, FIT_UINT32),
+            (0, 4, FIT_UINT32),
+            (1, 2, FIT_UINT16),
+            (2, 1, FIT_ENUM),
+            (3, 1, FIT_ENUM),
+            (4, 1, FIT_ENUM),
+        ],
+    );
+    data.push(4);
+    data.extend(end.to_le_bytes());
+    data.extend(elapsed);
+    data.extend(1u16.to_le_bytes());
+    // manual, activity event, stop
+    data.extend([0, 26, 1]);
+
+    let mut file = vec![14, 0x10];
+    file.extend(FIT_PROFILE_VERSION.to_le_bytes());
+    file.extend((data.len() as u32).to_le_bytes());
+    file.extend(b".FIT");
+    file.extend(fit_crc(&file).to_le_bytes());
+    file.extend(data);
+    file.extend(fit_crc(&file).to_le_bytes());
+    file
+}
+
+/// The CRC-16 of the FIT SDK
+fn fit_crc(bytes: &[u8]) -> u16 {
+    const TABLE: [u16; 16] = [
+        0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800,
+        0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+    ];
+
+    bytes.iter().fold(0, |crc, byte| {
+        let crc = (crc >> 4) ^ TABLE[usize::from(crc & 0xF)] ^ TABLE[usize::from(byte & 0xF)];
+        (crc >> 4) ^ TABLE[usize::from(crc & 0xF)] ^ TABLE[usize::from(byte >> 4)]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The element names in document order, after checking that every tag is closed in the
+    /// right order
+    fn elements(xml: &str) -> Vec<&str> {
+        let mut open = vec![];
+        let mut names = vec![];
+        for tag in xml.split('<').skip(1) {
+            let tag = &tag[..tag.find('>').expect("unterminated tag")];
+            if tag.starts_with('?') {
+                continue;
+            }
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(open.pop(), Some(name), "mismatched </{name}>");
+                continue;
+            }
+            let name = tag.split_whitespace().next().unwrap();
+            names.push(name);
+            if !tag.ends_with('/') {
+                open.push(name);
+            }
+        }
+        assert!(open.is_empty(), "unclosed {open:?}");
+        names
+    }
+
+    #[test]
+    fn tcx_of_a_short_session() {
+        let started_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let session = SessionExport {
+            started_at,
+            duration: Duration::from_secs(120),
+            samples: [90, 120, 150]
+                .iter()
+                .enumerate()
+                .map(|(i, bpm)| (started_at + Duration::from_secs(i as u64 * 60), *bpm))
+                .collect(),
+            calories: Some(21.6),
+        };
+
+        let tcx = tcx(&session);
+        let elements = elements(&tcx);
+        assert_eq!(elements[0], "TrainingCenterDatabase");
+        assert_eq!(
+            elements
+                .iter()
+                .filter(|name| **name == "Trackpoint")
+                .count(),
+            3
+        );
+        assert!(tcx.contains("<Id>2023-11-14T22:13:20Z</Id>"));
+        assert!(tcx.contains("<TotalTimeSeconds>120</TotalTimeSeconds>"));
+        assert!(tcx.contains("<Calories>22</Calories>"));
+        assert!(tcx.contains("<AverageHeartRateBpm><Value>120</Value></AverageHeartRateBpm>"));
+        assert!(tcx.contains("<MaximumHeartRateBpm><Value>150</Value></MaximumHeartRateBpm>"));
+        assert!(tcx.contains(
+            "<Trackpoint><Time>2023-11-14T22:15:20Z</Time><HeartRateBpm><Value>150</Value></HeartRateBpm></Trackpoint>"
+        ));
+    }
+}
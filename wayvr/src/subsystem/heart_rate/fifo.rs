@@ -0,0 +1,149 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, bail};
+use wlx_common::config::GeneralConfig;
+
+use super::HeartRateSource;
+
+const REOPEN_DELAY: Duration = Duration::from_secs(5);
+
+/// Heart rate streamed into a named pipe as one integer per line, by bridges that push readings
+/// as they come in. Unlike `FileHeartRate`, every line is picked up right away.
+pub struct FifoHeartRate {
+    receiver: mpsc::Receiver<FifoEvent>,
+    running: Arc<AtomicBool>,
+    last_rate: Option<u32>,
+    last_read_at: Option<SystemTime>,
+}
+
+impl FifoHeartRate {
+    pub fn new(path: PathBuf) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+
+        // opening blocks until there is a writer, so the worker may only notice
+        // that it should stop once the next one shows up
+        let worker_running = running.clone();
+        std::thread::spawn(move || {
+            run_worker(&path, &sender, &worker_running);
+            log::info!("Heart rate FIFO worker stopped.");
+        });
+
+        Self {
+            receiver,
+            running,
+            last_rate: None,
+            last_read_at: None,
+        }
+    }
+}
+
+impl HeartRateSource for FifoHeartRate {
+    fn name(&self) -> &'static str {
+        "fifo"
+    }
+
+    fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {
+        for event in self.receiver.try_iter() {
+            match event {
+                FifoEvent::Rate(rate) => {
+                    self.last_rate = Some(rate);
+                    self.last_read_at = Some(SystemTime::now());
+                }
+                FifoEvent::Disconnected => {
+                    self.last_rate = None;
+                    self.last_read_at = None;
+                }
+            }
+        }
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.last_rate
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        self.last_read_at
+    }
+}
+
+impl Drop for FifoHeartRate {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+enum FifoEvent {
+    Rate(u32),
+    /// The writer closed its end
+    Disconnected,
+}
+
+fn run_worker(path: &Path, sender: &mpsc::Sender<FifoEvent>, running: &AtomicBool) {
+    while running.load(Ordering::Relaxed) {
+        match read_fifo(path, sender, running) {
+            Ok(()) => log::debug!("Heart rate FIFO {}: writer disconnected", path.display()),
+            Err(e) => log::warn!("Heart rate FIFO: {e:?}"),
+        }
+
+        if sender.send(FifoEvent::Disconnected).is_err() {
+            return;
+        }
+
+        // a new writer is waited for by opening again, errors need a pause though
+        if !path.metadata().is_ok_and(|meta| meta.file_type().is_fifo()) {
+            std::thread::sleep(REOPEN_DELAY);
+        }
+    }
+}
+
+/// Returns once the writer closes the pipe
+fn read_fifo(
+    path: &Path,
+    sender: &mpsc::Sender<FifoEvent>,
+    running: &AtomicBool,
+) -> anyhow::Result<()> {
+    let meta = path
+        .metadata()
+        .with_context(|| format!("Could not access {}", path.display()))?;
+    // a regular file would be read to its end over and over
+    if !meta.file_type().is_fifo() {
+        bail!(
+            "{} is not a named pipe, use hr_file_path for files",
+            path.display()
+        );
+    }
+
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    log::info!("Heart rate FIFO {}: writer connected", path.display());
+
+    for line in BufReader::new(file).lines() {
+        if !running.load(Ordering::Relaxed) {
+            break;
+        }
+
+        let line = line?;
+        match line.trim().parse::<u32>() {
+            Ok(rate) => {
+                if sender.send(FifoEvent::Rate(rate)).is_err() {
+                    break;
+                }
+            }
+            Err(_) if line.trim().is_empty() => {}
+            Err(_) => log::debug!("Heart rate FIFO: ignoring malformed line {line:?}"),
+        }
+    }
+
+    Ok(())
+}
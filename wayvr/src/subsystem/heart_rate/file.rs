@@ -0,0 +1,69 @@
+use std::{path::Path, time::SystemTime};
+
+use wlx_common::config::GeneralConfig;
+
+use super::HeartRateSource;
+
+/// Heart rate from a file containing the latest BPM as a plain integer,
+/// as written by other tools or bridges.
+#[derive(Default)]
+pub struct FileHeartRate {
+    last_rate: Option<u32>,
+    last_modified: Option<SystemTime>,
+}
+
+impl FileHeartRate {
+    fn read(&mut self, path: &Path) {
+        let modified = match std::fs::metadata(path).and_then(|meta| meta.modified()) {
+            Ok(modified) => modified,
+            Err(_) => {
+                self.last_rate = None;
+                self.last_modified = None;
+                return;
+            }
+        };
+
+        if self.last_modified == Some(modified) {
+            return;
+        }
+        self.last_modified = Some(modified);
+
+        self.last_rate = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| contents.trim().parse().ok());
+
+        if self.last_rate.is_none() {
+            log::debug!("Heart rate file {} has no valid reading", path.display());
+        }
+    }
+}
+
+impl HeartRateSource for FileHeartRate {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn update(&mut self, config: &GeneralConfig, _watch_visible: bool) {
+        let Some(path) = config
+            .hr_file_path
+            .as_deref()
+            .filter(|path| !path.trim().is_empty())
+        else {
+            self.last_rate = None;
+            self.last_modified = None;
+            return;
+        };
+
+        // only stat on every update, the contents are re-read once the file changes
+        self.read(Path::new(path));
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.last_rate
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        // the file was written at the time of the reading
+        self.last_rate.and(self.last_modified)
+    }
+}
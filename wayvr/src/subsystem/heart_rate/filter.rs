@@ -0,0 +1,150 @@
+use std::time::SystemTime;
+
+use wlx_common::config::GeneralConfig;
+
+use super::HeartRateSource;
+
+/// Discards readings outside of `hr_min_bpm..=hr_max_bpm`, e.g. from BLE parsing glitches
+/// or Fitbit sync artifacts. The last plausible reading is kept in their place.
+pub struct RangeFilter {
+    inner: Box<dyn HeartRateSource>,
+    last_rate: Option<u32>,
+    last_read_at: Option<SystemTime>,
+    last_reading_time: Option<SystemTime>,
+    /// Last reading of `inner`, whether it was accepted or not
+    last_seen: Option<(u32, Option<SystemTime>)>,
+    /// Whether the last `update` accepted a new reading
+    fresh: bool,
+}
+
+impl RangeFilter {
+    pub fn new(inner: Box<dyn HeartRateSource>) -> Self {
+        Self {
+            inner,
+            last_rate: None,
+            last_read_at: None,
+            last_reading_time: None,
+            last_seen: None,
+            fresh: false,
+        }
+    }
+}
+
+impl HeartRateSource for RangeFilter {
+    fn inner(&self) -> Option<&dyn HeartRateSource> {
+        Some(self.inner.as_ref())
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn HeartRateSource> {
+        Some(self.inner.as_mut())
+    }
+
+    fn update(&mut self, config: &GeneralConfig, watch_visible: bool) {
+        self.inner.update(config, watch_visible);
+        self.fresh = false;
+
+        let Some(rate) = self.inner.last_rate() else {
+            self.last_rate = None;
+            self.last_read_at = None;
+            self.last_reading_time = None;
+            self.last_seen = None;
+            return;
+        };
+
+        let read_at = self.inner.last_read_at();
+        if self.last_seen == Some((rate, read_at)) {
+            return;
+        }
+        self.last_seen = Some((rate, read_at));
+
+        if (config.hr_min_bpm..=config.hr_max_bpm).contains(&rate) {
+            self.last_rate = Some(rate);
+            self.last_read_at = read_at;
+            self.last_reading_time = self.inner.last_reading_time();
+            self.fresh = self.inner.is_fresh();
+        } else {
+            log::debug!(
+                "Discarding implausible heart rate of {rate} bpm from {}",
+                self.inner.name()
+            );
+        }
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.last_rate
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        self.last_read_at
+    }
+
+    fn last_reading_time(&self) -> Option<SystemTime> {
+        self.last_reading_time
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.fresh
+    }
+
+    fn is_stale(&self) -> bool {
+        self.last_rate.is_none() || self.inner.is_stale()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc, time::Duration};
+
+    use super::*;
+
+    /// Hands out the reading the test set, as a new one every time it changes
+    struct Strap(Rc<Cell<Option<u32>>>, Option<(u32, SystemTime)>);
+
+    impl HeartRateSource for Strap {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {
+            let rate = self.0.get();
+            self.1 = rate.map(|rate| {
+                let previous = self.1.map_or(SystemTime::UNIX_EPOCH, |(_, at)| at);
+                (rate, previous + Duration::from_secs(1))
+            });
+        }
+
+        fn last_rate(&self) -> Option<u32> {
+            self.1.map(|(rate, _)| rate)
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            self.1.map(|(_, at)| at)
+        }
+    }
+
+    fn filtered(rates: &[u32]) -> Option<u32> {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"hr_min_bpm": 25, "hr_max_bpm": 250}"#).unwrap();
+        let rate = Rc::new(Cell::new(None));
+        let mut filter = RangeFilter::new(Box::new(Strap(rate.clone(), None)));
+        for &reading in rates {
+            rate.set(Some(reading));
+            filter.update(&config, true);
+        }
+        filter.last_rate()
+    }
+
+    #[test]
+    fn in_range_readings_pass() {
+        assert_eq!(filtered(&[25]), Some(25));
+        assert_eq!(filtered(&[72, 250]), Some(250));
+    }
+
+    #[test]
+    fn reading_below_the_floor_keeps_the_last_one() {
+        assert_eq!(filtered(&[72, 0]), Some(72));
+        assert_eq!(filtered(&[24]), None);
+    }
+
+    #[test]
+    fn reading_above_the_ceiling_keeps_the_last_one() {
+        assert_eq!(filtered(&[80, 500]), Some(80));
+        assert_eq!(filtered(&[80, 251, 82]), Some(82));
+    }
+}
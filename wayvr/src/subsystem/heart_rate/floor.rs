@@ -0,0 +1,160 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use wlx_common::config::GeneralConfig;
+
+use super::HeartRateSource;
+
+/// Keeps showing the last reading at or above `floor` while `inner` reads below it, since optical
+/// sensors often read far too low for a while after they make contact. Once the low readings
+/// went on for `grace` they are taken as real and shown.
+/// Expects to wrap a `RangeFilter`, which already discarded the impossible ones.
+pub struct FloorHold {
+    inner: Box<dyn HeartRateSource>,
+    floor: u32,
+    grace: Duration,
+    /// Last reading shown, with when it was read
+    last_rate: Option<u32>,
+    last_read_at: Option<SystemTime>,
+    last_reading_time: Option<SystemTime>,
+    /// When the current run of readings below `floor` began
+    low_since: Option<Instant>,
+    /// Whether the last `update` passed on a new reading
+    fresh: bool,
+}
+
+impl FloorHold {
+    pub fn new(inner: Box<dyn HeartRateSource>, floor: u32, grace: Duration) -> Self {
+        Self {
+            inner,
+            floor,
+            grace,
+            last_rate: None,
+            last_read_at: None,
+            last_reading_time: None,
+            low_since: None,
+            fresh: false,
+        }
+    }
+
+    fn accept(&mut self, rate: u32) {
+        self.last_rate = Some(rate);
+        self.last_read_at = self.inner.last_read_at();
+        self.last_reading_time = self.inner.last_reading_time();
+        self.fresh = true;
+    }
+}
+
+impl HeartRateSource for FloorHold {
+    fn inner(&self) -> Option<&dyn HeartRateSource> {
+        Some(self.inner.as_ref())
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn HeartRateSource> {
+        Some(self.inner.as_mut())
+    }
+
+    fn update(&mut self, config: &GeneralConfig, watch_visible: bool) {
+        self.inner.update(config, watch_visible);
+        self.fresh = false;
+
+        let Some(rate) = self.inner.last_rate() else {
+            self.last_rate = None;
+            self.last_read_at = None;
+            self.last_reading_time = None;
+            self.low_since = None;
+            return;
+        };
+
+        if !self.inner.is_fresh() {
+            return;
+        }
+
+        if rate >= self.floor {
+            self.low_since = None;
+            self.accept(rate);
+            return;
+        }
+
+        let low_since = *self.low_since.get_or_insert_with(|| {
+            log::debug!(
+                "Holding heart rate of {} while it reads {rate} bpm, below {} bpm",
+                self.inner.name(),
+                self.floor
+            );
+            Instant::now()
+        });
+        if low_since.elapsed() >= self.grace {
+            self.accept(rate);
+        }
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.last_rate
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        self.last_read_at
+    }
+
+    fn last_reading_time(&self) -> Option<SystemTime> {
+        self.last_reading_time
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.fresh
+    }
+
+    fn is_stale(&self) -> bool {
+        self.last_rate.is_none() || self.inner.is_stale()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    /// An optical sensor reading whatever the test set
+    struct Sensor(Rc<Cell<Option<u32>>>);
+
+    impl HeartRateSource for Sensor {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            self.0.get()
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            self.0.get().map(|_| SystemTime::now())
+        }
+    }
+
+    #[test]
+    fn low_readings_are_held_then_accepted() {
+        let config: GeneralConfig = serde_json::from_str("{}").unwrap();
+        let sensor = Rc::new(Cell::new(None));
+        let mut hold = FloorHold::new(
+            Box::new(Sensor(sensor.clone())),
+            50,
+            Duration::from_secs(30),
+        );
+        let mut read = |hold: &mut FloorHold, rate| {
+            sensor.set(Some(rate));
+            hold.update(&config, true);
+            hold.last_rate()
+        };
+
+        assert_eq!(read(&mut hold, 72), Some(72));
+        assert_eq!(read(&mut hold, 38), Some(72));
+        assert_eq!(read(&mut hold, 40), Some(72));
+
+        hold.low_since = Some(Instant::now() - Duration::from_secs(31));
+        assert_eq!(read(&mut hold, 41), Some(41));
+        assert_eq!(read(&mut hold, 60), Some(60));
+        assert_eq!(hold.low_since, None);
+
+        // a new run of low readings is held again
+        assert_eq!(read(&mut hold, 42), Some(60));
+    }
+}
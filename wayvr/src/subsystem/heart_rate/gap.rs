@@ -0,0 +1,60 @@
+use std::time::{Duration, Instant};
+
+use wlx_common::config::GeneralConfig;
+
+/// Tells a short dropout of the readings from a real gap, so derived state like the smoothing
+/// window, the trend baseline or an interpolation survives a single missed poll. Only gaps
+/// longer than `hr_gap_tolerance_secs` count.
+#[derive(Default)]
+pub struct GapTolerance {
+    missing_since: Option<Instant>,
+}
+
+impl GapTolerance {
+    /// Whether derived state should be dropped, i.e. the reading is missing for longer than
+    /// the tolerance. Always true right away with a tolerance of 0.
+    pub fn exceeded(&mut self, has_reading: bool, config: &GeneralConfig) -> bool {
+        self.exceeded_at(has_reading, config, Instant::now())
+    }
+
+    fn exceeded_at(&mut self, has_reading: bool, config: &GeneralConfig, now: Instant) -> bool {
+        if has_reading {
+            self.missing_since = None;
+            return false;
+        }
+        let missing_since = *self.missing_since.get_or_insert(now);
+        now.duration_since(missing_since) >= Duration::from_secs(config.hr_gap_tolerance_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_gaps_past_the_tolerance_count() {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"hr_gap_tolerance_secs": 10}"#).unwrap();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut gap = GapTolerance::default();
+
+        // one missed poll of a source polling every 5 seconds
+        assert!(!gap.exceeded_at(true, &config, at(0)));
+        assert!(!gap.exceeded_at(false, &config, at(5)));
+        assert!(!gap.exceeded_at(true, &config, at(10)));
+
+        // the tolerance counts from the first missing reading
+        assert!(!gap.exceeded_at(false, &config, at(15)));
+        assert!(!gap.exceeded_at(false, &config, at(20)));
+        assert!(gap.exceeded_at(false, &config, at(25)));
+        assert!(!gap.exceeded_at(true, &config, at(30)));
+    }
+
+    #[test]
+    fn no_tolerance_drops_right_away() {
+        let config: GeneralConfig = serde_json::from_str("{}").unwrap();
+        let mut gap = GapTolerance::default();
+        assert!(gap.exceeded_at(false, &config, Instant::now()));
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use super::HeartRateSource;
+
+/// An output of the heart rate and when it last sent one
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct OutputHealth {
+    pub name: String,
+    /// Unix time, None while it didn't send anything yet
+    pub last_sent: Option<u64>,
+}
+
+/// Summary of the heart rate pipeline from the source to the outputs, for `GetHealth` and
+/// `--status`. All times are unix times.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Health {
+    pub source: String,
+    /// initializing, live, stale or disconnected
+    pub status: String,
+    pub bpm: Option<u32>,
+    /// With `hr_daily_max`
+    #[serde(default)]
+    pub today_max: Option<u32>,
+    /// Of the last successful reading
+    pub last_success: Option<u64>,
+    pub poll_in_flight: bool,
+    /// Why the source isn't polling, see `IdleReason`
+    pub idle_reason: Option<String>,
+    /// Failed polls in a row, the backoff grows with them
+    pub failures: u32,
+    /// Of a streaming source, e.g. BLE subscriptions restarted by the watchdog
+    #[serde(default)]
+    pub reconnects: u32,
+    pub next_poll_at: Option<u64>,
+    pub token_expires_at: Option<u64>,
+    pub outputs: Vec<OutputHealth>,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+impl Health {
+    /// Only reads from `source`, the outputs are added with `output`
+    pub fn new(source: &dyn HeartRateSource) -> Self {
+        let poll = source.poll_health();
+        Self {
+            source: source.name().to_string(),
+            status: <&str>::from(source.status()).to_string(),
+            bpm: source.smoothed_rate(),
+            today_max: None,
+            last_success: source.last_read_at().map(unix_secs),
+            poll_in_flight: source.poll_in_flight(),
+            idle_reason: source
+                .idle_reason()
+                .map(|reason| <&str>::from(reason).to_string()),
+            failures: poll.failures,
+            reconnects: poll.reconnects,
+            next_poll_at: poll.next_poll_at.map(unix_secs),
+            token_expires_at: poll.token_expires_at.map(unix_secs),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn output(&mut self, name: &str, last_sent: Option<SystemTime>) {
+        self.outputs.push(OutputHealth {
+            name: name.to_string(),
+            last_sent: last_sent.map(unix_secs),
+        });
+    }
+}
+
+/// Local time of day of a unix time, `-` for None
+fn time_of_day(ts: Option<u64>) -> String {
+    ts.map_or_else(
+        || "-".to_string(),
+        |ts| {
+            DateTime::<Local>::from(SystemTime::UNIX_EPOCH + Duration::from_secs(ts))
+                .format("%H:%M:%S")
+                .to_string()
+        },
+    )
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Source:         {} ({})", self.source, self.status)?;
+        match self.bpm {
+            Some(bpm) => writeln!(f, "Heart rate:     {bpm} BPM")?,
+            None => writeln!(f, "Heart rate:     -")?,
+        }
+        if let Some(today_max) = self.today_max {
+            writeln!(f, "Today's max:    {today_max} BPM")?;
+        }
+        writeln!(f, "Last reading:   {}", time_of_day(self.last_success))?;
+        writeln!(
+            f,
+            "Polling:        {}",
+            match (&self.idle_reason, self.poll_in_flight) {
+                (Some(reason), _) => format!("idle, {reason}"),
+                (None, true) => "request in flight".to_string(),
+                (None, false) => format!("next at {}", time_of_day(self.next_poll_at)),
+            }
+        )?;
+        writeln!(f, "Failed polls:   {}", self.failures)?;
+        if self.reconnects > 0 {
+            writeln!(f, "Reconnects:     {}", self.reconnects)?;
+        }
+        if self.token_expires_at.is_some() {
+            writeln!(f, "Token expires:  {}", time_of_day(self.token_expires_at))?;
+        }
+        writeln!(f, "Outputs:")?;
+        if self.outputs.is_empty() {
+            writeln!(f, "  none")?;
+        }
+        for output in &self.outputs {
+            match output.last_sent {
+                Some(_) => writeln!(
+                    f,
+                    "  {:<14}last sent {}",
+                    output.name,
+                    time_of_day(output.last_sent)
+                )?,
+                None => writeln!(f, "  {:<14}nothing sent yet", output.name)?,
+            }
+        }
+        Ok(())
+    }
+}
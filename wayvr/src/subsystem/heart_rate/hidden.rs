@@ -0,0 +1,147 @@
+use std::time::{Duration, Instant};
+
+use wlx_common::config::GeneralConfig;
+
+use super::HeartRateSource;
+
+/// Shows no heart rate once the watch was hidden for `hold`, for `hr_watch_hidden` `blank` and
+/// `hold_for`. The reading itself is kept and shows again as soon as the watch does.
+pub struct BlankWhileHidden {
+    inner: Box<dyn HeartRateSource>,
+    hold: Duration,
+    /// When the watch was hidden, None while it's visible
+    hidden_since: Option<Instant>,
+}
+
+/// How long the reading is held while the watch is hidden, None for `hold`, which keeps it
+pub fn blank_after(config: &GeneralConfig) -> Option<Duration> {
+    match config.hr_watch_hidden.trim().to_lowercase().as_str() {
+        "hold" | "" => None,
+        "blank" => Some(Duration::ZERO),
+        "hold_for" => Some(Duration::from_secs(config.hr_watch_hidden_hold_secs)),
+        _ => {
+            log::warn!(
+                "Unknown hr_watch_hidden '{}', holding the last reading.",
+                config.hr_watch_hidden
+            );
+            None
+        }
+    }
+}
+
+impl BlankWhileHidden {
+    pub fn new(inner: Box<dyn HeartRateSource>, hold: Duration) -> Self {
+        Self {
+            inner,
+            hold,
+            hidden_since: None,
+        }
+    }
+
+    fn blanked(&self) -> bool {
+        self.hidden_since
+            .is_some_and(|hidden_since| hidden_since.elapsed() >= self.hold)
+    }
+}
+
+impl HeartRateSource for BlankWhileHidden {
+    fn inner(&self) -> Option<&dyn HeartRateSource> {
+        Some(self.inner.as_ref())
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn HeartRateSource> {
+        Some(self.inner.as_mut())
+    }
+
+    fn update(&mut self, config: &GeneralConfig, watch_visible: bool) {
+        self.inner.update(config, watch_visible);
+        if watch_visible {
+            self.hidden_since = None;
+        } else {
+            self.hidden_since.get_or_insert_with(Instant::now);
+        }
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.inner.last_rate().filter(|_| !self.blanked())
+    }
+
+    fn raw_rate(&self) -> Option<u32> {
+        self.inner.raw_rate().filter(|_| !self.blanked())
+    }
+
+    fn last_hrv(&self) -> Option<f32> {
+        self.inner.last_hrv().filter(|_| !self.blanked())
+    }
+
+    fn is_fresh(&self) -> bool {
+        !self.blanked() && self.inner.is_fresh()
+    }
+
+    fn is_stale(&self) -> bool {
+        self.blanked() || self.inner.is_stale()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    /// A strap that keeps reading 88 BPM
+    struct Strap;
+
+    impl HeartRateSource for Strap {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            Some(88)
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            Some(SystemTime::now())
+        }
+    }
+
+    fn policy(json: &str) -> (GeneralConfig, Option<Duration>) {
+        let config: GeneralConfig = serde_json::from_str(json).unwrap();
+        let blank_after = blank_after(&config);
+        (config, blank_after)
+    }
+
+    #[test]
+    fn hold_keeps_the_reading() {
+        assert_eq!(policy(r#"{"hr_watch_hidden": "hold"}"#).1, None);
+        assert_eq!(policy(r#"{"hr_watch_hidden": "sometimes"}"#).1, None);
+    }
+
+    #[test]
+    fn blank_hides_the_reading_right_away() {
+        let (config, blank_after) = policy(r#"{"hr_watch_hidden": "blank"}"#);
+        let mut source = BlankWhileHidden::new(Box::new(Strap), blank_after.unwrap());
+
+        source.update(&config, true);
+        assert_eq!(source.last_rate(), Some(88));
+        source.update(&config, false);
+        assert_eq!(source.last_rate(), None);
+        assert!(source.is_stale());
+        source.update(&config, true);
+        assert_eq!(source.last_rate(), Some(88));
+    }
+
+    #[test]
+    fn hold_for_blanks_once_the_time_is_up() {
+        let (config, blank_after) =
+            policy(r#"{"hr_watch_hidden": "hold_for", "hr_watch_hidden_hold_secs": 30}"#);
+        assert_eq!(blank_after, Some(Duration::from_secs(30)));
+        let mut source = BlankWhileHidden::new(Box::new(Strap), blank_after.unwrap());
+
+        source.update(&config, false);
+        assert_eq!(source.last_rate(), Some(88));
+        source.hidden_since = Some(Instant::now() - Duration::from_secs(31));
+        source.update(&config, false);
+        assert_eq!(source.last_rate(), None);
+        assert_eq!(source.raw_rate(), None);
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime},
+};
+
+use serde::Serialize;
+use wlx_common::config::GeneralConfig;
+
+use super::HeartRateSource;
+
+/// One entry of the history, `bpm` is None where the heart rate went away
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct HistoryEntry {
+    /// Unix time of the reading, or of when the heart rate went away
+    pub ts: u64,
+    pub bpm: Option<u32>,
+}
+
+/// The last `hr_history_len` readings, for overlays drawing a graph of the recent heart rate.
+/// A gap in the readings is recorded as one entry without a rate, so the graph can show a break.
+/// Oldest entries are dropped once it's full.
+#[derive(Default)]
+pub struct ReadingHistory {
+    entries: VecDeque<HistoryEntry>,
+    last_read_at: Option<SystemTime>,
+    /// Counts up with every change, so consumers can tell whether to send it again
+    generation: u64,
+}
+
+impl ReadingHistory {
+    /// Records the latest reading of `source` if it is a new one, or a gap once it went away
+    pub fn observe(&mut self, source: &dyn HeartRateSource, config: &GeneralConfig) {
+        let len = config.hr_history_len;
+        if len == 0 {
+            if !self.entries.is_empty() {
+                *self = Self::default();
+            }
+            return;
+        }
+
+        let reading = source
+            .last_rate()
+            .zip(source.last_read_at())
+            .filter(|_| !source.is_stale());
+        match reading {
+            Some((rate, read_at)) if self.last_read_at != Some(read_at) => {
+                self.last_read_at = Some(read_at);
+                self.push(unix_secs(read_at), Some(rate), len);
+            }
+            None if self.entries.back().is_some_and(|entry| entry.bpm.is_some()) => {
+                self.push(unix_secs(SystemTime::now()), None, len);
+            }
+            _ => {}
+        }
+    }
+
+    /// Puts readings from before startup in front of the ones seen so far, see `take_backfill`
+    pub fn backfill(&mut self, readings: &[(SystemTime, u32)], config: &GeneralConfig) {
+        let len = config.hr_history_len;
+        if len == 0 || readings.is_empty() {
+            return;
+        }
+
+        let first_ts = self.entries.front().map(|entry| entry.ts);
+        let mut entries: VecDeque<HistoryEntry> = readings
+            .iter()
+            .map(|(read_at, bpm)| HistoryEntry {
+                ts: unix_secs(*read_at),
+                bpm: Some(*bpm),
+            })
+            .filter(|entry| first_ts.is_none_or(|first_ts| entry.ts < first_ts))
+            .collect();
+        entries.append(&mut self.entries);
+        while entries.len() > len {
+            entries.pop_front();
+        }
+        self.entries = entries;
+        self.generation += 1;
+    }
+
+    fn push(&mut self, ts: u64, bpm: Option<u32>, len: usize) {
+        while self.entries.len() >= len {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(HistoryEntry { ts, bpm });
+        self.generation += 1;
+    }
+
+    /// Oldest first
+    pub fn entries(&self) -> impl ExactSizeIterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub const fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The reading the graph gets next, None while the strap is off
+    struct Next(Option<(u32, SystemTime)>);
+
+    impl HeartRateSource for Next {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            self.0.map(|(rate, _)| rate)
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            self.0.map(|(_, read_at)| read_at)
+        }
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn bpms(history: &ReadingHistory) -> Vec<Option<u32>> {
+        history.entries().map(|entry| entry.bpm).collect()
+    }
+
+    #[test]
+    fn oldest_readings_are_dropped_at_capacity() {
+        let config: GeneralConfig = serde_json::from_str(r#"{"hr_history_len": 3}"#).unwrap();
+        let mut history = ReadingHistory::default();
+
+        for (secs, rate) in [(1, 70), (2, 71), (3, 72), (4, 73), (5, 74)] {
+            history.observe(&Next(Some((rate, at(secs)))), &config);
+        }
+        // the same reading again isn't a new one
+        history.observe(&Next(Some((74, at(5)))), &config);
+
+        assert_eq!(bpms(&history), [Some(72), Some(73), Some(74)]);
+        let ts: Vec<u64> = history.entries().map(|entry| entry.ts).collect();
+        assert_eq!(ts, [3, 4, 5]);
+        assert_eq!(history.generation(), 5);
+    }
+
+    #[test]
+    fn gap_is_recorded_once() {
+        let config: GeneralConfig = serde_json::from_str(r#"{"hr_history_len": 4}"#).unwrap();
+        let mut history = ReadingHistory::default();
+
+        history.observe(&Next(Some((70, at(1)))), &config);
+        history.observe(&Next(None), &config);
+        history.observe(&Next(None), &config);
+        history.observe(&Next(Some((90, at(30)))), &config);
+
+        assert_eq!(bpms(&history), [Some(70), None, Some(90)]);
+    }
+
+    #[test]
+    fn backfill_goes_in_front_and_keeps_the_capacity() {
+        let config: GeneralConfig = serde_json::from_str(r#"{"hr_history_len": 4}"#).unwrap();
+        let mut history = ReadingHistory::default();
+        history.observe(&Next(Some((80, at(300)))), &config);
+
+        // the last one overlaps the live reading
+        let readings: Vec<_> = [(60, 70), (120, 72), (180, 75), (240, 78), (300, 80)]
+            .into_iter()
+            .map(|(secs, rate)| (at(secs), rate))
+            .collect();
+        history.backfill(&readings, &config);
+
+        assert_eq!(bpms(&history), [Some(72), Some(75), Some(78), Some(80)]);
+        let ts: Vec<u64> = history.entries().map(|entry| entry.ts).collect();
+        assert_eq!(ts, [120, 180, 240, 300]);
+        assert_eq!(history.generation(), 2);
+    }
+}
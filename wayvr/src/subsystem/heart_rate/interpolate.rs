@@ -0,0 +1,175 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use strum::EnumString;
+use wlx_common::config::GeneralConfig;
+
+use super::{HeartRateSource, gap::GapTolerance};
+
+/// Readings further apart than this are a gap, not a step to glide over
+const MAX_TWEEN: Duration = Duration::from_secs(120);
+
+#[derive(Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum Tween {
+    Linear,
+    /// Starts and ends slowly, like a smoothstep
+    Ease,
+}
+
+impl Tween {
+    /// Progress of the displayed value for `t` of the time between the readings, both from 0 to 1
+    fn progress(self, t: f32) -> f32 {
+        let t = t.clamp(0., 1.);
+        match self {
+            Self::Linear => t,
+            Self::Ease => t * t * 2f32.mul_add(-t, 3.),
+        }
+    }
+}
+
+/// Glides from one reading of `inner` to the next over as long as they were apart, for sources
+/// like Fitbit that only have a new value once a minute and would otherwise show steps.
+/// The displayed value lags behind by a reading, `raw_rate` still has the latest one.
+pub struct InterpolatedHeartRate {
+    inner: Box<dyn HeartRateSource>,
+    tween: Tween,
+    /// The displayed value when the latest reading came in
+    from: Option<u32>,
+    /// The latest reading, and when it came in
+    to: Option<(u32, Instant)>,
+    /// How long it takes to glide from `from` to `to`
+    span: Duration,
+    last_read_at: Option<SystemTime>,
+    gap: GapTolerance,
+}
+
+impl InterpolatedHeartRate {
+    pub fn new(inner: Box<dyn HeartRateSource>, tween: Tween) -> Self {
+        Self {
+            inner,
+            tween,
+            from: None,
+            to: None,
+            span: Duration::ZERO,
+            last_read_at: None,
+            gap: GapTolerance::default(),
+        }
+    }
+
+    fn rate_at(&self, now: Instant) -> Option<u32> {
+        let (to, at) = self.to?;
+        let from = self.from.unwrap_or(to);
+        if self.span.is_zero() {
+            return Some(to);
+        }
+
+        let t = now.saturating_duration_since(at).as_secs_f32() / self.span.as_secs_f32();
+        let progress = self.tween.progress(t);
+        let (from, to) = (from as f32, to as f32);
+        Some((to - from).mul_add(progress, from).round() as u32)
+    }
+}
+
+impl HeartRateSource for InterpolatedHeartRate {
+    fn inner(&self) -> Option<&dyn HeartRateSource> {
+        Some(self.inner.as_ref())
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn HeartRateSource> {
+        Some(self.inner.as_mut())
+    }
+
+    fn update(&mut self, config: &GeneralConfig, watch_visible: bool) {
+        self.inner.update(config, watch_visible);
+
+        let rate = self.inner.last_rate();
+        let gap_exceeded = self.gap.exceeded(rate.is_some(), config);
+        let Some(rate) = rate else {
+            // after a short dropout, the next reading is glided to from where it left off
+            if gap_exceeded {
+                self.from = None;
+                self.to = None;
+                self.last_read_at = None;
+            }
+            return;
+        };
+
+        let read_at = self.inner.last_read_at();
+        if read_at == self.last_read_at {
+            return;
+        }
+        self.last_read_at = read_at;
+
+        // a tween that was cut short continues from where it got to
+        let now = Instant::now();
+        self.from = self.rate_at(now);
+        self.span = self
+            .to
+            .map(|(_, at)| now.duration_since(at))
+            .filter(|span| *span <= MAX_TWEEN)
+            .unwrap_or_default();
+        self.to = Some((rate, now));
+    }
+
+    /// None while `inner` has no reading, also during a dropout that's bridged
+    fn last_rate(&self) -> Option<u32> {
+        self.inner.last_rate()?;
+        self.rate_at(Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The minute value Fitbit last reported
+    struct Minute(u32);
+
+    impl HeartRateSource for Minute {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            Some(self.0)
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            Some(SystemTime::UNIX_EPOCH)
+        }
+    }
+
+    /// Gliding from 70 to the latest reading of 80, which came in a minute after the one before
+    fn gliding(tween: Tween, read_at: Instant) -> InterpolatedHeartRate {
+        InterpolatedHeartRate {
+            from: Some(70),
+            to: Some((80, read_at)),
+            span: Duration::from_secs(60),
+            ..InterpolatedHeartRate::new(Box::new(Minute(80)), tween)
+        }
+    }
+
+    #[test]
+    fn linear_tween() {
+        let read_at = Instant::now();
+        let interpolated = gliding(Tween::Linear, read_at);
+        let after = |secs| interpolated.rate_at(read_at + Duration::from_secs(secs));
+
+        assert_eq!(after(0), Some(70));
+        assert_eq!(after(30), Some(75));
+        assert_eq!(after(45), Some(78));
+        assert_eq!(after(60), Some(80));
+        assert_eq!(after(90), Some(80));
+        assert_eq!(interpolated.raw_rate(), Some(80));
+    }
+
+    #[test]
+    fn eased_tween() {
+        let read_at = Instant::now();
+        let interpolated = gliding(Tween::Ease, read_at);
+        let after = |secs| interpolated.rate_at(read_at + Duration::from_secs(secs));
+
+        assert_eq!(after(30), Some(75));
+        // slower than linear at first
+        assert_eq!(after(15), Some(72));
+        assert_eq!(after(60), Some(80));
+    }
+}
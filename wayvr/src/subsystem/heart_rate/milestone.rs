@@ -0,0 +1,174 @@
+use std::time::{Duration, Instant};
+
+use strum::IntoStaticStr;
+use wlx_common::config::GeneralConfig;
+
+use super::zone::HrZone;
+
+/// A point of a workout worth telling about, see `hr_milestone_cooldown` and
+/// `hr_milestone_new_peak`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum Milestone {
+    /// Back within `hr_milestone_resting_margin_bpm` of the resting rate after leaving the
+    /// rest zone
+    CooldownComplete,
+    /// Above the highest reading of the session so far
+    NewPeak,
+}
+
+impl Milestone {
+    const fn index(self) -> usize {
+        self as usize
+    }
+
+    const fn enabled(self, config: &GeneralConfig) -> bool {
+        match self {
+            Self::CooldownComplete => config.hr_milestone_cooldown,
+            Self::NewPeak => config.hr_milestone_new_peak,
+        }
+    }
+}
+
+/// Turns the readings into milestones. Each one fires on the edge only, e.g. once when the
+/// rate gets back down rather than for every reading near the resting rate, and no more than
+/// once per `hr_milestone_cooldown_secs` of its kind.
+#[derive(Default)]
+pub struct MilestoneTracker {
+    /// Set once the rate left the rest zone, until the cooldown completes
+    worked_out: bool,
+    /// Session max as of the last update, None after the stats were reset
+    peak: Option<u32>,
+    fired_at: [Option<Instant>; 2],
+}
+
+impl MilestoneTracker {
+    /// `rate` is the smoothed rate with its `zone`, `session_max` the max of the stats
+    pub fn update(
+        &mut self,
+        rate: Option<u32>,
+        zone: Option<HrZone>,
+        resting: Option<u32>,
+        session_max: Option<u32>,
+        config: &GeneralConfig,
+    ) -> Vec<Milestone> {
+        self.update_at(rate, zone, resting, session_max, config, Instant::now())
+    }
+
+    fn update_at(
+        &mut self,
+        rate: Option<u32>,
+        zone: Option<HrZone>,
+        resting: Option<u32>,
+        session_max: Option<u32>,
+        config: &GeneralConfig,
+        now: Instant,
+    ) -> Vec<Milestone> {
+        let mut reached = Vec::new();
+
+        if zone.is_some_and(|zone| zone > HrZone::Rest) {
+            self.worked_out = true;
+        }
+        if self.worked_out
+            && let (Some(rate), Some(resting)) = (rate, resting)
+            && rate <= resting + config.hr_milestone_resting_margin_bpm
+        {
+            self.worked_out = false;
+            reached.push(Milestone::CooldownComplete);
+        }
+
+        match (self.peak, session_max) {
+            (Some(peak), Some(max)) if max > peak => {
+                self.peak = Some(max);
+                reached.push(Milestone::NewPeak);
+            }
+            // the first reading after a reset sets the bar, it's no peak yet
+            (Some(peak), Some(max)) if max == peak => {}
+            _ => self.peak = session_max,
+        }
+
+        reached.retain(|milestone| self.fire(*milestone, config, now));
+        reached
+    }
+
+    /// Whether `milestone` is enabled and its cooldown is over. An occurrence inside the
+    /// cooldown is dropped rather than held back.
+    fn fire(&mut self, milestone: Milestone, config: &GeneralConfig, now: Instant) -> bool {
+        if !milestone.enabled(config) {
+            return false;
+        }
+        let cooldown = Duration::from_secs(config.hr_milestone_cooldown_secs);
+        let fired_at = &mut self.fired_at[milestone.index()];
+        if fired_at.is_some_and(|at| now.duration_since(at) < cooldown) {
+            return false;
+        }
+        *fired_at = Some(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystem::heart_rate::zone::ZoneTracker;
+
+    /// The milestones of a workout ramping up from and back down to a resting rate of 60,
+    /// one reading every 10 seconds, with the seconds they were reached at
+    fn workout(mut config: serde_json::Value) -> Vec<(u64, Milestone)> {
+        config["hr_zone_bounds"] = serde_json::json!([120, 140, 170]);
+        config["hr_milestone_resting_margin_bpm"] = 5.into();
+        config["hr_milestone_cooldown_secs"] = 30.into();
+        let config: GeneralConfig = serde_json::from_value(config).unwrap();
+
+        let start = Instant::now();
+        let mut zones = ZoneTracker::default();
+        let mut tracker = MilestoneTracker::default();
+        let mut session_max = None;
+        let mut reached = vec![];
+        let rates = [60, 80, 100, 120, 140, 160, 150, 130, 100, 80, 66, 64, 63];
+        for (secs, rate) in (0..).step_by(10).zip(rates) {
+            zones.update(Some(rate), &config, Some(60), 200);
+            session_max = Some(session_max.map_or(rate, |max: u32| max.max(rate)));
+            let now = start + Duration::from_secs(secs);
+            for milestone in tracker.update_at(
+                Some(rate),
+                zones.zone(),
+                Some(60),
+                session_max,
+                &config,
+                now,
+            ) {
+                reached.push((secs, milestone));
+            }
+        }
+        reached
+    }
+
+    #[test]
+    fn ramp_up_and_down_reaches_each_milestone_on_the_edge() {
+        assert_eq!(
+            workout(serde_json::json!({
+                "hr_milestone_cooldown": true,
+                "hr_milestone_new_peak": true
+            })),
+            [
+                (10, Milestone::NewPeak),
+                // the peaks at 20 and 30 seconds were within the cooldown
+                (40, Milestone::NewPeak),
+                (110, Milestone::CooldownComplete),
+            ]
+        );
+    }
+
+    #[test]
+    fn milestones_are_enabled_one_by_one() {
+        assert_eq!(
+            workout(serde_json::json!({"hr_milestone_cooldown": true})),
+            [(110, Milestone::CooldownComplete)]
+        );
+        assert_eq!(
+            workout(serde_json::json!({"hr_milestone_new_peak": true})),
+            [(10, Milestone::NewPeak), (40, Milestone::NewPeak)]
+        );
+    }
+}
@@ -0,0 +1,705 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use strum::{AsRefStr, EnumString, IntoStaticStr};
+use wlx_common::config::{GeneralConfig, HrFormat};
+
+use crate::subsystem::{fitbit::FitbitState, garmin::GarminState, hid::ant};
+
+pub mod bias;
+pub mod ble;
+// only outputs on their own threads subscribe
+#[cfg_attr(not(feature = "pulsoid"), allow(dead_code))]
+pub mod broadcast;
+pub mod cache;
+pub mod change;
+pub mod command;
+pub mod composite;
+pub mod control;
+pub mod daily;
+pub mod export;
+pub mod fifo;
+pub mod file;
+pub mod filter;
+pub mod floor;
+pub mod gap;
+pub mod health;
+pub mod hidden;
+pub mod history;
+pub mod interpolate;
+pub mod milestone;
+pub mod notify;
+pub mod peak;
+pub mod recorder;
+pub mod replay;
+pub mod resting;
+pub mod restore;
+pub mod settle;
+pub mod simulated;
+pub mod smoothing;
+pub mod stats;
+pub mod status_file;
+pub mod summary;
+pub mod zone;
+
+/// A provider of heart rate readings. `update` is called regularly and must not block.
+///
+/// Wrappers that add to another source return it from `inner` and `inner_mut`. Everything
+/// they don't override is then forwarded to it, except for the methods deriving what's
+/// displayed from `last_rate`, which see the rate of the wrapper.
+pub trait HeartRateSource {
+    /// The wrapped source, None for the ones that produce readings themselves
+    fn inner(&self) -> Option<&dyn HeartRateSource> {
+        None
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn HeartRateSource> {
+        None
+    }
+
+    /// Identifies where readings come from, e.g. in logs
+    fn name(&self) -> &'static str {
+        self.inner().map_or("none", HeartRateSource::name)
+    }
+
+    fn update(&mut self, config: &GeneralConfig, watch_visible: bool) {
+        if let Some(inner) = self.inner_mut() {
+            inner.update(config, watch_visible);
+        }
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.inner()?.last_rate()
+    }
+
+    /// When `last_rate` was received
+    fn last_read_at(&self) -> Option<SystemTime> {
+        self.inner()?.last_read_at()
+    }
+
+    /// When `last_rate` was measured, which is a while before `last_read_at` for sources
+    /// that sync behind, e.g. the time of the Fitbit dataset entry.
+    /// Same as `last_read_at` for sources that don't know.
+    fn last_reading_time(&self) -> Option<SystemTime> {
+        match self.inner() {
+            Some(inner) => inner.last_reading_time(),
+            None => self.last_read_at(),
+        }
+    }
+
+    /// The reading before `hr_bias` or `fitbit_interpolation` was applied,
+    /// for comparing against other sources
+    fn raw_rate(&self) -> Option<u32> {
+        self.inner()
+            .map_or_else(|| self.last_rate(), HeartRateSource::raw_rate)
+    }
+
+    /// The rate to display. Same as `last_rate` unless the source applies smoothing.
+    fn smoothed_rate(&self) -> Option<u32> {
+        self.last_rate()
+    }
+
+    /// The displayed rate as text
+    fn formatted(&self, format: HrFormat) -> String {
+        format_rate(self.smoothed_rate(), format)
+    }
+
+    /// Direction the heart rate is heading in, for arrow indicators
+    fn trend(&self) -> Trend {
+        Trend::Steady
+    }
+
+    /// Intensity zone of the displayed rate
+    fn zone(&self) -> Option<zone::HrZone> {
+        None
+    }
+
+    /// Where the displayed rate is between the resting and the maximum rate, from 0 to 1.
+    /// None until the resting rate is known.
+    fn reserve_percent(&self) -> Option<f32> {
+        None
+    }
+
+    /// Whether the last `update` brought in a new reading, so consumers know when to re-emit it.
+    /// Sources that can't tell repeated readings apart always report true.
+    fn is_fresh(&self) -> bool {
+        self.inner().is_none_or(HeartRateSource::is_fresh)
+    }
+
+    /// Whether the last reading is too old to be trusted, e.g. because the device went out of range.
+    fn is_stale(&self) -> bool {
+        self.inner()
+            .map_or_else(|| self.last_rate().is_none(), HeartRateSource::is_stale)
+    }
+
+    /// Lifecycle of the connection, so a UI can tell starting up apart from a lost connection
+    fn status(&self) -> HrStatus {
+        if let Some(inner) = self.inner() {
+            return inner.status().with_rate(self.last_rate());
+        }
+        match self.last_rate() {
+            Some(rate) if self.is_stale() => HrStatus::Stale(rate),
+            Some(rate) => HrStatus::Live(rate),
+            None => HrStatus::Disconnected,
+        }
+    }
+
+    /// Why the source isn't polling right now, so a frozen reading can be told apart from a lost one.
+    /// None while it polls, and for sources that receive readings without asking for them.
+    fn idle_reason(&self) -> Option<IdleReason> {
+        self.inner()?.idle_reason()
+    }
+
+    /// How many more requests the source's API allows before its quota resets
+    fn quota_remaining(&self) -> Option<u32> {
+        self.inner()?.quota_remaining()
+    }
+
+    /// RMSSD-based heart rate variability in milliseconds.
+    /// Only sources that receive RR intervals are able to provide this.
+    fn last_hrv(&self) -> Option<f32> {
+        self.inner()?.last_hrv()
+    }
+
+    /// Charge of the sensor, if it reports one
+    fn battery_percent(&self) -> Option<u8> {
+        self.inner()?.battery_percent()
+    }
+
+    /// Address or name of the physical device the readings come from,
+    /// for sources that can pick between several
+    fn device(&self) -> Option<&str> {
+        self.inner()?.device()
+    }
+
+    /// Pauses or resumes polling, to save API quota while readings aren't needed.
+    /// Sources that receive readings without asking for them ignore this.
+    fn set_polling_enabled(&mut self, enabled: bool) {
+        if let Some(inner) = self.inner_mut() {
+            inner.set_polling_enabled(enabled);
+        }
+    }
+
+    fn polling_enabled(&self) -> bool {
+        self.inner().is_none_or(HeartRateSource::polling_enabled)
+    }
+
+    /// Delays the polls of the source by `phase` whenever its schedule restarts, so sources
+    /// on the same schedule don't all send their requests at once.
+    fn set_poll_phase(&mut self, phase: Duration) {
+        if let Some(inner) = self.inner_mut() {
+            inner.set_poll_phase(phase);
+        }
+    }
+
+    /// Polls on the next `update` instead of waiting for the schedule, and restarts the ramp.
+    /// Does nothing while a request is in flight.
+    fn force_poll(&mut self) {
+        if let Some(inner) = self.inner_mut() {
+            inner.force_poll();
+        }
+    }
+
+    /// Pins the next source of a composite source, or goes back to picking one by priority
+    /// after the last one. Other sources ignore this.
+    fn cycle_source(&mut self) {
+        if let Some(inner) = self.inner_mut() {
+            inner.cycle_source();
+        }
+    }
+
+    /// Goes back to picking the source by priority after `cycle_source`
+    fn clear_source_override(&mut self) {
+        if let Some(inner) = self.inner_mut() {
+            inner.clear_source_override();
+        }
+    }
+
+    /// The source pinned with `cycle_source`, None while it's picked by priority
+    fn source_override(&self) -> Option<&'static str> {
+        self.inner()?.source_override()
+    }
+
+    fn poll_in_flight(&self) -> bool {
+        self.inner().is_some_and(HeartRateSource::poll_in_flight)
+    }
+
+    /// Readings from before startup the source fetched in one go, oldest first, see
+    /// `fitbit_backfill_minutes`. Each of them is only handed out once.
+    fn take_backfill(&mut self) -> Vec<(SystemTime, u32)> {
+        self.inner_mut()
+            .map_or_else(Vec::new, |inner| inner.take_backfill())
+    }
+
+    /// Backoff and token state of a polling source, for `GetHealth`
+    fn poll_health(&self) -> PollHealth {
+        self.inner()
+            .map(HeartRateSource::poll_health)
+            .unwrap_or_default()
+    }
+
+    /// What the account behind the source knows about the user, e.g. the Fitbit profile
+    fn source_profile(&self) -> SourceProfile {
+        self.inner()
+            .map(HeartRateSource::source_profile)
+            .unwrap_or_default()
+    }
+
+    /// Whether the source lost its authorization and needs the user to log in again
+    fn auth_revoked(&self) -> bool {
+        self.inner().is_some_and(HeartRateSource::auth_revoked)
+    }
+
+    /// The credentials lack the permission to read the heart rate, e.g. Fitbit's
+    /// `heartrate` scope. Polling stops until they change.
+    fn scope_missing(&self) -> bool {
+        self.inner().is_some_and(HeartRateSource::scope_missing)
+    }
+
+    /// Two live sources of a composite read further apart than `hr_composite_disagree_bpm`,
+    /// e.g. because a strap is worn wrong or the wrong device is paired
+    fn sources_disagree(&self) -> bool {
+        self.inner().is_some_and(HeartRateSource::sources_disagree)
+    }
+
+    /// Called once the main loop exits. Sources that persist state, e.g. rotated tokens,
+    /// finish up outstanding requests here.
+    fn flush_on_shutdown(&mut self) {
+        if let Some(inner) = self.inner_mut() {
+            inner.flush_on_shutdown();
+        }
+    }
+}
+
+/// Fills in for `hr_resting` and `hr_profile_age` where they aren't set, see
+/// `zone::max_rate`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct SourceProfile {
+    pub resting: Option<u32>,
+    pub age: Option<u32>,
+}
+
+/// Empty for sources that neither poll nor stream
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct PollHealth {
+    /// Failed polls in a row, which the backoff grows with
+    pub failures: u32,
+    /// Times a streaming source lost its connection and set it up again
+    pub reconnects: u32,
+    pub next_poll_at: Option<SystemTime>,
+    pub token_expires_at: Option<SystemTime>,
+}
+
+impl PollHealth {
+    /// Wall-clock time of `at`, for sources that schedule with `Instant`
+    pub fn wall_time(at: Instant) -> SystemTime {
+        let now = Instant::now();
+        if at >= now {
+            SystemTime::now() + (at - now)
+        } else {
+            SystemTime::now() - (now - at)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum HrStatus {
+    /// No reading was received yet
+    Initializing,
+    Live(u32),
+    /// The last reading, which is too old to be trusted
+    Stale(u32),
+    Disconnected,
+}
+
+pub fn format_rate(rate: Option<u32>, format: HrFormat) -> String {
+    match (rate, format) {
+        (Some(rate), HrFormat::Bare) => rate.to_string(),
+        (Some(rate), HrFormat::Padded) => format!("{rate:03}"),
+        (Some(rate), HrFormat::Suffix) => format!("{rate} bpm"),
+        (None, HrFormat::Padded) => "---".to_string(),
+        (None, HrFormat::Bare | HrFormat::Suffix) => "--".to_string(),
+    }
+}
+
+impl HrStatus {
+    /// The same state for the rate a wrapper reports instead
+    #[must_use]
+    pub const fn with_rate(self, rate: Option<u32>) -> Self {
+        match (self, rate) {
+            (Self::Live(_), Some(rate)) => Self::Live(rate),
+            (Self::Stale(_), Some(rate)) => Self::Stale(rate),
+            (Self::Live(_) | Self::Stale(_), None) => Self::Initializing,
+            (status, _) => status,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum IdleReason {
+    /// Polls are skipped while the watch isn't in view
+    WatchHidden,
+    /// Polls are skipped while the headset is in standby, see `hr_pause_while_headset_idle`
+    HeadsetIdle,
+    /// Polling was turned off, see `set_polling_enabled`
+    Paused,
+    Disconnected,
+    /// Polls succeed, but the newest reading didn't change for `fitbit_sync_stall_secs`
+    NotSyncing,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Trend {
+    Rising,
+    Falling,
+    Steady,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, AsRefStr, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum HeartRateSourceKind {
+    Fitbit,
+    Garmin,
+    Ble,
+    File,
+    Fifo,
+    Replay,
+    Ant,
+    Simulated,
+    None,
+    Composite,
+}
+
+/// Creates the source selected by `hr_source`. Fitbit is used if nothing is selected.
+/// The config keys `from_config` builds the chain from, changing one of them needs `rebuild`.
+/// The Fitbit tokens aren't among them, Fitbit rotates and saves them while it runs.
+pub const SOURCE_CONFIG_KEYS: &[&str] = &[
+    "hr_source",
+    "hr_composite_sources",
+    "hr_composite_mode",
+    "fitbit_mock",
+    "fitbit_interpolation",
+    "hr_ble_address",
+    "hr_ble_switch_after_secs",
+    "hr_ble_watchdog_secs",
+    "hr_ant_device",
+    "hr_ant_device_number",
+    "hr_fifo_path",
+    "hr_replay_path",
+    "hr_log_packets",
+    "hr_sim_pattern",
+    "hr_sim_base",
+    "hr_sim_amplitude",
+    "hr_sim_period_secs",
+    "hr_floor_bpm",
+    "hr_floor_grace_secs",
+    "hr_settle_readings",
+    "hr_bias",
+    "hr_cache_windows",
+    "hr_restore_max_age_minutes",
+    "hr_watch_hidden",
+    "hr_watch_hidden_hold_secs",
+];
+
+/// A new chain from `config` in place of `previous`, after one of `SOURCE_CONFIG_KEYS` changed.
+/// `previous` is flushed as on shutdown, and its last reading is shown as stale until the new
+/// chain has one of its own. Paused polling stays paused.
+pub fn rebuild(
+    mut previous: Box<dyn HeartRateSource>,
+    config: &GeneralConfig,
+) -> Box<dyn HeartRateSource> {
+    previous.flush_on_shutdown();
+
+    let mut source = from_config(config);
+    if let (Some(rate), Some(read_at)) = (previous.last_rate(), previous.last_read_at()) {
+        source = Box::new(restore::RestoredHeartRate::carried_over(
+            source,
+            (rate, read_at),
+        ));
+    }
+    source.set_polling_enabled(previous.polling_enabled());
+    source
+}
+
+pub fn from_config(config: &GeneralConfig) -> Box<dyn HeartRateSource> {
+    let kind = parse_kind(config.hr_source.as_deref().unwrap_or("fitbit"));
+    log::info!("Using heart rate source: {}", kind.as_ref());
+
+    if kind != HeartRateSourceKind::Composite {
+        return create(kind, config);
+    }
+
+    let sources = config
+        .hr_composite_sources
+        .iter()
+        .map(|name| parse_kind(name))
+        .filter(|kind| match kind {
+            HeartRateSourceKind::None => false,
+            HeartRateSourceKind::Composite => {
+                log::warn!("A composite heart rate source can't contain itself.");
+                false
+            }
+            _ => true,
+        })
+        .map(|kind| create(kind, config))
+        .collect();
+
+    let mode = config
+        .hr_composite_mode
+        .trim()
+        .to_lowercase()
+        .parse()
+        .unwrap_or_else(|_| {
+            log::warn!(
+                "Unknown hr_composite_mode '{}', using priority.",
+                config.hr_composite_mode
+            );
+            composite::CompositeMode::Priority
+        });
+    Box::new(composite::CompositeHeartRate::new(sources, mode))
+}
+
+fn parse_kind(name: &str) -> HeartRateSourceKind {
+    name.trim().to_lowercase().parse().unwrap_or_else(|_| {
+        log::warn!("Unknown heart rate source '{name}', disabling heart rate.");
+        HeartRateSourceKind::None
+    })
+}
+
+/// Every source is wrapped in a `RangeFilter`, so glitched readings are never shown,
+/// in a `FloorHold` if `hr_floor_bpm` is set,
+/// in an `InterpolatedHeartRate` for Fitbit if `fitbit_interpolation` is set,
+/// in a `BiasedHeartRate` if `hr_bias` has an entry for it
+/// and in a `CachedHeartRate` if `hr_cache_windows` has one.
+fn create(kind: HeartRateSourceKind, config: &GeneralConfig) -> Box<dyn HeartRateSource> {
+    let non_empty = |value: Option<&str>| {
+        value
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| value.trim().to_string())
+    };
+
+    let source: Box<dyn HeartRateSource> = match kind {
+        HeartRateSourceKind::Fitbit if config.fitbit_mock => Box::new(FitbitState::mock()),
+        HeartRateSourceKind::Fitbit => {
+            Box::new(FitbitState::from_config(config).unwrap_or_else(|e| {
+                log::error!("Fitbit is not set up correctly: {e}");
+                FitbitState::default()
+            }))
+        }
+        HeartRateSourceKind::Garmin => Box::new(GarminState::default()),
+        HeartRateSourceKind::Ble => Box::new(ble::BleHeartRate::new(
+            config
+                .hr_ble_address
+                .as_deref()
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|address| non_empty(Some(address)))
+                .collect(),
+            Duration::from_secs(config.hr_ble_switch_after_secs),
+            (config.hr_ble_watchdog_secs > 0)
+                .then(|| Duration::from_secs(config.hr_ble_watchdog_secs)),
+            config.hr_log_packets,
+        )),
+        HeartRateSourceKind::File => Box::new(file::FileHeartRate::default()),
+        HeartRateSourceKind::Fifo => match non_empty(config.hr_fifo_path.as_deref()) {
+            Some(path) => Box::new(fifo::FifoHeartRate::new(path.into())),
+            None => {
+                log::warn!("hr_fifo_path is not set, there is no heart rate.");
+                Box::new(NoHeartRate)
+            }
+        },
+        HeartRateSourceKind::Replay => match non_empty(config.hr_replay_path.as_deref()) {
+            Some(path) => match replay::ReplayHeartRate::new(path.as_ref()) {
+                Ok(replay) => Box::new(replay),
+                Err(e) => {
+                    log::warn!("Heart rate replay: {e:?}");
+                    Box::new(NoHeartRate)
+                }
+            },
+            None => {
+                log::warn!("hr_replay_path is not set, there is no heart rate.");
+                Box::new(NoHeartRate)
+            }
+        },
+        HeartRateSourceKind::Simulated => Box::new(simulated::SimulatedHeartRate::default()),
+        HeartRateSourceKind::Ant => Box::new(ant::AntHeartRate::new(
+            non_empty(config.hr_ant_device.as_deref())
+                .unwrap_or_else(|| ant::DEFAULT_ANT_DEVICE.to_string()),
+            config.hr_ant_device_number,
+            config.hr_log_packets,
+        )),
+        HeartRateSourceKind::None | HeartRateSourceKind::Composite => Box::new(NoHeartRate),
+    };
+
+    let mut source: Box<dyn HeartRateSource> = Box::new(filter::RangeFilter::new(source));
+    if config.hr_floor_bpm > 0 {
+        source = Box::new(floor::FloorHold::new(
+            source,
+            config.hr_floor_bpm,
+            Duration::from_secs(config.hr_floor_grace_secs),
+        ));
+    }
+    if config.hr_settle_readings > 0 {
+        source = Box::new(settle::SettlingHeartRate::new(
+            source,
+            config.hr_settle_readings,
+        ));
+    }
+    if kind == HeartRateSourceKind::Fitbit {
+        match config.fitbit_interpolation.trim().to_lowercase().as_str() {
+            "off" | "" => {}
+            tween => match tween.parse() {
+                Ok(tween) => {
+                    source = Box::new(interpolate::InterpolatedHeartRate::new(source, tween));
+                }
+                Err(_) => log::warn!(
+                    "Unknown fitbit_interpolation '{}', showing the readings as they are.",
+                    config.fitbit_interpolation
+                ),
+            },
+        }
+    }
+    if let Some(&bias) = config.hr_bias.get(kind.as_ref()).filter(|bias| **bias != 0) {
+        source = Box::new(bias::BiasedHeartRate::new(source, bias));
+    }
+
+    if let Some(&secs) = config.hr_cache_windows.get(kind.as_ref())
+        && secs > 0
+    {
+        source = Box::new(cache::CachedHeartRate::new(
+            source,
+            Duration::from_secs(secs),
+        ));
+    }
+
+    if config.hr_restore_max_age_minutes > 0 {
+        source = Box::new(restore::RestoredHeartRate::new(
+            source,
+            Duration::from_secs(u64::from(config.hr_restore_max_age_minutes) * 60),
+        ));
+    }
+
+    // outermost, so a cached or restored reading is blanked as well
+    match hidden::blank_after(config) {
+        Some(hold) => Box::new(hidden::BlankWhileHidden::new(source, hold)),
+        None => source,
+    }
+}
+
+/// Used when heart rate is disabled
+pub struct NoHeartRate;
+
+impl HeartRateSource for NoHeartRate {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+    fn last_rate(&self) -> Option<u32> {
+        None
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        None
+    }
+}
+
+/// Root mean square of successive differences between RR intervals, in milliseconds.
+pub fn rmssd(rr_intervals: &[f32]) -> Option<f32> {
+    if rr_intervals.len() < 2 {
+        return None;
+    }
+
+    let sum_of_squares: f32 = rr_intervals
+        .windows(2)
+        .map(|pair| (pair[1] - pair[0]).powi(2))
+        .sum();
+
+    Some((sum_of_squares / (rr_intervals.len() - 1) as f32).sqrt())
+}
+
+/// Logs the raw bytes of a packet from a strap as hex at trace level, for `hr_log_packets`
+pub fn log_packet(source: &str, bytes: &[u8]) {
+    if !log::log_enabled!(log::Level::Trace) {
+        return;
+    }
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    log::trace!("{source} packet: {}", hex.join(" "));
+}
+
+/// Heart rate from the mean of the RR intervals in milliseconds, 60000 / mean.
+pub fn rr_bpm(rr_intervals: &[f32]) -> Option<u32> {
+    let mean = rr_intervals.iter().sum::<f32>() / rr_intervals.len() as f32;
+    (mean > 0.0).then(|| (60_000.0 / mean).round() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct LastReading {
+        rate: u32,
+        read_at: SystemTime,
+    }
+
+    impl HeartRateSource for LastReading {
+        fn name(&self) -> &'static str {
+            "last_reading"
+        }
+
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            Some(self.rate)
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            Some(self.read_at)
+        }
+    }
+
+    #[test]
+    fn rebuilding_keeps_the_last_reading_as_stale() {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"hr_source": "none", "hr_restore_max_age_minutes": 0}"#)
+                .unwrap();
+        let read_at = SystemTime::now();
+
+        let mut source = rebuild(Box::new(LastReading { rate: 95, read_at }), &config);
+        source.update(&config, true);
+        assert_eq!(source.last_rate(), Some(95));
+        assert_eq!(source.last_read_at(), Some(read_at));
+        assert!(source.is_stale());
+        assert_eq!(source.status(), HrStatus::Stale(95));
+    }
+
+    #[test]
+    fn rate_from_rr_intervals() {
+        assert_eq!(rr_bpm(&[800., 750., 850.]), Some(75));
+        assert_eq!(rr_bpm(&[1000.]), Some(60));
+        assert_eq!(rr_bpm(&[]), None);
+    }
+
+    #[test]
+    fn rate_formats() {
+        assert_eq!(format_rate(Some(72), HrFormat::Bare), "72");
+        assert_eq!(format_rate(Some(72), HrFormat::Padded), "072");
+        assert_eq!(format_rate(Some(7), HrFormat::Padded), "007");
+        assert_eq!(format_rate(Some(142), HrFormat::Padded), "142");
+        assert_eq!(format_rate(Some(72), HrFormat::Suffix), "72 bpm");
+        assert_eq!(format_rate(None, HrFormat::Padded), "---");
+        assert_eq!(format_rate(None, HrFormat::Bare), "--");
+        assert_eq!(format_rate(None, HrFormat::Suffix), "--");
+    }
+
+    #[test]
+    fn source_config_keys_are_config_keys() {
+        let config =
+            serde_json::to_value(serde_json::from_str::<GeneralConfig>("{}").unwrap()).unwrap();
+        for key in SOURCE_CONFIG_KEYS {
+            assert!(config.get(key).is_some(), "{key} is not in the config");
+        }
+    }
+}
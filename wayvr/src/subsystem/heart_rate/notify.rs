@@ -0,0 +1,418 @@
+use std::{
+    collections::HashMap,
+    process::Command,
+    time::{Duration, Instant},
+};
+
+use strum::EnumString;
+use wlx_common::config::GeneralConfig;
+
+use crate::subsystem::dbus::DbusConnector;
+
+use super::{milestone::Milestone, zone::HrZone};
+
+/// How long a desktop notification stays up, in ms
+const NOTIFICATION_TIMEOUT: i32 = 15_000;
+/// How often a running `hr_notify_command` is checked for having exited
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where heart rate notifications go, see `hr_notify_backend`
+#[derive(Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum NotifyBackend {
+    /// freedesktop notifications over D-Bus
+    Desktop,
+    /// Runs `hr_notify_command`
+    Command,
+}
+
+/// A notification about the heart rate, with the reading it's about
+pub struct HrNotification {
+    pub summary: String,
+    pub body: String,
+    pub bpm: Option<u32>,
+    pub zone: Option<HrZone>,
+    /// Sent to check the setup, not about a real reading
+    pub test: bool,
+}
+
+impl HrNotification {
+    /// About the heart rate crossing `hr_threshold_bpm`. A `test` one says so.
+    pub fn threshold(threshold: u32, bpm: Option<u32>, zone: Option<HrZone>, test: bool) -> Self {
+        let summary = format!("Heart rate above {threshold} BPM");
+        if test {
+            return Self {
+                summary: format!("Test: {summary}"),
+                body: "Only a test, your heart rate notifications work.".to_string(),
+                bpm,
+                zone,
+                test,
+            };
+        }
+        Self {
+            summary,
+            body: format!("Your heart rate is at {} BPM.", bpm.unwrap_or(threshold)),
+            bpm,
+            zone,
+            test,
+        }
+    }
+
+    /// `hr_notify_command` with `{summary}`, `{body}`, `{bpm}` and `{zone}` filled in,
+    /// the last two empty without a reading
+    pub fn command_args(&self, command: &[String]) -> Vec<String> {
+        let bpm = self.bpm.map(|bpm| bpm.to_string()).unwrap_or_default();
+        let zone: &str = self.zone.map_or("", Into::into);
+        command
+            .iter()
+            .map(|arg| {
+                arg.replace("{summary}", &self.summary)
+                    .replace("{body}", &self.body)
+                    .replace("{bpm}", &bpm)
+                    .replace("{zone}", zone)
+            })
+            .collect()
+    }
+}
+
+/// What a heart rate notification is about, each kind has its own `hr_notify_cooldown_secs`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum HrAlert {
+    Threshold,
+    BatteryLow,
+    AuthRevoked,
+    ScopeMissing,
+    SourcesDisagree,
+    SignalLost,
+    RestingCalibrated,
+    Milestone(Milestone),
+    SessionEnded,
+}
+
+/// Sends all heart rate notifications, so a flapping reading doesn't repeat the same one
+#[derive(Default)]
+pub struct HrNotifier {
+    /// When each kind of notification was last sent
+    last_sent: HashMap<HrAlert, Instant>,
+}
+
+impl HrNotifier {
+    /// Shows `notification` through `hr_notify_backend`, unless one of the same kind was sent
+    /// within `hr_notify_cooldown_secs`. Returns whether it was sent.
+    pub fn send(
+        &mut self,
+        alert: HrAlert,
+        notification: HrNotification,
+        config: &GeneralConfig,
+    ) -> bool {
+        let cooldown = Duration::from_secs(config.hr_notify_cooldown_secs);
+        if !self.allow(alert, Instant::now(), cooldown) {
+            log::debug!("Skipping the {alert:?} notification, one was sent recently.");
+            return false;
+        }
+        show(notification, config);
+        true
+    }
+
+    fn allow(&mut self, alert: HrAlert, now: Instant, cooldown: Duration) -> bool {
+        if self
+            .last_sent
+            .get(&alert)
+            .is_some_and(|sent_at| now.duration_since(*sent_at) < cooldown)
+        {
+            return false;
+        }
+        self.last_sent.insert(alert, now);
+        true
+    }
+}
+
+/// Tells when the heart rate was gone for a while after having been live, see
+/// `hr_signal_lost_minutes`
+#[derive(Default)]
+pub struct SignalLostWatch {
+    /// Last time the heart rate was live, while the source was polling
+    live_at: Option<Instant>,
+    warned: bool,
+}
+
+impl SignalLostWatch {
+    /// For a hidden watch or paused polling, which don't count as a loss
+    pub const fn forget(&mut self) {
+        self.live_at = None;
+    }
+
+    /// True once per loss, when it's been `lost_after` since the heart rate was last live
+    pub fn tick(&mut self, live: bool, lost_after: Duration, now: Instant) -> bool {
+        if live {
+            self.live_at = Some(now);
+            self.warned = false;
+            return false;
+        }
+
+        if self.warned
+            || !self
+                .live_at
+                .is_some_and(|live_at| now.duration_since(live_at) >= lost_after)
+        {
+            return false;
+        }
+        self.warned = true;
+        true
+    }
+}
+
+/// Shows `notification` through `hr_notify_backend`. Neither backend blocks the caller.
+fn show(notification: HrNotification, config: &GeneralConfig) {
+    let backend = config.hr_notify_backend.trim().parse().unwrap_or_else(|_| {
+        log::warn!(
+            "Unknown hr_notify_backend '{}', using desktop.",
+            config.hr_notify_backend
+        );
+        NotifyBackend::Desktop
+    });
+
+    match backend {
+        NotifyBackend::Desktop => {
+            // notify_send blocks on the D-Bus call
+            std::thread::spawn(move || {
+                if let Err(e) = DbusConnector::notify_send(
+                    &notification.summary,
+                    &notification.body,
+                    1,
+                    NOTIFICATION_TIMEOUT,
+                    0,
+                    false,
+                ) {
+                    log::warn!("Could not show the heart rate notification: {e:?}");
+                }
+            });
+        }
+        NotifyBackend::Command => {
+            let Some(command) = notify_command(&notification, config) else {
+                log::warn!("hr_notify_backend is command, but hr_notify_command is empty.");
+                return;
+            };
+            let timeout = Duration::from_secs(config.hr_notify_command_timeout_secs);
+            std::thread::spawn(move || run_command(command, timeout));
+        }
+    }
+}
+
+/// `hr_notify_command` for `notification`, None if it's empty
+fn notify_command(notification: &HrNotification, config: &GeneralConfig) -> Option<Command> {
+    let args = notification.command_args(&config.hr_notify_command);
+    let (program, args) = args.split_first()?;
+    let mut command = Command::new(program);
+    command.args(args);
+    if let Some(bpm) = notification.bpm {
+        command.env("WAYVR_HR_BPM", bpm.to_string());
+    }
+    if let Some(zone) = notification.zone {
+        command.env("WAYVR_HR_ZONE", <&str>::from(zone));
+    }
+    if notification.test {
+        command.env("WAYVR_HR_TEST", "1");
+    }
+    Some(command)
+}
+
+/// Kills the command once it ran for `timeout`, so a hanging one doesn't pile up
+fn run_command(mut command: Command, timeout: Duration) {
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            log::warn!("Could not run hr_notify_command: {e}");
+            return;
+        }
+    };
+
+    let started_at = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                if !status.success() {
+                    log::warn!("hr_notify_command exited with {status}.");
+                }
+                return;
+            }
+            Ok(None) if started_at.elapsed() >= timeout => {
+                log::warn!(
+                    "hr_notify_command still ran after {}s, stopping it.",
+                    timeout.as_secs()
+                );
+                let _ = child.kill();
+                let _ = child.wait();
+                return;
+            }
+            Ok(None) => std::thread::sleep(COMMAND_POLL_INTERVAL),
+            Err(e) => {
+                log::warn!("Could not wait for hr_notify_command: {e}");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    fn notification(bpm: Option<u32>, zone: Option<HrZone>) -> HrNotification {
+        HrNotification {
+            summary: "Heart rate above 170 BPM".to_string(),
+            body: "Take a break".to_string(),
+            bpm,
+            zone,
+            test: false,
+        }
+    }
+
+    #[test]
+    fn command_args_fill_in_the_placeholders() {
+        let command = ["say", "{summary}: {bpm} ({zone})", "{body}", "--literal"].map(String::from);
+
+        assert_eq!(
+            notification(Some(172), Some(HrZone::FatBurn)).command_args(&command),
+            [
+                "say",
+                "Heart rate above 170 BPM: 172 (fat_burn)",
+                "Take a break",
+                "--literal"
+            ]
+        );
+    }
+
+    #[test]
+    fn command_args_leave_missing_readings_empty() {
+        let command = ["say", "{bpm}|{zone}"].map(String::from);
+        assert_eq!(
+            notification(None, None).command_args(&command),
+            ["say", "|"]
+        );
+    }
+
+    #[test]
+    fn notify_command_runs_the_configured_program() {
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"hr_notify_backend": "command", "hr_notify_command": ["notify-phone", "{bpm} bpm"]}"#,
+        )
+        .unwrap();
+
+        let command =
+            notify_command(&notification(Some(150), Some(HrZone::Cardio)), &config).unwrap();
+        assert_eq!(command.get_program(), "notify-phone");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["150 bpm"]);
+        let envs: Vec<_> = command.get_envs().collect();
+        assert!(envs.contains(&(OsStr::new("WAYVR_HR_BPM"), Some(OsStr::new("150")))));
+        assert!(envs.contains(&(OsStr::new("WAYVR_HR_ZONE"), Some(OsStr::new("cardio")))));
+    }
+
+    #[test]
+    fn alerts_of_one_kind_are_sent_once_per_cooldown() {
+        let mut notifier = HrNotifier::default();
+        let cooldown = Duration::from_secs(60);
+        let start = Instant::now();
+
+        assert!(notifier.allow(HrAlert::Threshold, start, cooldown));
+        assert!(!notifier.allow(
+            HrAlert::Threshold,
+            start + Duration::from_secs(10),
+            cooldown
+        ));
+        assert!(notifier.allow(
+            HrAlert::BatteryLow,
+            start + Duration::from_secs(10),
+            cooldown
+        ));
+        assert!(notifier.allow(
+            HrAlert::Threshold,
+            start + Duration::from_secs(61),
+            cooldown
+        ));
+    }
+
+    #[test]
+    fn zero_cooldown_sends_every_alert() {
+        let mut notifier = HrNotifier::default();
+        let now = Instant::now();
+        assert!(notifier.allow(HrAlert::SignalLost, now, Duration::ZERO));
+        assert!(notifier.allow(HrAlert::SignalLost, now, Duration::ZERO));
+    }
+
+    #[test]
+    fn signal_lost_fires_once_after_the_delay() {
+        let lost_after = Duration::from_secs(120);
+        let start = Instant::now();
+        let mut watch = SignalLostWatch::default();
+
+        // never live, nothing was lost
+        assert!(!watch.tick(false, lost_after, start + lost_after));
+
+        assert!(!watch.tick(true, lost_after, start));
+        assert!(!watch.tick(false, lost_after, start + Duration::from_secs(119)));
+        assert!(watch.tick(false, lost_after, start + lost_after));
+        assert!(!watch.tick(false, lost_after, start + Duration::from_secs(300)));
+
+        // live again, the next loss warns again
+        let back = start + Duration::from_secs(400);
+        assert!(!watch.tick(true, lost_after, back));
+        assert!(watch.tick(false, lost_after, back + lost_after));
+    }
+
+    #[test]
+    fn hidden_watch_is_not_a_loss() {
+        let lost_after = Duration::from_secs(120);
+        let start = Instant::now();
+        let mut watch = SignalLostWatch::default();
+
+        watch.tick(true, lost_after, start);
+        watch.forget();
+        assert!(!watch.tick(false, lost_after, start + Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn test_notifications_say_so() {
+        let notification = HrNotification::threshold(170, Some(120), None, true);
+        assert!(notification.summary.starts_with("Test: "));
+
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"hr_notify_backend": "command", "hr_notify_command": ["notify-phone"]}"#,
+        )
+        .unwrap();
+        let command = notify_command(&notification, &config).unwrap();
+        assert!(
+            command
+                .get_envs()
+                .any(|env| env == (OsStr::new("WAYVR_HR_TEST"), Some(OsStr::new("1"))))
+        );
+
+        let real = HrNotification::threshold(170, Some(175), None, false);
+        let command = notify_command(&real, &config).unwrap();
+        assert!(command.get_envs().all(|(key, _)| key != "WAYVR_HR_TEST"));
+    }
+
+    #[test]
+    fn test_notifications_share_the_threshold_cooldown() {
+        // an empty command sends nothing, but still counts for the cooldown
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"hr_notify_backend": "command", "hr_notify_cooldown_secs": 60}"#,
+        )
+        .unwrap();
+        let mut notifier = HrNotifier::default();
+
+        let real = HrNotification::threshold(170, Some(175), None, false);
+        assert!(notifier.send(HrAlert::Threshold, real, &config));
+        let test = HrNotification::threshold(170, Some(175), None, true);
+        assert!(!notifier.send(HrAlert::Threshold, test, &config));
+    }
+
+    #[test]
+    fn notify_command_needs_a_program() {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"hr_notify_backend": "command"}"#).unwrap();
+        assert!(notify_command(&notification(None, None), &config).is_none());
+    }
+}
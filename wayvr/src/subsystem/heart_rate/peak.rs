@@ -0,0 +1,121 @@
+use std::time::{Duration, Instant};
+
+/// Holds the highest recent rate for a while and then lets it fall back to the current one,
+/// like an audio peak meter, so a quickly changing number stays readable.
+pub struct PeakHold {
+    peak: Option<f32>,
+    held_until: Instant,
+    last_update: Instant,
+}
+
+impl Default for PeakHold {
+    fn default() -> Self {
+        Self {
+            peak: None,
+            held_until: Instant::now(),
+            last_update: Instant::now(),
+        }
+    }
+}
+
+impl PeakHold {
+    /// A new peak is held for `dwell`, then falls by `decay` BPM per second until it meets
+    /// `rate`. A decay of 0 drops it to `rate` right away.
+    pub fn update(&mut self, rate: Option<u32>, dwell: Duration, decay: f32) {
+        self.update_at(rate, dwell, decay, Instant::now());
+    }
+
+    fn update_at(&mut self, rate: Option<u32>, dwell: Duration, decay: f32, now: Instant) {
+        // the peak only decays after the dwell, however long ago the last update was
+        let decay_from = std::mem::replace(&mut self.last_update, now).max(self.held_until);
+        let elapsed = now.saturating_duration_since(decay_from).as_secs_f32();
+
+        let Some(rate) = rate.map(|rate| rate as f32) else {
+            self.peak = None;
+            return;
+        };
+
+        match self.peak {
+            Some(peak) if rate < peak => {
+                if now < self.held_until {
+                    return;
+                }
+                self.peak = Some(if decay > 0.0 {
+                    decay.mul_add(-elapsed, peak).max(rate)
+                } else {
+                    rate
+                });
+            }
+            _ => {
+                self.peak = Some(rate);
+                self.held_until = now + dwell;
+            }
+        }
+    }
+
+    pub fn rate(&self) -> Option<u32> {
+        self.peak.map(|peak| peak.round() as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DWELL: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn spike_is_held_then_decays() {
+        let start = Instant::now();
+        let at = |secs: f32| start + Duration::from_secs_f32(secs);
+        let mut hold = PeakHold {
+            peak: None,
+            held_until: start,
+            last_update: start,
+        };
+
+        hold.update_at(Some(90), DWELL, 10.0, at(0.0));
+        hold.update_at(Some(150), DWELL, 10.0, at(1.0));
+        hold.update_at(Some(95), DWELL, 10.0, at(2.0));
+        hold.update_at(Some(95), DWELL, 10.0, at(2.9));
+        assert_eq!(hold.rate(), Some(150));
+
+        // 10 BPM per second once the dwell is over at 3 seconds
+        hold.update_at(Some(95), DWELL, 10.0, at(3.9));
+        assert_eq!(hold.rate(), Some(141));
+        hold.update_at(Some(95), DWELL, 10.0, at(6.9));
+        assert_eq!(hold.rate(), Some(111));
+        hold.update_at(Some(95), DWELL, 10.0, at(10.0));
+        assert_eq!(hold.rate(), Some(95));
+    }
+
+    #[test]
+    fn decay_starts_at_the_end_of_the_dwell() {
+        let start = Instant::now();
+        let at = |secs: f32| start + Duration::from_secs_f32(secs);
+        let mut hold = PeakHold {
+            peak: None,
+            held_until: start,
+            last_update: start,
+        };
+
+        // no update in between, so the time held must not count as decay
+        hold.update_at(Some(150), DWELL, 10.0, at(0.0));
+        hold.update_at(Some(95), DWELL, 10.0, at(2.02));
+        assert_eq!(hold.rate(), Some(150));
+    }
+
+    #[test]
+    fn no_decay_drops_to_the_rate() {
+        let start = Instant::now();
+        let mut hold = PeakHold::default();
+
+        hold.update_at(Some(150), DWELL, 0.0, start);
+        hold.update_at(Some(95), DWELL, 0.0, start + Duration::from_secs(1));
+        assert_eq!(hold.rate(), Some(150));
+        hold.update_at(Some(95), DWELL, 0.0, start + Duration::from_secs(3));
+        assert_eq!(hold.rate(), Some(95));
+        hold.update_at(None, DWELL, 0.0, start + Duration::from_secs(4));
+        assert_eq!(hold.rate(), None);
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Local};
+
+use super::HeartRateSource;
+
+/// Buffered rows are written out at least this often
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Row {
+    time: SystemTime,
+    /// None marks a gap in the readings
+    bpm: Option<u32>,
+    source: &'static str,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Csv,
+    Jsonl,
+}
+
+/// Appends every new reading to a log file, for reviewing a session afterward.
+///
+/// Files ending in `.jsonl` get one JSON object per line, anything else is written as CSV.
+/// Once the file grows past `max_size`, it is moved to `<path>.1` and a new one is started.
+pub struct HeartRateRecorder {
+    sender: mpsc::Sender<Row>,
+    last_read_at: Option<SystemTime>,
+    in_gap: bool,
+    /// When the last row was handed to the writer
+    last_row_at: Option<SystemTime>,
+}
+
+impl HeartRateRecorder {
+    pub fn new(path: PathBuf, max_size: u64) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || run_writer(&path, max_size, &receiver));
+
+        Self {
+            sender,
+            last_read_at: None,
+            // no gap row before the first reading
+            in_gap: true,
+            last_row_at: None,
+        }
+    }
+
+    pub fn tick(&mut self, source: &dyn HeartRateSource) {
+        let reading = source
+            .last_rate()
+            .zip(source.last_read_at())
+            .filter(|_| !source.is_stale());
+
+        let row = match reading {
+            Some((rate, read_at)) if self.last_read_at != Some(read_at) => {
+                self.last_read_at = Some(read_at);
+                self.in_gap = false;
+                Row {
+                    time: read_at,
+                    bpm: Some(rate),
+                    source: source.name(),
+                }
+            }
+            None if !self.in_gap => {
+                self.in_gap = true;
+                Row {
+                    time: SystemTime::now(),
+                    bpm: None,
+                    source: source.name(),
+                }
+            }
+            _ => return,
+        };
+
+        self.last_row_at = Some(SystemTime::now());
+        let _ = self.sender.send(row);
+    }
+
+    pub const fn last_row_at(&self) -> Option<SystemTime> {
+        self.last_row_at
+    }
+}
+
+fn run_writer(path: &Path, max_size: u64, receiver: &mpsc::Receiver<Row>) {
+    let format = if path.extension().is_some_and(|ext| ext == "jsonl") {
+        Format::Jsonl
+    } else {
+        Format::Csv
+    };
+
+    let mut writer: Option<(BufWriter<File>, u64)> = None;
+    loop {
+        let row = match receiver.recv_timeout(FLUSH_INTERVAL) {
+            Ok(row) => row,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if let Some((file, _)) = writer.as_mut() {
+                    let _ = file.flush();
+                }
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+
+        if writer.as_ref().is_some_and(|(_, size)| *size >= max_size) {
+            if let Some((mut file, _)) = writer.take() {
+                let _ = file.flush();
+            }
+            rotate(path);
+        }
+
+        if writer.is_none() {
+            match open_log(path, format) {
+                Ok(opened) => writer = Some(opened),
+                Err(e) => {
+                    log::warn!("Could not open heart rate log {}: {e:?}", path.display());
+                    continue;
+                }
+            }
+        }
+
+        let Some((file, size)) = writer.as_mut() else {
+            continue;
+        };
+        let line = format_row(&row, format);
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            log::warn!("Could not write heart rate log: {e:?}");
+            writer = None;
+            continue;
+        }
+        *size += line.len() as u64;
+    }
+
+    if let Some((mut file, _)) = writer {
+        let _ = file.flush();
+    }
+}
+
+fn open_log(path: &Path, format: Format) -> anyhow::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut size = file.metadata()?.len();
+    let mut file = BufWriter::new(file);
+
+    if size == 0 && format == Format::Csv {
+        let header = "timestamp,bpm,source\n";
+        file.write_all(header.as_bytes())?;
+        size += header.len() as u64;
+    }
+
+    Ok((file, size))
+}
+
+fn rotate(path: &Path) {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    if let Err(e) = std::fs::rename(path, &rotated) {
+        log::warn!("Could not rotate heart rate log {}: {e:?}", path.display());
+    }
+}
+
+fn format_row(row: &Row, format: Format) -> String {
+    let timestamp = DateTime::<Local>::from(row.time).to_rfc3339();
+    match format {
+        Format::Csv => {
+            let bpm = row.bpm.map(|bpm| bpm.to_string()).unwrap_or_default();
+            format!("{timestamp},{bpm},{}\n", row.source)
+        }
+        Format::Jsonl => {
+            let json = serde_json::json!({
+                "timestamp": timestamp,
+                "bpm": row.bpm,
+                "source": row.source,
+            });
+            format!("{json}\n")
+        }
+    }
+}
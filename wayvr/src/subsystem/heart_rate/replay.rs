@@ -0,0 +1,192 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, bail};
+use chrono::DateTime;
+use wlx_common::config::GeneralConfig;
+
+use super::HeartRateSource;
+
+/// Longer stretches without a reading in the recording are played back as stale
+const REPLAY_GAP: Duration = Duration::from_secs(30);
+
+/// Lowest `hr_replay_speed`, so playback never stops entirely
+const MIN_SPEED: f32 = 0.01;
+
+/// Plays back a log written with `hr_log_path` as if it came in live, for reproducing
+/// bug reports and for demos. Each row shows up after the same delay as in the recording,
+/// divided by `hr_replay_speed`. Rows without a bpm and long pauses between rows are
+/// played back as stale readings.
+pub struct ReplayHeartRate {
+    /// Time since the first row, and the bpm or None for a gap
+    rows: Vec<(Duration, Option<u32>)>,
+    next_row: usize,
+    /// How far into the recording the playback is
+    position: Duration,
+    last_update_at: Option<Instant>,
+    /// Position of the last reading
+    last_row_at: Option<Duration>,
+    in_gap: bool,
+    finished: bool,
+    fresh: bool,
+    last_rate: Option<u32>,
+    last_read_at: Option<SystemTime>,
+}
+
+impl ReplayHeartRate {
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        let rows = parse_rows(&text);
+        let Some(&(first, _)) = rows.first() else {
+            bail!("{} has no readings", path.display());
+        };
+        log::info!(
+            "Replaying {} heart rate rows from {}",
+            rows.len(),
+            path.display()
+        );
+
+        Ok(Self {
+            rows: rows
+                .into_iter()
+                .map(|(time, bpm)| (time.duration_since(first).unwrap_or_default(), bpm))
+                .collect(),
+            next_row: 0,
+            position: Duration::ZERO,
+            last_update_at: None,
+            last_row_at: None,
+            in_gap: false,
+            finished: false,
+            fresh: false,
+            last_rate: None,
+            last_read_at: None,
+        })
+    }
+
+    fn restart(&mut self) {
+        self.next_row = 0;
+        self.position = Duration::ZERO;
+        self.last_row_at = None;
+    }
+}
+
+impl HeartRateSource for ReplayHeartRate {
+    fn name(&self) -> &'static str {
+        "replay"
+    }
+
+    fn update(&mut self, config: &GeneralConfig, _watch_visible: bool) {
+        self.fresh = false;
+
+        // the speed may change while playing, so the position moves on step by step
+        let now = Instant::now();
+        if let Some(last_update_at) = self.last_update_at {
+            let elapsed = now.duration_since(last_update_at);
+            self.position += elapsed.mul_f32(config.hr_replay_speed.max(MIN_SPEED));
+        }
+        self.last_update_at = Some(now);
+
+        if self.finished && config.hr_replay_loop {
+            self.finished = false;
+            self.restart();
+        }
+
+        while let Some(&(offset, bpm)) = self.rows.get(self.next_row)
+            && offset <= self.position
+        {
+            self.next_row += 1;
+            match bpm {
+                Some(rate) => {
+                    self.last_rate = Some(rate);
+                    self.last_read_at = Some(SystemTime::now());
+                    self.last_row_at = Some(offset);
+                    self.in_gap = false;
+                    self.fresh = true;
+                }
+                None => self.in_gap = true,
+            }
+        }
+
+        if self.next_row >= self.rows.len() && !self.finished {
+            if config.hr_replay_loop {
+                log::debug!("Heart rate replay reached its end, starting over.");
+                self.restart();
+            } else {
+                log::info!("Heart rate replay finished.");
+                self.finished = true;
+            }
+        }
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.last_rate
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        self.last_read_at
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.fresh
+    }
+
+    fn is_stale(&self) -> bool {
+        self.last_rate.is_none()
+            || self.in_gap
+            || self
+                .last_row_at
+                .is_some_and(|last_row_at| self.position.saturating_sub(last_row_at) > REPLAY_GAP)
+    }
+}
+
+/// Rows of the CSV or JSONL log in the order of their timestamps.
+/// Lines that can't be parsed, like the CSV header, are skipped.
+fn parse_rows(text: &str) -> Vec<(SystemTime, Option<u32>)> {
+    let mut rows: Vec<_> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            let row = if line.starts_with('{') {
+                parse_json_row(line)
+            } else {
+                parse_csv_row(line)
+            };
+            if row.is_none() && !line.starts_with("timestamp,") {
+                log::debug!("Heart rate replay: ignoring malformed line {line:?}");
+            }
+            row
+        })
+        .collect();
+    rows.sort_by_key(|(time, _)| *time);
+    rows
+}
+
+fn parse_csv_row(line: &str) -> Option<(SystemTime, Option<u32>)> {
+    let mut fields = line.split(',');
+    let time = parse_timestamp(fields.next()?)?;
+    let bpm = match fields.next().map(str::trim) {
+        None | Some("") => None,
+        Some(bpm) => Some(bpm.parse().ok()?),
+    };
+    Some((time, bpm))
+}
+
+fn parse_json_row(line: &str) -> Option<(SystemTime, Option<u32>)> {
+    let value: serde_json::Value = serde_json::from_str(line).ok()?;
+    let time = parse_timestamp(value.get("timestamp")?.as_str()?)?;
+    let bpm = match value.get("bpm") {
+        None | Some(serde_json::Value::Null) => None,
+        Some(bpm) => Some(u32::try_from(bpm.as_u64()?).ok()?),
+    };
+    Some((time, bpm))
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    DateTime::parse_from_rfc3339(timestamp.trim())
+        .ok()
+        .map(SystemTime::from)
+}
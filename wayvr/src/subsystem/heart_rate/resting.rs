@@ -0,0 +1,35 @@
+use std::time::{Duration, Instant};
+
+/// Samples the heart rate over a quiet period to suggest a value for `hr_resting`.
+pub struct RestingCalibration {
+    until: Instant,
+    samples: Vec<u32>,
+}
+
+impl RestingCalibration {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            until: Instant::now() + duration,
+            samples: vec![],
+        }
+    }
+
+    pub fn sample(&mut self, rate: u32) {
+        self.samples.push(rate);
+    }
+
+    pub fn is_done(&self) -> bool {
+        Instant::now() >= self.until
+    }
+
+    /// The lower quartile of the samples. The rate keeps settling during the period and
+    /// moving only ever pushes it up, so the low end is closer to the true resting rate.
+    pub fn result(mut self) -> Option<u32> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        self.samples.sort_unstable();
+        Some(self.samples[self.samples.len() / 4])
+    }
+}
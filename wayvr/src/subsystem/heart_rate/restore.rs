@@ -0,0 +1,228 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use wlx_common::{cache_dir, config::GeneralConfig};
+
+use super::{HeartRateSource, HrStatus};
+
+const LAST_READING_FILE: &str = "last_heart_rate.json";
+
+#[derive(Serialize, Deserialize)]
+struct LastReading {
+    source: String,
+    bpm: u32,
+    /// Unix time of the reading
+    ts: u64,
+}
+
+/// Shows the reading `inner` had when wayvr last shut down until it has one of its own, so the
+/// overlay isn't empty while the first poll is in flight. The restored reading is always stale,
+/// and it's only used if it's no older than `max_age` and from the same source.
+pub struct RestoredHeartRate {
+    inner: Box<dyn HeartRateSource>,
+    path: PathBuf,
+    /// Dropped for good once `inner` has a reading
+    restored: Option<(u32, SystemTime)>,
+}
+
+impl RestoredHeartRate {
+    pub fn new(inner: Box<dyn HeartRateSource>, max_age: Duration) -> Self {
+        Self::from_file(inner, cache_dir::get_path(LAST_READING_FILE), max_age)
+    }
+
+    fn from_file(inner: Box<dyn HeartRateSource>, path: PathBuf, max_age: Duration) -> Self {
+        let restored = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<LastReading>(&json).ok())
+            .filter(|last| last.source == inner.name())
+            .map(|last| {
+                (
+                    last.bpm,
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(last.ts),
+                )
+            })
+            .filter(|(_, read_at)| read_at.elapsed().is_ok_and(|age| age <= max_age));
+        if let Some((rate, _)) = restored {
+            log::info!("Showing the last heart rate of {rate} bpm until a new reading comes in.");
+        }
+
+        Self {
+            inner,
+            path,
+            restored,
+        }
+    }
+
+    /// Shows `last`, the reading of the chain `inner` replaces, until `inner` has one of its own
+    pub fn carried_over(inner: Box<dyn HeartRateSource>, last: (u32, SystemTime)) -> Self {
+        Self {
+            inner,
+            path: cache_dir::get_path(LAST_READING_FILE),
+            restored: Some(last),
+        }
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let (Some(bpm), Some(read_at)) = (self.inner.last_rate(), self.inner.last_read_at()) else {
+            return Ok(());
+        };
+        let json = serde_json::to_string(&LastReading {
+            source: self.inner.name().to_string(),
+            bpm,
+            ts: read_at.duration_since(SystemTime::UNIX_EPOCH)?.as_secs(),
+        })?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, json)?;
+        Ok(())
+    }
+}
+
+impl HeartRateSource for RestoredHeartRate {
+    fn inner(&self) -> Option<&dyn HeartRateSource> {
+        Some(self.inner.as_ref())
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn HeartRateSource> {
+        Some(self.inner.as_mut())
+    }
+
+    fn update(&mut self, config: &GeneralConfig, watch_visible: bool) {
+        self.inner.update(config, watch_visible);
+        if self.restored.is_some() && self.inner.last_rate().is_some() {
+            self.restored = None;
+        }
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.inner
+            .last_rate()
+            .or(self.restored.map(|(rate, _)| rate))
+    }
+
+    fn raw_rate(&self) -> Option<u32> {
+        self.inner
+            .raw_rate()
+            .or(self.restored.map(|(rate, _)| rate))
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        self.inner
+            .last_read_at()
+            .or(self.restored.map(|(_, read_at)| read_at))
+    }
+
+    fn last_reading_time(&self) -> Option<SystemTime> {
+        self.inner
+            .last_reading_time()
+            .or(self.restored.map(|(_, read_at)| read_at))
+    }
+
+    fn is_stale(&self) -> bool {
+        self.restored.is_some() || self.inner.is_stale()
+    }
+
+    fn status(&self) -> HrStatus {
+        match self.restored {
+            Some((rate, _)) => HrStatus::Stale(rate),
+            None => self.inner.status(),
+        }
+    }
+
+    fn flush_on_shutdown(&mut self) {
+        self.inner.flush_on_shutdown();
+        if let Err(e) = self.save() {
+            log::warn!("Could not save the last heart rate: {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    /// A source whose first reading the test hands in
+    struct FirstPoll(Rc<Cell<Option<u32>>>);
+
+    impl HeartRateSource for FirstPoll {
+        fn name(&self) -> &'static str {
+            "first_poll"
+        }
+
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            self.0.get()
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            self.0.get().map(|_| SystemTime::now())
+        }
+    }
+
+    /// A saved reading `age` old, in a file of its own
+    fn saved(file: &str, source: &str, age: Duration) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("wayvr-hr-{}-{file}", std::process::id()));
+        let ts = (SystemTime::now() - age)
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        std::fs::write(
+            &path,
+            format!(r#"{{"source": "{source}", "bpm": 64, "ts": {ts}}}"#),
+        )
+        .unwrap();
+        path
+    }
+
+    #[test]
+    fn restored_reading_is_stale_until_a_live_one() {
+        let config: GeneralConfig = serde_json::from_str("{}").unwrap();
+        let path = saved("restored", "first_poll", Duration::from_secs(60));
+        let first_poll = Rc::new(Cell::new(None));
+        let mut source = RestoredHeartRate::from_file(
+            Box::new(FirstPoll(first_poll.clone())),
+            path.clone(),
+            Duration::from_secs(600),
+        );
+        std::fs::remove_file(path).unwrap();
+
+        source.update(&config, true);
+        assert_eq!(source.last_rate(), Some(64));
+        assert!(source.is_stale());
+        assert_eq!(source.status(), HrStatus::Stale(64));
+
+        first_poll.set(Some(80));
+        source.update(&config, true);
+        assert_eq!(source.last_rate(), Some(80));
+        assert!(!source.is_stale());
+
+        // gone for good, also if the source loses its reading again
+        first_poll.set(None);
+        source.update(&config, true);
+        assert_eq!(source.last_rate(), None);
+    }
+
+    #[test]
+    fn old_or_foreign_readings_are_not_restored() {
+        for (file, source, age) in [
+            ("old", "first_poll", Duration::from_secs(3600)),
+            ("foreign", "fitbit", Duration::from_secs(60)),
+        ] {
+            let path = saved(file, source, age);
+            let restored = RestoredHeartRate::from_file(
+                Box::new(FirstPoll(Rc::default())),
+                path.clone(),
+                Duration::from_secs(600),
+            );
+            std::fs::remove_file(path).unwrap();
+            assert_eq!(restored.last_rate(), None);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::time::SystemTime;
+
+use wlx_common::config::GeneralConfig;
+
+use super::HeartRateSource;
+
+/// Holds back the readings of `inner` after it (re)connects until `required` plausible readings
+/// arrived in a row, since straps often report garbage or a sudden jump in the first second.
+/// Expects to wrap a `RangeFilter`, so that only accepted readings are fresh.
+pub struct SettlingHeartRate {
+    inner: Box<dyn HeartRateSource>,
+    required: u32,
+    /// Plausible readings in a row since the source (re)connected
+    count: u32,
+    /// Whether the last `update` passed on a new reading
+    fresh: bool,
+}
+
+impl SettlingHeartRate {
+    pub fn new(inner: Box<dyn HeartRateSource>, required: u32) -> Self {
+        Self {
+            inner,
+            required,
+            count: 0,
+            fresh: false,
+        }
+    }
+
+    const fn is_settled(&self) -> bool {
+        self.count >= self.required
+    }
+}
+
+impl HeartRateSource for SettlingHeartRate {
+    fn inner(&self) -> Option<&dyn HeartRateSource> {
+        Some(self.inner.as_ref())
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn HeartRateSource> {
+        Some(self.inner.as_mut())
+    }
+
+    fn update(&mut self, config: &GeneralConfig, watch_visible: bool) {
+        self.inner.update(config, watch_visible);
+        self.fresh = false;
+
+        // lost the connection, or the range filter discarded the latest reading
+        let implausible = self
+            .inner
+            .raw_rate()
+            .is_some_and(|raw| !(config.hr_min_bpm..=config.hr_max_bpm).contains(&raw));
+        if self.inner.last_rate().is_none() || self.inner.is_stale() || implausible {
+            self.count = 0;
+            return;
+        }
+
+        if !self.inner.is_fresh() {
+            return;
+        }
+
+        if !self.is_settled() {
+            self.count += 1;
+            if !self.is_settled() {
+                return;
+            }
+            log::debug!(
+                "Heart rate of {} settled after {} readings",
+                self.inner.name(),
+                self.required
+            );
+        }
+        self.fresh = true;
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.inner.last_rate().filter(|_| self.is_settled())
+    }
+
+    fn raw_rate(&self) -> Option<u32> {
+        self.inner.raw_rate().filter(|_| self.is_settled())
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        self.inner.last_read_at().filter(|_| self.is_settled())
+    }
+
+    fn last_reading_time(&self) -> Option<SystemTime> {
+        self.inner.last_reading_time().filter(|_| self.is_settled())
+    }
+
+    fn last_hrv(&self) -> Option<f32> {
+        self.inner.last_hrv().filter(|_| self.is_settled())
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.fresh
+    }
+
+    fn is_stale(&self) -> bool {
+        !self.is_settled() || self.inner.is_stale()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    /// A strap whose connection the test controls, None while it's disconnected
+    struct Link(Rc<Cell<Option<u32>>>);
+
+    impl HeartRateSource for Link {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            self.0.get()
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            self.0.get().map(|_| SystemTime::now())
+        }
+    }
+
+    #[test]
+    fn readings_after_a_reconnect_are_held_back() {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"hr_min_bpm": 25, "hr_max_bpm": 250}"#).unwrap();
+        let link = Rc::new(Cell::new(None));
+        let mut settling = SettlingHeartRate::new(Box::new(Link(link.clone())), 3);
+        let mut read = |rate: Option<u32>| {
+            link.set(rate);
+            settling.update(&config, true);
+            settling.last_rate()
+        };
+
+        assert_eq!(read(Some(180)), None);
+        assert_eq!(read(Some(82)), None);
+        assert_eq!(read(Some(80)), Some(80));
+        assert_eq!(read(Some(81)), Some(81));
+
+        // reconnecting starts over, as does a reading out of range
+        assert_eq!(read(None), None);
+        assert_eq!(read(Some(79)), None);
+        assert_eq!(read(Some(300)), None);
+        assert_eq!(read(Some(79)), None);
+        assert_eq!(read(Some(80)), None);
+        assert_eq!(read(Some(81)), Some(81));
+    }
+}
@@ -0,0 +1,78 @@
+use std::{
+    f32::consts::TAU,
+    time::{Duration, Instant, SystemTime},
+};
+
+use wlx_common::config::GeneralConfig;
+
+use super::HeartRateSource;
+
+/// Straps send about one reading per second, so the simulation does the same
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Generated heart rate for demos, screenshots and testing the pipeline without hardware.
+///
+/// `sine` oscillates around `hr_sim_base`, `workout` repeatedly warms up to
+/// `hr_sim_base + hr_sim_amplitude`, holds there and cools down again.
+pub struct SimulatedHeartRate {
+    started_at: Instant,
+    next_sample_at: Instant,
+    last_rate: Option<u32>,
+    last_read_at: Option<SystemTime>,
+}
+
+impl Default for SimulatedHeartRate {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            next_sample_at: Instant::now(),
+            last_rate: None,
+            last_read_at: None,
+        }
+    }
+}
+
+impl HeartRateSource for SimulatedHeartRate {
+    fn name(&self) -> &'static str {
+        "simulated"
+    }
+
+    fn update(&mut self, config: &GeneralConfig, _watch_visible: bool) {
+        let now = Instant::now();
+        if now < self.next_sample_at {
+            return;
+        }
+        self.next_sample_at = now + SAMPLE_INTERVAL;
+
+        let period = config.hr_sim_period_secs.max(1.);
+        let phase = (now.duration_since(self.started_at).as_secs_f32() % period) / period;
+        let offset = match config.hr_sim_pattern.trim() {
+            "workout" => workout(phase),
+            _ => (phase * TAU).sin(),
+        };
+
+        let rate = config.hr_sim_amplitude.mul_add(offset, config.hr_sim_base);
+        self.last_rate = Some(rate.round().max(0.) as u32);
+        self.last_read_at = Some(SystemTime::now());
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.last_rate
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        self.last_read_at
+    }
+}
+
+/// 0 at rest and 1 at the peak, for `phase` in 0..1
+fn workout(phase: f32) -> f32 {
+    match phase {
+        // warm up
+        p if p < 0.2 => 0.9 * p / 0.2,
+        // intervals around the peak
+        p if p < 0.7 => 0.1f32.mul_add(((p - 0.2) * 5. * TAU).sin(), 0.9),
+        // cool down
+        p => 0.9 * (1. - (p - 0.7) / 0.3),
+    }
+}
@@ -0,0 +1,422 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use wlx_common::config::{GeneralConfig, HrFormat};
+
+use super::{
+    HeartRateSource, IdleReason, NoHeartRate, Trend, format_rate,
+    gap::GapTolerance,
+    peak::PeakHold,
+    resting::RestingCalibration,
+    zone::{self, HrZone, ZoneTracker},
+};
+
+/// Sources are sampled at a fixed rate, so the window length doesn't depend on the poll rate
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time constant of the trend baseline, in samples. Long enough to span
+/// a few of Fitbit's one-minute readings.
+const TREND_BASELINE_SAMPLES: f32 = 120.;
+
+/// Sliding window average over the readings of another source.
+pub struct SmoothedHeartRate {
+    inner: Box<dyn HeartRateSource>,
+    samples: VecDeque<u32>,
+    window: usize,
+    baseline: Option<f32>,
+    trend_deadband: f32,
+    next_sample_at: Instant,
+    gap: GapTolerance,
+    zone: ZoneTracker,
+    /// Only used with `hr_peak_hold_secs` set
+    peak: Option<PeakHold>,
+    /// `hr_resting`, or the calibrated rate if it's not set, or the one of the source profile
+    resting: Option<u32>,
+    max_rate: u32,
+    calibration: Option<RestingCalibration>,
+    calibrated_resting: Option<u32>,
+    /// The result of a calibration that finished, until it's taken
+    new_calibrated_resting: Option<u32>,
+    /// Whether polls are held back because the headset is idle, reported in place of a hidden watch
+    headset_idle: bool,
+}
+
+impl SmoothedHeartRate {
+    pub fn new(inner: Box<dyn HeartRateSource>) -> Self {
+        Self {
+            inner,
+            samples: VecDeque::new(),
+            window: 1,
+            baseline: None,
+            trend_deadband: 0.,
+            next_sample_at: Instant::now(),
+            gap: GapTolerance::default(),
+            zone: ZoneTracker::default(),
+            peak: None,
+            resting: None,
+            max_rate: 0,
+            calibration: None,
+            calibrated_resting: None,
+            new_calibrated_resting: None,
+            headset_idle: false,
+        }
+    }
+
+    pub const fn set_headset_idle(&mut self, idle: bool) {
+        self.headset_idle = idle;
+    }
+
+    /// Replaces the source chain with one built from `config`, see `heart_rate::rebuild`.
+    /// The smoothing window, zone and calibration carry on.
+    pub fn rebuild_source(&mut self, config: &GeneralConfig) {
+        let previous = std::mem::replace(&mut self.inner, Box::new(NoHeartRate));
+        self.inner = super::rebuild(previous, config);
+    }
+
+    /// The rate for the overlay, `smoothed_rate` held at recent peaks with `hr_peak_hold_secs`
+    pub fn display_rate(&self) -> Option<u32> {
+        self.peak
+            .as_ref()
+            .map_or_else(|| self.smoothed_rate(), PeakHold::rate)
+    }
+
+    fn update_peak(&mut self, config: &GeneralConfig) {
+        let Some(dwell) = Duration::try_from_secs_f32(config.hr_peak_hold_secs)
+            .ok()
+            .filter(|dwell| !dwell.is_zero())
+        else {
+            self.peak = None;
+            return;
+        };
+
+        let rate = self.smoothed_rate();
+        self.peak
+            .get_or_insert_default()
+            .update(rate, dwell, config.hr_peak_decay_bpm_per_sec);
+    }
+
+    /// Samples the heart rate for `duration` and then uses the resting rate found in it
+    /// for the rest of the session, unless `hr_resting` is set.
+    pub fn start_resting_calibration(&mut self, duration: Duration) {
+        log::info!(
+            "Calibrating the resting heart rate for {}s.",
+            duration.as_secs()
+        );
+        self.calibration = Some(RestingCalibration::new(duration));
+    }
+
+    /// `hr_resting`, a calibrated rate or the one of the source profile, in that order
+    pub const fn resting(&self) -> Option<u32> {
+        self.resting
+    }
+
+    pub const fn is_calibrating_resting(&self) -> bool {
+        self.calibration.is_some()
+    }
+
+    /// The resting rate a calibration found, once after it finished
+    pub const fn take_calibrated_resting(&mut self) -> Option<u32> {
+        self.new_calibrated_resting.take()
+    }
+
+    fn finish_calibration(&mut self) {
+        if !self
+            .calibration
+            .as_ref()
+            .is_some_and(RestingCalibration::is_done)
+        {
+            return;
+        }
+
+        match self.calibration.take().and_then(RestingCalibration::result) {
+            Some(resting) => {
+                log::info!(
+                    "Resting heart rate calibrated to {resting} BPM, set hr_resting: {resting} to keep it."
+                );
+                self.calibrated_resting = Some(resting);
+                self.new_calibrated_resting = Some(resting);
+            }
+            None => log::warn!("No heart rate came in during the resting calibration."),
+        }
+    }
+
+    fn sample(&mut self, rate: u32) {
+        while self.samples.len() >= self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rate);
+        if let Some(calibration) = self.calibration.as_mut() {
+            calibration.sample(rate);
+        }
+
+        let rate = rate as f32;
+        self.baseline = Some(self.baseline.map_or(rate, |baseline| {
+            baseline + (rate - baseline) / TREND_BASELINE_SAMPLES
+        }));
+    }
+}
+
+impl HeartRateSource for SmoothedHeartRate {
+    fn inner(&self) -> Option<&dyn HeartRateSource> {
+        Some(self.inner.as_ref())
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn HeartRateSource> {
+        Some(self.inner.as_mut())
+    }
+
+    fn update(&mut self, config: &GeneralConfig, watch_visible: bool) {
+        self.inner.update(config, watch_visible);
+        self.window = config.hr_smoothing_window.max(1);
+        self.trend_deadband = config.hr_trend_deadband;
+        self.finish_calibration();
+        let profile = self.inner.source_profile();
+        self.resting = config
+            .hr_resting
+            .or(self.calibrated_resting)
+            .or(profile.resting);
+        self.max_rate = zone::max_rate(config, profile.age);
+
+        let rate = self.inner.last_rate();
+        // a stale reading is a dropout too, it must not keep filling the window
+        let stale = self.inner.is_stale();
+        let gap_exceeded = self.gap.exceeded(rate.is_some() && !stale, config);
+        // don't blend readings across a gap, but bridge a short dropout
+        if gap_exceeded {
+            self.samples.clear();
+            self.baseline = None;
+        }
+        let Some(rate) = rate else {
+            self.zone.update(None, config, self.resting, self.max_rate);
+            self.update_peak(config);
+            return;
+        };
+
+        let now = Instant::now();
+        if !stale && now >= self.next_sample_at {
+            self.next_sample_at = now + SAMPLE_INTERVAL;
+            self.sample(rate);
+        }
+
+        // zones follow the displayed rate, so the tint matches the number
+        self.zone
+            .update(self.smoothed_rate(), config, self.resting, self.max_rate);
+        self.update_peak(config);
+    }
+
+    fn idle_reason(&self) -> Option<IdleReason> {
+        match self.inner.idle_reason() {
+            Some(IdleReason::WatchHidden) if self.headset_idle => Some(IdleReason::HeadsetIdle),
+            reason => reason,
+        }
+    }
+
+    fn smoothed_rate(&self) -> Option<u32> {
+        let rate = self.inner.last_rate()?;
+        if self.window <= 1 || self.samples.is_empty() {
+            return Some(rate);
+        }
+
+        let sum: u32 = self.samples.iter().sum();
+        let len = self.samples.len() as u32;
+        Some((sum + len / 2) / len)
+    }
+
+    fn zone(&self) -> Option<HrZone> {
+        self.zone.zone()
+    }
+
+    /// Shows the held peak, see `display_rate`
+    fn formatted(&self, format: HrFormat) -> String {
+        format_rate(self.display_rate(), format)
+    }
+
+    fn reserve_percent(&self) -> Option<f32> {
+        let resting = self.resting?;
+        let rate = self.smoothed_rate()?;
+        if self.max_rate <= resting {
+            return None;
+        }
+
+        Some(((rate as f32 - resting as f32) / (self.max_rate - resting) as f32).clamp(0.0, 1.0))
+    }
+
+    fn trend(&self) -> Trend {
+        let (Some(rate), Some(baseline)) = (self.smoothed_rate(), self.baseline) else {
+            return Trend::Steady;
+        };
+
+        let delta = rate as f32 - baseline;
+        if delta > self.trend_deadband {
+            Trend::Rising
+        } else if delta < -self.trend_deadband {
+            Trend::Falling
+        } else {
+            Trend::Steady
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+    use crate::subsystem::heart_rate::SourceProfile;
+
+    /// Reports whatever the test put into the cell
+    struct Reading(Rc<Cell<Option<u32>>>);
+
+    impl HeartRateSource for Reading {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            self.0.get()
+        }
+
+        fn last_read_at(&self) -> Option<std::time::SystemTime> {
+            None
+        }
+    }
+
+    /// Keeps reporting 80 BPM, stale once the test says so
+    struct Aging(Rc<Cell<bool>>);
+
+    impl HeartRateSource for Aging {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            Some(80)
+        }
+
+        fn last_read_at(&self) -> Option<std::time::SystemTime> {
+            None
+        }
+
+        fn is_stale(&self) -> bool {
+            self.0.get()
+        }
+    }
+
+    /// Reads 150 BPM from an account that knows the resting rate and age of the user
+    struct Profiled(SourceProfile);
+
+    impl HeartRateSource for Profiled {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            Some(150)
+        }
+
+        fn last_read_at(&self) -> Option<std::time::SystemTime> {
+            None
+        }
+
+        fn source_profile(&self) -> SourceProfile {
+            self.0
+        }
+    }
+
+    #[test]
+    fn source_profile_fills_in_for_unset_resting_rate_and_age() {
+        let zone_with = |config: serde_json::Value| {
+            let config: GeneralConfig = serde_json::from_value(config).unwrap();
+            let mut smoothed = SmoothedHeartRate::new(Box::new(Profiled(SourceProfile {
+                resting: Some(60),
+                age: Some(40),
+            })));
+            smoothed.update(&config, true);
+            (smoothed.resting, smoothed.max_rate, smoothed.zone())
+        };
+
+        // a reserve of 180 - 60, with the cardio zone from 144
+        assert_eq!(
+            zone_with(serde_json::json!({"hr_max_from_age": true, "hr_zones_from_reserve": true})),
+            (Some(60), 180, Some(HrZone::Cardio))
+        );
+        // a reserve of 194 - 100, with the fat burn zone from 156
+        assert_eq!(
+            zone_with(serde_json::json!({
+                "hr_max_from_age": true,
+                "hr_zones_from_reserve": true,
+                "hr_resting": 100,
+                "hr_profile_age": 20
+            })),
+            (Some(100), 194, Some(HrZone::Rest))
+        );
+    }
+
+    #[test]
+    fn missed_reading_within_the_tolerance_keeps_the_smoothing() {
+        let after_dropout = |config: &str| {
+            let config: GeneralConfig = serde_json::from_str(config).unwrap();
+            let reading = Rc::new(Cell::new(Some(80)));
+            let mut smoothed = SmoothedHeartRate::new(Box::new(Reading(reading.clone())));
+            smoothed.update(&config, true);
+            reading.set(None);
+            smoothed.update(&config, true);
+            (smoothed.samples.len(), smoothed.baseline.is_some())
+        };
+
+        assert_eq!(after_dropout(r#"{"hr_gap_tolerance_secs": 10}"#), (1, true));
+        assert_eq!(after_dropout(r#"{"hr_gap_tolerance_secs": 0}"#), (0, false));
+    }
+
+    #[test]
+    fn stale_reading_resets_the_smoothing() {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"hr_gap_tolerance_secs": 0}"#).unwrap();
+        let stale = Rc::new(Cell::new(false));
+        let mut smoothed = SmoothedHeartRate::new(Box::new(Aging(stale.clone())));
+        smoothed.update(&config, true);
+        assert_eq!(
+            (smoothed.samples.len(), smoothed.baseline.is_some()),
+            (1, true)
+        );
+
+        stale.set(true);
+        smoothed.next_sample_at = Instant::now();
+        smoothed.update(&config, true);
+        assert_eq!(
+            (smoothed.samples.len(), smoothed.baseline.is_some()),
+            (0, false)
+        );
+
+        // still not sampled while it stays stale
+        smoothed.next_sample_at = Instant::now();
+        smoothed.update(&config, true);
+        assert!(smoothed.samples.is_empty());
+    }
+
+    /// Feeds `rates` one sample each, with a deadband of 2 BPM
+    fn trend_after(rates: impl IntoIterator<Item = u32>) -> Trend {
+        let reading = Rc::new(Cell::new(None));
+        let mut smoothed = SmoothedHeartRate::new(Box::new(Reading(reading.clone())));
+        smoothed.trend_deadband = 2.0;
+        for rate in rates {
+            reading.set(Some(rate));
+            smoothed.sample(rate);
+        }
+        smoothed.trend()
+    }
+
+    #[test]
+    fn rising_ramp_is_rising() {
+        let settled = std::iter::repeat_n(60, 60);
+        assert_eq!(trend_after(settled.chain(60..90)), Trend::Rising);
+    }
+
+    #[test]
+    fn falling_ramp_is_falling() {
+        let settled = std::iter::repeat_n(120, 60);
+        assert_eq!(trend_after(settled.chain((90..120).rev())), Trend::Falling);
+    }
+
+    #[test]
+    fn noise_within_the_deadband_is_steady() {
+        let noise = (0..120).map(|i| if i % 2 == 0 { 69 } else { 71 });
+        assert_eq!(trend_after(noise), Trend::Steady);
+    }
+}
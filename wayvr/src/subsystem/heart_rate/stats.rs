@@ -0,0 +1,467 @@
+use std::{
+    collections::VecDeque,
+    f32::consts::TAU,
+    time::{Duration, Instant, SystemTime},
+};
+
+use wlx_common::config::GeneralConfig;
+
+use super::{HeartRateSource, zone::HrZone};
+
+/// Longest time between two observations that is still counted towards the calories and the
+/// time in zone
+const MAX_CALORIE_STEP: Duration = Duration::from_secs(5);
+/// Readings the coherence is computed over, resampled to one per second
+const COHERENCE_WINDOW_SECS: usize = 64;
+/// Coarser sources can't show the oscillation of breathing, e.g. Fitbit with `1min`
+const COHERENCE_MAX_SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A stretch of readings on one side of `hr_segment_threshold_bpm`
+pub struct Segment {
+    pub started_at: SystemTime,
+    /// Whether the readings are above the threshold, e.g. work rather than rest
+    pub above: bool,
+    pub min: u32,
+    pub max: u32,
+    sum: u64,
+    count: u64,
+}
+
+impl Segment {
+    fn new(started_at: SystemTime, above: bool, rate: u32) -> Self {
+        Self {
+            started_at,
+            above,
+            min: rate,
+            max: rate,
+            sum: u64::from(rate),
+            count: 1,
+        }
+    }
+
+    fn record(&mut self, rate: u32) {
+        self.min = self.min.min(rate);
+        self.max = self.max.max(rate);
+        self.sum += u64::from(rate);
+        self.count += 1;
+    }
+
+    pub fn avg(&self) -> f32 {
+        self.sum as f32 / self.count as f32
+    }
+}
+
+/// Minimum, maximum and average heart rate since the last `reset`.
+/// They are kept across source switches, the readings of all sources count towards them.
+#[derive(Default)]
+pub struct HeartRateStats {
+    min: Option<u32>,
+    max: Option<u32>,
+    sum: u64,
+    count: u64,
+    last_read_at: Option<SystemTime>,
+    /// Estimated kcal burned, if the profile is configured
+    calories: Option<f32>,
+    last_observed_at: Option<Instant>,
+    /// Set by `start_session`, for the duration of the session
+    session_started_at: Option<Instant>,
+    /// Set by `stop_session`, nothing is recorded until the next session starts
+    session_stopped_at: Option<Instant>,
+    /// Readings of the last `COHERENCE_WINDOW_SECS`, oldest first
+    samples: VecDeque<(Instant, u32)>,
+    /// Every reading since `start_session`, for exporting the session
+    session_samples: Vec<(SystemTime, u32)>,
+    /// Time spent in each `HrZone` since `start_session`
+    session_zone_time: [Duration; 4],
+    /// With `hr_segment_threshold_bpm`, oldest first
+    segments: Vec<Segment>,
+    /// Since when the readings are on the other side of the threshold than the last segment,
+    /// with the time of the first of them
+    crossed_at: Option<(Instant, SystemTime)>,
+}
+
+impl HeartRateStats {
+    /// Records the latest reading of `source`, if it is a new one.
+    pub fn observe(&mut self, source: &dyn HeartRateSource, config: &GeneralConfig) {
+        self.observe_at(source, config, Instant::now());
+    }
+
+    pub(super) fn observe_at(
+        &mut self,
+        source: &dyn HeartRateSource,
+        config: &GeneralConfig,
+        now: Instant,
+    ) {
+        let last_observed_at = self.last_observed_at.take();
+        if self.session_stopped_at.is_some() || source.is_stale() {
+            return;
+        }
+
+        let (Some(rate), Some(read_at)) = (source.last_rate(), source.last_read_at()) else {
+            return;
+        };
+
+        self.last_observed_at = Some(now);
+        let elapsed = last_observed_at
+            .map(|at| now.duration_since(at).min(MAX_CALORIE_STEP))
+            .unwrap_or_default();
+        match calories_per_minute(rate, config) {
+            Some(per_minute) => {
+                *self.calories.get_or_insert(0.) += per_minute * elapsed.as_secs_f32() / 60.;
+            }
+            None => self.calories = None,
+        }
+        if self.session_started_at.is_some()
+            && let Some(zone) = source.zone()
+        {
+            self.session_zone_time[zone.index()] += elapsed;
+        }
+
+        if self.last_read_at == Some(read_at) {
+            return;
+        }
+        self.last_read_at = Some(read_at);
+        self.record(rate);
+        self.segment(rate, read_at, config, now);
+        if self.session_started_at.is_some() {
+            self.session_samples.push((read_at, rate));
+        }
+
+        self.samples.push_back((now, rate));
+        let window = Duration::from_secs(COHERENCE_WINDOW_SECS as u64);
+        while self
+            .samples
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > window)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn record(&mut self, rate: u32) {
+        self.min = Some(self.min.map_or(rate, |min| min.min(rate)));
+        self.max = Some(self.max.map_or(rate, |max| max.max(rate)));
+        self.sum += u64::from(rate);
+        self.count += 1;
+    }
+
+    /// Counts readings from before startup towards the min, max and average, see
+    /// `take_backfill`. They are too coarse for the calories, the zones and the coherence.
+    pub fn backfill(&mut self, readings: &[(SystemTime, u32)]) {
+        if self.session_stopped_at.is_some() {
+            return;
+        }
+        for (_, rate) in readings {
+            self.record(*rate);
+        }
+    }
+
+    /// Adds `rate` to the current segment, or starts a new one once the readings stayed on the
+    /// other side of the threshold for `hr_segment_after_secs`. Readings until then still count
+    /// towards the current one, so a short spike doesn't split it.
+    fn segment(&mut self, rate: u32, read_at: SystemTime, config: &GeneralConfig, now: Instant) {
+        let Some(threshold) = config.hr_segment_threshold_bpm else {
+            return;
+        };
+        let above = rate > threshold;
+
+        let Some(current) = self.segments.last_mut() else {
+            self.segments.push(Segment::new(read_at, above, rate));
+            return;
+        };
+        if current.above == above {
+            self.crossed_at = None;
+            current.record(rate);
+            return;
+        }
+
+        let (crossed_at, started_at) = *self.crossed_at.get_or_insert_with(|| (now, read_at));
+        if now.duration_since(crossed_at) >= Duration::from_secs(config.hr_segment_after_secs) {
+            self.crossed_at = None;
+            self.segments.push(Segment::new(started_at, above, rate));
+        } else {
+            current.record(rate);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        // keep the last reading time so the current reading isn't counted again
+        *self = Self {
+            last_read_at: self.last_read_at,
+            last_observed_at: self.last_observed_at,
+            ..Default::default()
+        };
+    }
+
+    /// Resets the stats and starts timing a session
+    pub fn start_session(&mut self) {
+        self.start_session_at(Instant::now());
+    }
+
+    pub(super) fn start_session_at(&mut self, now: Instant) {
+        self.reset();
+        self.session_started_at = Some(now);
+    }
+
+    /// Freezes the stats until the next `start_session` or `reset`.
+    /// Returns false if no session is running.
+    pub fn stop_session(&mut self) -> bool {
+        self.stop_session_at(Instant::now())
+    }
+
+    pub(super) fn stop_session_at(&mut self, now: Instant) -> bool {
+        if self.session_started_at.is_none() || self.session_stopped_at.is_some() {
+            return false;
+        }
+        self.session_stopped_at = Some(now);
+        true
+    }
+
+    /// How long the current or last session ran, None unless one was started
+    pub fn session_duration(&self) -> Option<Duration> {
+        let started_at = self.session_started_at?;
+        Some(
+            self.session_stopped_at
+                .unwrap_or_else(Instant::now)
+                .duration_since(started_at),
+        )
+    }
+
+    pub const fn min(&self) -> Option<u32> {
+        self.min
+    }
+
+    pub const fn max(&self) -> Option<u32> {
+        self.max
+    }
+
+    pub fn avg(&self) -> Option<f32> {
+        (self.count > 0).then(|| self.sum as f32 / self.count as f32)
+    }
+
+    pub const fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The readings of the current or last session with the time they were read, oldest first
+    pub fn session_samples(&self) -> &[(SystemTime, u32)] {
+        &self.session_samples
+    }
+
+    /// The segments since the last `reset`, oldest first. Empty without `hr_segment_threshold_bpm`.
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// How long the heart rate was in `zone` during the current or last session
+    pub const fn session_zone_time(&self, zone: HrZone) -> Duration {
+        self.session_zone_time[zone.index()]
+    }
+
+    /// How regularly the heart rate oscillates over the last minute, from 0 to 1, as a simple
+    /// stand-in for HRV coherence: the share of the spectrum around its strongest peak in the
+    /// band of slow breathing. Calm breathing at a steady pace gets close to 1.
+    /// None unless a reading arrived at least every `COHERENCE_MAX_SAMPLE_INTERVAL` on average.
+    pub fn coherence(&self) -> Option<f32> {
+        let (first_at, _) = *self.samples.front()?;
+        let (last_at, _) = *self.samples.back()?;
+        let span = last_at.duration_since(first_at);
+        if span.as_secs_f32() < COHERENCE_WINDOW_SECS as f32 * 0.9
+            || span / (self.samples.len() as u32) > COHERENCE_MAX_SAMPLE_INTERVAL
+        {
+            return None;
+        }
+
+        Some(coherence(&resample(&self.samples, last_at)))
+    }
+
+    /// Estimated kcal burned while the heart rate was live.
+    /// None unless age, weight and gender are configured.
+    pub const fn calories_session(&self) -> Option<f32> {
+        self.calories
+    }
+}
+
+/// One rate per second for the `COHERENCE_WINDOW_SECS` up to `end`, interpolated linearly
+fn resample(samples: &VecDeque<(Instant, u32)>, end: Instant) -> Vec<f32> {
+    let mut next = 0;
+    (0..COHERENCE_WINDOW_SECS)
+        .rev()
+        .map(|secs_before_end| {
+            let at = end
+                .checked_sub(Duration::from_secs(secs_before_end as u64))
+                .unwrap_or(end);
+            while next + 1 < samples.len() && samples[next + 1].0 <= at {
+                next += 1;
+            }
+
+            let (before_at, before) = samples[next];
+            match samples.get(next + 1) {
+                Some(&(after_at, after)) if after_at > before_at && at > before_at => {
+                    let progress = at.duration_since(before_at).as_secs_f32()
+                        / after_at.duration_since(before_at).as_secs_f32();
+                    (after as f32 - before as f32).mul_add(progress.min(1.), before as f32)
+                }
+                _ => before as f32,
+            }
+        })
+        .collect()
+}
+
+/// Power around the strongest peak between 0.04 and 0.26 Hz, relative to everything up to
+/// 0.4 Hz, of a series sampled once per second
+fn coherence(series: &[f32]) -> f32 {
+    let len = series.len();
+    let mean = series.iter().sum::<f32>() / len as f32;
+    let power = |bin: usize| {
+        let (re, im) = series
+            .iter()
+            .enumerate()
+            .fold((0., 0.), |(re, im), (i, rate)| {
+                let angle = TAU * (bin * i) as f32 / len as f32;
+                let value = rate - mean;
+                (
+                    value.mul_add(angle.cos(), re),
+                    value.mul_add(-angle.sin(), im),
+                )
+            });
+        re.mul_add(re, im * im)
+    };
+
+    let bin_of = |hz: f32| (hz * len as f32).round() as usize;
+    let spectrum: Vec<f32> = (0..=bin_of(0.4)).map(power).collect();
+    let total: f32 = spectrum[1..].iter().sum();
+    if total <= f32::EPSILON {
+        return 0.;
+    }
+
+    let peak = (bin_of(0.04)..=bin_of(0.26))
+        .max_by(|a, b| spectrum[*a].total_cmp(&spectrum[*b]))
+        .unwrap_or(1);
+    let around_peak: f32 = spectrum[peak - 1..=peak + 1].iter().sum();
+    (around_peak / total).min(1.)
+}
+
+/// Keytel et al. (2005), which estimates energy expenditure from the heart rate during exercise
+fn calories_per_minute(rate: u32, config: &GeneralConfig) -> Option<f32> {
+    let age = config.hr_profile_age? as f32;
+    let weight = config.hr_profile_weight_kg?;
+    let rate = rate as f32;
+
+    let kilojoules = match config.hr_profile_gender.as_deref()?.trim() {
+        "male" => 0.2017f32.mul_add(
+            age,
+            0.1988f32.mul_add(weight, 0.6309f32.mul_add(rate, -55.0969)),
+        ),
+        "female" => 0.074f32.mul_add(
+            age,
+            (-0.1263f32).mul_add(weight, 0.4472f32.mul_add(rate, -20.4022)),
+        ),
+        _ => return None,
+    };
+    Some((kilojoules / 4.184).max(0.))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stats holding one reading of `rate(second)` every `step` seconds over a minute
+    fn sampled(step: u64, rate: impl Fn(u64) -> u32) -> HeartRateStats {
+        let start = Instant::now();
+        HeartRateStats {
+            samples: (0..COHERENCE_WINDOW_SECS as u64)
+                .step_by(step as usize)
+                .map(|secs| (start + Duration::from_secs(secs), rate(secs)))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// The same reading until it is replaced
+    struct Reading {
+        rate: u32,
+        read_at: SystemTime,
+    }
+
+    impl HeartRateSource for Reading {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            Some(self.rate)
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            Some(self.read_at)
+        }
+    }
+
+    #[test]
+    fn rest_work_rest_is_split_into_segments() {
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"hr_segment_threshold_bpm": 110, "hr_segment_after_secs": 30}"#,
+        )
+        .unwrap();
+        let start = Instant::now();
+        let wall_start = SystemTime::UNIX_EPOCH;
+        let mut stats = HeartRateStats::default();
+
+        // a reading every 10 seconds: one minute of rest, two of work, one of rest
+        for secs in (0..240).step_by(10) {
+            let rate = if (60..180).contains(&secs) { 150 } else { 70 };
+            let reading = Reading {
+                rate,
+                read_at: wall_start + Duration::from_secs(secs),
+            };
+            stats.observe_at(&reading, &config, start + Duration::from_secs(secs));
+        }
+
+        let segments: Vec<_> = stats
+            .segments()
+            .iter()
+            .map(|segment| {
+                let started = segment.started_at.duration_since(wall_start).unwrap();
+                (started.as_secs(), segment.above, segment.min, segment.max)
+            })
+            .collect();
+        // a segment takes the readings of the next one until those lasted 30 seconds
+        assert_eq!(
+            segments,
+            [
+                (0, false, 70, 150),
+                (60, true, 70, 150),
+                (180, false, 70, 70)
+            ]
+        );
+        assert_eq!(stats.segments()[2].count, 3);
+        assert_eq!(
+            (stats.min(), stats.max(), stats.count()),
+            (Some(70), Some(150), 24)
+        );
+    }
+
+    #[test]
+    fn slow_breathing_is_coherent() {
+        // six breaths a minute
+        let stats = sampled(1, |secs| {
+            (70. + 6. * (TAU * 0.1 * secs as f32).sin()).round() as u32
+        });
+        let coherence = stats.coherence().unwrap();
+        assert!(coherence > 0.8, "{coherence}");
+    }
+
+    #[test]
+    fn jitter_is_not_coherent() {
+        let stats = sampled(1, |secs| if secs % 2 == 0 { 65 } else { 75 });
+        let coherence = stats.coherence().unwrap();
+        assert!(coherence < 0.3, "{coherence}");
+    }
+
+    #[test]
+    fn coarse_readings_have_no_coherence() {
+        let stats = sampled(5, |secs| {
+            (70. + 6. * (TAU * 0.1 * secs as f32).sin()).round() as u32
+        });
+        assert_eq!(stats.coherence(), None);
+    }
+}
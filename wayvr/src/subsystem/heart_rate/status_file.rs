@@ -0,0 +1,147 @@
+use std::{path::PathBuf, time::SystemTime};
+
+use wlx_common::config::{GeneralConfig, HrFormat};
+
+use super::{HeartRateSource, Trend, change::ChangeThreshold, format_rate, zone::HrZone};
+
+/// What `{stale}` turns into while the reading is stale
+const STALE_MARKER: &str = "*";
+
+/// Fills in the `hr_status_file_template` placeholders: `{bpm}`, `--` without a reading,
+/// `{zone}` and `{trend}` as an arrow, both empty without a reading, and `{stale}`.
+pub fn render(
+    template: &str,
+    rate: Option<u32>,
+    zone: Option<HrZone>,
+    trend: Trend,
+    stale: bool,
+) -> String {
+    let zone: &str = zone.map_or("", Into::into);
+    let trend = match (rate, trend) {
+        (None, _) => "",
+        (Some(_), Trend::Rising) => "↑",
+        (Some(_), Trend::Falling) => "↓",
+        (Some(_), Trend::Steady) => "→",
+    };
+
+    template
+        .replace("{bpm}", &format_rate(rate, HrFormat::Bare))
+        .replace("{zone}", zone)
+        .replace("{trend}", trend)
+        .replace("{stale}", if stale { STALE_MARKER } else { "" })
+}
+
+/// Keeps a one line text file with the current heart rate for status bars like waybar or
+/// polybar to read. It's only rewritten once the text changes, the rate only once it moved by
+/// `hr_change_threshold`. The new text is written next to it and moved over the file, so
+/// readers never see it half written.
+pub struct StatusFile {
+    path: PathBuf,
+    threshold: ChangeThreshold,
+    written: Option<String>,
+    written_at: Option<SystemTime>,
+}
+
+impl StatusFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            threshold: ChangeThreshold::default(),
+            written: None,
+            written_at: None,
+        }
+    }
+
+    pub fn tick(&mut self, source: &dyn HeartRateSource, config: &GeneralConfig) {
+        let rate = self.threshold.apply(source.smoothed_rate(), config);
+        let text = render(
+            &config.hr_status_file_template,
+            rate,
+            source.zone(),
+            source.trend(),
+            rate.is_some() && source.is_stale(),
+        );
+        if self.written.as_ref() == Some(&text) {
+            return;
+        }
+
+        match self.write(&text) {
+            Ok(()) => self.written_at = Some(SystemTime::now()),
+            Err(e) => log::warn!(
+                "Could not write the heart rate to {}: {e:?}",
+                self.path.display()
+            ),
+        }
+        // not retried every tick if it failed, only with the next change
+        self.written = Some(text);
+    }
+
+    pub const fn written_at(&self) -> Option<SystemTime> {
+        self.written_at
+    }
+
+    fn write(&self, text: &str) -> anyhow::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, format!("{text}\n"))?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = "♥ {bpm}{stale} {trend} ({zone})";
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        assert_eq!(
+            render(
+                TEMPLATE,
+                Some(142),
+                Some(HrZone::Cardio),
+                Trend::Rising,
+                false
+            ),
+            "♥ 142 ↑ (cardio)"
+        );
+        assert_eq!(
+            render(
+                TEMPLATE,
+                Some(64),
+                Some(HrZone::FatBurn),
+                Trend::Steady,
+                false
+            ),
+            "♥ 64 → (fat_burn)"
+        );
+    }
+
+    #[test]
+    fn stale_and_missing_readings() {
+        assert_eq!(
+            render(
+                TEMPLATE,
+                Some(142),
+                Some(HrZone::Cardio),
+                Trend::Falling,
+                true
+            ),
+            "♥ 142* ↓ (cardio)"
+        );
+        assert_eq!(
+            render(TEMPLATE, None, None, Trend::Rising, false),
+            "♥ --  ()"
+        );
+    }
+
+    #[test]
+    fn text_without_placeholders_is_kept() {
+        assert_eq!(
+            render("{heart} bpm", Some(70), None, Trend::Steady, false),
+            "{heart} bpm"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use wlx_common::config::GeneralConfig;
+
+use super::{
+    HeartRateSource,
+    notify::{HrAlert, HrNotification, HrNotifier},
+    stats::HeartRateStats,
+    zone::HrZone,
+};
+
+/// `h:mm:ss`, or `m:ss` under an hour
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// One line per `hr_session_notification` field of the session that just ended, in the
+/// configured order. Fields without a value, e.g. `calories` without a profile, are left out.
+pub fn session_summary(stats: &HeartRateStats, fields: &[String]) -> String {
+    let bpm =
+        |rate: Option<u32>| rate.map_or_else(|| "-".to_string(), |rate| format!("{rate} BPM"));
+
+    fields
+        .iter()
+        .filter_map(|field| match field.trim() {
+            "duration" => Some(format!(
+                "Duration: {}",
+                format_duration(stats.session_duration().unwrap_or_default())
+            )),
+            "avg" => Some(format!(
+                "Average: {}",
+                bpm(stats.avg().map(|avg| avg.round() as u32))
+            )),
+            "min" => Some(format!("Min: {}", bpm(stats.min()))),
+            "max" => Some(format!("Max: {}", bpm(stats.max()))),
+            "zones" => {
+                let zones: Vec<String> = HrZone::ALL
+                    .into_iter()
+                    .map(|zone| {
+                        let name: &str = zone.into();
+                        format!("{name} {}", format_duration(stats.session_zone_time(zone)))
+                    })
+                    .collect();
+                Some(format!("Zones: {}", zones.join(", ")))
+            }
+            "calories" => stats
+                .calories_session()
+                .map(|calories| format!("Calories: {} kcal", calories.round() as u32)),
+            other => {
+                log::warn!("Unknown hr_session_notification field '{other}'.");
+                None
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Shows a notification summing up the session that just ended through `hr_notify_backend`,
+/// if `hr_session_notification` has any fields.
+pub fn notify_session_ended(
+    notifier: &mut HrNotifier,
+    config: &GeneralConfig,
+    stats: &HeartRateStats,
+    source: &dyn HeartRateSource,
+) {
+    if config.hr_session_notification.is_empty() {
+        return;
+    }
+
+    notifier.send(
+        HrAlert::SessionEnded,
+        HrNotification {
+            summary: "Heart rate session ended".to_string(),
+            body: session_summary(stats, &config.hr_session_notification),
+            bpm: source.smoothed_rate(),
+            zone: source.zone(),
+            test: false,
+        },
+        config,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Instant, SystemTime};
+
+    use super::*;
+
+    /// A reading in a zone the test picks, read at a time it picks
+    struct Workout {
+        rate: u32,
+        zone: HrZone,
+        read_at: SystemTime,
+    }
+
+    impl HeartRateSource for Workout {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            Some(self.rate)
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            Some(self.read_at)
+        }
+
+        fn zone(&self) -> Option<HrZone> {
+            Some(self.zone)
+        }
+    }
+
+    /// A 25 second session with a reading every 5 seconds
+    fn finished_session() -> HeartRateStats {
+        let config: GeneralConfig = serde_json::from_str("{}").unwrap();
+        let start = Instant::now();
+        let mut stats = HeartRateStats::default();
+        stats.start_session_at(start);
+
+        let readings = [
+            (95, HrZone::Rest),
+            (110, HrZone::FatBurn),
+            (112, HrZone::FatBurn),
+            (150, HrZone::Cardio),
+            (171, HrZone::Peak),
+        ];
+        for (i, (rate, zone)) in readings.into_iter().enumerate() {
+            let offset = Duration::from_secs(5 * i as u64);
+            let workout = Workout {
+                rate,
+                zone,
+                read_at: SystemTime::UNIX_EPOCH + offset,
+            };
+            stats.observe_at(&workout, &config, start + offset);
+        }
+        stats.stop_session_at(start + Duration::from_secs(25));
+        stats
+    }
+
+    #[test]
+    fn summary_of_a_known_session() {
+        let fields = ["duration", "avg", "min", "max", "zones", "calories"].map(String::from);
+        assert_eq!(
+            session_summary(&finished_session(), &fields),
+            "Duration: 0:25\n\
+             Average: 128 BPM\n\
+             Min: 95 BPM\n\
+             Max: 171 BPM\n\
+             Zones: rest 0:00, fat_burn 0:10, cardio 0:05, peak 0:05"
+        );
+    }
+
+    #[test]
+    fn summary_keeps_the_configured_order() {
+        let fields = [" max", "duration", "unknown"].map(String::from);
+        assert_eq!(
+            session_summary(&finished_session(), &fields),
+            "Max: 171 BPM\nDuration: 0:25"
+        );
+    }
+
+    #[test]
+    fn long_durations_have_hours() {
+        assert_eq!(format_duration(Duration::from_secs(59)), "0:59");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1:02:03");
+    }
+}
@@ -0,0 +1,182 @@
+use strum::IntoStaticStr;
+use wlx_common::config::GeneralConfig;
+
+/// Lower bounds of the zones above `Rest`, as fractions of `hr_max`
+const DEFAULT_ZONE_FRACTIONS: [f32; 3] = [0.6, 0.7, 0.85];
+/// Green, yellow, orange and red
+const DEFAULT_ZONE_COLORS: [&str; 4] = ["#4caf50", "#ffeb3b", "#ff9800", "#f44336"];
+
+/// Intensity of the heart rate, e.g. for tinting it
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum HrZone {
+    Rest,
+    FatBurn,
+    Cardio,
+    Peak,
+}
+
+impl HrZone {
+    pub const ALL: [Self; 4] = [Self::Rest, Self::FatBurn, Self::Cardio, Self::Peak];
+
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Maps rates into zones. A zone is only left downwards once the rate is
+/// `hr_zone_hysteresis` BPM below its lower bound, so a rate at a boundary doesn't flicker.
+#[derive(Default)]
+pub struct ZoneTracker {
+    zone: Option<HrZone>,
+}
+
+impl ZoneTracker {
+    /// `resting` is the resting rate, if known, for zones based on the heart rate reserve,
+    /// and `max` the one from `max_rate`
+    pub fn update(
+        &mut self,
+        rate: Option<u32>,
+        config: &GeneralConfig,
+        resting: Option<u32>,
+        max: u32,
+    ) {
+        let Some(rate) = rate else {
+            self.zone = None;
+            return;
+        };
+
+        let bounds = zone_bounds(config, resting, max);
+        let target = HrZone::ALL
+            .into_iter()
+            .rev()
+            .find(|zone| zone.index() == 0 || rate >= bounds[zone.index() - 1])
+            .unwrap_or(HrZone::Rest);
+
+        self.zone = Some(match self.zone {
+            Some(current) if target < current => {
+                let hysteresis = config.hr_zone_hysteresis;
+                // drop as far down as the hysteresis allows
+                HrZone::ALL[target.index()..=current.index()]
+                    .iter()
+                    .copied()
+                    .rev()
+                    .find(|zone| {
+                        zone.index() == target.index()
+                            || rate + hysteresis >= bounds[zone.index() - 1]
+                    })
+                    .unwrap_or(target)
+            }
+            _ => target,
+        });
+    }
+
+    pub const fn zone(&self) -> Option<HrZone> {
+        self.zone
+    }
+}
+
+/// The `hr_zone_colors` entry of `zone` as #rrggbb, or the default if it isn't one
+pub fn color(zone: HrZone, config: &GeneralConfig) -> &str {
+    config
+        .hr_zone_colors
+        .get(zone.index())
+        .map(|color| color.trim())
+        .filter(|color| {
+            color.len() == 7
+                && color.starts_with('#')
+                && color[1..].bytes().all(|byte| byte.is_ascii_hexdigit())
+        })
+        .unwrap_or(DEFAULT_ZONE_COLORS[zone.index()])
+}
+
+/// `hr_max`, or 208 - 0.7 × age (Tanaka) with `hr_max_from_age` and an age. That's
+/// `hr_profile_age`, or else `source_age` from the profile of the source.
+pub fn max_rate(config: &GeneralConfig, source_age: Option<u32>) -> u32 {
+    match config.hr_profile_age.or(source_age) {
+        Some(age) if config.hr_max_from_age => 0.7f32.mul_add(-(age as f32), 208.).round() as u32,
+        _ => config.hr_max,
+    }
+}
+
+/// `hr_zone_bounds` if all three are given, or the default fractions of the maximum rate.
+/// With `hr_zones_from_reserve` and a known resting rate, the fractions are of the
+/// heart rate reserve above it instead (Karvonen).
+fn zone_bounds(config: &GeneralConfig, resting: Option<u32>, max: u32) -> [u32; 3] {
+    if let [fat_burn, cardio, peak] = config.hr_zone_bounds.as_slice() {
+        return [*fat_burn, *cardio, *peak];
+    }
+
+    match resting.filter(|_| config.hr_zones_from_reserve) {
+        Some(resting) if max > resting => DEFAULT_ZONE_FRACTIONS.map(|fraction| {
+            ((max - resting) as f32)
+                .mul_add(fraction, resting as f32)
+                .round() as u32
+        }),
+        _ => DEFAULT_ZONE_FRACTIONS.map(|fraction| (max as f32 * fraction).round() as u32),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Zones starting at 120, 140 and 170 BPM with 3 BPM of hysteresis
+    fn zones_of(rates: &[u32]) -> Vec<Option<HrZone>> {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"hr_zone_bounds": [120, 140, 170], "hr_zone_hysteresis": 3}"#)
+                .unwrap();
+        let mut tracker = ZoneTracker::default();
+        rates
+            .iter()
+            .map(|&rate| {
+                tracker.update(Some(rate), &config, None, 200);
+                tracker.zone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lower_bounds_belong_to_their_zone() {
+        assert_eq!(
+            zones_of(&[119, 120, 140, 170]),
+            [
+                Some(HrZone::Rest),
+                Some(HrZone::FatBurn),
+                Some(HrZone::Cardio),
+                Some(HrZone::Peak)
+            ]
+        );
+    }
+
+    #[test]
+    fn dropping_below_a_bound_within_the_hysteresis_keeps_the_zone() {
+        assert_eq!(
+            zones_of(&[140, 139, 137, 141, 136]),
+            [
+                Some(HrZone::Cardio),
+                Some(HrZone::Cardio),
+                Some(HrZone::Cardio),
+                Some(HrZone::Cardio),
+                Some(HrZone::FatBurn)
+            ]
+        );
+    }
+
+    #[test]
+    fn a_big_drop_skips_zones() {
+        assert_eq!(
+            zones_of(&[175, 100]),
+            [Some(HrZone::Peak), Some(HrZone::Rest)]
+        );
+    }
+
+    #[test]
+    fn no_reading_has_no_zone() {
+        let config: GeneralConfig = serde_json::from_str("{}").unwrap();
+        let mut tracker = ZoneTracker::default();
+        tracker.update(Some(150), &config, None, 200);
+        tracker.update(None, &config, None, 200);
+        assert_eq!(tracker.zone(), None);
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, bail};
+use wlx_common::config::GeneralConfig;
+
+use crate::subsystem::heart_rate::{HeartRateSource, log_packet};
+
+/// ANT USB sticks are handled by the usb_serial_simple kernel driver
+pub const DEFAULT_ANT_DEVICE: &str = "/dev/ttyUSB0";
+
+const SYNC: u8 = 0xA4;
+
+const MSG_CHANNEL_EVENT: u8 = 0x40;
+const MSG_ASSIGN_CHANNEL: u8 = 0x42;
+const MSG_CHANNEL_PERIOD: u8 = 0x43;
+const MSG_RF_FREQUENCY: u8 = 0x45;
+const MSG_NETWORK_KEY: u8 = 0x46;
+const MSG_RESET_SYSTEM: u8 = 0x4A;
+const MSG_OPEN_CHANNEL: u8 = 0x4B;
+const MSG_BROADCAST_DATA: u8 = 0x4E;
+const MSG_CHANNEL_ID: u8 = 0x51;
+
+const EVENT_RX_FAIL_GO_TO_SEARCH: u8 = 0x08;
+const EVENT_CHANNEL_CLOSED: u8 = 0x07;
+
+const ANT_PLUS_NETWORK_KEY: [u8; 8] = [0xB9, 0xA5, 0x21, 0xFB, 0xBD, 0x72, 0xC3, 0x45];
+const HR_DEVICE_TYPE: u8 = 120;
+const HR_CHANNEL_PERIOD: u16 = 8070;
+const ANT_PLUS_FREQUENCY: u8 = 57;
+
+const CHANNEL: u8 = 0;
+const NETWORK: u8 = 0;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Heart rate from an ANT+ strap, received through an ANT USB stick.
+pub struct AntHeartRate {
+    receiver: mpsc::Receiver<Option<u32>>,
+    running: Arc<AtomicBool>,
+    last_rate: Option<u32>,
+    last_read_at: Option<SystemTime>,
+}
+
+impl AntHeartRate {
+    /// `device_number` pins a specific strap, 0 pairs with the first one found.
+    /// `log_packets` logs every message from the stick as received, before it is decoded.
+    pub fn new(path: String, device_number: u16, log_packets: bool) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let worker_running = running.clone();
+        std::thread::spawn(move || {
+            run_worker(&path, device_number, log_packets, &sender, &worker_running);
+            log::info!("ANT+ heart rate worker stopped.");
+        });
+
+        Self {
+            receiver,
+            running,
+            last_rate: None,
+            last_read_at: None,
+        }
+    }
+}
+
+impl HeartRateSource for AntHeartRate {
+    fn name(&self) -> &'static str {
+        "ant"
+    }
+
+    fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {
+        for rate in self.receiver.try_iter() {
+            self.last_rate = rate;
+            self.last_read_at = rate.map(|_| SystemTime::now());
+        }
+    }
+
+    fn last_rate(&self) -> Option<u32> {
+        self.last_rate
+    }
+
+    fn last_read_at(&self) -> Option<SystemTime> {
+        self.last_read_at
+    }
+}
+
+impl Drop for AntHeartRate {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+fn run_worker(
+    path: &str,
+    device_number: u16,
+    log_packets: bool,
+    sender: &mpsc::Sender<Option<u32>>,
+    running: &AtomicBool,
+) {
+    let mut warned = false;
+    while running.load(Ordering::Relaxed) {
+        match open_stick(path) {
+            Ok(mut stick) => {
+                warned = false;
+                if let Err(e) = receive(&mut stick, device_number, log_packets, sender, running) {
+                    log::warn!("ANT+ heart rate: {e:?}");
+                }
+            }
+            // the dongle may be plugged in later, so only complain once
+            Err(e) if !warned => {
+                log::warn!("ANT+ heart rate: no ANT stick at {path}: {e:?}");
+                warned = true;
+            }
+            Err(_) => {}
+        }
+
+        if sender.send(None).is_err() {
+            return;
+        }
+
+        let retry_at = Instant::now() + RECONNECT_DELAY;
+        while running.load(Ordering::Relaxed) && Instant::now() < retry_at {
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
+
+fn open_stick(path: &str) -> anyhow::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)?;
+
+    // raw 115200 8N1, reads return after 500ms at most so the worker can stop
+    unsafe {
+        let fd = file.as_raw_fd();
+        let mut tio: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &raw mut tio) != 0 {
+            bail!("{path} is not a serial device");
+        }
+        libc::cfmakeraw(&raw mut tio);
+        libc::cfsetspeed(&raw mut tio, libc::B115200);
+        tio.c_cc[libc::VMIN] = 0;
+        tio.c_cc[libc::VTIME] = 5;
+        if libc::tcsetattr(fd, libc::TCSANOW, &raw const tio) != 0 {
+            bail!("Could not configure {path}");
+        }
+    }
+
+    Ok(file)
+}
+
+fn receive(
+    stick: &mut File,
+    device_number: u16,
+    log_packets: bool,
+    sender: &mpsc::Sender<Option<u32>>,
+    running: &AtomicBool,
+) -> anyhow::Result<()> {
+    send_message(stick, MSG_RESET_SYSTEM, &[0])?;
+    // the stick needs a moment to come back up after a reset
+    std::thread::sleep(Duration::from_millis(500));
+
+    let [number_lo, number_hi] = device_number.to_le_bytes();
+    let [period_lo, period_hi] = HR_CHANNEL_PERIOD.to_le_bytes();
+
+    let mut key = vec![NETWORK];
+    key.extend(ANT_PLUS_NETWORK_KEY);
+    send_message(stick, MSG_NETWORK_KEY, &key)?;
+    send_message(stick, MSG_ASSIGN_CHANNEL, &[CHANNEL, 0x00, NETWORK])?;
+    send_message(
+        stick,
+        MSG_CHANNEL_ID,
+        &[CHANNEL, number_lo, number_hi, HR_DEVICE_TYPE, 0],
+    )?;
+    send_message(stick, MSG_CHANNEL_PERIOD, &[CHANNEL, period_lo, period_hi])?;
+    send_message(stick, MSG_RF_FREQUENCY, &[CHANNEL, ANT_PLUS_FREQUENCY])?;
+    send_message(stick, MSG_OPEN_CHANNEL, &[CHANNEL])?;
+    log::info!("ANT+ heart rate: searching for a strap");
+
+    let mut buf = Vec::with_capacity(64);
+    let mut chunk = [0u8; 64];
+    while running.load(Ordering::Relaxed) {
+        let len = match stick.read(&mut chunk) {
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).context("ANT stick was removed"),
+        };
+        buf.extend_from_slice(&chunk[..len]);
+
+        while let Some((id, data)) = take_message(&mut buf) {
+            if log_packets {
+                log_packet(&format!("ANT+ heart rate message {id:#04x}"), &data);
+            }
+            match (id, data.as_slice()) {
+                (MSG_BROADCAST_DATA, [CHANNEL, payload @ ..]) if payload.len() >= 8 => {
+                    // every data page carries the computed heart rate in its last byte
+                    let _ = sender.send(Some(u32::from(payload[7])));
+                }
+                (MSG_CHANNEL_EVENT, [CHANNEL, 1, EVENT_RX_FAIL_GO_TO_SEARCH, ..]) => {
+                    let _ = sender.send(None);
+                }
+                (MSG_CHANNEL_EVENT, [CHANNEL, 1, EVENT_CHANNEL_CLOSED, ..]) => {
+                    // closes after the search times out, keep looking
+                    send_message(stick, MSG_OPEN_CHANNEL, &[CHANNEL])?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn send_message(stick: &mut File, id: u8, data: &[u8]) -> anyhow::Result<()> {
+    let mut message = vec![SYNC, data.len() as u8, id];
+    message.extend_from_slice(data);
+    message.push(checksum(&message));
+    message.extend([0, 0]); // padding, required by some sticks
+    stick.write_all(&message)?;
+    Ok(())
+}
+
+/// Pops the next complete and valid message off `buf`, discarding any garbage before it.
+fn take_message(buf: &mut Vec<u8>) -> Option<(u8, Vec<u8>)> {
+    loop {
+        let start = buf.iter().position(|byte| *byte == SYNC)?;
+        buf.drain(..start);
+
+        let len = usize::from(*buf.get(1)?);
+        let total = len + 4;
+        if buf.len() < total {
+            return None;
+        }
+
+        if checksum(&buf[..total - 1]) != buf[total - 1] {
+            buf.drain(..1);
+            continue;
+        }
+
+        let message: Vec<u8> = buf.drain(..total).collect();
+        return Some((message[2], message[3..3 + len].to_vec()));
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |acc, byte| acc ^ byte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captured_broadcast_after_garbage() {
+        // the tail of an earlier message, then page 4 of a strap at 75 BPM
+        let mut buf = vec![
+            0x3b, 0x00, 0xa4, 0x09, 0x4e, 0x00, 0x04, 0x08, 0x82, 0x4f, 0x2c, 0x15, 0x9b, 0x4b,
+            0xcb,
+        ];
+
+        let (id, data) = take_message(&mut buf).unwrap();
+        assert_eq!(id, MSG_BROADCAST_DATA);
+        assert_eq!(
+            data,
+            [CHANNEL, 0x04, 0x08, 0x82, 0x4f, 0x2c, 0x15, 0x9b, 0x4b]
+        );
+        assert_eq!(data.last(), Some(&75));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn corrupted_message_is_skipped() {
+        let mut buf = vec![
+            0xa4, 0x09, 0x4e, 0x00, 0x04, 0x08, 0x82, 0x4f, 0x2c, 0x15, 0x9b, 0x4b, 0x00,
+        ];
+        assert_eq!(take_message(&mut buf), None);
+    }
+}
@@ -13,6 +13,8 @@ use std::{fs::File, sync::atomic::AtomicBool};
 use strum::{EnumIter, EnumString, IntoEnumIterator};
 use xkbcommon::xkb;
 
+pub mod ant;
+
 #[cfg(feature = "wayland")]
 pub mod wayland;
 
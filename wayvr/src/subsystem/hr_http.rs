@@ -0,0 +1,214 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::subsystem::heart_rate::{HeartRateSource, history::ReadingHistory};
+
+/// Polls /hr.json instead of reloading, so OBS doesn't flash a blank page
+const HR_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<style>
+body { margin: 0; font: bold 64px sans-serif; color: white; text-shadow: 0 0 4px black; }
+.stale { opacity: 0.5; }
+</style>
+</head>
+<body>
+<span id="hr">--</span>
+<script>
+async function refresh() {
+  try {
+    const hr = await (await fetch("/hr.json", { cache: "no-store" })).json();
+    const element = document.getElementById("hr");
+    element.textContent = hr.bpm ?? "--";
+    element.className = hr.stale ? "stale" : "";
+  } catch (e) {}
+}
+refresh();
+setInterval(refresh, 1000);
+</script>
+</body>
+</html>
+"#;
+
+/// What the endpoint shows. Deliberately built from the rate alone, so nothing
+/// a source keeps internally (e.g. tokens) can end up in a response.
+#[derive(Clone, Default, Serialize)]
+struct Snapshot {
+    bpm: Option<u32>,
+    /// Unix time of the reading
+    ts: Option<u64>,
+    stale: bool,
+    source: &'static str,
+}
+
+/// Serves the heart rate over plain HTTP for OBS browser sources and scripts.
+/// /history.json has the recent readings for graphs, see `ReadingHistory`.
+/// Requests are answered one at a time on a single thread, they're tiny.
+pub struct HrHttpServer {
+    snapshot: Arc<Mutex<Snapshot>>,
+    /// The history as JSON, only serialized again when it changed
+    history: Arc<Mutex<String>>,
+    history_generation: Option<u64>,
+}
+
+impl HrHttpServer {
+    /// `allow_origin` is sent as `Access-Control-Allow-Origin`, without it only same-origin pages
+    /// and clients that aren't browsers can read the responses.
+    pub fn new(bind: &str, port: u16, allow_origin: Option<String>) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind((bind, port))
+            .with_context(|| format!("Could not listen on {bind}:{port}"))?;
+
+        let snapshot = Arc::new(Mutex::new(Snapshot::default()));
+        let history = Arc::new(Mutex::new(String::from("[]")));
+        let server_snapshot = snapshot.clone();
+        let server_history = history.clone();
+        let allow_origin = allow_origin
+            .map(|origin| origin.trim().to_string())
+            .filter(|origin| !origin.is_empty());
+        std::thread::spawn(move || {
+            run_server(
+                &listener,
+                &server_snapshot,
+                &server_history,
+                allow_origin.as_deref(),
+            );
+        });
+
+        log::info!("Serving heart rate on http://{bind}:{port}/hr.json");
+        Ok(Self {
+            snapshot,
+            history,
+            history_generation: None,
+        })
+    }
+
+    pub fn tick(&mut self, source: &dyn HeartRateSource, history: &ReadingHistory) {
+        let snapshot = Snapshot {
+            bpm: source.smoothed_rate(),
+            ts: source
+                .last_reading_time()
+                .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|since_epoch| since_epoch.as_secs()),
+            stale: source.is_stale(),
+            source: source.name(),
+        };
+
+        if let Ok(mut current) = self.snapshot.lock() {
+            *current = snapshot;
+        }
+
+        if self.history_generation != Some(history.generation()) {
+            self.history_generation = Some(history.generation());
+            match serde_json::to_string(&history.entries().collect::<Vec<_>>()) {
+                Ok(json) => {
+                    if let Ok(mut current) = self.history.lock() {
+                        *current = json;
+                    }
+                }
+                Err(e) => log::error!("Could not serialize heart rate history: {e:?}"),
+            }
+        }
+    }
+}
+
+fn run_server(
+    listener: &TcpListener,
+    snapshot: &Mutex<Snapshot>,
+    history: &Mutex<String>,
+    allow_origin: Option<&str>,
+) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let _ = handle_client(stream, snapshot, history, allow_origin)
+                    .inspect_err(|e| log::debug!("Heart rate HTTP request failed: {e:?}"));
+            }
+            Err(e) => log::debug!("Heart rate HTTP accept failed: {e}"),
+        }
+    }
+}
+
+fn handle_client(
+    mut stream: TcpStream,
+    snapshot: &Mutex<Snapshot>,
+    history: &Mutex<String>,
+    allow_origin: Option<&str>,
+) -> anyhow::Result<()> {
+    // a stalled client must not hold up the others
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(2)))?;
+
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let path = path.split('?').next().unwrap_or(path);
+
+    let (status, content_type, body) = match path {
+        "/hr.json" => {
+            let snapshot = snapshot
+                .lock()
+                .map(|snapshot| snapshot.clone())
+                .unwrap_or_default();
+            (
+                "200 OK",
+                "application/json",
+                serde_json::to_string(&snapshot)?,
+            )
+        }
+        "/history.json" => {
+            let history = history
+                .lock()
+                .map(|history| history.clone())
+                .unwrap_or_else(|_| String::from("[]"));
+            ("200 OK", "application/json", history)
+        }
+        "/" | "/hr.html" => ("200 OK", "text/html; charset=utf-8", HR_PAGE.to_string()),
+        _ => ("404 Not Found", "text/plain", "Not Found".to_string()),
+    };
+
+    stream.write_all(response(status, content_type, &body, allow_origin).as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn response(status: &str, content_type: &str, body: &str, allow_origin: Option<&str>) -> String {
+    let cors = allow_origin
+        .map(|origin| format!("Access-Control-Allow-Origin: {origin}\r\nVary: Origin\r\n"))
+        .unwrap_or_default();
+    format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-store\r\n{cors}Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_cors_header_by_default() {
+        let response = response("200 OK", "application/json", "{}", None);
+        assert!(!response.contains("Access-Control-Allow-Origin"));
+        assert!(response.ends_with("\r\n\r\n{}"));
+    }
+
+    #[test]
+    fn cors_header_names_the_configured_origin() {
+        let response = response(
+            "200 OK",
+            "application/json",
+            "{}",
+            Some("https://overlay.example.com"),
+        );
+        assert!(response.contains("Access-Control-Allow-Origin: https://overlay.example.com\r\n"));
+        assert!(!response.contains("Access-Control-Allow-Origin: *"));
+    }
+}
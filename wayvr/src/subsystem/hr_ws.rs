@@ -0,0 +1,204 @@
+use std::{
+    io::ErrorKind,
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use serde::Serialize;
+use tungstenite::{Message, WebSocket};
+
+use crate::subsystem::heart_rate::HeartRateSource;
+
+/// How often the server thread looks for new clients while nothing changes
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// One message of the stream. Every message is the whole state, so a client that
+/// reconnects is up to date with the first one it gets.
+#[derive(Clone, PartialEq, Serialize)]
+struct State {
+    bpm: Option<u32>,
+    zone: Option<&'static str>,
+    stale: bool,
+    /// Unix time of the reading, in ms
+    ts: Option<u64>,
+}
+
+/// Streams the heart rate as JSON over a websocket for custom overlays, e.g. in a browser or
+/// Electron. A message goes out on every change, and a client gets the current state right
+/// after connecting. Clients that can't keep up are dropped instead of holding up the others.
+pub struct HrWsServer {
+    sender: Sender<String>,
+    last_sent: Option<State>,
+}
+
+impl HrWsServer {
+    pub fn new(bind: &str, port: u16) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind((bind, port))
+            .with_context(|| format!("Could not listen on {bind}:{port}"))?;
+        listener.set_nonblocking(true)?;
+
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || run_server(&listener, &receiver));
+
+        log::info!("Streaming heart rate on ws://{bind}:{port}");
+        Ok(Self {
+            sender,
+            last_sent: None,
+        })
+    }
+
+    pub fn tick(&mut self, source: &dyn HeartRateSource) {
+        let state = State {
+            bpm: source.smoothed_rate(),
+            zone: source.zone().map(Into::into),
+            stale: source.is_stale(),
+            ts: source.last_reading_time().map(|read_at| {
+                read_at
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64
+            }),
+        };
+        if self.last_sent.as_ref() == Some(&state) {
+            return;
+        }
+
+        match serde_json::to_string(&state) {
+            Ok(json) => {
+                let _ = self.sender.send(json);
+            }
+            Err(e) => log::error!("Could not serialize heart rate: {e:?}"),
+        }
+        self.last_sent = Some(state);
+    }
+}
+
+/// Stops once the `HrWsServer` is gone
+fn run_server(listener: &TcpListener, states: &Receiver<String>) {
+    let mut clients: Vec<WebSocket<TcpStream>> = vec![];
+    let mut current: Option<String> = None;
+
+    loop {
+        loop {
+            match listener.accept() {
+                Ok((stream, addr)) => match accept_client(stream) {
+                    Ok(mut client) => {
+                        log::debug!("Heart rate stream client connected: {addr}");
+                        let greeted = current
+                            .as_ref()
+                            .is_none_or(|json| client.send(Message::text(json.clone())).is_ok());
+                        if greeted {
+                            clients.push(client);
+                        }
+                    }
+                    Err(e) => log::debug!("Heart rate stream handshake failed: {e:?}"),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("Heart rate stream stopped: {e}");
+                    return;
+                }
+            }
+        }
+
+        clients.retain_mut(read_client);
+
+        match states.recv_timeout(ACCEPT_INTERVAL) {
+            Ok(json) => {
+                // a full send buffer means the client fell behind
+                clients.retain_mut(|client| client.send(Message::text(json.clone())).is_ok());
+                current = Some(json);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// Reads what the client sent, which also answers its pings. False once it went away.
+fn read_client(client: &mut WebSocket<TcpStream>) -> bool {
+    loop {
+        match client.read() {
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => return true,
+            Err(_) => return false,
+        }
+    }
+}
+
+fn accept_client(stream: TcpStream) -> anyhow::Result<WebSocket<TcpStream>> {
+    stream.set_nonblocking(false)?;
+    // a stalled handshake must not hold up the others
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(2)))?;
+    let client = tungstenite::accept(stream).map_err(|e| anyhow::anyhow!("{e}"))?;
+    client.get_ref().set_nonblocking(true)?;
+    Ok(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server thread on a free port, fed through the returned sender like `HrWsServer` does
+    fn server() -> (Sender<String>, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || run_server(&listener, &receiver));
+        (sender, addr)
+    }
+
+    fn connect(addr: &str) -> WebSocket<TcpStream> {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        tungstenite::client(format!("ws://{addr}/"), stream)
+            .unwrap()
+            .0
+    }
+
+    fn next_text(client: &mut WebSocket<TcpStream>) -> String {
+        match client.read().unwrap() {
+            Message::Text(text) => text.to_string(),
+            other => panic!("expected text, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn state_serializes_to_the_documented_shape() {
+        let state = State {
+            bpm: Some(72),
+            zone: Some("cardio"),
+            stale: false,
+            ts: Some(1_714_567_890_000),
+        };
+        assert_eq!(
+            serde_json::to_string(&state).unwrap(),
+            r#"{"bpm":72,"zone":"cardio","stale":false,"ts":1714567890000}"#
+        );
+    }
+
+    #[test]
+    fn new_client_gets_the_current_state_right_away() {
+        let (states, addr) = server();
+        let mut first = connect(&addr);
+        let state = r#"{"bpm":72,"zone":null,"stale":false,"ts":1}"#;
+        states.send(state.to_string()).unwrap();
+        // once the first client has it, the server holds it as the current state
+        assert_eq!(next_text(&mut first), state);
+
+        let mut second = connect(&addr);
+        assert_eq!(next_text(&mut second), state);
+
+        // both get the next change
+        let change = r#"{"bpm":75,"zone":null,"stale":false,"ts":2}"#;
+        states.send(change.to_string()).unwrap();
+        assert_eq!(next_text(&mut first), change);
+        assert_eq!(next_text(&mut second), change);
+    }
+}
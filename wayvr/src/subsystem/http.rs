@@ -0,0 +1,230 @@
+use std::{
+    io::{ErrorKind, Write},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use wlx_common::config::GeneralConfig;
+
+/// curl exit code for CURLE_OPERATION_TIMEDOUT
+const CURL_TIMED_OUT: i32 = 28;
+
+const USER_AGENT: &str = concat!("wayvr-hr/", env!("CARGO_PKG_VERSION"));
+
+/// Looked up in `PATH`
+pub const DEFAULT_CURL: &str = "curl";
+
+/// Transport settings shared by the polling sources
+#[derive(Clone)]
+pub struct CurlOptions {
+    /// The curl binary, a path or a name looked up in `PATH`
+    pub binary: String,
+    pub timeout: Duration,
+    pub proxy: Option<String>,
+    pub ca_cert: Option<String>,
+    /// Skip certificate verification. Redirects aren't followed then, so it stays on one host.
+    pub insecure: bool,
+    /// Sent with every request, as `Name: value`
+    pub headers: Vec<String>,
+}
+
+impl CurlOptions {
+    /// The curl binary, proxy and CA certificate from the config, which all sources share.
+    /// Certificates are verified and no extra headers are sent.
+    pub fn from_config(config: &GeneralConfig, timeout: Duration) -> Self {
+        let non_empty = |value: Option<&str>| {
+            value
+                .filter(|value| !value.trim().is_empty())
+                .map(|value| value.trim().to_string())
+        };
+
+        Self {
+            binary: non_empty(config.fitbit_curl_path.as_deref())
+                .unwrap_or_else(|| DEFAULT_CURL.to_string()),
+            timeout,
+            proxy: non_empty(config.fitbit_proxy.as_deref()),
+            ca_cert: non_empty(config.fitbit_ca_cert.as_deref()),
+            insecure: false,
+            headers: vec![],
+        }
+    }
+}
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+    /// Values of the requested response headers in the same order, empty if missing
+    pub headers: Vec<String>,
+}
+
+/// Runs curl with `args` appended and returns the response, including the values of
+/// `capture_headers`. Requests can't hang, curl gives up on its own after the timeout.
+pub fn curl_with_status(
+    options: &CurlOptions,
+    capture_headers: &[&str],
+    args: Vec<String>,
+) -> anyhow::Result<HttpResponse> {
+    run_curl(options, capture_headers, args, None)
+}
+
+/// Like `curl_with_status`, with `form` as the request body. It's handed to curl on stdin,
+/// since other users could read secrets in it from the command line, e.g. in `/proc`.
+pub fn curl_with_form(
+    options: &CurlOptions,
+    capture_headers: &[&str],
+    mut args: Vec<String>,
+    form: &str,
+) -> anyhow::Result<HttpResponse> {
+    args.extend(["--data".into(), "@-".into()]);
+    run_curl(options, capture_headers, args, Some(form))
+}
+
+fn run_curl(
+    options: &CurlOptions,
+    capture_headers: &[&str],
+    args: Vec<String>,
+    stdin: Option<&str>,
+) -> anyhow::Result<HttpResponse> {
+    let mut write_out = String::new();
+    for header in capture_headers {
+        write_out.push_str(&format!("\n%header{{{header}}}"));
+    }
+    write_out.push_str("\n%{http_code}");
+
+    let timeout = options.timeout.as_secs().to_string();
+    let mut full_args = vec![
+        "--silent".into(),
+        "--show-error".into(),
+        "--connect-timeout".into(),
+        timeout.clone(),
+        "--max-time".into(),
+        timeout,
+        "--write-out".into(),
+        write_out,
+        "--user-agent".into(),
+        USER_AGENT.into(),
+    ];
+    if options.insecure {
+        full_args.push("--insecure".into());
+    } else {
+        full_args.push("--location".into());
+    }
+    if let Some(proxy) = options.proxy.as_ref() {
+        full_args.extend(["--proxy".into(), proxy.clone()]);
+    }
+    if let Some(ca_cert) = options.ca_cert.as_ref() {
+        full_args.extend(["--cacert".into(), ca_cert.clone()]);
+    }
+    for header in &options.headers {
+        full_args.extend(["--header".into(), header.clone()]);
+    }
+    full_args.extend(args);
+
+    let mut child = match Command::new(&options.binary)
+        .args(full_args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(anyhow::anyhow!(
+                "curl not found at {}; set fitbit_curl_path or install curl",
+                options.binary
+            ));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    // curl reads all of it before sending the request, and sees the end once it's dropped
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if output.status.code() == Some(CURL_TIMED_OUT) {
+        return Err(anyhow::anyhow!(
+            "Request timed out after {}s",
+            options.timeout.as_secs()
+        ));
+    }
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "curl exited with status {}",
+            output.status.code().unwrap_or(-1)
+        ));
+    }
+
+    parse_output(&output.stdout, capture_headers.len())
+}
+
+/// Splits the stdout of curl into the body, followed by the header values and the status
+/// written by `--write-out`, one per line
+fn parse_output(stdout: &[u8], header_count: usize) -> anyhow::Result<HttpResponse> {
+    let mut parts = stdout.split(|b| *b == b'\n').collect::<Vec<_>>();
+    // empty if curl stopped before --write-out, "000" if no response was received
+    let status = parts
+        .pop()
+        .and_then(|bytes| std::str::from_utf8(bytes).ok())
+        .and_then(|status| status.trim().parse::<u16>().ok())
+        .filter(|status| *status != 0)
+        .ok_or_else(|| anyhow::anyhow!("curl produced no status"))?;
+
+    let mut headers = vec![];
+    for _ in 0..header_count {
+        let value = parts
+            .pop()
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+            .map(|value| value.trim().to_string())
+            .unwrap_or_default();
+        headers.push(value);
+    }
+    headers.reverse();
+
+    let body = parts.join(&b'\n');
+    Ok(HttpResponse {
+        status,
+        body,
+        headers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_and_headers_follow_the_body() {
+        let response = parse_output(b"{\"a\":\n1}\n42\n\n200", 2).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.headers, ["42", ""]);
+        assert_eq!(response.body, b"{\"a\":\n1}");
+    }
+
+    #[test]
+    fn missing_status_is_an_error() {
+        let outputs: [&[u8]; 4] = [b"", b"{\"a\": 1}", b"{}\n000", b"{}\nnot a status\n"];
+        for stdout in outputs {
+            let error = parse_output(stdout, 0).err().unwrap();
+            assert_eq!(error.to_string(), "curl produced no status");
+        }
+    }
+
+    #[test]
+    fn missing_binary_names_the_setting() {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"fitbit_curl_path": " /nonexistent/bin/curl "}"#).unwrap();
+        let options = CurlOptions::from_config(&config, Duration::from_secs(5));
+
+        let error = curl_with_status(&options, &[], vec!["https://example.invalid".into()])
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "curl not found at /nonexistent/bin/curl; set fitbit_curl_path or install curl"
+        );
+    }
+}
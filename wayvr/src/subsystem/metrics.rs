@@ -0,0 +1,180 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+
+use crate::subsystem::heart_rate::HeartRateSource;
+
+#[derive(Default)]
+struct Metrics {
+    bpm: Option<u32>,
+    polls: u64,
+    /// Failed polls by HTTP status, 0 for network errors
+    poll_errors: BTreeMap<u16, u64>,
+    last_success: Option<SystemTime>,
+    token_refreshes: u64,
+    /// Whether the last successful poll had to refresh the access token first
+    last_poll_refreshed: bool,
+}
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    bpm: None,
+    polls: 0,
+    poll_errors: BTreeMap::new(),
+    last_success: None,
+    token_refreshes: 0,
+    last_poll_refreshed: false,
+});
+
+pub fn record_poll_success(refreshed: bool) {
+    if let Ok(mut metrics) = METRICS.lock() {
+        metrics.polls += 1;
+        metrics.last_success = Some(SystemTime::now());
+        metrics.last_poll_refreshed = refreshed;
+    }
+}
+
+pub fn record_token_refresh() {
+    if let Ok(mut metrics) = METRICS.lock() {
+        metrics.token_refreshes += 1;
+    }
+}
+
+pub fn record_poll_error(status: u16) {
+    if let Ok(mut metrics) = METRICS.lock() {
+        metrics.polls += 1;
+        *metrics.poll_errors.entry(status).or_default() += 1;
+    }
+}
+
+/// Exposes the heart rate and the health of the polls for Prometheus on `/metrics`.
+pub struct MetricsServer;
+
+impl MetricsServer {
+    pub fn new(bind: &str) -> anyhow::Result<Self> {
+        let listener =
+            TcpListener::bind(bind).with_context(|| format!("Could not listen on {bind}"))?;
+        std::thread::spawn(move || run_server(&listener));
+
+        log::info!("Serving heart rate metrics on http://{bind}/metrics");
+        Ok(Self)
+    }
+
+    pub fn tick(&mut self, source: &dyn HeartRateSource) {
+        if let Ok(mut metrics) = METRICS.lock() {
+            metrics.bpm = source.smoothed_rate();
+        }
+    }
+}
+
+fn run_server(listener: &TcpListener) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let _ = handle_client(stream)
+                    .inspect_err(|e| log::debug!("Metrics request failed: {e:?}"));
+            }
+            Err(e) => log::debug!("Metrics accept failed: {e}"),
+        }
+    }
+}
+
+fn handle_client(mut stream: TcpStream) -> anyhow::Result<()> {
+    // a stalled scraper must not hold up the next one
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(2)))?;
+
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+
+    let (status, body) = if path == "/metrics" {
+        ("200 OK", render())
+    } else {
+        ("404 Not Found", "Not Found".to_string())
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Prometheus text exposition format
+fn render() -> String {
+    let Ok(metrics) = METRICS.lock() else {
+        return String::new();
+    };
+
+    let mut out = String::new();
+    if let Some(bpm) = metrics.bpm {
+        let _ = writeln!(out, "# HELP wayvr_hr_bpm Current heart rate.");
+        let _ = writeln!(out, "# TYPE wayvr_hr_bpm gauge");
+        let _ = writeln!(out, "wayvr_hr_bpm {bpm}");
+    }
+
+    let _ = writeln!(out, "# HELP wayvr_hr_poll_total Fitbit polls made.");
+    let _ = writeln!(out, "# TYPE wayvr_hr_poll_total counter");
+    let _ = writeln!(out, "wayvr_hr_poll_total {}", metrics.polls);
+
+    let _ = writeln!(
+        out,
+        "# HELP wayvr_hr_poll_errors_total Failed Fitbit polls by HTTP status, 0 for network errors."
+    );
+    let _ = writeln!(out, "# TYPE wayvr_hr_poll_errors_total counter");
+    for (status, count) in &metrics.poll_errors {
+        let _ = writeln!(
+            out,
+            "wayvr_hr_poll_errors_total{{status=\"{status}\"}} {count}"
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP wayvr_hr_token_refresh_total Fitbit access token refreshes since startup."
+    );
+    let _ = writeln!(out, "# TYPE wayvr_hr_token_refresh_total counter");
+    let _ = writeln!(
+        out,
+        "wayvr_hr_token_refresh_total {}",
+        metrics.token_refreshes
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP wayvr_hr_last_poll_refreshed Whether the last successful Fitbit poll refreshed the token."
+    );
+    let _ = writeln!(out, "# TYPE wayvr_hr_last_poll_refreshed gauge");
+    let _ = writeln!(
+        out,
+        "wayvr_hr_last_poll_refreshed {}",
+        u8::from(metrics.last_poll_refreshed)
+    );
+
+    if let Some(last_success) = metrics
+        .last_success
+        .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+    {
+        let _ = writeln!(
+            out,
+            "# HELP wayvr_hr_last_success_timestamp Unix time of the last successful Fitbit poll."
+        );
+        let _ = writeln!(out, "# TYPE wayvr_hr_last_success_timestamp gauge");
+        let _ = writeln!(
+            out,
+            "wayvr_hr_last_success_timestamp {}",
+            last_success.as_secs()
+        );
+    }
+
+    out
+}
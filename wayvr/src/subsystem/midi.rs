@@ -0,0 +1,148 @@
+use anyhow::{Context, bail};
+use midir::{MidiOutput, MidiOutputConnection, os::unix::VirtualOutput};
+use wlx_common::config::GeneralConfig;
+
+use crate::subsystem::heart_rate::{HeartRateSource, change::ChangeThreshold};
+
+const CLIENT_NAME: &str = "WayVR";
+/// Name of the port created for `midi_port: virtual`, and of the connection to a system port
+const PORT_NAME: &str = "WayVR heart rate";
+
+/// Maps `rate` linearly from `min_bpm..=max_bpm` onto 0..=127, clamped at both ends
+pub fn midi_value(rate: u32, min_bpm: u32, max_bpm: u32) -> u8 {
+    if max_bpm <= min_bpm {
+        return if rate >= max_bpm { 127 } else { 0 };
+    }
+    let t = (rate.clamp(min_bpm, max_bpm) - min_bpm) as f32 / (max_bpm - min_bpm) as f32;
+    (t * 127.).round() as u8
+}
+
+/// Turns the rate into the message to send, unless it maps to the value sent last
+#[derive(Default)]
+struct MidiMapping {
+    threshold: ChangeThreshold,
+    last_sent: Option<u8>,
+}
+
+impl MidiMapping {
+    /// The mapped rate on `midi_channel`, only once it moved by `hr_change_threshold`.
+    /// None while there is no reading.
+    fn message(&mut self, rate: Option<u32>, config: &GeneralConfig) -> Option<[u8; 3]> {
+        let rate = self.threshold.apply(rate, config)?;
+        let value = midi_value(rate, config.midi_min_bpm, config.midi_max_bpm);
+        if self.last_sent == Some(value) {
+            return None;
+        }
+        self.last_sent = Some(value);
+
+        let channel = config.midi_channel.clamp(1, 16) - 1;
+        Some(match config.midi_note {
+            Some(note) => [0x90 | channel, note.min(127), value],
+            None => [0xB0 | channel, config.midi_cc.min(127), value],
+        })
+    }
+}
+
+/// Sends the heart rate as a MIDI control change, or as the velocity of a note with
+/// `midi_note`, for lighting and visuals driven by MIDI.
+pub struct MidiSender {
+    connection: MidiOutputConnection,
+    mapping: MidiMapping,
+}
+
+impl MidiSender {
+    /// Connects to the first output port whose name contains `port`, or creates a port of its
+    /// own for other programs to connect to if it's `virtual`.
+    pub fn new(port: &str) -> anyhow::Result<Self> {
+        let output = MidiOutput::new(CLIENT_NAME)?;
+
+        let connection = if port.eq_ignore_ascii_case("virtual") {
+            let connection = output
+                .create_virtual(PORT_NAME)
+                .map_err(|e| anyhow::anyhow!("{e}"))
+                .context("Could not create a virtual MIDI port")?;
+            log::info!("Sending heart rate on the virtual MIDI port {PORT_NAME}");
+            connection
+        } else {
+            let Some(found) = output.ports().into_iter().find(|candidate| {
+                output
+                    .port_name(candidate)
+                    .is_ok_and(|name| name.contains(port))
+            }) else {
+                bail!("No MIDI output port matches '{port}'.");
+            };
+            let name = output.port_name(&found).unwrap_or_default();
+            let connection = output
+                .connect(&found, PORT_NAME)
+                .map_err(|e| anyhow::anyhow!("{e}"))
+                .with_context(|| format!("Could not connect to MIDI port {name}"))?;
+            log::info!("Sending heart rate to MIDI port {name}");
+            connection
+        };
+
+        Ok(Self {
+            connection,
+            mapping: MidiMapping::default(),
+        })
+    }
+
+    /// Sends the mapped rate on `midi_channel` whenever it changes, the rate only once it moved
+    /// by `hr_change_threshold`. Nothing is sent while there is no reading.
+    pub fn send_heart_rate(
+        &mut self,
+        source: &dyn HeartRateSource,
+        config: &GeneralConfig,
+    ) -> anyhow::Result<()> {
+        let Some(message) = self.mapping.message(source.smoothed_rate(), config) else {
+            return Ok(());
+        };
+        self.connection
+            .send(&message)
+            .map_err(|e| anyhow::anyhow!("{e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_map_onto_the_cc_range() {
+        assert_eq!(midi_value(60, 60, 187), 0);
+        assert_eq!(midi_value(124, 60, 187), 64);
+        assert_eq!(midi_value(187, 60, 187), 127);
+        // clamped outside the range
+        assert_eq!(midi_value(40, 60, 187), 0);
+        assert_eq!(midi_value(220, 60, 187), 127);
+        // an empty range is a switch at its bound
+        assert_eq!(midi_value(99, 100, 100), 0);
+        assert_eq!(midi_value(100, 100, 100), 127);
+    }
+
+    #[test]
+    fn unchanged_values_are_not_sent_again() {
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"midi_channel": 2, "midi_cc": 20, "midi_min_bpm": 60, "midi_max_bpm": 187,
+                "hr_change_threshold": 3, "hr_change_force_secs": 60}"#,
+        )
+        .unwrap();
+        let mut mapping = MidiMapping::default();
+
+        assert_eq!(mapping.message(Some(124), &config), Some([0xB1, 20, 64]));
+        // within the change threshold
+        assert_eq!(mapping.message(Some(126), &config), None);
+        assert_eq!(mapping.message(None, &config), None);
+        // the value sent last
+        assert_eq!(mapping.message(Some(124), &config), None);
+        assert_eq!(mapping.message(Some(130), &config), Some([0xB1, 20, 70]));
+    }
+
+    #[test]
+    fn note_velocity_instead_of_a_cc() {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"midi_note": 60, "midi_min_bpm": 60, "midi_max_bpm": 187}"#)
+                .unwrap();
+        let mut mapping = MidiMapping::default();
+        assert_eq!(mapping.message(Some(187), &config), Some([0x90, 60, 127]));
+    }
+}
@@ -1,8 +1,29 @@
 pub mod dbus;
 pub mod fitbit;
+pub mod garmin;
+pub mod heart_rate;
 pub mod hid;
+pub mod http;
 pub mod input;
 pub mod notifications;
 
+#[cfg(feature = "discord")]
+pub mod discord;
+
+#[cfg(feature = "hr-http")]
+pub mod hr_http;
+
+#[cfg(feature = "hr-ws")]
+pub mod hr_ws;
+
+#[cfg(feature = "prometheus")]
+pub mod metrics;
+
+#[cfg(feature = "midi")]
+pub mod midi;
+
 #[cfg(feature = "osc")]
 pub mod osc;
+
+#[cfg(feature = "pulsoid")]
+pub mod pulsoid;
@@ -2,12 +2,13 @@ use anyhow::Context;
 use dbus::message::MatchRule;
 use serde::Deserialize;
 use std::{
+    collections::HashMap,
     sync::{
         Arc,
         atomic::{AtomicBool, Ordering},
         mpsc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 use wlx_common::overlays::ToastTopic;
 
@@ -17,6 +18,8 @@ pub struct NotificationManager {
     rx_toast: mpsc::Receiver<Toast>,
     tx_toast: mpsc::SyncSender<Toast>,
     running: Arc<AtomicBool>,
+    /// When a notification of each topic and title was last shown, for the cooldown
+    last_shown: HashMap<(ToastTopic, String), Instant>,
 }
 
 impl NotificationManager {
@@ -26,20 +29,55 @@ impl NotificationManager {
             rx_toast,
             tx_toast,
             running: Arc::new(AtomicBool::new(true)),
+            last_shown: HashMap::new(),
         }
     }
 
-    pub fn submit_pending(&self, app: &mut AppState) {
-        if app.session.config.notifications_enabled {
-            self.rx_toast.try_iter().for_each(|toast| {
-                toast.submit(app);
-            });
-        } else {
+    pub fn submit_pending(&mut self, app: &mut AppState) {
+        if !app.session.config.notifications_enabled {
             // consume without submitting
             self.rx_toast.try_iter().last();
+            return;
+        }
+
+        let cooldown = Duration::from_secs(app.session.config.notifications_cooldown_secs);
+        let pending: Vec<Toast> = self.rx_toast.try_iter().collect();
+        for toast in self.throttle(pending, cooldown, Instant::now()) {
+            toast.submit(app);
         }
     }
 
+    /// Coalesces identical toasts and drops the ones whose topic and title
+    /// were shown less than `cooldown` ago
+    fn throttle(&mut self, toasts: Vec<Toast>, cooldown: Duration, now: Instant) -> Vec<Toast> {
+        self.last_shown
+            .retain(|_, shown_at| now.duration_since(*shown_at) < cooldown);
+
+        let mut pending: Vec<Toast> = vec![];
+        for toast in toasts {
+            let duplicate = pending.iter().any(|other| {
+                other.topic == toast.topic && other.title == toast.title && other.body == toast.body
+            });
+            if !duplicate {
+                pending.push(toast);
+            }
+        }
+
+        pending.retain(|toast| {
+            if cooldown.is_zero() {
+                return true;
+            }
+            let key = (toast.topic, toast.title.clone());
+            if self.last_shown.contains_key(&key) {
+                log::debug!("Not showing notification '{}': cooling down", toast.title);
+                return false;
+            }
+            self.last_shown.insert(key, now);
+            true
+        });
+        pending
+    }
+
     pub fn run_dbus(&mut self, dbus: &mut DbusConnector) {
         let rule = MatchRule::new_method_call()
             .with_member("Notify")
@@ -188,3 +226,49 @@ struct XsoMessage {
     sourceApp: Option<String>,
     alwaysShow: Option<bool>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn high_rate(body: &str) -> Toast {
+        Toast::new(
+            ToastTopic::DesktopNotification,
+            "Heart rate high".into(),
+            body.into(),
+        )
+    }
+
+    #[test]
+    fn identical_pending_toasts_are_coalesced() {
+        let mut manager = NotificationManager::new();
+        let shown = manager.throttle(
+            vec![
+                high_rate("150 BPM"),
+                high_rate("150 BPM"),
+                high_rate("152 BPM"),
+            ],
+            Duration::ZERO,
+            Instant::now(),
+        );
+        let bodies: Vec<&str> = shown.iter().map(|toast| toast.body.as_str()).collect();
+        assert_eq!(bodies, ["150 BPM", "152 BPM"]);
+    }
+
+    #[test]
+    fn repeats_within_the_cooldown_are_dropped() {
+        let mut manager = NotificationManager::new();
+        let start = Instant::now();
+        let mut shown_at = |secs, body| {
+            let at = start + Duration::from_secs(secs);
+            manager
+                .throttle(vec![high_rate(body)], Duration::from_secs(60), at)
+                .len()
+        };
+
+        assert_eq!(shown_at(0, "150 BPM"), 1);
+        // the cooldown goes by topic and title, not by the rate in the body
+        assert_eq!(shown_at(10, "155 BPM"), 0);
+        assert_eq!(shown_at(60, "150 BPM"), 1);
+    }
+}
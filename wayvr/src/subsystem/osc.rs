@@ -1,57 +1,318 @@
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
-    time::Instant,
+    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::bail;
-use rosc::{OscMessage, OscPacket, OscType};
+use rosc::{OscBundle, OscMessage, OscPacket, OscTime, OscType};
+use wlx_common::config::GeneralConfig;
 
 use crate::{
     backend::input::TrackedDevice,
     overlays::{keyboard::KEYBOARD_NAME, watch::WATCH_NAME},
+    subsystem::heart_rate::{HeartRateSource, change::ChangeThreshold, zone::HrZone},
     windowing::manager::OverlayWindowManager,
 };
 
 use crate::backend::input::TrackedDeviceRole;
 
+/// VRChat drops parameters sent faster than this, and it paces the slew rate.
+const HEART_RATE_PERCENT_INTERVAL: Duration = Duration::from_millis(100);
+
 pub struct OscSender {
     last_sent_overlay: Instant,
     last_sent_device: Instant,
-    upstream: UdpSocket,
+    /// One socket per address family of the endpoints
+    upstream_v4: Option<UdpSocket>,
+    upstream_v6: Option<UdpSocket>,
+    endpoints: Vec<SocketAddr>,
+    last_sent_heart_rate: Option<(Option<u32>, Option<HrZone>)>,
+    last_sent_heart_rate_at: Option<SystemTime>,
+    /// For `osc_min_send_interval_ms`
+    heart_rate_sent_at: Option<Instant>,
+    last_sent_zone_bools: Option<Option<HrZone>>,
+    heart_rate_threshold: ChangeThreshold,
+    next_heartbeat_at: Option<Instant>,
+    /// Where `HeartRatePercent` glided to, before the `osc_precision` rounding
+    heart_rate_percent: Option<f32>,
+    last_sent_heart_rate_percent: Option<f32>,
+    heart_rate_percent_stepped_at: Instant,
+    last_sent_haptic_intensity: Option<f32>,
 }
 
 impl OscSender {
-    pub fn new(send_port: u16) -> anyhow::Result<Self> {
-        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
-
-        let Ok(upstream) = UdpSocket::bind("0.0.0.0:0") else {
-            bail!("Failed to bind UDP socket - OSC will not function.");
-        };
+    /// Sends to each of `endpoints`, or to `send_port` on localhost if there are none.
+    /// An endpoint that doesn't resolve is skipped, see `resolve_endpoint` for the forms accepted.
+    pub fn new(send_port: u16, endpoints: &[String]) -> anyhow::Result<Self> {
+        let mut addrs: Vec<SocketAddr> = endpoints
+            .iter()
+            .filter_map(|endpoint| {
+                resolve_endpoint(endpoint, send_port)
+                    .inspect_err(|e| log::warn!("Invalid OSC endpoint {endpoint}: {e}"))
+                    .ok()
+            })
+            .collect();
+
+        if addrs.is_empty() {
+            addrs.push(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), send_port));
+        }
 
-        let Ok(()) = upstream.connect(SocketAddr::new(ip, send_port)) else {
-            bail!("Failed to connect UDP socket - OSC will not function.");
+        let bind = |bind_addr: &str| {
+            UdpSocket::bind(bind_addr)
+                .inspect_err(|e| log::warn!("Could not bind OSC socket on {bind_addr}: {e}"))
+                .ok()
         };
+        let upstream_v4 = addrs
+            .iter()
+            .any(SocketAddr::is_ipv4)
+            .then(|| bind("0.0.0.0:0"))
+            .flatten();
+        let upstream_v6 = addrs
+            .iter()
+            .any(SocketAddr::is_ipv6)
+            .then(|| bind("[::]:0"))
+            .flatten();
+
+        if upstream_v4.is_none() && upstream_v6.is_none() {
+            bail!("Failed to bind UDP socket - OSC will not function.");
+        }
 
         Ok(Self {
-            upstream,
+            upstream_v4,
+            upstream_v6,
+            endpoints: addrs,
             last_sent_overlay: Instant::now(),
             last_sent_device: Instant::now(),
+            last_sent_heart_rate: None,
+            last_sent_heart_rate_at: None,
+            heart_rate_sent_at: None,
+            last_sent_zone_bools: None,
+            heart_rate_threshold: ChangeThreshold::default(),
+            next_heartbeat_at: None,
+            heart_rate_percent: None,
+            last_sent_heart_rate_percent: None,
+            heart_rate_percent_stepped_at: Instant::now(),
+            last_sent_haptic_intensity: None,
         })
     }
 
     pub fn send_message(&self, addr: String, args: Vec<OscType>) -> anyhow::Result<()> {
-        let packet = OscPacket::Message(OscMessage { addr, args });
-        let Ok(bytes) = rosc::encoder::encode(&packet) else {
+        self.send_packet(&OscPacket::Message(OscMessage { addr, args }))
+    }
+
+    fn send_packet(&self, packet: &OscPacket) -> anyhow::Result<()> {
+        let Ok(bytes) = rosc::encoder::encode(packet) else {
             bail!("Could not encode OSC packet.");
         };
 
-        let Ok(_) = self.upstream.send(&bytes) else {
-            bail!("Could not send OSC packet.");
-        };
+        // an unreachable endpoint must not keep the others from receiving
+        for endpoint in &self.endpoints {
+            let upstream = if endpoint.is_ipv6() {
+                self.upstream_v6.as_ref()
+            } else {
+                self.upstream_v4.as_ref()
+            };
+            let Some(upstream) = upstream else {
+                continue;
+            };
+            if let Err(e) = upstream.send_to(&bytes, endpoint) {
+                log::debug!("Could not send OSC packet to {endpoint}: {e}");
+            }
+        }
 
         Ok(())
     }
 
+    /// Sends `HeartRate` in BPM and `HeartRateZone` from 0 (rest) to 3 (peak) whenever they change,
+    /// the rate only once it moved by `hr_change_threshold`. Both are -1 while there is no reading.
+    /// With `osc_heart_rate_bundle`, both go out in one bundle timestamped with the time of sending,
+    /// so recorders get them together. The names come from `osc_heart_rate_address` and
+    /// `osc_heart_rate_zone_address`, see `parameter_address`.
+    /// Changes within `osc_min_send_interval_ms` of the last send are held back, and the latest
+    /// of them goes out once it passed.
+    pub fn send_heart_rate(
+        &mut self,
+        source: &dyn HeartRateSource,
+        config: &GeneralConfig,
+    ) -> anyhow::Result<()> {
+        let rate = self
+            .heart_rate_threshold
+            .apply(source.smoothed_rate(), config);
+        let params = (rate, source.zone());
+        if self.last_sent_heart_rate == Some(params) {
+            return Ok(());
+        }
+        let min_interval = Duration::from_millis(config.osc_min_send_interval_ms);
+        if self
+            .heart_rate_sent_at
+            .is_some_and(|sent_at| sent_at.elapsed() < min_interval)
+        {
+            return Ok(());
+        }
+        self.last_sent_heart_rate = Some(params);
+        self.last_sent_heart_rate_at = Some(SystemTime::now());
+        self.heart_rate_sent_at = Some(Instant::now());
+
+        let (rate, zone) = params;
+        let messages = [
+            (
+                &config.osc_heart_rate_address,
+                rate.map_or(-1, |rate| i32::try_from(rate).unwrap_or(i32::MAX)),
+            ),
+            (
+                &config.osc_heart_rate_zone_address,
+                zone.map_or(-1, |zone| zone as i32),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(address, value)| {
+            Some(OscMessage {
+                addr: parameter_address(address)?,
+                args: vec![OscType::Int(value)],
+            })
+        })
+        .collect::<Vec<_>>();
+
+        if config.osc_heart_rate_bundle {
+            return self.send_packet(&heart_rate_bundle(SystemTime::now(), messages)?);
+        }
+
+        for message in messages {
+            self.send_packet(&OscPacket::Message(message))?;
+        }
+        Ok(())
+    }
+
+    pub const fn last_sent_heart_rate_at(&self) -> Option<SystemTime> {
+        self.last_sent_heart_rate_at
+    }
+
+    /// Sends the `osc_zone_bool_addresses` whenever the zone changes, true for the current zone
+    /// and false for the others, so at most one of them is true at a time.
+    pub fn send_zone_bools(
+        &mut self,
+        source: &dyn HeartRateSource,
+        config: &GeneralConfig,
+    ) -> anyhow::Result<()> {
+        if config.osc_zone_bool_addresses.is_empty() {
+            return Ok(());
+        }
+
+        let current = source.zone();
+        if self.last_sent_zone_bools == Some(current) {
+            return Ok(());
+        }
+        self.last_sent_zone_bools = Some(current);
+
+        for zone in HrZone::ALL {
+            let name: &str = zone.into();
+            let Some(address) = config
+                .osc_zone_bool_addresses
+                .get(name)
+                .and_then(|address| parameter_address(address))
+            else {
+                continue;
+            };
+            self.send_message(address, vec![OscType::Bool(current == Some(zone))])?;
+        }
+        Ok(())
+    }
+
+    /// Sends `HeartRatePercent`, the rate mapped from `osc_hr_percent_min_bpm`..`osc_hr_percent_max_bpm`
+    /// onto 0..1 for animations. Each send moves it by at most `osc_hr_percent_max_step`, so it
+    /// glides instead of jumping with every reading. Holds its value while there is no reading.
+    pub fn send_heart_rate_percent(
+        &mut self,
+        source: &dyn HeartRateSource,
+        config: &GeneralConfig,
+    ) -> anyhow::Result<()> {
+        if self.heart_rate_percent_stepped_at.elapsed() < HEART_RATE_PERCENT_INTERVAL {
+            return Ok(());
+        }
+
+        let Some(rate) = source.smoothed_rate() else {
+            return Ok(());
+        };
+
+        let target = match source.reserve_percent() {
+            Some(reserve) if config.osc_hr_percent_from_reserve => reserve,
+            _ => hr_percent(
+                rate,
+                config.osc_hr_percent_min_bpm,
+                config.osc_hr_percent_max_bpm,
+            ),
+        };
+
+        let step = config.osc_hr_percent_max_step;
+        let percent = match self.heart_rate_percent {
+            Some(current) if step > 0.0 => current + (target - current).clamp(-step, step),
+            _ => target,
+        };
+        // glide on the exact value, so a coarse precision can't stall it
+        self.heart_rate_percent = Some(percent);
+        self.heart_rate_percent_stepped_at = Instant::now();
+        let percent = quantize(percent, "hr_percent", config);
+        if self.last_sent_heart_rate_percent == Some(percent) {
+            return Ok(());
+        }
+
+        self.last_sent_heart_rate_percent = Some(percent);
+        let Some(address) = parameter_address(&config.osc_hr_percent_address) else {
+            return Ok(());
+        };
+        self.send_message(address, vec![OscType::Float(percent)])
+    }
+
+    /// Sends the rate as a 0..1 intensity to `osc_haptic_address`, for haptic vests.
+    /// 0 while there is no reading or the heart rate is stale, see `haptic_intensity` for the curve.
+    pub fn send_haptic_intensity(
+        &mut self,
+        source: &dyn HeartRateSource,
+        config: &GeneralConfig,
+    ) -> anyhow::Result<()> {
+        let intensity = source
+            .smoothed_rate()
+            .filter(|_| !source.is_stale())
+            .map_or(0.0, |rate| haptic_intensity(rate, config));
+        let intensity = quantize(intensity, "haptic", config);
+        if self.last_sent_haptic_intensity == Some(intensity) {
+            return Ok(());
+        }
+
+        self.last_sent_haptic_intensity = Some(intensity);
+        self.send_message(
+            config.osc_haptic_address.clone(),
+            vec![OscType::Float(intensity)],
+        )
+    }
+
+    /// Sends an argument-less message to `address` once per beat at the current rate.
+    /// A new reading only changes the pace of the next beats, so the cadence doesn't skip.
+    pub fn send_heartbeat(
+        &mut self,
+        source: &dyn HeartRateSource,
+        address: &str,
+    ) -> anyhow::Result<()> {
+        let rate = source
+            .smoothed_rate()
+            .filter(|rate| *rate > 0 && !source.is_stale());
+        let Some(rate) = rate else {
+            self.next_heartbeat_at = None;
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        let next_beat_at = *self.next_heartbeat_at.get_or_insert(now);
+        if now < next_beat_at {
+            return Ok(());
+        }
+
+        let interval = Duration::from_millis(60_000 / u64::from(rate));
+        // after a hitch, continue from now instead of sending the missed beats at once
+        self.next_heartbeat_at = Some((next_beat_at + interval).max(now));
+        self.send_message(address.into(), vec![])
+    }
+
     #[allow(clippy::too_many_lines)]
     pub fn send_params<D>(
         &mut self,
@@ -222,6 +483,33 @@ impl OscSender {
     }
 }
 
+/// `host:port`, `[v6]:port` or a bare host/IP which gets `default_port`. Hostnames are resolved
+/// through DNS and the first address returned is used.
+fn resolve_endpoint(endpoint: &str, default_port: u16) -> std::io::Result<SocketAddr> {
+    let endpoint = endpoint.trim();
+    let bare_host = endpoint
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .unwrap_or(endpoint);
+
+    if let Ok(addr) = endpoint.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    if let Ok(ip) = bare_host.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, default_port));
+    }
+
+    let mut addrs = if endpoint.contains(':') {
+        endpoint.to_socket_addrs()?
+    } else {
+        (endpoint, default_port).to_socket_addrs()?
+    };
+
+    addrs
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses found"))
+}
+
 pub fn parse_osc_value(s: &str) -> anyhow::Result<OscType> {
     let lower = s.to_lowercase();
 
@@ -247,3 +535,423 @@ pub fn parse_osc_value(s: &str) -> anyhow::Result<OscType> {
         }
     }
 }
+
+/// Maps `rate` linearly from `min_bpm..=max_bpm` onto 0..=1, clamped at both ends. Only meant
+/// for the animation range, the readings themselves are checked against `hr_min_bpm`.
+pub fn hr_percent(rate: u32, min_bpm: u32, max_bpm: u32) -> f32 {
+    if max_bpm <= min_bpm {
+        return if rate >= max_bpm { 1.0 } else { 0.0 };
+    }
+    (rate.clamp(min_bpm, max_bpm) - min_bpm) as f32 / (max_bpm - min_bpm) as f32
+}
+
+/// `value` rounded to the `osc_precision` decimal places of `metric`, as is without an entry
+fn quantize(value: f32, metric: &str, config: &GeneralConfig) -> f32 {
+    let Some(&places) = config.osc_precision.get(metric) else {
+        return value;
+    };
+    // f32 has no more than that
+    let scale = 10f32.powi(i32::from(places.min(6)));
+    (value * scale).round() / scale
+}
+
+/// 0 up to `osc_haptic_rest_bpm`, rising to 1 at `osc_haptic_max_bpm`. `exponential` stays low
+/// for longer and ramps up towards the top, more so the higher `osc_haptic_exponent` is.
+fn haptic_intensity(rate: u32, config: &GeneralConfig) -> f32 {
+    let (rest, max) = (config.osc_haptic_rest_bpm, config.osc_haptic_max_bpm);
+    if rate <= rest || max <= rest {
+        return 0.0;
+    }
+
+    let progress = ((rate - rest) as f32 / (max - rest) as f32).min(1.0);
+    let steepness = config.osc_haptic_exponent;
+    match config.osc_haptic_curve.trim() {
+        "exponential" if steepness > 0.0 => (steepness * progress).exp_m1() / steepness.exp_m1(),
+        _ => progress,
+    }
+}
+
+/// A bare parameter name goes under /avatar/parameters/, an address starting with a slash
+/// is kept as it is. None for an empty one, which isn't sent.
+fn parameter_address(configured: &str) -> Option<String> {
+    let configured = configured.trim();
+    if configured.is_empty() {
+        None
+    } else if configured.starts_with('/') {
+        Some(configured.to_string())
+    } else {
+        Some(format!("/avatar/parameters/{configured}"))
+    }
+}
+
+/// `messages` as one bundle with an NTP timetag of `time`
+fn heart_rate_bundle(
+    time: SystemTime,
+    messages: impl IntoIterator<Item = OscMessage>,
+) -> anyhow::Result<OscPacket> {
+    let Ok(timetag) = OscTime::try_from(time) else {
+        bail!("Could not convert the time into an OSC timetag.");
+    };
+
+    Ok(OscPacket::Bundle(OscBundle {
+        timetag,
+        content: messages.into_iter().map(OscPacket::Message).collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystem::heart_rate::zone::ZoneTracker;
+
+    fn listener() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        socket
+    }
+
+    fn receive(socket: &UdpSocket) -> OscPacket {
+        let mut buf = [0u8; rosc::decoder::MTU];
+        let len = socket.recv(&mut buf).unwrap();
+        rosc::decoder::decode_udp(&buf[..len]).unwrap().1
+    }
+
+    /// Always reads the same rate
+    struct Steady(u32);
+
+    impl HeartRateSource for Steady {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            Some(self.0)
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            Some(SystemTime::now())
+        }
+    }
+
+    #[test]
+    fn configured_names_are_sent() {
+        let socket = listener();
+        let endpoint = socket.local_addr().unwrap().to_string();
+        let mut sender = OscSender::new(9000, &[endpoint]).unwrap();
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"osc_heart_rate_address": "Heartrate", "osc_heart_rate_zone_address": "/hr/zone"}"#,
+        )
+        .unwrap();
+
+        sender.send_heart_rate(&Steady(72), &config).unwrap();
+
+        let mut received = vec![];
+        for _ in 0..2 {
+            let OscPacket::Message(message) = receive(&socket) else {
+                panic!("expected a message");
+            };
+            received.push((message.addr, message.args));
+        }
+        assert_eq!(
+            received,
+            [
+                (
+                    "/avatar/parameters/Heartrate".to_string(),
+                    vec![OscType::Int(72)]
+                ),
+                ("/hr/zone".to_string(), vec![OscType::Int(-1)]),
+            ]
+        );
+    }
+
+    /// Reads `rate` and puts it into zones like the real sources do
+    struct Zoned {
+        rate: u32,
+        tracker: ZoneTracker,
+    }
+
+    impl HeartRateSource for Zoned {
+        fn update(&mut self, _config: &GeneralConfig, _watch_visible: bool) {}
+
+        fn last_rate(&self) -> Option<u32> {
+            Some(self.rate)
+        }
+
+        fn last_read_at(&self) -> Option<SystemTime> {
+            Some(SystemTime::now())
+        }
+
+        fn zone(&self) -> Option<HrZone> {
+            self.tracker.zone()
+        }
+    }
+
+    #[test]
+    fn one_zone_bool_is_true_and_hysteresis_keeps_it_from_flapping() {
+        let socket = listener();
+        let endpoint = socket.local_addr().unwrap().to_string();
+        let mut sender = OscSender::new(9000, &[endpoint]).unwrap();
+        let config: GeneralConfig = serde_json::from_value(serde_json::json!({
+            "hr_zone_bounds": [120, 140, 170],
+            "hr_zone_hysteresis": 3,
+            "osc_zone_bool_addresses": {
+                "rest": "InRest",
+                "fat_burn": "InFatBurn",
+                "cardio": "InCardio",
+                "peak": "InPeak"
+            }
+        }))
+        .unwrap();
+        let mut source = Zoned {
+            rate: 0,
+            tracker: ZoneTracker::default(),
+        };
+
+        // the addresses set to true by each batch that was sent
+        let mut batches = vec![];
+        for rate in [140, 139, 137, 141, 138, 136] {
+            source.rate = rate;
+            source.tracker.update(Some(rate), &config, None, 200);
+            let sent_before = sender.last_sent_zone_bools;
+            sender.send_zone_bools(&source, &config).unwrap();
+            if sender.last_sent_zone_bools == sent_before {
+                continue;
+            }
+
+            let mut enabled = vec![];
+            for _ in HrZone::ALL {
+                let OscPacket::Message(message) = receive(&socket) else {
+                    panic!("expected a message");
+                };
+                if message.args == [OscType::Bool(true)] {
+                    enabled.push(message.addr);
+                }
+            }
+            batches.push(enabled);
+        }
+
+        assert_eq!(
+            batches,
+            [
+                vec!["/avatar/parameters/InCardio".to_string()],
+                vec!["/avatar/parameters/InFatBurn".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn bursts_are_coalesced_to_the_send_interval() {
+        let socket = listener();
+        let endpoint = socket.local_addr().unwrap().to_string();
+        let mut sender = OscSender::new(9000, &[endpoint]).unwrap();
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"osc_heart_rate_address": "HR", "osc_heart_rate_zone_address": "HRZone",
+                "osc_min_send_interval_ms": 500}"#,
+        )
+        .unwrap();
+        let mut sent_rates = vec![];
+        let mut receive_rate = || {
+            for _ in 0..2 {
+                let OscPacket::Message(message) = receive(&socket) else {
+                    panic!("expected a message");
+                };
+                if message.addr == "/avatar/parameters/HR" {
+                    sent_rates.push(message.args);
+                }
+            }
+        };
+
+        for rate in 72..=76 {
+            sender.send_heart_rate(&Steady(rate), &config).unwrap();
+        }
+        receive_rate();
+
+        // the source stopped changing, its last rate goes out once the interval passed
+        sender.send_heart_rate(&Steady(76), &config).unwrap();
+        assert_eq!(sender.last_sent_heart_rate, Some((Some(72), None)));
+        sender.heart_rate_sent_at = Some(Instant::now() - Duration::from_millis(600));
+        sender.send_heart_rate(&Steady(76), &config).unwrap();
+        receive_rate();
+
+        assert_eq!(sent_rates, [vec![OscType::Int(72)], vec![OscType::Int(76)]]);
+    }
+
+    #[test]
+    fn endpoint_forms() {
+        let resolved = |endpoint| resolve_endpoint(endpoint, 9000).unwrap().to_string();
+
+        assert_eq!(resolved("192.168.1.20:9001"), "192.168.1.20:9001");
+        assert_eq!(resolved(" 192.168.1.20 "), "192.168.1.20:9000");
+        assert_eq!(resolved("[::1]:9001"), "[::1]:9001");
+        assert_eq!(resolved("[fe80::1]"), "[fe80::1]:9000");
+        assert_eq!(resolved("::1"), "[::1]:9000");
+
+        let localhost = resolve_endpoint("localhost:9002", 9000).unwrap();
+        assert!(localhost.ip().is_loopback());
+        assert_eq!(localhost.port(), 9002);
+        assert_eq!(resolve_endpoint("localhost", 9000).unwrap().port(), 9000);
+        assert!(resolve_endpoint("quest.invalid", 9000).is_err());
+    }
+
+    #[test]
+    fn parameter_addresses() {
+        assert_eq!(
+            parameter_address(" HR "),
+            Some("/avatar/parameters/HR".to_string())
+        );
+        assert_eq!(
+            parameter_address("/avatar/parameters/HeartRate"),
+            Some("/avatar/parameters/HeartRate".to_string())
+        );
+        assert_eq!(parameter_address("/hr"), Some("/hr".to_string()));
+        assert_eq!(parameter_address(""), None);
+    }
+
+    fn haptic_config(curve: &str) -> GeneralConfig {
+        serde_json::from_value(serde_json::json!({
+            "osc_haptic_curve": curve,
+            "osc_haptic_rest_bpm": 60,
+            "osc_haptic_max_bpm": 180,
+            "osc_haptic_exponent": 3.0,
+        }))
+        .unwrap()
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn percent_follows_the_configured_range() {
+        let percent_with = |config: &str, rate| {
+            let config: GeneralConfig = serde_json::from_str(config).unwrap();
+            hr_percent(
+                rate,
+                config.osc_hr_percent_min_bpm,
+                config.osc_hr_percent_max_bpm,
+            )
+        };
+        let athlete = r#"{"osc_hr_min": 50, "osc_hr_max": 190}"#;
+        let everyday = r#"{"osc_hr_percent_min_bpm": 60, "osc_hr_percent_max_bpm": 160}"#;
+
+        assert!(close(percent_with(athlete, 50), 0.0));
+        assert!(close(percent_with(athlete, 120), 0.5));
+        assert!(close(percent_with(athlete, 190), 1.0));
+        assert!(close(percent_with(everyday, 60), 0.0));
+        assert!(close(percent_with(everyday, 110), 0.5));
+        assert!(close(percent_with(everyday, 160), 1.0));
+        // clamped outside the range
+        assert!(close(percent_with(everyday, 45), 0.0));
+        assert!(close(percent_with(everyday, 185), 1.0));
+    }
+
+    #[test]
+    fn linear_haptic_intensity() {
+        let config = haptic_config("linear");
+        assert!(close(haptic_intensity(50, &config), 0.0));
+        assert!(close(haptic_intensity(60, &config), 0.0));
+        assert!(close(haptic_intensity(120, &config), 0.5));
+        assert!(close(haptic_intensity(180, &config), 1.0));
+        assert!(close(haptic_intensity(200, &config), 1.0));
+    }
+
+    #[test]
+    fn exponential_haptic_intensity_ramps_up_late() {
+        let config = haptic_config("exponential");
+        assert!(close(haptic_intensity(60, &config), 0.0));
+        let moderate = haptic_intensity(120, &config);
+        // (e^1.5 - 1) / (e^3 - 1)
+        assert!((moderate - 0.182).abs() < 1e-3, "{moderate}");
+        assert!(close(haptic_intensity(180, &config), 1.0));
+    }
+
+    #[test]
+    fn derived_floats_are_sent_with_the_configured_precision() {
+        let socket = listener();
+        let endpoint = socket.local_addr().unwrap().to_string();
+        let mut sender = OscSender::new(9000, &[endpoint]).unwrap();
+        let config: GeneralConfig = serde_json::from_str(
+            r#"{"osc_haptic_rest_bpm": 70, "osc_haptic_max_bpm": 180,
+                "osc_precision": {"haptic": 2}}"#,
+        )
+        .unwrap();
+
+        // 50 of 110 BPM, 0.4545...
+        sender.send_haptic_intensity(&Steady(120), &config).unwrap();
+        let OscPacket::Message(message) = receive(&socket) else {
+            panic!("expected a message");
+        };
+        let [OscType::Float(intensity)] = *message.args.as_slice() else {
+            panic!("expected a float, got {:?}", message.args);
+        };
+        assert!(close(intensity, 0.45), "{intensity}");
+    }
+
+    #[test]
+    fn precision_applies_per_metric() {
+        let config: GeneralConfig =
+            serde_json::from_str(r#"{"osc_precision": {"hr_percent": 0, "haptic": 1}}"#).unwrap();
+        assert!(close(quantize(0.46, "hr_percent", &config), 0.0));
+        assert!(close(quantize(0.46, "haptic", &config), 0.5));
+        assert!(close(quantize(0.4567, "hrv", &config), 0.4567));
+    }
+
+    #[test]
+    fn bundle_carries_the_timetag_and_messages() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        let messages = [
+            OscMessage {
+                addr: "/avatar/parameters/HeartRate".into(),
+                args: vec![OscType::Int(72)],
+            },
+            OscMessage {
+                addr: "/avatar/parameters/HeartRateZone".into(),
+                args: vec![OscType::Int(1)],
+            },
+        ];
+
+        let bundle = heart_rate_bundle(time, messages.clone()).unwrap();
+        let encoded = rosc::encoder::encode(&bundle).unwrap();
+        let OscPacket::Bundle(decoded) = rosc::decoder::decode_udp(&encoded).unwrap().1 else {
+            panic!("expected a bundle");
+        };
+
+        assert_eq!(decoded.timetag, OscTime::try_from(time).unwrap());
+        // 0.5s as a fraction of 2^32
+        assert_eq!(decoded.timetag.fractional, 1 << 31);
+        let contained: Vec<OscMessage> = decoded
+            .content
+            .into_iter()
+            .map(|packet| match packet {
+                OscPacket::Message(message) => message,
+                OscPacket::Bundle(_) => panic!("expected a message"),
+            })
+            .collect();
+        assert_eq!(contained, messages);
+    }
+
+    #[test]
+    fn every_endpoint_receives_the_packet() {
+        let listeners = [listener(), listener(), listener()];
+        let endpoints: Vec<String> = listeners
+            .iter()
+            .map(|socket| socket.local_addr().unwrap().to_string())
+            .collect();
+        let sender = OscSender::new(9000, &endpoints).unwrap();
+
+        sender
+            .send_message(
+                "/avatar/parameters/HeartRate".into(),
+                vec![OscType::Int(72)],
+            )
+            .unwrap();
+
+        for socket in &listeners {
+            let OscPacket::Message(message) = receive(socket) else {
+                panic!("expected a message");
+            };
+            assert_eq!(message.addr, "/avatar/parameters/HeartRate");
+            assert_eq!(message.args, [OscType::Int(72)]);
+        }
+    }
+}
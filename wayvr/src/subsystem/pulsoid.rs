@@ -0,0 +1,79 @@
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use tungstenite::{Message, WebSocket};
+
+use crate::subsystem::heart_rate::broadcast::ReadingSubscriber;
+
+/// Widgets expect a steady stream, so the rate is re-sent even if it didn't change
+const RESEND_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Serves the heart rate on a local websocket in the shape Pulsoid widgets expect,
+/// e.g. for OBS browser sources. The readings come from the `ReadingBroadcast`,
+/// the server thread stops once that is gone.
+pub struct PulsoidServer;
+
+impl PulsoidServer {
+    pub fn new(port: u16, readings: ReadingSubscriber) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .with_context(|| format!("Could not listen on port {port}"))?;
+        listener.set_nonblocking(true)?;
+
+        std::thread::spawn(move || run_server(&listener, readings));
+
+        log::info!("Serving heart rate on ws://localhost:{port}");
+        Ok(Self)
+    }
+}
+
+fn run_server(listener: &TcpListener, mut readings: ReadingSubscriber) {
+    let mut clients: Vec<WebSocket<TcpStream>> = vec![];
+    let mut rate = None;
+    let mut sent_at: Option<Instant> = None;
+
+    while !readings.is_closed() {
+        loop {
+            match listener.accept() {
+                Ok((stream, addr)) => match accept_client(stream) {
+                    Ok(client) => {
+                        log::debug!("Heart rate websocket client connected: {addr}");
+                        clients.push(client);
+                    }
+                    Err(e) => log::debug!("Heart rate websocket handshake failed: {e:?}"),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("Heart rate websocket stopped: {e}");
+                    return;
+                }
+            }
+        }
+
+        let changed = readings.wait_changed(Duration::from_millis(100));
+        if let Some(new_rate) = changed {
+            rate = new_rate;
+        }
+
+        // nothing is sent without a reading, widgets keep showing the last one
+        let Some(current) = rate else {
+            continue;
+        };
+        if changed.is_some() || sent_at.is_none_or(|sent_at| sent_at.elapsed() >= RESEND_INTERVAL) {
+            let json = format!(r#"{{"data":{{"heartRate":{current}}}}}"#);
+            clients.retain_mut(|client| client.send(Message::text(json.clone())).is_ok());
+            sent_at = Some(Instant::now());
+        }
+    }
+}
+
+fn accept_client(stream: TcpStream) -> anyhow::Result<WebSocket<TcpStream>> {
+    stream.set_nonblocking(false)?;
+    // a stalled client must not hold up the others
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(2)))?;
+    tungstenite::accept(stream).map_err(|e| anyhow::anyhow!("{e}"))
+}
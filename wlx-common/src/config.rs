@@ -50,6 +50,39 @@ pub enum AltModifier {
 	Meta,
 }
 
+/// How the heart rate is shown as text
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsRefStr, EnumString)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum HrFormat {
+	/// e.g. `72`, or `--` without a reading
+	Bare,
+	/// Three digits for segment displays, e.g. `072`, or `---` without a reading
+	Padded,
+	/// e.g. `72 bpm`, or `--` without a reading
+	#[default]
+	Suffix,
+}
+
+/// An alternate set of Fitbit credentials, selected with `fitbit_profile`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FitbitProfile {
+	#[serde(default)]
+	pub access_token: Option<String>,
+
+	#[serde(default)]
+	pub refresh_token: Option<String>,
+
+	#[serde(default)]
+	pub user_id: Option<String>,
+
+	#[serde(default)]
+	pub client_id: Option<String>,
+
+	#[serde(default)]
+	pub client_secret: Option<String>,
+}
+
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, AsRefStr, EnumString, EnumProperty, VariantArray)]
 pub enum HandsfreePointer {
 	#[strum(props(Translation = "APP_SETTINGS.OPTION.NONE"))]
@@ -112,6 +145,270 @@ const fn def_osc_port() -> u16 {
 	9000
 }
 
+const fn def_osc_hr_percent_min_bpm() -> u32 {
+	40
+}
+
+const fn def_osc_hr_percent_max_bpm() -> u32 {
+	200
+}
+
+const fn def_osc_hr_percent_max_step() -> f32 {
+	0.02
+}
+
+fn def_osc_heart_rate_address() -> String {
+	"HeartRate".to_string()
+}
+
+fn def_osc_heart_rate_zone_address() -> String {
+	"HeartRateZone".to_string()
+}
+
+fn def_osc_hr_percent_address() -> String {
+	"HeartRatePercent".to_string()
+}
+
+fn def_osc_haptic_address() -> String {
+	"/avatar/parameters/HeartRateHaptic".to_string()
+}
+
+fn def_osc_haptic_curve() -> String {
+	"linear".to_string()
+}
+
+const fn def_osc_haptic_rest_bpm() -> u32 {
+	70
+}
+
+const fn def_osc_haptic_max_bpm() -> u32 {
+	180
+}
+
+const fn def_osc_haptic_exponent() -> f32 {
+	3.0
+}
+
+const fn def_midi_channel() -> u8 {
+	1
+}
+
+const fn def_midi_cc() -> u8 {
+	20
+}
+
+const fn def_midi_min_bpm() -> u32 {
+	40
+}
+
+const fn def_midi_max_bpm() -> u32 {
+	200
+}
+
+fn def_osc_heartbeat_address() -> String {
+	"/heartbeat".to_string()
+}
+
+const fn def_fitbit_oauth_port() -> u16 {
+	8189
+}
+
+const fn def_hr_trend_deadband() -> f32 {
+	2.0
+}
+
+const fn def_hr_peak_decay_bpm_per_sec() -> f32 {
+	5.0
+}
+
+const fn def_hr_change_force_secs() -> u64 {
+	30
+}
+
+const fn def_hr_min_bpm() -> u32 {
+	25
+}
+
+const fn def_hr_max_bpm() -> u32 {
+	250
+}
+
+const fn def_hr_floor_grace_secs() -> u64 {
+	60
+}
+
+fn def_hr_watch_hidden() -> String {
+	"hold".to_string()
+}
+
+const fn def_hr_watch_hidden_hold_secs() -> u64 {
+	60
+}
+
+const fn def_hr_history_len() -> usize {
+	120
+}
+
+const fn def_hr_segment_after_secs() -> u64 {
+	30
+}
+
+const fn def_hr_threshold_cooldown_secs() -> u64 {
+	60
+}
+
+const fn def_hr_ble_switch_after_secs() -> u64 {
+	30
+}
+
+const fn def_hr_ble_watchdog_secs() -> u64 {
+	10
+}
+
+const fn def_hr_milestone_resting_margin_bpm() -> u32 {
+	10
+}
+
+const fn def_hr_milestone_cooldown_secs() -> u64 {
+	300
+}
+
+const fn def_hr_low_battery_percent() -> u8 {
+	15
+}
+
+fn def_hr_sim_pattern() -> String {
+	"sine".to_string()
+}
+
+const fn def_hr_sim_base() -> f32 {
+	70.0
+}
+
+const fn def_hr_sim_amplitude() -> f32 {
+	8.0
+}
+
+const fn def_hr_sim_period_secs() -> f32 {
+	60.0
+}
+
+const fn def_hr_max() -> u32 {
+	190
+}
+
+const fn def_hr_resting_calibration_secs() -> u64 {
+	120
+}
+
+const fn def_hr_zone_hysteresis() -> u32 {
+	3
+}
+
+fn def_hr_status_file_template() -> String {
+	"♥ {bpm}".to_string()
+}
+
+fn def_hr_composite_mode() -> String {
+	"priority".to_string()
+}
+
+fn def_hr_notify_backend() -> String {
+	"desktop".to_string()
+}
+
+const fn def_hr_notify_command_timeout_secs() -> u64 {
+	10
+}
+
+const fn def_hr_notify_cooldown_secs() -> u64 {
+	60
+}
+
+const fn def_hr_log_max_size_mb() -> u64 {
+	10
+}
+
+fn def_hr_session_export_format() -> String {
+	"tcx".to_string()
+}
+
+fn def_hr_http_bind() -> String {
+	"127.0.0.1".to_string()
+}
+
+fn def_hr_ws_bind() -> String {
+	"127.0.0.1".to_string()
+}
+
+const fn def_fitbit_request_timeout_secs() -> u64 {
+	10
+}
+
+const fn def_fitbit_hourly_request_budget() -> u32 {
+	120
+}
+
+const fn def_fitbit_request_retries() -> u32 {
+	2
+}
+
+const fn def_fitbit_visibility_debounce_ms() -> u64 {
+	500
+}
+
+const fn def_fitbit_stale_after_secs() -> u64 {
+	300
+}
+
+const fn def_fitbit_sync_stall_secs() -> u64 {
+	600
+}
+
+const fn def_fitbit_auth_error_tolerance() -> u32 {
+	2
+}
+
+const fn def_fitbit_auth_error_window_secs() -> u64 {
+	600
+}
+
+const fn def_fitbit_warmup_polls() -> u32 {
+	3
+}
+
+const fn def_fitbit_warmup_interval_secs() -> u64 {
+	10
+}
+
+const fn def_fitbit_midnight_grace_minutes() -> u32 {
+	15
+}
+
+const fn def_fitbit_token_refresh_margin_secs() -> u64 {
+	60
+}
+
+const fn def_fitbit_average_entries() -> u32 {
+	1
+}
+
+fn def_fitbit_interpolation() -> String {
+	"off".to_string()
+}
+
+fn def_fitbit_detail_level() -> String {
+	"1min".to_string()
+}
+
+fn def_fitbit_duplicate_times() -> String {
+	"last".to_string()
+}
+
+fn def_fitbit_api_version() -> String {
+	"1".to_string()
+}
+
 fn def_timezones() -> Vec<String> {
 	const EMEA: i32 = -60 * 60; // UTC-1
 	const APAC: i32 = 5 * 60 * 60; // UTC+5
@@ -187,6 +484,9 @@ pub struct GeneralConfig {
 	#[serde(default = "def_true")]
 	pub notifications_sound_enabled: bool,
 
+	#[serde(default)]
+	pub notifications_cooldown_secs: u64,
+
 	#[serde(default)]
 	pub notification_topics: IdMap<ToastTopic, ToastDisplayMethod>,
 
@@ -205,9 +505,93 @@ pub struct GeneralConfig {
 	#[serde(default = "def_point7")]
 	pub watch_view_angle_max: f32,
 
+	#[serde(default = "def_true")]
+	pub config_watch: bool,
+
 	#[serde(default = "def_osc_port")]
 	pub osc_out_port: u16,
 
+	#[serde(default)]
+	pub osc_out_endpoints: Vec<String>,
+
+	#[serde(default = "def_false")]
+	pub osc_heart_rate_bundle: bool,
+
+	#[serde(default)]
+	pub osc_min_send_interval_ms: u64,
+
+	#[serde(default = "def_osc_heart_rate_address")]
+	pub osc_heart_rate_address: String,
+
+	#[serde(default = "def_osc_heart_rate_zone_address")]
+	pub osc_heart_rate_zone_address: String,
+
+	#[serde(default = "def_osc_hr_percent_address")]
+	pub osc_hr_percent_address: String,
+
+	#[serde(default)]
+	pub osc_zone_bool_addresses: HashMap<String, String>,
+
+	#[serde(default = "def_osc_hr_percent_min_bpm", alias = "osc_hr_min")]
+	pub osc_hr_percent_min_bpm: u32,
+
+	#[serde(default = "def_osc_hr_percent_max_bpm", alias = "osc_hr_max")]
+	pub osc_hr_percent_max_bpm: u32,
+
+	#[serde(default = "def_osc_hr_percent_max_step")]
+	pub osc_hr_percent_max_step: f32,
+
+	#[serde(default = "def_false")]
+	pub osc_hr_percent_from_reserve: bool,
+
+	#[serde(default = "def_false")]
+	pub osc_haptic_enabled: bool,
+
+	#[serde(default = "def_osc_haptic_address")]
+	pub osc_haptic_address: String,
+
+	#[serde(default = "def_osc_haptic_curve")]
+	pub osc_haptic_curve: String,
+
+	#[serde(default = "def_osc_haptic_rest_bpm")]
+	pub osc_haptic_rest_bpm: u32,
+
+	#[serde(default = "def_osc_haptic_max_bpm")]
+	pub osc_haptic_max_bpm: u32,
+
+	#[serde(default = "def_osc_haptic_exponent")]
+	pub osc_haptic_exponent: f32,
+
+	#[serde(default = "def_false")]
+	pub osc_heartbeat_enabled: bool,
+
+	#[serde(default = "def_osc_heartbeat_address")]
+	pub osc_heartbeat_address: String,
+
+	#[serde(default)]
+	pub osc_precision: HashMap<String, u8>,
+
+	#[serde(default)]
+	pub midi_port: Option<String>,
+
+	#[serde(default = "def_midi_channel")]
+	pub midi_channel: u8,
+
+	#[serde(default = "def_midi_cc")]
+	pub midi_cc: u8,
+
+	#[serde(default)]
+	pub midi_note: Option<u8>,
+
+	#[serde(default = "def_midi_min_bpm")]
+	pub midi_min_bpm: u32,
+
+	#[serde(default = "def_midi_max_bpm")]
+	pub midi_max_bpm: u32,
+
+	#[serde(default)]
+	pub discord_client_id: Option<String>,
+
 	#[serde(default = "def_false")]
 	pub upright_screen_fix: bool,
 
@@ -301,6 +685,381 @@ pub struct GeneralConfig {
 	#[serde(default)]
 	pub fitbit_client_secret: Option<String>,
 
+	#[serde(default)]
+	pub fitbit_profile: Option<String>,
+
+	#[serde(default)]
+	pub fitbit_profiles: HashMap<String, FitbitProfile>,
+
+	#[serde(default = "def_fitbit_oauth_port")]
+	pub fitbit_oauth_port: u16,
+
+	#[serde(default = "def_fitbit_request_timeout_secs")]
+	pub fitbit_request_timeout_secs: u64,
+
+	#[serde(default)]
+	pub fitbit_curl_path: Option<String>,
+
+	#[serde(default = "def_fitbit_hourly_request_budget")]
+	pub fitbit_hourly_request_budget: u32,
+
+	#[serde(default = "def_fitbit_request_retries")]
+	pub fitbit_request_retries: u32,
+
+	#[serde(default = "def_fitbit_visibility_debounce_ms")]
+	pub fitbit_visibility_debounce_ms: u64,
+
+	#[serde(default = "def_false")]
+	pub fitbit_poll_on_startup: bool,
+
+	#[serde(default = "def_fitbit_warmup_polls")]
+	pub fitbit_warmup_polls: u32,
+
+	#[serde(default = "def_fitbit_warmup_interval_secs")]
+	pub fitbit_warmup_interval_secs: u64,
+
+	#[serde(default = "def_fitbit_stale_after_secs")]
+	pub fitbit_stale_after_secs: u64,
+
+	#[serde(default = "def_fitbit_sync_stall_secs")]
+	pub fitbit_sync_stall_secs: u64,
+
+	#[serde(default = "def_fitbit_auth_error_tolerance")]
+	pub fitbit_auth_error_tolerance: u32,
+
+	#[serde(default = "def_fitbit_auth_error_window_secs")]
+	pub fitbit_auth_error_window_secs: u64,
+
+	#[serde(default = "def_fitbit_detail_level")]
+	pub fitbit_detail_level: String,
+
+	#[serde(default = "def_fitbit_duplicate_times")]
+	pub fitbit_duplicate_times: String,
+
+	#[serde(default)]
+	pub fitbit_lookback_minutes: u32,
+
+	#[serde(default)]
+	pub fitbit_backfill_minutes: u32,
+
+	#[serde(default = "def_fitbit_average_entries")]
+	pub fitbit_average_entries: u32,
+
+	#[serde(default)]
+	pub fitbit_settle_secs: u32,
+
+	#[serde(default = "def_fitbit_midnight_grace_minutes")]
+	pub fitbit_midnight_grace_minutes: u32,
+
+	#[serde(default = "def_fitbit_token_refresh_margin_secs")]
+	pub fitbit_token_refresh_margin_secs: u64,
+
+	#[serde(default = "def_fitbit_interpolation")]
+	pub fitbit_interpolation: String,
+
+	#[serde(default)]
+	pub fitbit_proxy: Option<String>,
+
+	#[serde(default)]
+	pub fitbit_api_base_url: Option<String>,
+
+	#[serde(default = "def_fitbit_api_version")]
+	pub fitbit_api_version: String,
+
+	#[serde(default)]
+	pub fitbit_ca_cert: Option<String>,
+
+	#[serde(default = "def_false")]
+	pub fitbit_allow_insecure_tls: bool,
+
+	#[serde(default)]
+	pub fitbit_extra_headers: Vec<String>,
+
+	#[serde(default = "def_false")]
+	pub fitbit_mock: bool,
+
+	#[serde(default)]
+	pub fitbit_debug_dump_path: Option<String>,
+
+	#[serde(default)]
+	pub garmin_access_token: Option<String>,
+
+	#[serde(default)]
+	pub garmin_refresh_token: Option<String>,
+
+	#[serde(default)]
+	pub garmin_client_id: Option<String>,
+
+	#[serde(default)]
+	pub garmin_client_secret: Option<String>,
+
+	#[serde(default)]
+	pub hr_source: Option<String>,
+
+	#[serde(default)]
+	pub hr_composite_sources: Vec<String>,
+
+	#[serde(default)]
+	pub hr_composite_disagree_bpm: u32,
+
+	#[serde(default = "def_false")]
+	pub hr_composite_disagree_notify: bool,
+
+	#[serde(default = "def_hr_composite_mode")]
+	pub hr_composite_mode: String,
+
+	#[serde(default = "def_one")]
+	pub hr_composite_blend_bpm_per_min: f32,
+
+	#[serde(default)]
+	pub hr_cache_windows: HashMap<String, u64>,
+
+	#[serde(default)]
+	pub hr_restore_max_age_minutes: u32,
+
+	#[serde(default)]
+	pub hr_gap_tolerance_secs: u64,
+
+	#[serde(default)]
+	pub hr_daily_max: bool,
+
+	#[serde(default)]
+	pub hr_daily_timezone: Option<String>,
+
+	#[serde(default)]
+	pub hr_bias: HashMap<String, i32>,
+
+	#[serde(default)]
+	pub hr_ble_address: Option<String>,
+
+	#[serde(default = "def_hr_ble_switch_after_secs")]
+	pub hr_ble_switch_after_secs: u64,
+
+	#[serde(default = "def_hr_ble_watchdog_secs")]
+	pub hr_ble_watchdog_secs: u64,
+
+	#[serde(default)]
+	pub hr_ble_rr_average: usize,
+
+	#[serde(default)]
+	pub hr_file_path: Option<String>,
+
+	#[serde(default)]
+	pub hr_fifo_path: Option<String>,
+
+	#[serde(default)]
+	pub hr_replay_path: Option<String>,
+
+	#[serde(default = "def_one")]
+	pub hr_replay_speed: f32,
+
+	#[serde(default = "def_true")]
+	pub hr_replay_loop: bool,
+
+	#[serde(default = "def_hr_low_battery_percent")]
+	pub hr_low_battery_percent: u8,
+
+	#[serde(default)]
+	pub hr_signal_lost_minutes: u32,
+
+	#[serde(default)]
+	pub hr_pause_while_headset_idle: bool,
+
+	#[serde(default)]
+	pub hr_threshold_bpm: Option<u32>,
+
+	#[serde(default)]
+	pub hr_threshold_action: Option<String>,
+
+	#[serde(default = "def_hr_threshold_cooldown_secs")]
+	pub hr_threshold_cooldown_secs: u64,
+
+	#[serde(default)]
+	pub hr_ant_device: Option<String>,
+
+	#[serde(default)]
+	pub hr_ant_device_number: u16,
+
+	#[serde(default)]
+	pub hr_log_packets: bool,
+
+	#[serde(default = "def_hr_sim_pattern")]
+	pub hr_sim_pattern: String,
+
+	#[serde(default = "def_hr_sim_base")]
+	pub hr_sim_base: f32,
+
+	#[serde(default = "def_hr_sim_amplitude")]
+	pub hr_sim_amplitude: f32,
+
+	#[serde(default = "def_hr_sim_period_secs")]
+	pub hr_sim_period_secs: f32,
+
+	#[serde(default = "def_hr_min_bpm")]
+	pub hr_min_bpm: u32,
+
+	#[serde(default = "def_hr_max_bpm")]
+	pub hr_max_bpm: u32,
+
+	#[serde(default)]
+	pub hr_floor_bpm: u32,
+
+	#[serde(default = "def_hr_floor_grace_secs")]
+	pub hr_floor_grace_secs: u64,
+
+	#[serde(default)]
+	pub hr_settle_readings: u32,
+
+	#[serde(default = "def_hr_watch_hidden")]
+	pub hr_watch_hidden: String,
+
+	#[serde(default = "def_hr_watch_hidden_hold_secs")]
+	pub hr_watch_hidden_hold_secs: u64,
+
+	#[serde(default)]
+	pub hr_smoothing_window: usize,
+
+	#[serde(default = "def_hr_history_len")]
+	pub hr_history_len: usize,
+
+	#[serde(default = "def_hr_trend_deadband")]
+	pub hr_trend_deadband: f32,
+
+	#[serde(default)]
+	pub hr_format: HrFormat,
+
+	#[serde(default)]
+	pub hr_change_threshold: u32,
+
+	#[serde(default = "def_hr_change_force_secs")]
+	pub hr_change_force_secs: u64,
+
+	#[serde(default)]
+	pub hr_peak_hold_secs: f32,
+
+	#[serde(default = "def_hr_peak_decay_bpm_per_sec")]
+	pub hr_peak_decay_bpm_per_sec: f32,
+
+	#[serde(default = "def_hr_max")]
+	pub hr_max: u32,
+
+	#[serde(default = "def_false")]
+	pub hr_max_from_age: bool,
+
+	#[serde(default)]
+	pub hr_resting: Option<u32>,
+
+	#[serde(default = "def_hr_resting_calibration_secs")]
+	pub hr_resting_calibration_secs: u64,
+
+	#[serde(default = "def_false")]
+	pub hr_zones_from_reserve: bool,
+
+	#[serde(default)]
+	pub hr_zone_bounds: Vec<u32>,
+
+	#[serde(default = "def_hr_zone_hysteresis")]
+	pub hr_zone_hysteresis: u32,
+
+	#[serde(default)]
+	pub hr_zone_colors: Vec<String>,
+
+	#[serde(default)]
+	pub hr_profile_age: Option<u32>,
+
+	#[serde(default)]
+	pub hr_profile_weight_kg: Option<f32>,
+
+	#[serde(default)]
+	pub hr_profile_gender: Option<String>,
+
+	#[serde(default)]
+	pub hr_segment_threshold_bpm: Option<u32>,
+
+	#[serde(default = "def_hr_segment_after_secs")]
+	pub hr_segment_after_secs: u64,
+
+	#[serde(default = "def_false")]
+	pub hr_dbus: bool,
+
+	#[serde(default)]
+	pub hr_dbus_alive_interval_secs: u64,
+
+	#[serde(default)]
+	pub hr_log_path: Option<String>,
+
+	#[serde(default = "def_hr_log_max_size_mb")]
+	pub hr_log_max_size_mb: u64,
+
+	#[serde(default)]
+	pub hr_status_file_path: Option<String>,
+
+	#[serde(default = "def_hr_status_file_template")]
+	pub hr_status_file_template: String,
+
+	#[serde(default = "def_false")]
+	pub hr_log_readings: bool,
+
+	#[serde(default)]
+	pub hr_session_export_dir: Option<String>,
+
+	#[serde(default = "def_hr_session_export_format")]
+	pub hr_session_export_format: String,
+
+	#[serde(default)]
+	pub hr_session_notification: Vec<String>,
+
+	#[serde(default = "def_hr_notify_backend")]
+	pub hr_notify_backend: String,
+
+	#[serde(default)]
+	pub hr_notify_command: Vec<String>,
+
+	#[serde(default = "def_hr_notify_command_timeout_secs")]
+	pub hr_notify_command_timeout_secs: u64,
+
+	#[serde(default = "def_hr_notify_cooldown_secs")]
+	pub hr_notify_cooldown_secs: u64,
+
+	#[serde(default)]
+	pub hr_milestone_cooldown: bool,
+
+	#[serde(default)]
+	pub hr_milestone_new_peak: bool,
+
+	#[serde(default = "def_hr_milestone_resting_margin_bpm")]
+	pub hr_milestone_resting_margin_bpm: u32,
+
+	#[serde(default = "def_hr_milestone_cooldown_secs")]
+	pub hr_milestone_cooldown_secs: u64,
+
+	#[serde(default)]
+	pub hr_milestone_notify: bool,
+
+	#[serde(default)]
+	pub pulsoid_port: Option<u16>,
+
+	#[serde(default)]
+	pub hr_http_port: Option<u16>,
+
+	#[serde(default = "def_hr_http_bind")]
+	pub hr_http_bind: String,
+
+	#[serde(default)]
+	pub hr_http_allow_origin: Option<String>,
+
+	#[serde(default)]
+	pub hr_ws_port: Option<u16>,
+
+	#[serde(default = "def_hr_ws_bind")]
+	pub hr_ws_bind: String,
+
+	#[serde(default)]
+	pub hr_metrics_bind: Option<String>,
+
+	#[serde(default)]
+	pub hr_control_socket: Option<String>,
+
 	#[serde(default)]
 	pub sets: Vec<SerializedWindowSet>,
 
@@ -3,7 +3,7 @@ use std::sync::Arc;
 use idmap_derive::IntegerId;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, IntegerId, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, IntegerId, Serialize, Deserialize)]
 pub enum ToastTopic {
 	System,
 	Error,